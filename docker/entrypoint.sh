@@ -10,4 +10,6 @@ echo "PostgreSQL started"
 
 echo "Hostname: "
 hostname
-./service run
+# exec, so that the termination signal reaches the service, which drains
+# mutations in flight before stopping.
+exec ./service run
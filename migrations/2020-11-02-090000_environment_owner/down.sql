@@ -0,0 +1,48 @@
+DROP FUNCTION IF EXISTS list_environments();
+DROP FUNCTION IF EXISTS get_environment_by_id(UUID);
+DROP FUNCTION IF EXISTS create_environment(TEXT, INTEGER, TEXT);
+DROP FUNCTION IF EXISTS delete_environment(UUID);
+DROP TYPE IF EXISTS return_environment_type;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port)
+  VALUES (_name, _port)
+  RETURNING id, name, signature, port, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+ALTER TABLE environments DROP COLUMN owner;
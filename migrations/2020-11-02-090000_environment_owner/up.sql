@@ -0,0 +1,51 @@
+-- Record who created each environment, so that operators can only delete their own.
+
+ALTER TABLE environments ADD COLUMN owner TEXT NOT NULL DEFAULT 'nidavellir';
+
+DROP FUNCTION IF EXISTS list_environments();
+DROP FUNCTION IF EXISTS get_environment_by_id(UUID);
+DROP FUNCTION IF EXISTS create_environment(TEXT, INTEGER);
+DROP FUNCTION IF EXISTS delete_environment(UUID);
+DROP TYPE IF EXISTS return_environment_type;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  owner TEXT
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER, _owner TEXT)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port, owner)
+  VALUES (_name, _port, _owner)
+  RETURNING id, name, signature, port, created_at, updated_at, owner;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner;
+$$ LANGUAGE SQL;
@@ -0,0 +1,131 @@
+-- Projects own environments, and bound the resources they use with quotas.
+-- A NULL quota is unlimited.
+
+CREATE TABLE projects (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  name TEXT NOT NULL UNIQUE,
+  max_environments INTEGER,
+  max_indexes INTEGER,
+  max_memory INTEGER,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Existing environments are moved to a default project.
+INSERT INTO projects (name) VALUES ('default');
+
+ALTER TABLE environments ADD COLUMN project UUID REFERENCES projects(id);
+UPDATE environments SET project = (SELECT id FROM projects WHERE name = 'default');
+ALTER TABLE environments ALTER COLUMN project SET NOT NULL;
+ALTER TABLE environments ADD COLUMN memory INTEGER NOT NULL DEFAULT 0;
+
+CREATE TYPE return_project_type AS (
+  id UUID,
+  name TEXT,
+  max_environments INTEGER,
+  max_indexes INTEGER,
+  max_memory INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ
+);
+
+CREATE TYPE return_usage_type AS (
+  environments INTEGER,
+  indexes INTEGER,
+  memory INTEGER
+);
+
+CREATE FUNCTION list_projects()
+RETURNS SETOF return_project_type
+AS $$
+  SELECT id, name, max_environments, max_indexes, max_memory, created_at, updated_at
+  FROM projects
+  ORDER BY name;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_project_by_id(_id UUID)
+RETURNS return_project_type
+AS $$
+  SELECT id, name, max_environments, max_indexes, max_memory, created_at, updated_at
+  FROM projects
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_project(_name TEXT, _max_environments INTEGER, _max_indexes INTEGER, _max_memory INTEGER)
+RETURNS return_project_type
+AS $$
+  INSERT INTO projects (name, max_environments, max_indexes, max_memory)
+  VALUES (_name, _max_environments, _max_indexes, _max_memory)
+  RETURNING id, name, max_environments, max_indexes, max_memory, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION update_project_quota(_id UUID, _max_environments INTEGER, _max_indexes INTEGER, _max_memory INTEGER)
+RETURNS return_project_type
+AS $$
+  UPDATE projects
+  SET max_environments = _max_environments,
+      max_indexes = _max_indexes,
+      max_memory = _max_memory,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, max_environments, max_indexes, max_memory, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_project_usage(_id UUID)
+RETURNS return_usage_type
+AS $$
+  SELECT
+    (SELECT COUNT(*)::INTEGER FROM environments WHERE project = _id),
+    (SELECT COUNT(*)::INTEGER FROM indexes i JOIN environments e ON i.environment = e.id WHERE e.project = _id),
+    (SELECT COALESCE(SUM(memory), 0)::INTEGER FROM environments WHERE project = _id);
+$$ LANGUAGE SQL;
+
+DROP FUNCTION IF EXISTS list_environments();
+DROP FUNCTION IF EXISTS get_environment_by_id(UUID);
+DROP FUNCTION IF EXISTS create_environment(TEXT, INTEGER, TEXT);
+DROP FUNCTION IF EXISTS delete_environment(UUID);
+DROP TYPE IF EXISTS return_environment_type;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  owner TEXT,
+  project UUID,
+  memory INTEGER
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER, _owner TEXT, _project UUID, _memory INTEGER)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port, owner, project, memory)
+  VALUES (_name, _port, _owner, _project, _memory)
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory;
+$$ LANGUAGE SQL;
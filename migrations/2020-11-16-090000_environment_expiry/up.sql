@@ -0,0 +1,91 @@
+-- Environments can be given an expiry date, after which they are torn down.
+-- expiry_warned records whether the warning preceding the expiry was emitted.
+
+ALTER TABLE environments ADD COLUMN expires_at TIMESTAMPTZ;
+ALTER TABLE environments ADD COLUMN expiry_warned BOOLEAN NOT NULL DEFAULT FALSE;
+
+DROP FUNCTION IF EXISTS list_environments();
+DROP FUNCTION IF EXISTS get_environment_by_id(UUID);
+DROP FUNCTION IF EXISTS create_environment(TEXT, INTEGER, TEXT, UUID, INTEGER);
+DROP FUNCTION IF EXISTS delete_environment(UUID);
+DROP TYPE IF EXISTS return_environment_type;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  owner TEXT,
+  project UUID,
+  memory INTEGER,
+  expires_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER, _owner TEXT, _project UUID, _memory INTEGER, _expires_at TIMESTAMPTZ)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port, owner, project, memory, expires_at)
+  VALUES (_name, _port, _owner, _project, _memory, _expires_at)
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION extend_environment(_id UUID, _expires_at TIMESTAMPTZ)
+RETURNS return_environment_type
+AS $$
+  UPDATE environments
+  SET expires_at = _expires_at,
+      expiry_warned = FALSE,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expiring_environments(_before TIMESTAMPTZ)
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= _before AND NOT expiry_warned
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expired_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= NOW()
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION mark_environment_warned(_id UUID)
+RETURNS VOID
+AS $$
+  UPDATE environments SET expiry_warned = TRUE WHERE id = _id;
+$$ LANGUAGE SQL;
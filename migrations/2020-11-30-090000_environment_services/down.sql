@@ -0,0 +1,89 @@
+-- Dropping the type drops all the functions returning environments.
+DROP TYPE IF EXISTS return_environment_type CASCADE;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  owner TEXT,
+  project UUID,
+  memory INTEGER,
+  expires_at TIMESTAMPTZ,
+  state environment_state
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER, _owner TEXT, _project UUID, _memory INTEGER, _expires_at TIMESTAMPTZ)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port, owner, project, memory, expires_at)
+  VALUES (_name, _port, _owner, _project, _memory, _expires_at)
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION extend_environment(_id UUID, _expires_at TIMESTAMPTZ)
+RETURNS return_environment_type
+AS $$
+  UPDATE environments
+  SET expires_at = _expires_at,
+      expiry_warned = FALSE,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expiring_environments(_before TIMESTAMPTZ)
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= _before AND NOT expiry_warned
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expired_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= NOW()
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION update_environment_state(_id UUID, _state environment_state)
+RETURNS return_environment_type
+AS $$
+  UPDATE environments
+  SET state = _state,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state;
+$$ LANGUAGE SQL;
+
+ALTER TABLE environments DROP COLUMN services;
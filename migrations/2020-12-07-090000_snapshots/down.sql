@@ -0,0 +1,2 @@
+DROP TYPE IF EXISTS return_snapshot_type CASCADE;
+DROP TABLE snapshots;
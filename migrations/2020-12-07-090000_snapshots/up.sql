@@ -0,0 +1,46 @@
+-- Snapshots of the volumes of environments. The environment a snapshot was taken
+-- from may be deleted, the snapshot remains usable.
+
+CREATE TABLE snapshots (
+  id UUID PRIMARY KEY,
+  name TEXT NOT NULL,
+  environment UUID REFERENCES environments(id) ON DELETE SET NULL,
+  environment_name TEXT NOT NULL,
+  path TEXT NOT NULL,
+  volumes TEXT[] NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TYPE return_snapshot_type AS (
+  id UUID,
+  name TEXT,
+  environment UUID,
+  environment_name TEXT,
+  path TEXT,
+  volumes TEXT[],
+  created_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_snapshots()
+RETURNS SETOF return_snapshot_type
+AS $$
+  SELECT id, name, environment, environment_name, path, volumes, created_at
+  FROM snapshots
+  ORDER BY created_at DESC;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_snapshot_by_id(_id UUID)
+RETURNS return_snapshot_type
+AS $$
+  SELECT id, name, environment, environment_name, path, volumes, created_at
+  FROM snapshots
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_snapshot(_id UUID, _name TEXT, _environment UUID, _environment_name TEXT, _path TEXT, _volumes TEXT[])
+RETURNS return_snapshot_type
+AS $$
+  INSERT INTO snapshots (id, name, environment, environment_name, path, volumes)
+  VALUES (_id, _name, _environment, _environment_name, _path, _volumes)
+  RETURNING id, name, environment, environment_name, path, volumes, created_at;
+$$ LANGUAGE SQL;
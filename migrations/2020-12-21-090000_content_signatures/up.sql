@@ -0,0 +1,127 @@
+-- Signatures are now computed by the service from the content of environments
+-- and indexes, and given when they are created.
+
+ALTER TABLE environments ALTER COLUMN signature DROP DEFAULT;
+ALTER TABLE indexes ALTER COLUMN signature DROP DEFAULT;
+
+CREATE INDEX environments_signature_idx ON environments (project, signature);
+
+-- Dropping the type drops all the functions returning environments.
+DROP TYPE IF EXISTS return_environment_type CASCADE;
+
+CREATE TYPE return_environment_type AS (
+  id UUID,
+  name TEXT,
+  signature TEXT,
+  port INTEGER,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  owner TEXT,
+  project UUID,
+  memory INTEGER,
+  expires_at TIMESTAMPTZ,
+  state environment_state,
+  services JSONB,
+  config JSONB
+);
+
+CREATE FUNCTION list_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config
+  FROM environments
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION get_environment_by_id(_id UUID)
+RETURNS return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config
+  FROM environments
+  WHERE id = _id;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_environment(_name TEXT, _port INTEGER, _owner TEXT, _project UUID, _memory INTEGER, _expires_at TIMESTAMPTZ, _services JSONB, _config JSONB, _signature TEXT)
+RETURNS return_environment_type
+AS $$
+  INSERT INTO environments (name, port, owner, project, memory, expires_at, services, config, signature)
+  VALUES (_name, _port, _owner, _project, _memory, _expires_at, _services, _config, _signature)
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_environment(_id UUID)
+RETURNS return_environment_type
+AS $$
+  DELETE FROM environments
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION extend_environment(_id UUID, _expires_at TIMESTAMPTZ)
+RETURNS return_environment_type
+AS $$
+  UPDATE environments
+  SET expires_at = _expires_at,
+      expiry_warned = FALSE,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expiring_environments(_before TIMESTAMPTZ)
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= _before AND NOT expiry_warned
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_expired_environments()
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config
+  FROM environments
+  WHERE expires_at IS NOT NULL AND expires_at <= NOW()
+  ORDER BY expires_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION update_environment_state(_id UUID, _state environment_state)
+RETURNS return_environment_type
+AS $$
+  UPDATE environments
+  SET state = _state,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION find_environment_by_signature(_project UUID, _signature TEXT)
+RETURNS SETOF return_environment_type
+AS $$
+  SELECT id, name, signature, port, created_at, updated_at, owner, project, memory, expires_at, state, services, config
+  FROM environments
+  WHERE project = _project AND signature = _signature AND state = 'running'
+  ORDER BY created_at
+  LIMIT 1;
+$$ LANGUAGE SQL;
+
+DROP FUNCTION IF EXISTS create_index(UUID, TEXT, TEXT, TEXT[]);
+
+CREATE FUNCTION create_index(_environment UUID, _index_type TEXT, _data_source TEXT, _regions TEXT[], _signature TEXT)
+RETURNS return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature)
+  VALUES (_environment, _index_type, _data_source, _regions, _signature)
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE OR REPLACE FUNCTION copy_environment_indexes(_source UUID, _target UUID)
+RETURNS SETOF return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature, status)
+  SELECT _target, index_type, data_source, regions, signature, status
+  FROM indexes
+  WHERE environment = _source
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
@@ -0,0 +1,6 @@
+DROP FUNCTION IF EXISTS list_audit_entries(TEXT, audit_kind, TEXT, UUID, UUID, audit_outcome, TIMESTAMPTZ, TIMESTAMPTZ, BIGINT);
+DROP TYPE IF EXISTS return_audit_entry_type CASCADE;
+DROP TABLE audit_entries;
+DROP FUNCTION IF EXISTS reject_audit_change();
+DROP TYPE audit_outcome;
+DROP TYPE audit_kind;
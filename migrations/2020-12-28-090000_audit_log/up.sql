@@ -0,0 +1,76 @@
+-- An append-only log of mutations and container operations. Entries keep the ids of
+-- the environments and indexes they affected, which may since have been deleted.
+
+CREATE TYPE audit_kind AS ENUM ('mutation', 'docker');
+
+CREATE TYPE audit_outcome AS ENUM ('success', 'failure');
+
+CREATE TABLE audit_entries (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  actor TEXT NOT NULL,
+  kind audit_kind NOT NULL,
+  operation TEXT NOT NULL,
+  arguments JSONB NOT NULL,
+  environment_id UUID,
+  index_id UUID,
+  outcome audit_outcome NOT NULL,
+  error TEXT,
+  duration BIGINT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_entries_created_at_idx ON audit_entries (created_at);
+CREATE INDEX audit_entries_environment_idx ON audit_entries (environment_id);
+
+CREATE FUNCTION reject_audit_change()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  RAISE EXCEPTION 'audit entries cannot be modified';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_entries_append_only
+BEFORE UPDATE OR DELETE ON audit_entries
+FOR EACH ROW EXECUTE PROCEDURE reject_audit_change();
+
+CREATE TYPE return_audit_entry_type AS (
+  id UUID,
+  actor TEXT,
+  kind audit_kind,
+  operation TEXT,
+  arguments JSONB,
+  environment_id UUID,
+  index_id UUID,
+  outcome audit_outcome,
+  error TEXT,
+  duration BIGINT,
+  created_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION create_audit_entry(_actor TEXT, _kind audit_kind, _operation TEXT, _arguments JSONB, _environment UUID, _index UUID, _outcome audit_outcome, _error TEXT, _duration BIGINT)
+RETURNS return_audit_entry_type
+AS $$
+  INSERT INTO audit_entries (actor, kind, operation, arguments, environment_id, index_id, outcome, error, duration)
+  VALUES (_actor, _kind, _operation, _arguments, _environment, _index, _outcome, _error, _duration)
+  RETURNING id, actor, kind, operation, arguments, environment_id, index_id, outcome, error, duration, created_at;
+$$ LANGUAGE SQL;
+
+-- Entries matching all the given criteria, a NULL criterion matching any entry,
+-- most recent first.
+CREATE FUNCTION list_audit_entries(_actor TEXT, _kind audit_kind, _operation TEXT, _environment UUID, _index UUID, _outcome audit_outcome, _since TIMESTAMPTZ, _until TIMESTAMPTZ, _limit BIGINT)
+RETURNS SETOF return_audit_entry_type
+AS $$
+  SELECT id, actor, kind, operation, arguments, environment_id, index_id, outcome, error, duration, created_at
+  FROM audit_entries
+  WHERE (_actor IS NULL OR actor = _actor)
+    AND (_kind IS NULL OR kind = _kind)
+    AND (_operation IS NULL OR operation = _operation)
+    AND (_environment IS NULL OR environment_id = _environment)
+    AND (_index IS NULL OR index_id = _index)
+    AND (_outcome IS NULL OR outcome = _outcome)
+    AND (_since IS NULL OR created_at >= _since)
+    AND (_until IS NULL OR created_at < _until)
+  ORDER BY created_at DESC
+  LIMIT _limit;
+$$ LANGUAGE SQL;
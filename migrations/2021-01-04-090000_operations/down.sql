@@ -0,0 +1,6 @@
+DROP FUNCTION IF EXISTS list_operations();
+DROP FUNCTION IF EXISTS delete_operation(UUID);
+DROP FUNCTION IF EXISTS create_operation(operation_kind, TEXT, TEXT, JSONB);
+DROP TYPE IF EXISTS return_operation_type CASCADE;
+DROP TABLE operations;
+DROP TYPE operation_kind;
@@ -0,0 +1,47 @@
+-- Operations in progress, such as provisioning an environment. An operation is
+-- removed once it completes, so the operations found when the service starts were
+-- interrupted, and are resumed or rolled back.
+
+CREATE TYPE operation_kind AS ENUM ('provision_environment', 'create_index');
+
+CREATE TABLE operations (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  kind operation_kind NOT NULL,
+  actor TEXT NOT NULL,
+  name TEXT NOT NULL,
+  arguments JSONB NOT NULL,
+  started_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TYPE return_operation_type AS (
+  id UUID,
+  kind operation_kind,
+  actor TEXT,
+  name TEXT,
+  arguments JSONB,
+  started_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION create_operation(_kind operation_kind, _actor TEXT, _name TEXT, _arguments JSONB)
+RETURNS return_operation_type
+AS $$
+  INSERT INTO operations (kind, actor, name, arguments)
+  VALUES (_kind, _actor, _name, _arguments)
+  RETURNING id, kind, actor, name, arguments, started_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_operation(_id UUID)
+RETURNS return_operation_type
+AS $$
+  DELETE FROM operations
+  WHERE id = _id
+  RETURNING id, kind, actor, name, arguments, started_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION list_operations()
+RETURNS SETOF return_operation_type
+AS $$
+  SELECT id, kind, actor, name, arguments, started_at
+  FROM operations
+  ORDER BY started_at;
+$$ LANGUAGE SQL;
@@ -0,0 +1 @@
+DROP FUNCTION IF EXISTS delete_index(UUID, UUID);
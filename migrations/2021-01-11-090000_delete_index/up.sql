@@ -0,0 +1,9 @@
+-- Indexes are removed from an environment when they are pruned from its manifest.
+
+CREATE FUNCTION delete_index(_environment UUID, _id UUID)
+RETURNS return_index_type
+AS $$
+  DELETE FROM indexes
+  WHERE environment = _environment AND id = _id
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
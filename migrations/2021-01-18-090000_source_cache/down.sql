@@ -0,0 +1,7 @@
+DROP FUNCTION IF EXISTS list_cached_artifacts();
+DROP FUNCTION IF EXISTS find_cached_artifact(TEXT, TEXT, TEXT);
+DROP FUNCTION IF EXISTS create_cached_artifact(TEXT, TEXT, TEXT, TEXT, BIGINT, TEXT);
+DROP FUNCTION IF EXISTS touch_cached_artifact(UUID);
+DROP FUNCTION IF EXISTS delete_cached_artifact(UUID);
+DROP TYPE IF EXISTS return_cached_artifact_type CASCADE;
+DROP TABLE cached_artifacts;
@@ -0,0 +1,71 @@
+-- Source data downloaded for indexes, cached on the host. Files are named by the digest
+-- of their content, several artifacts may share one.
+
+CREATE TABLE cached_artifacts (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  data_source TEXT NOT NULL,
+  region TEXT NOT NULL,
+  version TEXT NOT NULL,
+  digest TEXT NOT NULL,
+  size BIGINT NOT NULL,
+  url TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (data_source, region, version)
+);
+
+CREATE TYPE return_cached_artifact_type AS (
+  id UUID,
+  data_source TEXT,
+  region TEXT,
+  version TEXT,
+  digest TEXT,
+  size BIGINT,
+  url TEXT,
+  created_at TIMESTAMPTZ,
+  used_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_cached_artifacts()
+RETURNS SETOF return_cached_artifact_type
+AS $$
+  SELECT id, data_source, region, version, digest, size, url, created_at, used_at
+  FROM cached_artifacts
+  ORDER BY used_at DESC;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION find_cached_artifact(_data_source TEXT, _region TEXT, _version TEXT)
+RETURNS SETOF return_cached_artifact_type
+AS $$
+  SELECT id, data_source, region, version, digest, size, url, created_at, used_at
+  FROM cached_artifacts
+  WHERE data_source = _data_source AND region = _region AND version = _version;
+$$ LANGUAGE SQL;
+
+-- An artifact downloaded again replaces the previous one, keeping its id.
+CREATE FUNCTION create_cached_artifact(_data_source TEXT, _region TEXT, _version TEXT, _digest TEXT, _size BIGINT, _url TEXT)
+RETURNS return_cached_artifact_type
+AS $$
+  INSERT INTO cached_artifacts (data_source, region, version, digest, size, url)
+  VALUES (_data_source, _region, _version, _digest, _size, _url)
+  ON CONFLICT (data_source, region, version)
+  DO UPDATE SET digest = EXCLUDED.digest, size = EXCLUDED.size, url = EXCLUDED.url, used_at = NOW()
+  RETURNING id, data_source, region, version, digest, size, url, created_at, used_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION touch_cached_artifact(_id UUID)
+RETURNS return_cached_artifact_type
+AS $$
+  UPDATE cached_artifacts
+  SET used_at = NOW()
+  WHERE id = _id
+  RETURNING id, data_source, region, version, digest, size, url, created_at, used_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_cached_artifact(_id UUID)
+RETURNS return_cached_artifact_type
+AS $$
+  DELETE FROM cached_artifacts
+  WHERE id = _id
+  RETURNING id, data_source, region, version, digest, size, url, created_at, used_at;
+$$ LANGUAGE SQL;
@@ -0,0 +1,62 @@
+DROP FUNCTION IF EXISTS list_index_refreshes(UUID);
+DROP FUNCTION IF EXISTS create_index_refresh(UUID);
+DROP FUNCTION IF EXISTS finish_index_refresh(UUID, INTEGER, index_status, audit_outcome, TEXT);
+DROP FUNCTION IF EXISTS abandon_index_refreshes(TEXT);
+DROP TYPE IF EXISTS return_index_refresh_type CASCADE;
+DROP TABLE index_refreshes;
+
+DROP FUNCTION IF EXISTS list_due_indexes(TIMESTAMPTZ);
+DROP TYPE IF EXISTS return_scheduled_index_type CASCADE;
+
+DROP TYPE IF EXISTS return_index_type CASCADE;
+
+DROP INDEX IF EXISTS indexes_next_refresh_at_idx;
+ALTER TABLE indexes DROP COLUMN next_refresh_at;
+ALTER TABLE indexes DROP COLUMN schedule;
+ALTER TABLE indexes DROP COLUMN twerg_id;
+
+CREATE TYPE return_index_type AS (
+  id UUID,
+  index_type TEXT,
+  data_source TEXT,
+  regions TEXT[],
+  signature TEXT,
+  status index_status,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_environment_indexes(_environment UUID)
+RETURNS SETOF return_index_type
+AS $$
+  SELECT id, index_type, data_source, regions, signature, status, created_at, updated_at
+  FROM indexes
+  WHERE environment = _environment
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_index(_environment UUID, _index_type TEXT, _data_source TEXT, _regions TEXT[], _signature TEXT)
+RETURNS return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature)
+  VALUES (_environment, _index_type, _data_source, _regions, _signature)
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION copy_environment_indexes(_source UUID, _target UUID)
+RETURNS SETOF return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature, status)
+  SELECT _target, index_type, data_source, regions, signature, status
+  FROM indexes
+  WHERE environment = _source
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_index(_environment UUID, _id UUID)
+RETURNS return_index_type
+AS $$
+  DELETE FROM indexes
+  WHERE environment = _environment AND id = _id
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at;
+$$ LANGUAGE SQL;
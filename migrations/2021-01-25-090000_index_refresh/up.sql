@@ -0,0 +1,166 @@
+-- Indexes may be rebuilt on a cron schedule. A refresh builds a new index in the twerg,
+-- which replaces the one serving requests once it is available. Each refresh is
+-- recorded, with its outcome.
+
+ALTER TABLE indexes ADD COLUMN twerg_id INTEGER;
+ALTER TABLE indexes ADD COLUMN schedule TEXT;
+ALTER TABLE indexes ADD COLUMN next_refresh_at TIMESTAMPTZ;
+
+CREATE INDEX indexes_next_refresh_at_idx ON indexes (next_refresh_at);
+
+-- Dropping the type drops all the functions returning indexes.
+DROP TYPE IF EXISTS return_index_type CASCADE;
+
+CREATE TYPE return_index_type AS (
+  id UUID,
+  index_type TEXT,
+  data_source TEXT,
+  regions TEXT[],
+  signature TEXT,
+  status index_status,
+  created_at TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ,
+  twerg_id INTEGER,
+  schedule TEXT,
+  next_refresh_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_environment_indexes(_environment UUID)
+RETURNS SETOF return_index_type
+AS $$
+  SELECT id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at
+  FROM indexes
+  WHERE environment = _environment
+  ORDER BY created_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_index(_environment UUID, _index_type TEXT, _data_source TEXT, _regions TEXT[], _signature TEXT, _twerg_id INTEGER)
+RETURNS return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature, twerg_id)
+  VALUES (_environment, _index_type, _data_source, _regions, _signature, _twerg_id)
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION copy_environment_indexes(_source UUID, _target UUID)
+RETURNS SETOF return_index_type
+AS $$
+  INSERT INTO indexes (environment, index_type, data_source, regions, signature, status, twerg_id, schedule, next_refresh_at)
+  SELECT _target, index_type, data_source, regions, signature, status, twerg_id, schedule, next_refresh_at
+  FROM indexes
+  WHERE environment = _source
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_index(_environment UUID, _id UUID)
+RETURNS return_index_type
+AS $$
+  DELETE FROM indexes
+  WHERE environment = _environment AND id = _id
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at;
+$$ LANGUAGE SQL;
+
+-- A null schedule stops the refreshes of the index.
+CREATE FUNCTION schedule_index(_id UUID, _schedule TEXT, _next_refresh_at TIMESTAMPTZ)
+RETURNS return_index_type
+AS $$
+  UPDATE indexes
+  SET schedule = _schedule,
+      next_refresh_at = _next_refresh_at
+  WHERE id = _id
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at;
+$$ LANGUAGE SQL;
+
+-- The index built in the twerg replaces the one serving requests.
+CREATE FUNCTION swap_index(_id UUID, _twerg_id INTEGER, _status index_status)
+RETURNS return_index_type
+AS $$
+  UPDATE indexes
+  SET twerg_id = _twerg_id,
+      status = _status,
+      updated_at = NOW()
+  WHERE id = _id
+  RETURNING id, index_type, data_source, regions, signature, status, created_at, updated_at, twerg_id, schedule, next_refresh_at;
+$$ LANGUAGE SQL;
+
+CREATE TYPE return_scheduled_index_type AS (
+  environment UUID,
+  id UUID,
+  schedule TEXT,
+  next_refresh_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_due_indexes(_before TIMESTAMPTZ)
+RETURNS SETOF return_scheduled_index_type
+AS $$
+  SELECT environment, id, schedule, next_refresh_at
+  FROM indexes
+  WHERE schedule IS NOT NULL AND next_refresh_at <= _before
+  ORDER BY next_refresh_at;
+$$ LANGUAGE SQL;
+
+CREATE TABLE index_refreshes (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  index_id UUID NOT NULL REFERENCES indexes(id) ON DELETE CASCADE,
+  twerg_id INTEGER,
+  status index_status,
+  outcome audit_outcome,
+  error TEXT,
+  started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX index_refreshes_index_idx ON index_refreshes (index_id, started_at);
+
+CREATE TYPE return_index_refresh_type AS (
+  id UUID,
+  index_id UUID,
+  twerg_id INTEGER,
+  status index_status,
+  outcome audit_outcome,
+  error TEXT,
+  started_at TIMESTAMPTZ,
+  finished_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_index_refreshes(_index UUID)
+RETURNS SETOF return_index_refresh_type
+AS $$
+  SELECT id, index_id, twerg_id, status, outcome, error, started_at, finished_at
+  FROM index_refreshes
+  WHERE index_id = _index
+  ORDER BY started_at DESC;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_index_refresh(_index UUID)
+RETURNS return_index_refresh_type
+AS $$
+  INSERT INTO index_refreshes (index_id)
+  VALUES (_index)
+  RETURNING id, index_id, twerg_id, status, outcome, error, started_at, finished_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION finish_index_refresh(_id UUID, _twerg_id INTEGER, _status index_status, _outcome audit_outcome, _error TEXT)
+RETURNS return_index_refresh_type
+AS $$
+  UPDATE index_refreshes
+  SET twerg_id = _twerg_id,
+      status = _status,
+      outcome = _outcome,
+      error = _error,
+      finished_at = NOW()
+  WHERE id = _id
+  RETURNING id, index_id, twerg_id, status, outcome, error, started_at, finished_at;
+$$ LANGUAGE SQL;
+
+-- Refreshes still running when the service stopped are failed when it starts.
+CREATE FUNCTION abandon_index_refreshes(_error TEXT)
+RETURNS SETOF return_index_refresh_type
+AS $$
+  UPDATE index_refreshes
+  SET outcome = 'failure',
+      error = _error,
+      finished_at = NOW()
+  WHERE finished_at IS NULL
+  RETURNING id, index_id, twerg_id, status, outcome, error, started_at, finished_at;
+$$ LANGUAGE SQL;
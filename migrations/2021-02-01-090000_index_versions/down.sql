@@ -0,0 +1,5 @@
+DROP FUNCTION IF EXISTS list_index_versions(UUID);
+DROP FUNCTION IF EXISTS create_index_version(UUID, INTEGER);
+DROP FUNCTION IF EXISTS delete_index_version(UUID, INTEGER);
+DROP TYPE IF EXISTS return_index_version_type CASCADE;
+DROP TABLE index_versions;
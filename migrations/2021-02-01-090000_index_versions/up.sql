@@ -0,0 +1,40 @@
+-- The versions of an index which served requests before the current one, kept in
+-- the twerg so that the index can be rolled back to them.
+CREATE TABLE index_versions (
+  index_id UUID NOT NULL REFERENCES indexes(id) ON DELETE CASCADE,
+  twerg_id INTEGER NOT NULL,
+  retired_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (index_id, twerg_id)
+);
+
+CREATE TYPE return_index_version_type AS (
+  index_id UUID,
+  twerg_id INTEGER,
+  retired_at TIMESTAMPTZ
+);
+
+CREATE FUNCTION list_index_versions(_index UUID)
+RETURNS SETOF return_index_version_type
+AS $$
+  SELECT index_id, twerg_id, retired_at
+  FROM index_versions
+  WHERE index_id = _index
+  ORDER BY retired_at DESC;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION create_index_version(_index UUID, _twerg_id INTEGER)
+RETURNS return_index_version_type
+AS $$
+  INSERT INTO index_versions (index_id, twerg_id)
+  VALUES (_index, _twerg_id)
+  ON CONFLICT (index_id, twerg_id) DO UPDATE SET retired_at = NOW()
+  RETURNING index_id, twerg_id, retired_at;
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION delete_index_version(_index UUID, _twerg_id INTEGER)
+RETURNS return_index_version_type
+AS $$
+  DELETE FROM index_versions
+  WHERE index_id = _index AND twerg_id = _twerg_id
+  RETURNING index_id, twerg_id, retired_at;
+$$ LANGUAGE SQL;
@@ -0,0 +1,10 @@
+-- The project is locked until the end of the transaction, so that two
+-- transactions cannot both find room for one more environment or index.
+CREATE FUNCTION lock_project(_id UUID)
+RETURNS return_project_type
+AS $$
+  SELECT id, name, max_environments, max_indexes, max_memory, created_at, updated_at
+  FROM projects
+  WHERE id = _id
+  FOR UPDATE;
+$$ LANGUAGE SQL;
@@ -0,0 +1,10 @@
+-- Operations record their progress in their arguments, eg the id of the index a
+-- twerg accepted, so that they are recovered from where they were interrupted.
+CREATE FUNCTION update_operation(_id UUID, _arguments JSONB)
+RETURNS return_operation_type
+AS $$
+  UPDATE operations
+  SET arguments = _arguments
+  WHERE id = _id
+  RETURNING id, kind, actor, name, arguments, started_at;
+$$ LANGUAGE SQL;
@@ -1,16 +1,68 @@
-use juniper::{EmptySubscription, FieldResult, IntoFieldError, RootNode};
-use slog::info;
+use futures::stream::{Stream, StreamExt};
+use juniper::{FieldError, FieldResult, IntoFieldError, RootNode};
+use slog::{o, Logger};
+use std::pin::Pin;
+use uuid::Uuid;
 
-use crate::api::model;
+use crate::api::{manifest, model, operations};
+use crate::auth::{Identity, Role};
+use crate::error;
+use crate::events::Event;
 use crate::state::State;
+use crate::twerg::client::Client;
 
 #[derive(Debug, Clone)]
 pub struct Context {
     pub state: State,
+    pub identity: Option<Identity>,
+    /// Correlates the logs of the request, and is returned in the response extensions.
+    pub request_id: String,
+    /// The service's logger, with the request id.
+    pub logger: Logger,
 }
 
 impl juniper::Context for Context {}
 
+impl Context {
+    pub fn new(state: State, identity: Option<Identity>, request_id: String) -> Self {
+        let logger = state.logger.new(o!("request_id" => request_id.clone()));
+        Context {
+            state,
+            identity,
+            request_id,
+            logger,
+        }
+    }
+
+    /// The twerg client, forwarding the request id to twergs.
+    pub fn twerg(&self) -> Client {
+        self.state.twerg.with_request_id(&self.request_id)
+    }
+
+    /// Returns the identity of the caller, provided it has been granted at least `role`.
+    pub fn authorize(&self, role: Role) -> Result<&Identity, error::Error> {
+        let identity = self
+            .identity
+            .as_ref()
+            .ok_or(error::Error::Unauthenticated {
+                msg: String::from("Authentication required"),
+            })?;
+        if identity.role < role {
+            return Err(error::Error::Unauthorized {
+                msg: format!("{} requires the {:?} role", identity.subject, role),
+            });
+        }
+        Ok(identity)
+    }
+
+    /// The subject of the caller, for the audit log.
+    pub fn actor(&self) -> &str {
+        self.identity
+            .as_ref()
+            .map_or("anonymous", |identity| identity.subject.as_str())
+    }
+}
+
 pub struct Query;
 
 #[juniper::graphql_object(
@@ -22,11 +74,101 @@ impl Query {
         &self,
         context: &Context,
     ) -> FieldResult<model::MultiEnvironmentsResponseBody> {
-        info!(context.state.logger, "Request for environments");
-        model::list_environments(context)
+        operations::environments(context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the last lines logged by the services of an environment, or by one of them
+    async fn environment_logs(
+        &self,
+        id: Uuid,
+        service: Option<String>,
+        tail: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<model::EnvironmentLogsResponseBody> {
+        operations::environment_logs(id, service, tail, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the changes applying a YAML manifest would make, and, if prune is set,
+    /// the deletions pruning it would make
+    async fn diff_manifest(
+        &self,
+        manifest: String,
+        prune: Option<bool>,
+        context: &Context,
+    ) -> FieldResult<manifest::ManifestChangesResponseBody> {
+        operations::diff_manifest(manifest, prune.unwrap_or(false), context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the index types, with their data sources, and the regions indexes can cover
+    async fn index_types(&self, context: &Context) -> FieldResult<model::IndexTypesResponseBody> {
+        operations::index_types(context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the source data of indexes cached on the host, most recently used first
+    async fn cached_artifacts(
+        &self,
+        context: &Context,
+    ) -> FieldResult<model::MultiCachedArtifactsResponseBody> {
+        operations::cached_artifacts(context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the refreshes of an index, most recent first
+    async fn index_refreshes(
+        &self,
+        environment: Uuid,
+        index: Uuid,
+        context: &Context,
+    ) -> FieldResult<model::MultiIndexRefreshesResponseBody> {
+        operations::index_refreshes(environment, index, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the versions an index can be rolled back to, most recent first
+    async fn index_versions(
+        &self,
+        environment: Uuid,
+        index: Uuid,
+        context: &Context,
+    ) -> FieldResult<model::MultiIndexVersionsResponseBody> {
+        operations::index_versions(environment, index, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the list of snapshots
+    async fn snapshots(&self, context: &Context) -> FieldResult<model::MultiSnapshotsResponseBody> {
+        operations::snapshots(context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns the list of projects, with their quotas and current usage
+    async fn projects(&self, context: &Context) -> FieldResult<model::MultiProjectsResponseBody> {
+        operations::projects(context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Returns entries of the audit log, most recent first
+    async fn audit_log(
+        &self,
+        filter: Option<model::AuditFilterBody>,
+        context: &Context,
+    ) -> FieldResult<model::MultiAuditEntriesResponseBody> {
+        operations::audit_log(filter.unwrap_or_default(), context)
             .await
             .map_err(IntoFieldError::into_field_error)
-            .into()
     }
 }
 
@@ -41,11 +183,7 @@ impl Mutation {
         env: model::EnvironmentRequestBody,
         context: &Context,
     ) -> FieldResult<model::SingleEnvironmentResponseBody> {
-        info!(
-            context.state.logger,
-            "Request for environment '{}' creation", env.name
-        );
-        model::create_environment(env, context)
+        operations::create_environment(env, context)
             .await
             .map_err(IntoFieldError::into_field_error)
     }
@@ -55,11 +193,82 @@ impl Mutation {
         id: model::EnvironmentIdBody,
         context: &Context,
     ) -> FieldResult<model::SingleEnvironmentResponseBody> {
-        info!(
-            context.state.logger,
-            "Request for environment '{}' deletion", id.id
-        );
-        model::delete_environment(id, context)
+        operations::delete_environment(id, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn extend_environment(
+        &self,
+        id: model::EnvironmentIdBody,
+        ttl: i32,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::extend_environment(id, ttl, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn stop_environment(
+        &self,
+        id: model::EnvironmentIdBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::stop_environment(id, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn start_environment(
+        &self,
+        id: model::EnvironmentIdBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::start_environment(id, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn restart_service(
+        &self,
+        environment: model::EnvironmentIdBody,
+        service: String,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::restart_service(environment, service, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn snapshot_environment(
+        &self,
+        snapshot: model::SnapshotRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleSnapshotResponseBody> {
+        operations::snapshot_environment(snapshot, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn restore_environment(
+        &self,
+        snapshot: Uuid,
+        env: model::EnvironmentRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::restore_environment(snapshot, env, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn clone_environment(
+        &self,
+        source: Uuid,
+        env: model::EnvironmentRequestBody,
+        overrides: Option<model::CloneOverridesBody>,
+        context: &Context,
+    ) -> FieldResult<model::SingleEnvironmentResponseBody> {
+        operations::clone_environment(source, env, overrides, context)
             .await
             .map_err(IntoFieldError::into_field_error)
     }
@@ -69,15 +278,127 @@ impl Mutation {
         index: model::IndexRequestBody,
         context: &Context,
     ) -> FieldResult<model::SingleIndexResponseBody> {
-        info!(context.state.logger, "Request for index creation");
-        model::create_index(index, context)
+        operations::create_index(index, context)
             .await
             .map_err(IntoFieldError::into_field_error)
     }
+
+    /// Submits an index whose build failed to its twerg again
+    async fn retry_index(
+        &self,
+        index: model::IndexIdBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleIndexResponseBody> {
+        operations::retry_index(index, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Sets the cron schedule on which an index, or all the indexes of an environment,
+    /// are refreshed, or stops their refreshes if no schedule is given
+    async fn schedule_index_refresh(
+        &self,
+        schedule: model::ScheduleRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::MultiIndexesResponseBody> {
+        operations::schedule_index_refresh(schedule, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Starts a refresh of an index now, whatever its schedule
+    async fn refresh_index(
+        &self,
+        index: model::IndexIdBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleIndexRefreshResponseBody> {
+        operations::refresh_index(index, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Serves a previous version of an index again, and discards the current one
+    async fn rollback_index(
+        &self,
+        rollback: model::RollbackRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleIndexResponseBody> {
+        operations::rollback_index(rollback, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn create_project(
+        &self,
+        project: model::ProjectRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleProjectResponseBody> {
+        operations::create_project(project, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    async fn update_project_quota(
+        &self,
+        id: Uuid,
+        quota: model::QuotaRequestBody,
+        context: &Context,
+    ) -> FieldResult<model::SingleProjectResponseBody> {
+        operations::update_project_quota(id, quota, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Create the environments and indexes of a YAML manifest missing from the catalog,
+    /// and recreate the environments whose configuration changed
+    async fn apply_manifest(
+        &self,
+        manifest: String,
+        context: &Context,
+    ) -> FieldResult<manifest::ManifestChangesResponseBody> {
+        operations::apply_manifest(manifest, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+
+    /// Delete the environments of the project of a YAML manifest, and the indexes of
+    /// its environments, which it does not describe
+    async fn prune_manifest(
+        &self,
+        manifest: String,
+        context: &Context,
+    ) -> FieldResult<manifest::ManifestChangesResponseBody> {
+        operations::prune_manifest(manifest, context)
+            .await
+            .map_err(IntoFieldError::into_field_error)
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, FieldError>> + Send>>;
+
+pub struct Subscription;
+
+#[juniper::graphql_subscription(
+    Context = Context
+)]
+impl Subscription {
+    /// Events about environments, as they happen
+    async fn events(context: &Context) -> FieldResult<EventStream> {
+        context
+            .authorize(Role::Viewer)
+            .map_err(IntoFieldError::into_field_error)?;
+        // Events missed by a lagging subscriber are skipped.
+        let stream = context
+            .state
+            .events
+            .subscribe()
+            .filter_map(|event| async move { event.ok().map(Ok) });
+        Ok(Box::pin(stream))
+    }
 }
 
-type Schema = RootNode<'static, Query, Mutation, EmptySubscription<Context>>;
+type Schema = RootNode<'static, Query, Mutation, Subscription>;
 
 pub fn schema() -> Schema {
-    Schema::new(Query, Mutation, EmptySubscription::new())
+    Schema::new(Query, Mutation, Subscription)
 }
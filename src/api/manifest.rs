@@ -0,0 +1,456 @@
+//! Manifests describe the environments of a project in YAML, so that they can be
+//! checked into git. Applying a manifest converges the catalog and the twergs to it:
+//! missing environments and indexes are created, and environments whose services
+//! configuration changed are recreated. Pruning deletes the environments of the project,
+//! and the indexes of its environments, that the manifest does not describe. Both are
+//! idempotent, so an interrupted apply is completed by running it again.
+
+use juniper::{GraphQLEnum, GraphQLObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slog::info;
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+use crate::api::gql::Context;
+use crate::api::model::{self, CloneOverridesBody, ServiceOverrideBody};
+use crate::audit;
+use crate::db::model::ProvideData;
+use crate::docker;
+use crate::error;
+use crate::index_types;
+use crate::signature;
+
+/// The environments of a project.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Manifest {
+    pub project: Uuid,
+    #[serde(default)]
+    pub environments: Vec<EnvironmentManifest>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EnvironmentManifest {
+    pub name: String,
+    /// The twerg configuration template, as named in the settings. The default
+    /// configuration if none.
+    pub template: Option<String>,
+    /// Time to live, in seconds, given to the environment when it is created
+    pub ttl: Option<i32>,
+    /// Changes applied to the services of the template
+    #[serde(default)]
+    pub services: Vec<ServiceOverrideBody>,
+    #[serde(default)]
+    pub indexes: Vec<IndexManifest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct IndexManifest {
+    pub index_type: String,
+    pub data_source: String,
+    pub regions: Vec<String>,
+}
+
+impl IndexManifest {
+    fn signature(&self) -> String {
+        signature::index_signature(&self.index_type, &self.data_source, &self.regions)
+    }
+
+    fn validate(&self, context: &Context) -> Result<(), error::Error> {
+        index_types::validate(
+            &self.index_type,
+            &self.data_source,
+            &self.regions,
+            &context.state.settings.indexes,
+        )
+    }
+}
+
+impl Manifest {
+    pub fn parse(manifest: &str) -> Result<Self, error::Error> {
+        let manifest: Manifest = serde_yaml::from_str(manifest).context(error::YAMLError {
+            msg: String::from("Could not parse manifest"),
+        })?;
+
+        let mut names = HashSet::new();
+        for env in manifest.environments.iter() {
+            if !names.insert(env.name.as_str()) {
+                return Err(error::Error::MiscError {
+                    msg: format!("Environment {} is described twice", env.name),
+                });
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, GraphQLEnum, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeAction {
+    Create,
+    /// The environment is deleted, and created again with its new configuration
+    Recreate,
+    Delete,
+}
+
+/// A change converging the catalog to a manifest.
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChange {
+    pub action: ChangeAction,
+    /// The environment changed, or whose index is changed
+    pub environment: String,
+    /// The index changed, if the change is not to the environment itself
+    pub index: Option<IndexManifest>,
+}
+
+/// The response body for the changes planned, or made, to converge to a manifest
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChangesResponseBody {
+    pub changes: Vec<ManifestChange>,
+    pub changes_count: i32,
+}
+
+impl From<Vec<ManifestChange>> for ManifestChangesResponseBody {
+    fn from(changes: Vec<ManifestChange>) -> Self {
+        let changes_count = i32::try_from(changes.len()).unwrap();
+        Self {
+            changes,
+            changes_count,
+        }
+    }
+}
+
+impl audit::Affects for ManifestChangesResponseBody {}
+
+/// A step of the convergence to a manifest, with what is needed to carry it out.
+enum Step {
+    CreateEnvironment {
+        request: model::EnvironmentRequestBody,
+        config: Vec<docker::ServiceConfig>,
+    },
+    RecreateEnvironment {
+        id: Uuid,
+        request: model::EnvironmentRequestBody,
+        config: Vec<docker::ServiceConfig>,
+    },
+    DeleteEnvironment {
+        id: Uuid,
+        name: String,
+    },
+    /// The id of the environment is only known when it is not created by a previous
+    /// step.
+    CreateIndex {
+        environment: String,
+        environment_id: Option<Uuid>,
+        index: IndexManifest,
+    },
+    DeleteIndex {
+        environment: String,
+        environment_id: Uuid,
+        id: Uuid,
+        index: IndexManifest,
+    },
+}
+
+impl Step {
+    fn change(&self) -> ManifestChange {
+        let (action, environment, index) = match self {
+            Step::CreateEnvironment { request, .. } => (ChangeAction::Create, &request.name, None),
+            Step::RecreateEnvironment { request, .. } => {
+                (ChangeAction::Recreate, &request.name, None)
+            }
+            Step::DeleteEnvironment { name, .. } => (ChangeAction::Delete, name, None),
+            Step::CreateIndex {
+                environment, index, ..
+            } => (ChangeAction::Create, environment, Some(index)),
+            Step::DeleteIndex {
+                environment, index, ..
+            } => (ChangeAction::Delete, environment, Some(index)),
+        };
+        ManifestChange {
+            action,
+            environment: environment.clone(),
+            index: index.cloned(),
+        }
+    }
+}
+
+/// The changes applying the manifest would make, and, when pruning, the deletions
+/// pruning would make.
+pub async fn diff(
+    manifest: Manifest,
+    prune: bool,
+    context: &Context,
+) -> Result<ManifestChangesResponseBody, error::Error> {
+    let steps = plan(manifest, true, prune, context).await?;
+    Ok(ManifestChangesResponseBody::from(
+        steps.iter().map(Step::change).collect::<Vec<_>>(),
+    ))
+}
+
+/// Create the environments and indexes missing from the catalog, and recreate the
+/// environments whose configuration changed. Return the changes made.
+pub async fn apply(
+    manifest: Manifest,
+    context: &Context,
+) -> Result<ManifestChangesResponseBody, error::Error> {
+    let steps = plan(manifest, true, false, context).await?;
+    run(steps, context).await
+}
+
+/// Delete the environments and indexes absent from the manifest. Return the changes
+/// made.
+pub async fn prune(
+    manifest: Manifest,
+    context: &Context,
+) -> Result<ManifestChangesResponseBody, error::Error> {
+    let steps = plan(manifest, false, true, context).await?;
+    run(steps, context).await
+}
+
+/// Compare the manifest with the catalog, and return the steps converging to it, or
+/// pruning what it does not describe, or both.
+async fn plan(
+    manifest: Manifest,
+    converge: bool,
+    prune: bool,
+    context: &Context,
+) -> Result<Vec<Step>, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let mut existing = HashMap::new();
+    for mut env in tx
+        .get_all_environments()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get all them environments",
+        })?
+    {
+        env.indexes = tx
+            .get_environment_indexes(&env.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment indexes",
+            })?;
+        existing.insert(env.name.clone(), env);
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    let Manifest {
+        project,
+        environments,
+    } = manifest;
+    let mut steps = Vec::new();
+    let mut described = HashSet::new();
+
+    for env in environments {
+        described.insert(env.name.clone());
+        let current = existing.get(&env.name);
+        if let Some(current) = current {
+            if current.project != project {
+                return Err(error::Error::MiscError {
+                    msg: format!(
+                        "Environment {} belongs to project {}",
+                        env.name, current.project
+                    ),
+                });
+            }
+        }
+
+        let mut config = docker::get_template_config(
+            &context.state.settings,
+            env.template.as_deref(),
+            &context.logger,
+        )
+        .await?;
+        CloneOverridesBody {
+            services: Some(env.services),
+        }
+        .apply(&mut config)?;
+        let desired = serde_json::to_value(&config).context(error::JSONError {
+            msg: String::from("Could not serialize services configuration"),
+        })?;
+
+        let request = model::EnvironmentRequestBody {
+            name: env.name.clone(),
+            project,
+            ttl: env.ttl,
+            expires_at: None,
+            reuse: None,
+            template: env.template,
+        };
+        for index in env.indexes.iter() {
+            index.validate(context)?;
+        }
+        let mut signatures = HashSet::new();
+        let mut indexes = env.indexes;
+        indexes.retain(|index| signatures.insert(index.signature()));
+
+        let environment_id = match current {
+            None if converge => {
+                steps.push(Step::CreateEnvironment { request, config });
+                None
+            }
+            Some(current) if converge && current.config != desired => {
+                steps.push(Step::RecreateEnvironment {
+                    id: current.id,
+                    request,
+                    config,
+                });
+                None
+            }
+            Some(current) => {
+                if prune {
+                    for index in current.indexes.iter() {
+                        if !signatures.contains(&index.signature) {
+                            steps.push(Step::DeleteIndex {
+                                environment: env.name.clone(),
+                                environment_id: current.id,
+                                id: index.id,
+                                index: IndexManifest {
+                                    index_type: index.index_type.clone(),
+                                    data_source: index.data_source.clone(),
+                                    regions: index.regions.clone(),
+                                },
+                            });
+                        }
+                    }
+                }
+                // Only the indexes missing from the environment are created.
+                indexes.retain(|index| {
+                    let signature = index.signature();
+                    !current
+                        .indexes
+                        .iter()
+                        .any(|existing| existing.signature == signature)
+                });
+                Some(current.id)
+            }
+            None => continue,
+        };
+
+        if converge {
+            for index in indexes {
+                steps.push(Step::CreateIndex {
+                    environment: env.name.clone(),
+                    environment_id,
+                    index,
+                });
+            }
+        }
+    }
+
+    if prune {
+        let mut extra = existing
+            .values()
+            .filter(|env| env.project == project && !described.contains(&env.name))
+            .collect::<Vec<_>>();
+        extra.sort_by_key(|env| env.created_at);
+        for env in extra {
+            steps.push(Step::DeleteEnvironment {
+                id: env.id,
+                name: env.name.clone(),
+            });
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Carry out the steps, in order, and return the changes made.
+async fn run(
+    steps: Vec<Step>,
+    context: &Context,
+) -> Result<ManifestChangesResponseBody, error::Error> {
+    // The ids of the environments created by the steps, which their indexes need.
+    let mut ids = HashMap::new();
+    let mut changes = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        let change = step.change();
+        info!(
+            context.logger,
+            "Manifest change: {:?} of {}", change.action, change.environment
+        );
+        match step {
+            Step::CreateEnvironment { request, config } => {
+                let env = provision(request, config, context).await?;
+                ids.insert(env.name, env.id);
+            }
+            Step::RecreateEnvironment {
+                id,
+                request,
+                config,
+            } => {
+                model::teardown_environment(id, context).await?;
+                let env = provision(request, config, context).await?;
+                ids.insert(env.name, env.id);
+            }
+            Step::DeleteEnvironment { id, .. } => {
+                model::teardown_environment(id, context).await?;
+            }
+            Step::CreateIndex {
+                environment,
+                environment_id,
+                index,
+            } => {
+                let environment = environment_id
+                    .or_else(|| ids.get(&environment).copied())
+                    .ok_or(error::Error::Environment { env: environment })?;
+                let IndexManifest {
+                    index_type,
+                    data_source,
+                    regions,
+                } = index;
+                let request = model::IndexRequestBody {
+                    environment,
+                    index_type,
+                    data_source,
+                    regions,
+                    reuse: None,
+                };
+                model::create_index(request, context).await?;
+            }
+            Step::DeleteIndex {
+                environment_id, id, ..
+            } => {
+                model::delete_index(environment_id, id, context).await?;
+            }
+        }
+        changes.push(change);
+    }
+
+    Ok(ManifestChangesResponseBody::from(changes))
+}
+
+async fn provision(
+    request: model::EnvironmentRequestBody,
+    config: Vec<docker::ServiceConfig>,
+    context: &Context,
+) -> Result<model::Environment, error::Error> {
+    let name = request.name.clone();
+    model::provision_environment(request, config, None, context)
+        .await?
+        .env
+        .ok_or_else(|| error::Error::MiscError {
+            msg: format!("Could not create environment {}", name),
+        })
+}
@@ -1,3 +1,6 @@
 pub mod gql;
+pub mod manifest;
 pub mod model;
+pub mod operations;
+pub mod rest;
 pub mod utils;
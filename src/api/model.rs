@@ -1,23 +1,30 @@
-use chrono::{DateTime, Utc};
-use futures::stream::{self, TryStreamExt};
-use juniper::futures::TryFutureExt;
+use chrono::{DateTime, Duration, Utc};
 use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use slog::{debug, info};
+use serde_json::json;
+use slog::{debug, info, warn};
 use snafu::ResultExt;
-use sqlx::Connection;
 use std::convert::TryFrom;
+use std::time::Instant;
 use uuid::Uuid;
 
 use crate::api::gql::Context;
+use crate::audit;
+use crate::auth::Role;
+use crate::cache;
 use crate::db::model as db;
 use crate::db::model::ProvideData;
-use crate::db::Db;
+use crate::db::Transaction;
 use crate::docker;
 use crate::error;
-use crate::twerg::client;
+use crate::index_types;
+use crate::refresh;
+use crate::schedule::Schedule;
+use crate::shutdown;
+use crate::signature;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLEnum, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum IndexStatus {
     NotAvailable,
@@ -75,13 +82,60 @@ impl From<db::IndexStatus> for IndexStatus {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EnvironmentState {
+    Running,
+    Stopped,
+    PartiallyRunning,
+}
+
+impl From<EnvironmentState> for db::EnvironmentState {
+    fn from(state: EnvironmentState) -> Self {
+        match state {
+            EnvironmentState::Running => db::EnvironmentState::Running,
+            EnvironmentState::Stopped => db::EnvironmentState::Stopped,
+            EnvironmentState::PartiallyRunning => db::EnvironmentState::PartiallyRunning,
+        }
+    }
+}
+
+impl From<db::EnvironmentState> for EnvironmentState {
+    fn from(state: db::EnvironmentState) -> Self {
+        match state {
+            db::EnvironmentState::Running => EnvironmentState::Running,
+            db::EnvironmentState::Stopped => EnvironmentState::Stopped,
+            db::EnvironmentState::PartiallyRunning => EnvironmentState::PartiallyRunning,
+        }
+    }
+}
+
+impl EnvironmentState {
+    /// The state of a twerg given how many of its services are running.
+    pub fn from_running_count(running: usize, total: usize) -> Self {
+        if running == 0 {
+            EnvironmentState::Stopped
+        } else if running < total {
+            EnvironmentState::PartiallyRunning
+        } else {
+            EnvironmentState::Running
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Environment {
     pub id: Uuid,
     pub name: String,
     pub signature: String,
     pub port: i32, // We should use u16, but it does not implement GraphQLType
+    pub owner: String,
+    pub project: Uuid,
+    pub memory: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub state: EnvironmentState,
+    pub services: Vec<Service>,
     pub indexes: Vec<Index>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -94,6 +148,12 @@ impl From<db::EnvironmentEntity> for Environment {
             name,
             signature,
             port,
+            owner,
+            project,
+            memory,
+            expires_at,
+            state,
+            services,
             indexes,
             created_at,
             updated_at,
@@ -107,6 +167,12 @@ impl From<db::EnvironmentEntity> for Environment {
             name,
             signature,
             port,
+            owner,
+            project,
+            memory,
+            expires_at,
+            state: EnvironmentState::from(state),
+            services: services.into_iter().map(Service::from).collect(),
             indexes,
             created_at,
             updated_at,
@@ -114,11 +180,137 @@ impl From<db::EnvironmentEntity> for Environment {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Ulimit {
+    pub name: String,
+    pub soft: i32,
+    pub hard: i32,
+}
+
+/// The effective resource limits of a service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    /// Memory limit, in MiB
+    pub memory: Option<i32>,
+    /// Memory soft limit, in MiB
+    pub memory_reservation: Option<i32>,
+    pub cpu_shares: Option<i32>,
+    /// Microseconds of CPU time allowed per cpu period
+    pub cpu_quota: Option<i32>,
+    /// CPU period, in microseconds
+    pub cpu_period: Option<i32>,
+    pub pids_limit: Option<i32>,
+    pub ulimits: Vec<Ulimit>,
+    pub restart_policy: Option<String>,
+    pub restart_max_retries: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Service {
+    pub name: String,
+    pub image: String,
+    /// The digest of the image the service runs, when it was pulled from a registry
+    pub digest: Option<String>,
+    pub limits: Limits,
+}
+
+impl From<db::ServiceEntity> for Service {
+    fn from(entity: db::ServiceEntity) -> Self {
+        let db::ServiceEntity {
+            name,
+            image,
+            digest,
+            limits,
+        } = entity;
+
+        let db::LimitsEntity {
+            memory,
+            memory_reservation,
+            cpu_shares,
+            cpu_quota,
+            cpu_period,
+            pids_limit,
+            ulimits,
+            restart_policy,
+            restart_max_retries,
+        } = limits;
+
+        Service {
+            name,
+            image,
+            digest,
+            limits: Limits {
+                memory,
+                memory_reservation,
+                cpu_shares,
+                cpu_quota,
+                cpu_period,
+                pids_limit,
+                ulimits: ulimits
+                    .into_iter()
+                    .map(|ulimit| Ulimit {
+                        name: ulimit.name,
+                        soft: ulimit.soft,
+                        hard: ulimit.hard,
+                    })
+                    .collect(),
+                restart_policy,
+                restart_max_retries,
+            },
+        }
+    }
+}
+
+impl db::ServiceEntity {
+    /// The service as described by its configuration, with its image in the given registry.
+    fn from_config(config: &docker::ServiceConfig, registry: &str) -> Self {
+        let resources = config.resources.clone().unwrap_or_default();
+        let (restart_policy, restart_max_retries) = match resources.restart {
+            Some(restart) => (
+                serde_json::to_value(restart.policy)
+                    .ok()
+                    .and_then(|v| v.as_str().map(String::from)),
+                restart.max_retries,
+            ),
+            None => (None, None),
+        };
+
+        db::ServiceEntity {
+            name: config.service.clone(),
+            image: config.image(registry),
+            digest: None,
+            limits: db::LimitsEntity {
+                memory: resources.memory,
+                memory_reservation: resources.memory_reservation,
+                cpu_shares: resources.cpu_shares,
+                cpu_quota: resources.cpu_quota,
+                cpu_period: resources.cpu_period,
+                pids_limit: resources.pids_limit,
+                ulimits: resources
+                    .ulimits
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|ulimit| db::UlimitEntity {
+                        name: ulimit.name,
+                        soft: ulimit.soft,
+                        hard: ulimit.hard,
+                    })
+                    .collect(),
+                restart_policy,
+                restart_max_retries,
+            },
+        }
+    }
+}
+
 pub fn default_status() -> IndexStatus {
     IndexStatus::NotAvailable
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Index {
     pub id: Uuid,
@@ -128,6 +320,11 @@ pub struct Index {
     pub signature: String,
     #[serde(default = "default_status")]
     pub status: IndexStatus,
+    /// The id of the index in the twerg
+    pub twerg_id: Option<i32>,
+    /// The cron schedule on which the index is refreshed, in UTC
+    pub schedule: Option<String>,
+    pub next_refresh_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -141,9 +338,11 @@ impl From<db::IndexEntity> for Index {
             regions,
             signature,
             status,
+            twerg_id,
+            schedule,
+            next_refresh_at,
             created_at,
             updated_at,
-            ..
         } = entity;
 
         Index {
@@ -153,13 +352,16 @@ impl From<db::IndexEntity> for Index {
             regions,
             signature,
             status: IndexStatus::from(status),
+            twerg_id,
+            schedule,
+            next_refresh_at,
             created_at,
             updated_at,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, GraphQLObject)]
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SingleEnvironmentResponseBody {
     pub env: Option<Environment>,
@@ -172,7 +374,7 @@ impl From<Environment> for SingleEnvironmentResponseBody {
 }
 
 /// The response body for multiple environments
-#[derive(Debug, Deserialize, Serialize, GraphQLObject)]
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct MultiEnvironmentsResponseBody {
     pub envs: Vec<Environment>,
@@ -186,28 +388,100 @@ impl From<Vec<Environment>> for MultiEnvironmentsResponseBody {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, GraphQLInputObject)]
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
 pub struct EnvironmentRequestBody {
     pub name: String,
+    pub project: Uuid,
+    /// Time to live, in seconds. Mutually exclusive with expires_at.
+    pub ttl: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Return the running environment of the same name, if there is one, instead of
+    /// creating a new one. It must have the same owner, project and signature, and
+    /// keeps its expiry.
+    pub reuse: Option<bool>,
+    /// The twerg configuration template the environment is created from, as named in
+    /// the settings. The default configuration if none.
+    pub template: Option<String>,
+}
+
+impl EnvironmentRequestBody {
+    /// Returns when the requested environment should expire, if ever.
+    pub fn expiry(&self) -> Result<Option<DateTime<Utc>>, error::Error> {
+        match (self.ttl, self.expires_at) {
+            (Some(_), Some(_)) => Err(error::Error::MiscError {
+                msg: String::from("Cannot specify both ttl and expiresAt"),
+            }),
+            (Some(ttl), None) => expiry_from_ttl(Utc::now(), ttl).map(Some),
+            (None, Some(expires_at)) if expires_at <= Utc::now() => Err(error::Error::MiscError {
+                msg: format!("Expiry date {} is in the past", expires_at),
+            }),
+            (None, expires_at) => Ok(expires_at),
+        }
+    }
+}
+
+fn expiry_from_ttl(from: DateTime<Utc>, ttl: i32) -> Result<DateTime<Utc>, error::Error> {
+    if ttl <= 0 {
+        return Err(error::Error::MiscError {
+            msg: format!(
+                "Invalid ttl {}, expecting a positive number of seconds",
+                ttl
+            ),
+        });
+    }
+    Ok(from + Duration::seconds(i64::from(ttl)))
 }
 
 impl From<EnvironmentRequestBody> for db::InputEnvironmentEntity {
     fn from(request: EnvironmentRequestBody) -> Self {
-        let EnvironmentRequestBody { name, .. } = request;
+        let EnvironmentRequestBody {
+            name,
+            project,
+            expires_at,
+            ..
+        } = request;
 
-        // At this stage, when the user request an environment, the
-        // port is not known. So we set it to 0, and it will be assigned
-        // a value before it beeing used.
-        db::InputEnvironmentEntity { name, port: 0i32 }
+        // At this stage, when the user request an environment, the port, owner,
+        // services, memory and signature are not known. So we set them to defaults,
+        // and they will be assigned a value before beeing used.
+        db::InputEnvironmentEntity {
+            name,
+            port: 0i32,
+            owner: String::new(),
+            project,
+            memory: 0i32,
+            expires_at,
+            services: Vec::new(),
+            config: serde_json::Value::Null,
+            signature: String::new(),
+        }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, GraphQLInputObject)]
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
 pub struct EnvironmentIdBody {
     pub id: Uuid,
 }
 
-#[derive(Debug, Deserialize, Serialize, GraphQLObject)]
+/// The lines last logged by a service of an environment
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLogs {
+    pub service: String,
+    pub lines: Vec<String>,
+}
+
+/// The response body for the logs of an environment, by service
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvironmentLogsResponseBody {
+    pub services: Vec<ServiceLogs>,
+}
+
+/// The number of lines returned per service when none is requested.
+pub const DEFAULT_LOGS_TAIL: i32 = 100;
+
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SingleIndexResponseBody {
     pub index: Option<Index>,
@@ -219,16 +493,232 @@ impl From<Index> for SingleIndexResponseBody {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, GraphQLInputObject)]
+/// The response body for the indexes of an environment
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiIndexesResponseBody {
+    pub indexes: Vec<Index>,
+    pub indexes_count: i32,
+}
+
+impl From<Vec<Index>> for MultiIndexesResponseBody {
+    fn from(indexes: Vec<Index>) -> Self {
+        let indexes_count = i32::try_from(indexes.len()).unwrap();
+        Self {
+            indexes,
+            indexes_count,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
 pub struct IndexRequestBody {
     pub environment: Uuid,
     pub index_type: String,
     pub data_source: String,
     pub regions: Vec<String>,
+    /// Return an available index of the environment with the same signature, if
+    /// there is one, instead of creating a new one.
+    pub reuse: Option<bool>,
+}
+
+impl IndexRequestBody {
+    pub fn signature(&self) -> String {
+        signature::index_signature(&self.index_type, &self.data_source, &self.regions)
+    }
+
+    /// Check the index type, data source and regions against those supported.
+    pub fn validate(&self, context: &Context) -> Result<(), error::Error> {
+        index_types::validate(
+            &self.index_type,
+            &self.data_source,
+            &self.regions,
+            &context.state.settings.indexes,
+        )
+    }
+}
+
+/// A schedule for the refreshes of the indexes of an environment
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct ScheduleRequestBody {
+    pub environment: Uuid,
+    /// The index to schedule, all the indexes of the environment if none is given.
+    pub index: Option<Uuid>,
+    /// A cron expression in UTC, such as '0 3 * * 0'. The refreshes stop if none is
+    /// given.
+    pub schedule: Option<String>,
+}
+
+/// A refresh of an index, which built it again in its twerg
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexRefresh {
+    pub id: Uuid,
+    pub index: Uuid,
+    /// The id of the index built in the twerg
+    pub twerg_id: Option<i32>,
+    /// The last status of the index built
+    pub status: Option<IndexStatus>,
+    /// None while the refresh runs
+    pub outcome: Option<AuditOutcome>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// In milliseconds, once the refresh is finished
+    pub duration: Option<i32>,
+}
+
+impl From<db::IndexRefreshEntity> for IndexRefresh {
+    fn from(entity: db::IndexRefreshEntity) -> Self {
+        let db::IndexRefreshEntity {
+            id,
+            index,
+            twerg_id,
+            status,
+            outcome,
+            error,
+            started_at,
+            finished_at,
+        } = entity;
+
+        let duration = finished_at.map(|finished_at| {
+            i32::try_from((finished_at - started_at).num_milliseconds()).unwrap_or(i32::MAX)
+        });
+
+        IndexRefresh {
+            id,
+            index,
+            twerg_id,
+            status: status.map(IndexStatus::from),
+            outcome: outcome.map(AuditOutcome::from),
+            error,
+            started_at,
+            finished_at,
+            duration,
+        }
+    }
+}
+
+/// The response body for the refreshes of an index
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiIndexRefreshesResponseBody {
+    pub refreshes: Vec<IndexRefresh>,
+    pub refreshes_count: i32,
+}
+
+impl From<Vec<IndexRefresh>> for MultiIndexRefreshesResponseBody {
+    fn from(refreshes: Vec<IndexRefresh>) -> Self {
+        let refreshes_count = i32::try_from(refreshes.len()).unwrap();
+        Self {
+            refreshes,
+            refreshes_count,
+        }
+    }
+}
+
+/// The response body for a refresh of an index
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SingleIndexRefreshResponseBody {
+    pub refresh: Option<IndexRefresh>,
+}
+
+impl From<IndexRefresh> for SingleIndexRefreshResponseBody {
+    fn from(refresh: IndexRefresh) -> Self {
+        Self {
+            refresh: Some(refresh),
+        }
+    }
+}
+
+/// A version an index can be rolled back to
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexVersion {
+    /// The id of the version in the twerg
+    pub twerg_id: i32,
+    /// When the version stopped serving requests
+    pub retired_at: DateTime<Utc>,
+}
+
+impl From<db::IndexVersionEntity> for IndexVersion {
+    fn from(entity: db::IndexVersionEntity) -> Self {
+        IndexVersion {
+            twerg_id: entity.twerg_id,
+            retired_at: entity.retired_at,
+        }
+    }
+}
+
+/// The response body for the previous versions of an index
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiIndexVersionsResponseBody {
+    pub versions: Vec<IndexVersion>,
+    pub versions_count: i32,
+}
+
+impl From<Vec<IndexVersion>> for MultiIndexVersionsResponseBody {
+    fn from(versions: Vec<IndexVersion>) -> Self {
+        let versions_count = i32::try_from(versions.len()).unwrap();
+        Self {
+            versions,
+            versions_count,
+        }
+    }
+}
+
+/// An index of an environment
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct IndexIdBody {
+    pub environment: Uuid,
+    pub index: Uuid,
+}
+
+/// The version an index is rolled back to
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct RollbackRequestBody {
+    pub environment: Uuid,
+    pub index: Uuid,
+    /// The id in the twerg of a previous version, the most recent one if none is given.
+    pub version: Option<i32>,
+}
+
+/// An index type twergs can build, with the data sources it can be built from
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexTypeDescription {
+    pub name: String,
+    pub description: String,
+    pub data_sources: Vec<String>,
+}
+
+impl From<&index_types::IndexType> for IndexTypeDescription {
+    fn from(index_type: &index_types::IndexType) -> Self {
+        IndexTypeDescription {
+            name: String::from(index_type.name),
+            description: String::from(index_type.description),
+            data_sources: index_type
+                .data_sources
+                .iter()
+                .map(|source| String::from(*source))
+                .collect(),
+        }
+    }
+}
+
+/// The response body for the index types, and the regions indexes can be requested for
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexTypesResponseBody {
+    pub index_types: Vec<IndexTypeDescription>,
+    pub regions: Vec<String>,
 }
 
 impl From<IndexRequestBody> for db::InputIndexEntity {
     fn from(request: IndexRequestBody) -> Self {
+        let signature = request.signature();
         let IndexRequestBody {
             environment,
             index_type,
@@ -242,192 +732,1564 @@ impl From<IndexRequestBody> for db::InputIndexEntity {
             index_type,
             data_source,
             regions,
+            signature,
+            twerg_id: None,
         }
     }
 }
 
-/// Retrieve all environments
-pub async fn list_environments(
-    context: &Context,
-) -> Result<MultiEnvironmentsResponseBody, error::Error> {
-    async move {
-        let pool = &context.state.pool;
-
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
-            .await
-            .context(error::DBError {
-                msg: "could not initiate transaction",
-            })?;
-
-        let entities = tx
-            .get_all_environments()
-            .await
-            .context(error::DBProvideError {
-                msg: "Could not get all them environments",
-            })?;
-
-        // FIXME. Not sure this is the most efficient.... we open as many connections
-        // as there are environments, each of the connection retrieves the indexes for an
-        // environment. It is, at least, not very scalable.
-        let entities: Vec<db::EnvironmentEntity> =
-            stream::iter(entities.into_iter().map(|env| Ok(env)))
-                .and_then(|mut env| async move {
-                    let mut tx =
-                        pool.conn()
-                            .and_then(Connection::begin)
-                            .await
-                            .context(error::DBError {
-                                msg: "could not initiate transaction",
-                            })?;
-
-                    let indexes = tx.get_environment_indexes(&env.id).await.context(
-                        error::DBProvideError {
-                            msg: "Could not get all them environments",
-                        },
-                    )?;
-
-                    tx.commit().await.context(error::DBError {
-                        msg: "could not commit transaction",
-                    })?;
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub id: Uuid,
+    pub name: String,
+    /// The environment the snapshot was taken from, if it still exists
+    pub environment: Option<Uuid>,
+    pub environment_name: String,
+    pub volumes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
 
-                    env.indexes = indexes;
-                    Ok(env)
-                })
-                .try_collect()
-                .await?;
+impl From<db::SnapshotEntity> for Snapshot {
+    fn from(entity: db::SnapshotEntity) -> Self {
+        let db::SnapshotEntity {
+            id,
+            name,
+            environment,
+            environment_name,
+            volumes,
+            created_at,
+            ..
+        } = entity;
 
-        let environments = entities
-            .into_iter()
-            .map(Environment::from)
-            .collect::<Vec<_>>();
+        Snapshot {
+            id,
+            name,
+            environment,
+            environment_name,
+            volumes,
+            created_at,
+        }
+    }
+}
 
-        tx.commit().await.context(error::DBError {
-            msg: "could not commit transaction",
-        })?;
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SingleSnapshotResponseBody {
+    pub snapshot: Option<Snapshot>,
+}
 
-        Ok(MultiEnvironmentsResponseBody::from(environments))
+impl From<Snapshot> for SingleSnapshotResponseBody {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot: Some(snapshot),
+        }
     }
-    .await
 }
 
-/// Create a new environment
-pub async fn create_environment(
-    request: EnvironmentRequestBody,
-    context: &Context,
-) -> Result<SingleEnvironmentResponseBody, error::Error> {
-    async move {
-        let mut input = db::InputEnvironmentEntity::from(request);
-
-        let port =
-            docker::create_twerg(&input.name, &context.state.settings, &context.state.logger)
-                .await?;
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiSnapshotsResponseBody {
+    pub snapshots: Vec<Snapshot>,
+    pub snapshots_count: i32,
+}
 
-        input.port = port as i32;
-        debug!(context.state.logger, "Created Twerg at port {}", input.port);
+impl From<Vec<Snapshot>> for MultiSnapshotsResponseBody {
+    fn from(snapshots: Vec<Snapshot>) -> Self {
+        let snapshots_count = i32::try_from(snapshots.len()).unwrap();
+        Self {
+            snapshots,
+            snapshots_count,
+        }
+    }
+}
 
-        let pool = &context.state.pool;
+/// Source data of indexes, cached on the host
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedArtifact {
+    pub id: Uuid,
+    pub data_source: String,
+    pub region: String,
+    /// The upstream version, or the digest when the upstream server gives none
+    pub version: String,
+    /// The hex encoded SHA-256 of the content, naming the cached file
+    pub digest: String,
+    /// In bytes
+    pub size: f64,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    pub used_at: DateTime<Utc>,
+}
 
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
-            .await
-            .context(error::DBError {
-                msg: "could not initiate transaction",
-            })?;
+impl From<db::CachedArtifactEntity> for CachedArtifact {
+    fn from(entity: db::CachedArtifactEntity) -> Self {
+        let db::CachedArtifactEntity {
+            id,
+            data_source,
+            region,
+            version,
+            digest,
+            size,
+            url,
+            created_at,
+            used_at,
+        } = entity;
 
-        let resp = ProvideData::create_environment(&mut tx as &mut sqlx::PgConnection, &input)
-            .await
-            .context(error::DBProvideError {
-                msg: "Could not create environment",
-            })?;
+        CachedArtifact {
+            id,
+            data_source,
+            region,
+            version,
+            digest,
+            // Exact up to 8 PiB.
+            size: size as f64,
+            url,
+            created_at,
+            used_at,
+        }
+    }
+}
 
-        tx.commit().await.context(error::DBError {
-            msg: "could not commit create environment transaction.",
-        })?;
+/// The response body for the cached artifacts, most recently used first
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiCachedArtifactsResponseBody {
+    pub artifacts: Vec<CachedArtifact>,
+    pub artifacts_count: i32,
+}
 
-        let environment = Environment::from(resp);
-        Ok(SingleEnvironmentResponseBody::from(environment))
+impl From<Vec<CachedArtifact>> for MultiCachedArtifactsResponseBody {
+    fn from(artifacts: Vec<CachedArtifact>) -> Self {
+        let artifacts_count = i32::try_from(artifacts.len()).unwrap();
+        Self {
+            artifacts,
+            artifacts_count,
+        }
     }
-    .await
 }
 
-/// Retrieve an environment based on its id.
-pub async fn get_environment_by_id(
-    id: db::EntityId,
-    context: &Context,
-) -> Result<SingleEnvironmentResponseBody, error::Error> {
-    async move {
-        let pool = &context.state.pool;
+/// Changes applied to a service of a cloned environment.
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct ServiceOverrideBody {
+    pub service: String,
+    pub image: Option<String>,
+    pub tag: Option<String>,
+    /// Replaces the service's environment variables
+    pub envs: Option<Vec<String>>,
+}
 
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
-            .await
-            .context(error::DBError {
-                msg: "could not initiate transaction",
-            })?;
+#[derive(Debug, Default, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct CloneOverridesBody {
+    pub services: Option<Vec<ServiceOverrideBody>>,
+}
 
-        let environment =
-            ProvideData::get_environment_by_id(&mut tx as &mut sqlx::PgConnection, &id)
-                .await
-                .context(error::DBProvideError {
-                    msg: "Could not get environment",
+impl CloneOverridesBody {
+    /// Apply the overrides to the configuration of the source environment.
+    pub fn apply(self, config: &mut Vec<docker::ServiceConfig>) -> Result<(), error::Error> {
+        for over in self.services.unwrap_or_default() {
+            let service = config
+                .iter_mut()
+                .find(|c| c.service == over.service)
+                .ok_or_else(|| error::Error::MiscError {
+                    msg: format!("Cannot override unknown service {}", over.service),
                 })?;
+            if let Some(image) = over.image {
+                service.docker.image = image;
+            }
+            if let Some(tag) = over.tag {
+                service.docker.tag = tag;
+            }
+            if let Some(envs) = over.envs {
+                service.envs = Some(envs);
+            }
+        }
+        Ok(())
+    }
+}
 
-        let indexes = tx
-            .get_environment_indexes(&id)
-            .await
-            .context(error::DBProvideError {
-                msg: "Could not get all them environments",
-            })?;
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct SnapshotRequestBody {
+    pub environment: Uuid,
+    pub name: String,
+}
 
-        tx.commit().await.context(error::DBError {
-            msg: "could not commit get environment transaction.",
-        })?;
+/// Resource quotas of a project. A null quota is unlimited.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    pub max_environments: Option<i32>,
+    pub max_indexes: Option<i32>,
+    /// Total memory, in MiB
+    pub max_memory: Option<i32>,
+}
 
-        let mut environment = Environment::from(environment);
-        environment.indexes = indexes.into_iter().map(Index::from).collect::<Vec<_>>();
+/// Resources currently used by a project.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub environments: i32,
+    pub indexes: i32,
+    /// Total memory, in MiB
+    pub memory: i32,
+}
+
+impl From<db::UsageEntity> for Usage {
+    fn from(entity: db::UsageEntity) -> Self {
+        let db::UsageEntity {
+            environments,
+            indexes,
+            memory,
+        } = entity;
+
+        Usage {
+            environments,
+            indexes,
+            memory,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub id: Uuid,
+    pub name: String,
+    pub quota: Quota,
+    pub usage: Usage,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Project {
+    fn new(entity: db::ProjectEntity, usage: db::UsageEntity) -> Self {
+        let db::ProjectEntity {
+            id,
+            name,
+            max_environments,
+            max_indexes,
+            max_memory,
+            created_at,
+            updated_at,
+        } = entity;
+
+        Project {
+            id,
+            name,
+            quota: Quota {
+                max_environments,
+                max_indexes,
+                max_memory,
+            },
+            usage: Usage::from(usage),
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SingleProjectResponseBody {
+    pub project: Option<Project>,
+}
+
+impl From<Project> for SingleProjectResponseBody {
+    fn from(project: Project) -> Self {
+        Self {
+            project: Some(project),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiProjectsResponseBody {
+    pub projects: Vec<Project>,
+    pub projects_count: i32,
+}
+
+impl From<Vec<Project>> for MultiProjectsResponseBody {
+    fn from(projects: Vec<Project>) -> Self {
+        let projects_count = i32::try_from(projects.len()).unwrap();
+        Self {
+            projects,
+            projects_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct QuotaRequestBody {
+    pub max_environments: Option<i32>,
+    pub max_indexes: Option<i32>,
+    /// Total memory, in MiB
+    pub max_memory: Option<i32>,
+}
+
+impl From<QuotaRequestBody> for db::QuotaEntity {
+    fn from(request: QuotaRequestBody) -> Self {
+        let QuotaRequestBody {
+            max_environments,
+            max_indexes,
+            max_memory,
+        } = request;
+
+        db::QuotaEntity {
+            max_environments,
+            max_indexes,
+            max_memory,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct ProjectRequestBody {
+    pub name: String,
+    pub quota: QuotaRequestBody,
+}
+
+impl From<ProjectRequestBody> for db::InputProjectEntity {
+    fn from(request: ProjectRequestBody) -> Self {
+        let ProjectRequestBody { name, quota } = request;
+
+        db::InputProjectEntity {
+            name,
+            quota: db::QuotaEntity::from(quota),
+        }
+    }
+}
+
+impl audit::Affects for SingleEnvironmentResponseBody {
+    fn environment(&self) -> Option<Uuid> {
+        self.env.as_ref().map(|env| env.id)
+    }
+}
+
+impl audit::Affects for SingleIndexResponseBody {
+    fn index(&self) -> Option<Uuid> {
+        self.index.as_ref().map(|index| index.id)
+    }
+}
+
+impl audit::Affects for MultiIndexesResponseBody {}
+
+impl audit::Affects for SingleSnapshotResponseBody {
+    fn environment(&self) -> Option<Uuid> {
+        self.snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.environment)
+    }
+}
+
+impl audit::Affects for SingleProjectResponseBody {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditKind {
+    Mutation,
+    Docker,
+}
+
+impl From<AuditKind> for db::AuditKind {
+    fn from(kind: AuditKind) -> Self {
+        match kind {
+            AuditKind::Mutation => db::AuditKind::Mutation,
+            AuditKind::Docker => db::AuditKind::Docker,
+        }
+    }
+}
+
+impl From<db::AuditKind> for AuditKind {
+    fn from(kind: db::AuditKind) -> Self {
+        match kind {
+            db::AuditKind::Mutation => AuditKind::Mutation,
+            db::AuditKind::Docker => AuditKind::Docker,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl From<AuditOutcome> for db::AuditOutcome {
+    fn from(outcome: AuditOutcome) -> Self {
+        match outcome {
+            AuditOutcome::Success => db::AuditOutcome::Success,
+            AuditOutcome::Failure => db::AuditOutcome::Failure,
+        }
+    }
+}
+
+impl From<db::AuditOutcome> for AuditOutcome {
+    fn from(outcome: db::AuditOutcome) -> Self {
+        match outcome {
+            db::AuditOutcome::Success => AuditOutcome::Success,
+            db::AuditOutcome::Failure => AuditOutcome::Failure,
+        }
+    }
+}
+
+/// An entry of the audit log
+#[derive(Debug, PartialEq, Serialize, Deserialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub actor: String,
+    pub kind: AuditKind,
+    pub operation: String,
+    /// The arguments of the operation, as JSON
+    pub arguments: String,
+    pub environment: Option<Uuid>,
+    pub index: Option<Uuid>,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+    /// In milliseconds
+    pub duration: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<db::AuditEntity> for AuditEntry {
+    fn from(entity: db::AuditEntity) -> Self {
+        let db::AuditEntity {
+            id,
+            actor,
+            kind,
+            operation,
+            arguments,
+            environment,
+            index,
+            outcome,
+            error,
+            duration,
+            created_at,
+        } = entity;
+
+        AuditEntry {
+            id,
+            actor,
+            kind: AuditKind::from(kind),
+            operation,
+            arguments: arguments.to_string(),
+            environment,
+            index,
+            outcome: AuditOutcome::from(outcome),
+            error,
+            duration: i32::try_from(duration).unwrap_or(i32::MAX),
+            created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, GraphQLObject, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiAuditEntriesResponseBody {
+    pub entries: Vec<AuditEntry>,
+    pub entries_count: i32,
+}
+
+impl From<Vec<AuditEntry>> for MultiAuditEntriesResponseBody {
+    fn from(entries: Vec<AuditEntry>) -> Self {
+        let entries_count = i32::try_from(entries.len()).unwrap();
+        Self {
+            entries,
+            entries_count,
+        }
+    }
+}
+
+/// The number of audit entries returned when no limit is given.
+const DEFAULT_AUDIT_LIMIT: i32 = 100;
+
+/// Criteria selecting audit entries, all of which must match.
+#[derive(Debug, Default, Deserialize, Serialize, GraphQLInputObject, JsonSchema)]
+pub struct AuditFilterBody {
+    pub actor: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub operation: Option<String>,
+    pub environment: Option<Uuid>,
+    pub index: Option<Uuid>,
+    pub outcome: Option<AuditOutcome>,
+    /// Entries recorded at or after this date
+    pub since: Option<DateTime<Utc>>,
+    /// Entries recorded before this date
+    pub until: Option<DateTime<Utc>>,
+    /// The maximum number of entries, the most recent ones, 100 by default
+    pub limit: Option<i32>,
+}
+
+impl From<AuditFilterBody> for db::AuditFilterEntity {
+    fn from(filter: AuditFilterBody) -> Self {
+        let AuditFilterBody {
+            actor,
+            kind,
+            operation,
+            environment,
+            index,
+            outcome,
+            since,
+            until,
+            limit,
+        } = filter;
+
+        db::AuditFilterEntity {
+            actor,
+            kind: kind.map(db::AuditKind::from),
+            operation,
+            environment,
+            index,
+            outcome: outcome.map(db::AuditOutcome::from),
+            since,
+            until,
+            limit: i64::from(limit.unwrap_or(DEFAULT_AUDIT_LIMIT).max(0)),
+        }
+    }
+}
+
+/// Retrieve entries of the audit log
+pub async fn list_audit_entries(
+    filter: AuditFilterBody,
+    context: &Context,
+) -> Result<MultiAuditEntriesResponseBody, error::Error> {
+    let filter = db::AuditFilterEntity::from(filter);
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let entities = tx
+        .get_audit_entries(&filter)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get audit entries",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    let entries = entities
+        .into_iter()
+        .map(AuditEntry::from)
+        .collect::<Vec<_>>();
+
+    Ok(MultiAuditEntriesResponseBody::from(entries))
+}
+
+/// Retrieve all environments
+pub async fn list_environments(
+    context: &Context,
+) -> Result<MultiEnvironmentsResponseBody, error::Error> {
+    async move {
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let entities = tx
+            .get_all_environments()
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get all them environments",
+            })?;
+
+        let mut environments = Vec::with_capacity(entities.len());
+        for mut env in entities {
+            env.indexes =
+                tx.get_environment_indexes(&env.id)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not get environment indexes",
+                    })?;
+            environments.push(Environment::from(env));
+        }
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit transaction",
+        })?;
+
+        Ok(MultiEnvironmentsResponseBody::from(environments))
+    }
+    .await
+}
+
+/// Create a new environment
+pub async fn create_environment(
+    request: EnvironmentRequestBody,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    let config = docker::get_template_config(
+        &context.state.settings,
+        request.template.as_deref(),
+        &context.logger,
+    )
+    .await?;
+    provision_environment(request, config, None, context).await
+}
+
+/// Create a new environment from the given services configuration, optionally
+/// restoring its volumes from a snapshot directory.
+pub(crate) async fn provision_environment(
+    request: EnvironmentRequestBody,
+    config: Vec<docker::ServiceConfig>,
+    restore: Option<String>,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let identity = context.authorize(Role::Operator)?;
+        let expires_at = request.expiry()?;
+        // An environment restored from a snapshot has its own data, it is never reused.
+        let reuse = request.reuse.unwrap_or(false) && restore.is_none();
+        let mut input = db::InputEnvironmentEntity::from(request);
+        input.expires_at = expires_at;
+        input.owner = identity.subject.clone();
+
+        input.config = serde_json::to_value(&config).context(error::JSONError {
+            msg: String::from("Could not serialize services configuration"),
+        })?;
+
+        input.services = config
+            .iter()
+            .map(|service| {
+                db::ServiceEntity::from_config(service, &context.state.settings.registry.url)
+            })
+            .collect();
+        input.memory = input
+            .services
+            .iter()
+            .map(|service| {
+                service
+                    .limits
+                    .memory
+                    .unwrap_or(context.state.settings.twerg.memory)
+            })
+            .sum();
+
+        // Nothing is pulled for an environment the project has no room for.
+        let existing = if reuse {
+            find_environment_by_name(&input.name, context).await?
+        } else {
+            None
+        };
+        match &existing {
+            Some(existing)
+                if existing.owner != input.owner
+                    || existing.project != input.project
+                    || existing.state != db::EnvironmentState::Running =>
+            {
+                return Err(error::Error::MiscError {
+                    msg: format!(
+                        "Environment {} is not a running environment of {} to reuse",
+                        existing.name, input.owner
+                    ),
+                });
+            }
+            Some(_) => {}
+            None => {
+                let mut tx = context
+                    .state
+                    .store
+                    .begin()
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "could not initiate transaction",
+                    })?;
+
+                check_environment_quota(&mut *tx, &input).await?;
+
+                tx.commit().await.context(error::DBProvideError {
+                    msg: "could not commit check quota transaction.",
+                })?;
+            }
+        }
+
+        let start = Instant::now();
+        let images = docker::images::resolve_images(
+            &input.name,
+            &config,
+            &context.state.settings,
+            &context.state.events,
+            &context.logger,
+        )
+        .await?;
+        input.signature = signature::environment_signature(&config, &images);
+
+        if let Some(existing) = existing {
+            if existing.signature != input.signature {
+                return Err(error::Error::MiscError {
+                    msg: format!(
+                        "Environment {} cannot be reused, its signature is {}",
+                        existing.name, existing.signature
+                    ),
+                });
+            }
+            info!(
+                context.logger,
+                "Reusing environment {} with signature {}", existing.name, input.signature
+            );
+            return get_environment_by_id(existing.id, context).await;
+        }
+
+        for service in input.services.iter_mut() {
+            service.digest = images
+                .get(&service.name)
+                .and_then(|image| image.digest.clone());
+        }
+
+        shutdown::Journal::provisioning(
+            &context.state,
+            context.actor(),
+            &input.name,
+            &config,
+            restore.as_deref(),
+        )
+        .run(async move {
+            let port = audit::Operation::docker(
+                &context.state,
+                context.actor(),
+                "create_twerg",
+                &json!({ "name": &input.name, "restore": &restore }),
+            )
+            .run(docker::create_twerg(
+                &input.name,
+                config.clone(),
+                &images,
+                restore.as_deref(),
+                &context.state.settings,
+                &context.state.events,
+                &context.logger,
+            ))
+            .await?;
+
+            input.port = port as i32;
+            debug!(context.logger, "Created Twerg at port {}", input.port);
+            context.state.metrics.observe_provisioning(start.elapsed());
+
+            let mut tx = context
+                .state
+                .store
+                .begin()
+                .await
+                .context(error::DBProvideError {
+                    msg: "could not initiate transaction",
+                })?;
+
+            // Another environment may have taken the room left in the project while
+            // this one was provisioned.
+            if let Err(err) = check_environment_quota(&mut *tx, &input).await {
+                drop(tx);
+                if let Err(err) = audit::Operation::docker(
+                    &context.state,
+                    context.actor(),
+                    "delete_twerg",
+                    &json!({ "name": &input.name }),
+                )
+                .run(docker::delete_twerg(&input.name, &config, &context.logger))
+                .await
+                {
+                    warn!(
+                        context.logger,
+                        "Could not delete twerg {} over quota: {}", input.name, err
+                    );
+                }
+                return Err(err);
+            }
+
+            let resp = tx
+                .create_environment(&input)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not create environment",
+                })?;
+
+            tx.commit().await.context(error::DBProvideError {
+                msg: "could not commit create environment transaction.",
+            })?;
+
+            let environment = Environment::from(resp);
+            Ok(SingleEnvironmentResponseBody::from(environment))
+        })
+        .await
+    }
+    .await
+}
+
+/// The environment with the given name, if there is one.
+async fn find_environment_by_name(
+    name: &str,
+    context: &Context,
+) -> Result<Option<db::EnvironmentEntity>, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let environments = tx
+        .get_all_environments()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get all them environments",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit find environment transaction.",
+    })?;
+
+    Ok(environments
+        .into_iter()
+        .find(|environment| environment.name == name))
+}
+
+/// Retrieve an environment based on its id.
+pub async fn get_environment_by_id(
+    id: db::EntityId,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let environment = tx
+            .get_environment_by_id(&id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment",
+            })?;
+
+        let indexes = tx
+            .get_environment_indexes(&id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get all them environments",
+            })?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit get environment transaction.",
+        })?;
+
+        let mut environment = Environment::from(environment);
+        environment.indexes = indexes.into_iter().map(Index::from).collect::<Vec<_>>();
 
         Ok(SingleEnvironmentResponseBody::from(environment))
     }
     .await
 }
 
-/// Delete an environment. Return the deleted environment.
-pub async fn delete_environment(
-    id: EnvironmentIdBody,
+/// Delete an environment. Return the deleted environment.
+/// Operators can only delete the environments they own.
+pub async fn delete_environment(
+    id: EnvironmentIdBody,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let identity = context.authorize(Role::Operator)?;
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let environment =
+            tx.get_environment_by_id(&id.id)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not get environment",
+                })?;
+
+        if !identity.can_manage(&environment.owner) {
+            return Err(error::Error::Unauthorized {
+                msg: format!(
+                    "{} cannot delete environment {} owned by {}",
+                    identity.subject, environment.name, environment.owner
+                ),
+            });
+        }
+
+        let resp = tx
+            .delete_environment(&id.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not delete environment",
+            })?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit delete environment transaction.",
+        })?;
+
+        let environment = Environment::from(resp);
+        Ok(SingleEnvironmentResponseBody::from(environment))
+    }
+    .await
+}
+
+/// Remove the twerg of an environment, its containers, network and volumes, and then
+/// its catalog entry. The entry is kept if the twerg cannot be removed, so that it is
+/// not leaked. Operators can only tear down the environments they own.
+pub async fn teardown_environment(
+    id: Uuid,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    let environment = get_managed_environment(&id, "tear down", context).await?;
+    let config = environment_config(&environment)?;
+
+    audit::Operation::docker(
+        &context.state,
+        context.actor(),
+        "delete_twerg",
+        &json!({ "name": &environment.name }),
+    )
+    .environment(environment.id)
+    .run(docker::delete_twerg(
+        &environment.name,
+        &config,
+        &context.logger,
+    ))
+    .await?;
+
+    delete_environment(EnvironmentIdBody { id }, context).await
+}
+
+/// Push back the expiry of an environment by `ttl` seconds, counted from its
+/// current expiry, or from now if it has none.
+pub async fn extend_environment(
+    id: EnvironmentIdBody,
+    ttl: i32,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let identity = context.authorize(Role::Operator)?;
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let environment =
+            tx.get_environment_by_id(&id.id)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not get environment",
+                })?;
+
+        if !identity.can_manage(&environment.owner) {
+            return Err(error::Error::Unauthorized {
+                msg: format!(
+                    "{} cannot extend environment {} owned by {}",
+                    identity.subject, environment.name, environment.owner
+                ),
+            });
+        }
+
+        let now = Utc::now();
+        let from = environment
+            .expires_at
+            .filter(|expires_at| *expires_at > now)
+            .unwrap_or(now);
+        let expires_at = expiry_from_ttl(from, ttl)?;
+
+        let resp =
+            tx.extend_environment(&id.id, &expires_at)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not extend environment",
+                })?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit extend environment transaction.",
+        })?;
+
+        let environment = Environment::from(resp);
+        Ok(SingleEnvironmentResponseBody::from(environment))
+    }
+    .await
+}
+
+/// Stop all the services of an environment, keeping its containers, data and port.
+pub async fn stop_environment(
+    id: EnvironmentIdBody,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let environment = get_managed_environment(&id.id, "stop", context).await?;
+        let config = environment_config(&environment)?;
+        audit::Operation::docker(
+            &context.state,
+            context.actor(),
+            "stop_twerg",
+            &json!({ "name": &environment.name }),
+        )
+        .environment(environment.id)
+        .run(docker::stop_twerg(
+            &environment.name,
+            &config,
+            &context.logger,
+        ))
+        .await?;
+        refresh_environment_state(environment, context).await
+    }
+    .await
+}
+
+/// Start all the services of a stopped, or partially running, environment.
+pub async fn start_environment(
+    id: EnvironmentIdBody,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let environment = get_managed_environment(&id.id, "start", context).await?;
+        let config = environment_config(&environment)?;
+        audit::Operation::docker(
+            &context.state,
+            context.actor(),
+            "start_twerg",
+            &json!({ "name": &environment.name }),
+        )
+        .environment(environment.id)
+        .run(docker::start_twerg(
+            &environment.name,
+            &config,
+            &context.logger,
+        ))
+        .await?;
+        refresh_environment_state(environment, context).await
+    }
+    .await
+}
+
+/// Restart a single service of an environment.
+pub async fn restart_service(
+    id: EnvironmentIdBody,
+    service: String,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let environment = get_managed_environment(&id.id, "restart", context).await?;
+        let config = environment_config(&environment)?;
+        audit::Operation::docker(
+            &context.state,
+            context.actor(),
+            "restart_service",
+            &json!({ "name": &environment.name, "service": &service }),
+        )
+        .environment(environment.id)
+        .run(docker::restart_service(
+            &environment.name,
+            &config,
+            &service,
+            &context.logger,
+        ))
+        .await?;
+        refresh_environment_state(environment, context).await
+    }
+    .await
+}
+
+/// Return the last `tail` lines logged by the services of an environment, or by one of
+/// them only.
+pub async fn environment_logs(
+    id: Uuid,
+    service: Option<String>,
+    tail: Option<i32>,
+    context: &Context,
+) -> Result<EnvironmentLogsResponseBody, error::Error> {
+    let tail = tail.unwrap_or(DEFAULT_LOGS_TAIL);
+    let tail = u32::try_from(tail)
+        .ok()
+        .filter(|tail| *tail > 0)
+        .ok_or_else(|| error::Error::MiscError {
+            msg: format!("Invalid number of log lines {}", tail),
+        })?;
+
+    let environment = get_managed_environment(&id, "read the logs of", context).await?;
+    let services = match service {
+        Some(service) => {
+            if !environment.services.iter().any(|s| s.name == service) {
+                return Err(error::Error::MiscError {
+                    msg: format!(
+                        "Unknown service {} in environment {}",
+                        service, environment.name
+                    ),
+                });
+            }
+            vec![service]
+        }
+        None => environment
+            .services
+            .iter()
+            .map(|s| s.name.clone())
+            .collect(),
+    };
+
+    let mut logs = Vec::with_capacity(services.len());
+    for service in services {
+        let lines =
+            docker::service_logs(&environment.name, &service, tail, &context.logger).await?;
+        logs.push(ServiceLogs { service, lines });
+    }
+
+    Ok(EnvironmentLogsResponseBody { services: logs })
+}
+
+/// Archive the volumes of an environment.
+pub async fn snapshot_environment(
+    request: SnapshotRequestBody,
+    context: &Context,
+) -> Result<SingleSnapshotResponseBody, error::Error> {
+    async move {
+        let environment =
+            get_managed_environment(&request.environment, "snapshot", context).await?;
+        let config = environment_config(&environment)?;
+
+        let id = Uuid::new_v4();
+        let path = format!("{}/{}", context.state.settings.snapshots.directory, id);
+        tokio::fs::create_dir_all(&path)
+            .await
+            .context(error::TokioIOError {
+                msg: format!("Could not create snapshot directory {}", path),
+            })?;
+
+        let volumes = audit::Operation::docker(
+            &context.state,
+            context.actor(),
+            "snapshot_twerg",
+            &json!({ "name": &environment.name, "path": &path }),
+        )
+        .environment(environment.id)
+        .run(docker::snapshot_twerg(
+            &environment.name,
+            &config,
+            &path,
+            &context.state.settings,
+            &context.logger,
+        ))
+        .await?;
+
+        let input = db::InputSnapshotEntity {
+            id,
+            name: request.name,
+            environment: environment.id,
+            environment_name: environment.name,
+            path,
+            volumes,
+        };
+
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let resp = tx
+            .create_snapshot(&input)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not create snapshot",
+            })?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit create snapshot transaction.",
+        })?;
+
+        Ok(SingleSnapshotResponseBody::from(Snapshot::from(resp)))
+    }
+    .await
+}
+
+/// Create a new environment, whose volumes are restored from a snapshot. The
+/// caller must be allowed to manage the environment the snapshot was taken
+/// from, and only admins can restore snapshots of environments which are gone.
+/// The environment has the services configuration of the requested template, or
+/// else of the source environment, or else the default one.
+pub async fn restore_environment(
+    snapshot: Uuid,
+    request: EnvironmentRequestBody,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        let snapshot = get_snapshot_by_id(&snapshot, context).await?;
+        let source = match snapshot.environment {
+            Some(source) => {
+                Some(get_managed_environment(&source, "restore a snapshot of", context).await?)
+            }
+            None => {
+                context.authorize(Role::Admin)?;
+                None
+            }
+        };
+        let config = match (&request.template, source) {
+            (None, Some(source)) => environment_config(&source)?,
+            (template, _) => {
+                docker::get_template_config(
+                    &context.state.settings,
+                    template.as_deref(),
+                    &context.logger,
+                )
+                .await?
+            }
+        };
+        provision_environment(request, config, Some(snapshot.path), context).await
+    }
+    .await
+}
+
+/// Create a new environment with the services configuration and the indexes of
+/// an existing one. When the source has volumes, they are snapshotted and
+/// restored in the clone, and its indexes are copied. Otherwise the indexes are
+/// created again in the clone. Clones cannot be given a template: their services
+/// are changed with overrides.
+pub async fn clone_environment(
+    source: Uuid,
+    request: EnvironmentRequestBody,
+    overrides: Option<CloneOverridesBody>,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    async move {
+        if let Some(template) = &request.template {
+            return Err(error::Error::MiscError {
+                msg: format!(
+                    "Clones have the services of their source, not of template {}",
+                    template
+                ),
+            });
+        }
+
+        let source = get_managed_environment(&source, "clone", context).await?;
+
+        let mut config = environment_config(&source)?;
+        overrides.unwrap_or_default().apply(&mut config)?;
+
+        let has_volumes = !docker::volumes::volume_names(&config).is_empty();
+
+        let restore = if has_volumes {
+            let snapshot = snapshot_environment(
+                SnapshotRequestBody {
+                    environment: source.id,
+                    name: format!("clone of {} as {}", source.name, request.name),
+                },
+                context,
+            )
+            .await?
+            .snapshot
+            .ok_or_else(|| error::Error::MiscError {
+                msg: format!("Could not snapshot {}", source.name),
+            })?;
+            Some(get_snapshot_by_id(&snapshot.id, context).await?.path)
+        } else {
+            None
+        };
+
+        let mut clone = provision_environment(request, config, restore, context)
+            .await?
+            .env
+            .ok_or_else(|| error::Error::MiscError {
+                msg: format!("Could not clone {}", source.name),
+            })?;
+
+        let indexes = clone_indexes(&source.id, &clone.id, has_volumes, context).await?;
+        clone.indexes.extend(indexes);
+        Ok(SingleEnvironmentResponseBody::from(clone))
+    }
+    .await
+}
+
+/// Give a clone the indexes of its source, and return them. They are copied when the
+/// volumes of the clone were restored from a snapshot of the source, and hold them
+/// already. Otherwise they are created again in the clone.
+pub async fn clone_indexes(
+    source: &Uuid,
+    clone: &Uuid,
+    copy: bool,
     context: &Context,
-) -> Result<SingleEnvironmentResponseBody, error::Error> {
-    async move {
-        let pool = &context.state.pool;
+) -> Result<Vec<Index>, error::Error> {
+    if copy {
+        return copy_environment_indexes(source, clone, context).await;
+    }
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes = tx
+        .get_environment_indexes(source)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get source indexes",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    // An index which cannot be replayed does not invalidate the clone,
+    // it can be requested again.
+    let mut replayed = Vec::with_capacity(indexes.len());
+    for source_index in indexes {
+        let source_index_id = source_index.id;
+        let request = IndexRequestBody {
+            environment: *clone,
+            index_type: source_index.index_type,
+            data_source: source_index.data_source,
+            regions: source_index.regions,
+            reuse: None,
+        };
+        match create_index(request, context).await {
+            Ok(SingleIndexResponseBody { index: Some(index) }) => replayed.push(index),
+            Ok(_) => {}
+            Err(err) => warn!(
+                context.logger,
+                "Could not replay index {} in {}: {}", source_index_id, clone, err
+            ),
+        }
+    }
+    Ok(replayed)
+}
+
+/// Record copies of the indexes of an environment in its clone, whose volumes hold
+/// them already, and return them.
+async fn copy_environment_indexes(
+    source: &Uuid,
+    clone: &Uuid,
+    context: &Context,
+) -> Result<Vec<Index>, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes =
+        tx.copy_environment_indexes(source, clone)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not copy indexes",
+            })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit copy indexes transaction.",
+    })?;
+
+    Ok(indexes.into_iter().map(Index::from).collect())
+}
+
+/// The index types, with their data sources, and the regions indexes can cover.
+pub fn list_index_types(context: &Context) -> IndexTypesResponseBody {
+    IndexTypesResponseBody {
+        index_types: index_types::INDEX_TYPES
+            .iter()
+            .map(IndexTypeDescription::from)
+            .collect(),
+        regions: context.state.settings.indexes.regions.clone(),
+    }
+}
+
+/// Retrieve the cached source data of indexes, most recently used first
+pub async fn list_cached_artifacts(
+    context: &Context,
+) -> Result<MultiCachedArtifactsResponseBody, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
 
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
+    let entities = tx
+        .get_all_cached_artifacts()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get cached artifacts",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    let artifacts = entities
+        .into_iter()
+        .map(CachedArtifact::from)
+        .collect::<Vec<_>>();
+    Ok(MultiCachedArtifactsResponseBody::from(artifacts))
+}
+
+/// Retrieve all snapshots
+pub async fn list_snapshots(context: &Context) -> Result<MultiSnapshotsResponseBody, error::Error> {
+    async move {
+        let mut tx = context
+            .state
+            .store
+            .begin()
             .await
-            .context(error::DBError {
+            .context(error::DBProvideError {
                 msg: "could not initiate transaction",
             })?;
 
-        let resp = ProvideData::delete_environment(&mut tx as &mut sqlx::PgConnection, &id.id)
+        let entities = tx
+            .get_all_snapshots()
             .await
             .context(error::DBProvideError {
-                msg: "Could not delete environment",
+                msg: "Could not get all them snapshots",
             })?;
 
-        tx.commit().await.context(error::DBError {
-            msg: "could not commit delete environment transaction.",
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit transaction",
         })?;
 
-        let environment = Environment::from(resp);
-        Ok(SingleEnvironmentResponseBody::from(environment))
+        let snapshots = entities.into_iter().map(Snapshot::from).collect::<Vec<_>>();
+        Ok(MultiSnapshotsResponseBody::from(snapshots))
     }
     .await
 }
 
-/// Create a new index
+async fn get_snapshot_by_id(
+    id: &Uuid,
+    context: &Context,
+) -> Result<db::SnapshotEntity, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let snapshot = tx
+        .get_snapshot_by_id(id)
+        .await
+        .context(error::DBProvideError {
+            msg: format!("Could not get snapshot {}", id),
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    Ok(snapshot)
+}
+
+/// Retrieve an environment, making sure the caller is allowed to act on it.
+async fn get_managed_environment(
+    id: &Uuid,
+    action: &str,
+    context: &Context,
+) -> Result<db::EnvironmentEntity, error::Error> {
+    let identity = context.authorize(Role::Operator)?;
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let environment = tx
+        .get_environment_by_id(id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get environment transaction.",
+    })?;
+
+    if !identity.can_manage(&environment.owner) {
+        return Err(error::Error::Unauthorized {
+            msg: format!(
+                "{} cannot {} environment {} owned by {}",
+                identity.subject, action, environment.name, environment.owner
+            ),
+        });
+    }
+
+    Ok(environment)
+}
+
+/// The configuration the twerg of an environment was created from.
+fn environment_config(
+    environment: &db::EnvironmentEntity,
+) -> Result<Vec<docker::ServiceConfig>, error::Error> {
+    docker::stored_config(&environment.name, &environment.config)
+}
+
+/// Inspect the containers of an environment, and record its resulting state.
+async fn refresh_environment_state(
+    environment: db::EnvironmentEntity,
+    context: &Context,
+) -> Result<SingleEnvironmentResponseBody, error::Error> {
+    let config = environment_config(&environment)?;
+    let (running, total) = docker::twerg_running_count(&environment.name, &config).await?;
+    let state = db::EnvironmentState::from(EnvironmentState::from_running_count(running, total));
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let resp = tx
+        .update_environment_state(&environment.id, &state)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not update environment state",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit update environment state transaction.",
+    })?;
+
+    let environment = Environment::from(resp);
+    Ok(SingleEnvironmentResponseBody::from(environment))
+}
+
+/// Create a new index. Operators can only create indexes in the environments they
+/// own, which count against the quota of their project.
 pub async fn create_index(
     request: IndexRequestBody,
     context: &Context,
@@ -437,45 +2299,743 @@ pub async fn create_index(
         // Second, we create a GraphQL query, which we submit to the twerg.
         // Third, we enter the information in the database.
 
+        request.validate(context)?;
+
+        get_managed_environment(&request.environment, "create an index in", context).await?;
         let environment = get_environment_by_id(request.environment, &context).await?;
 
         info!(
-            context.state.logger,
+            context.logger,
             "Retrieved environment from id {}", request.environment
         );
 
-        let environment = environment.env.ok_or_else(|| error::Error::MiscError {
+        let mut environment = environment.env.ok_or_else(|| error::Error::MiscError {
             msg: format!("Could not retrieve environment {}", request.environment),
         })?;
 
-        let _id = client::create_index(&request, environment.port, &context.state.logger).await?;
+        let signature = request.signature();
+        if request.reuse.unwrap_or(false) {
+            let existing = environment.indexes.iter().position(|index| {
+                index.signature == signature && index.status == IndexStatus::Available
+            });
+            if let Some(position) = existing {
+                let index = environment.indexes.swap_remove(position);
+                info!(
+                    context.logger,
+                    "Reusing index {} with signature {}", index.id, signature
+                );
+                return Ok(SingleIndexResponseBody::from(index));
+            }
+        }
+
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        check_index_quota(&mut *tx, &environment).await?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit check quota transaction.",
+        })?;
+
+        shutdown::Journal::index(&context.state, context.actor(), &environment.name, &request)
+            .run_with(|mut progress| async move {
+                let file = cache::source_file(
+                    &context.state,
+                    &request.data_source,
+                    &request.regions,
+                    &context.logger,
+                )
+                .await;
+                let twerg = context.twerg();
+                let endpoint = twerg.endpoint(&environment.name, environment.port);
+                let id = twerg
+                    .create_index(&endpoint, &request, file.as_deref(), &context.logger)
+                    .await?;
+
+                debug!(context.logger, "Requested Index Creation on Twerg");
+
+                // Should the service stop from here, the index the twerg accepted is
+                // recorded at the next start, rather than requested again.
+                progress.record("twerg_id", &id).await?;
+
+                let input = db::InputIndexEntity {
+                    twerg_id: Some(id),
+                    ..db::InputIndexEntity::from(request)
+                };
+
+                let mut tx = context
+                    .state
+                    .store
+                    .begin()
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "could not initiate transaction",
+                    })?;
+
+                // Another index may have taken the room left in the project while
+                // this one was requested.
+                if let Err(err) = check_index_quota(&mut *tx, &environment).await {
+                    drop(tx);
+                    if let Err(err) = twerg.delete_index(&endpoint, id, &context.logger).await {
+                        warn!(
+                            context.logger,
+                            "Could not delete index {} over quota from the twerg: {}", id, err
+                        );
+                    }
+                    return Err(err);
+                }
+
+                let resp = tx
+                    .create_index(&input)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not create index",
+                    })?;
+
+                tx.commit().await.context(error::DBProvideError {
+                    msg: "could not commit create index transaction.",
+                })?;
+
+                let environment = Index::from(resp);
+                Ok(SingleIndexResponseBody::from(environment))
+            })
+            .await
+    }
+    .await
+}
+
+/// Submit an index whose build failed to its twerg again, and return it. The index is
+/// built from the same source data, and keeps its id, so that it does not count twice
+/// against the quota of the project. Operators can only retry the indexes of the
+/// environments they own.
+pub async fn retry_index(
+    request: IndexIdBody,
+    context: &Context,
+) -> Result<SingleIndexResponseBody, error::Error> {
+    let environment =
+        get_managed_environment(&request.environment, "retry an index of", context).await?;
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let index = tx
+        .get_environment_indexes(&environment.id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment indexes",
+        })?
+        .into_iter()
+        .find(|index| index.id == request.index)
+        .ok_or_else(|| error::Error::MiscError {
+            msg: format!(
+                "Index {} not found in environment {}",
+                request.index, environment.name
+            ),
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get environment indexes transaction.",
+    })?;
+
+    let twerg = context.twerg();
+    let endpoint = twerg.endpoint(&environment.name, environment.port);
+    if let Some(id) = index.twerg_id {
+        let status = twerg.index_status(&endpoint, id, &context.logger).await?;
+        match status {
+            db::IndexStatus::DownloadingError
+            | db::IndexStatus::ProcessingError
+            | db::IndexStatus::IndexingError
+            | db::IndexStatus::ValidationError => {}
+            _ => {
+                return Err(error::Error::MiscError {
+                    msg: format!(
+                        "Index {} has not failed, its status is {:?}",
+                        index.id, status
+                    ),
+                })
+            }
+        }
+    }
+
+    let file = cache::source_file(
+        &context.state,
+        &index.data_source,
+        &index.regions,
+        &context.logger,
+    )
+    .await;
+    let submitted = IndexRequestBody {
+        environment: environment.id,
+        index_type: index.index_type.clone(),
+        data_source: index.data_source.clone(),
+        regions: index.regions.clone(),
+        reuse: None,
+    };
+    let id = twerg
+        .create_index(&endpoint, &submitted, file.as_deref(), &context.logger)
+        .await?;
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let retried = tx
+        .swap_index(&index.id, id, &db::IndexStatus::NotAvailable)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not swap index",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit swap index transaction.",
+    })?;
+
+    // The failed build is only taking space in the twerg.
+    if let Some(failed) = index.twerg_id {
+        if let Err(err) = twerg.delete_index(&endpoint, failed, &context.logger).await {
+            warn!(
+                context.logger,
+                "Could not delete failed index {} from the twerg: {}", failed, err
+            );
+        }
+    }
+
+    info!(
+        context.logger,
+        "Retried index {} of environment {} as {}", index.id, environment.name, id
+    );
+    Ok(SingleIndexResponseBody::from(Index::from(retried)))
+}
+
+/// Remove an index from the catalog of an environment. Return the deleted index.
+/// Operators can only delete the indexes of the environments they own.
+pub async fn delete_index(
+    environment: Uuid,
+    index: Uuid,
+    context: &Context,
+) -> Result<SingleIndexResponseBody, error::Error> {
+    let environment = get_managed_environment(&environment, "delete an index of", context).await?;
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let resp = tx
+        .delete_index(&environment.id, &index)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not delete index",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit delete index transaction.",
+    })?;
+
+    info!(
+        context.logger,
+        "Deleted index {} of environment {}", index, environment.name
+    );
+    Ok(SingleIndexResponseBody::from(Index::from(resp)))
+}
+
+/// Set or clear the refresh schedule of an index, or of all the indexes of an
+/// environment. Return the indexes scheduled. Operators can only schedule the indexes
+/// of the environments they own.
+pub async fn schedule_indexes(
+    request: ScheduleRequestBody,
+    context: &Context,
+) -> Result<MultiIndexesResponseBody, error::Error> {
+    let environment =
+        get_managed_environment(&request.environment, "schedule the indexes of", context).await?;
+
+    let schedule = request
+        .schedule
+        .as_deref()
+        .map(str::parse::<Schedule>)
+        .transpose()?;
+    let expression = schedule.as_ref().map(Schedule::to_string);
+    let next = schedule.and_then(|schedule| schedule.next_after(&Utc::now()));
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes =
+        tx.get_environment_indexes(&environment.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment indexes",
+            })?;
+
+    let indexes = match request.index {
+        Some(id) => {
+            let index = indexes
+                .into_iter()
+                .find(|index| index.id == id)
+                .ok_or_else(|| error::Error::MiscError {
+                    msg: format!("Index {} not found in environment {}", id, environment.name),
+                })?;
+            vec![index]
+        }
+        None => indexes,
+    };
+
+    let mut scheduled = Vec::with_capacity(indexes.len());
+    for index in indexes {
+        let entity = tx
+            .schedule_index(&index.id, expression.as_deref(), next.as_ref())
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not schedule index refresh",
+            })?;
+        scheduled.push(Index::from(entity));
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit schedule index transaction.",
+    })?;
+
+    info!(
+        context.logger,
+        "Scheduled {} indexes of environment {} on '{}'",
+        scheduled.len(),
+        environment.name,
+        expression.as_deref().unwrap_or("never")
+    );
+    Ok(MultiIndexesResponseBody::from(scheduled))
+}
+
+/// Retrieve the refreshes of an index, most recent first
+pub async fn list_index_refreshes(
+    environment: Uuid,
+    index: Uuid,
+    context: &Context,
+) -> Result<MultiIndexRefreshesResponseBody, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes =
+        tx.get_environment_indexes(&environment)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment indexes",
+            })?;
+
+    if !indexes.iter().any(|candidate| candidate.id == index) {
+        return Err(error::Error::MiscError {
+            msg: format!("Index {} not found in environment {}", index, environment),
+        });
+    }
+
+    let entities = tx
+        .get_index_refreshes(&index)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get index refreshes",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get index refreshes transaction.",
+    })?;
+
+    let refreshes = entities.into_iter().map(IndexRefresh::from).collect();
+    Ok(MultiIndexRefreshesResponseBody::from(refreshes))
+}
+
+/// Start a refresh of an index now, whatever its schedule, and return it. Operators can
+/// only refresh the indexes of the environments they own.
+pub async fn refresh_index(
+    request: IndexIdBody,
+    context: &Context,
+) -> Result<SingleIndexRefreshResponseBody, error::Error> {
+    let environment =
+        get_managed_environment(&request.environment, "refresh an index of", context).await?;
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes =
+        tx.get_environment_indexes(&environment.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment indexes",
+            })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get environment indexes transaction.",
+    })?;
+
+    if !indexes
+        .iter()
+        .any(|candidate| candidate.id == request.index)
+    {
+        return Err(error::Error::MiscError {
+            msg: format!(
+                "Index {} not found in environment {}",
+                request.index, environment.name
+            ),
+        });
+    }
+
+    let refresh = refresh::start(&context.state, &environment.id, &request.index).await?;
+
+    info!(
+        context.logger,
+        "Started refresh {} of index {} of environment {}",
+        refresh.id,
+        request.index,
+        environment.name
+    );
+    Ok(SingleIndexRefreshResponseBody::from(IndexRefresh::from(
+        refresh,
+    )))
+}
+
+/// Serve a previous version of an index again, and discard the current one. Return the
+/// index. Operators can only roll back the indexes of the environments they own.
+pub async fn rollback_index(
+    request: RollbackRequestBody,
+    context: &Context,
+) -> Result<SingleIndexResponseBody, error::Error> {
+    let environment =
+        get_managed_environment(&request.environment, "roll back an index of", context).await?;
+
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let index = tx
+        .get_environment_indexes(&environment.id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment indexes",
+        })?
+        .into_iter()
+        .find(|index| index.id == request.index)
+        .ok_or_else(|| error::Error::MiscError {
+            msg: format!(
+                "Index {} not found in environment {}",
+                request.index, environment.name
+            ),
+        })?;
+
+    let versions = tx
+        .get_index_versions(&index.id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get index versions",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get index versions transaction.",
+    })?;
+
+    let version = versions
+        .iter()
+        .map(|version| version.twerg_id)
+        .find(|version| {
+            request
+                .version
+                .map_or(true, |requested| requested == *version)
+        })
+        .ok_or_else(|| error::Error::MiscError {
+            msg: match request.version {
+                Some(version) => format!("Index {} has no version {}", index.id, version),
+                None => format!("Index {} has no previous version", index.id),
+            },
+        })?;
+
+    let twerg = context.twerg();
+    let index = refresh::publish(
+        &context.state,
+        &twerg,
+        &environment,
+        &index,
+        version,
+        false,
+        &context.logger,
+    )
+    .await?;
+
+    info!(
+        context.logger,
+        "Rolled back index {} of environment {} to version {}", index.id, environment.name, version
+    );
+    Ok(SingleIndexResponseBody::from(Index::from(index)))
+}
+
+/// Retrieve the versions an index can be rolled back to, most recent first
+pub async fn list_index_versions(
+    environment: Uuid,
+    index: Uuid,
+    context: &Context,
+) -> Result<MultiIndexVersionsResponseBody, error::Error> {
+    let mut tx = context
+        .state
+        .store
+        .begin()
+        .await
+        .context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+    let indexes =
+        tx.get_environment_indexes(&environment)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get environment indexes",
+            })?;
+
+    if !indexes.iter().any(|candidate| candidate.id == index) {
+        return Err(error::Error::MiscError {
+            msg: format!("Index {} not found in environment {}", index, environment),
+        });
+    }
+
+    let entities = tx
+        .get_index_versions(&index)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get index versions",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit get index versions transaction.",
+    })?;
+
+    let versions = entities.into_iter().map(IndexVersion::from).collect();
+    Ok(MultiIndexVersionsResponseBody::from(versions))
+}
 
-        debug!(context.state.logger, "Requested Index Creation on Twerg");
+/// Retrieve all projects, with their current usage
+pub async fn list_projects(context: &Context) -> Result<MultiProjectsResponseBody, error::Error> {
+    async move {
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let entities = tx.get_all_projects().await.context(error::DBProvideError {
+            msg: "Could not get all them projects",
+        })?;
+
+        let mut projects = Vec::with_capacity(entities.len());
+        for entity in entities {
+            let usage = tx
+                .get_project_usage(&entity.id)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not get project usage",
+                })?;
+            projects.push(Project::new(entity, usage));
+        }
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit transaction",
+        })?;
+
+        Ok(MultiProjectsResponseBody::from(projects))
+    }
+    .await
+}
+
+/// Create a new project
+pub async fn create_project(
+    request: ProjectRequestBody,
+    context: &Context,
+) -> Result<SingleProjectResponseBody, error::Error> {
+    async move {
+        let input = db::InputProjectEntity::from(request);
+
+        let mut tx = context
+            .state
+            .store
+            .begin()
+            .await
+            .context(error::DBProvideError {
+                msg: "could not initiate transaction",
+            })?;
+
+        let entity = tx
+            .create_project(&input)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not create project",
+            })?;
+
+        let usage = tx
+            .get_project_usage(&entity.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get project usage",
+            })?;
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit create project transaction.",
+        })?;
 
-        let input = db::InputIndexEntity::from(request);
+        Ok(SingleProjectResponseBody::from(Project::new(entity, usage)))
+    }
+    .await
+}
 
-        let pool = &context.state.pool;
+/// Replace the quotas of a project
+pub async fn update_project_quota(
+    id: Uuid,
+    quota: QuotaRequestBody,
+    context: &Context,
+) -> Result<SingleProjectResponseBody, error::Error> {
+    async move {
+        let quota = db::QuotaEntity::from(quota);
 
-        let mut tx = pool
-            .conn()
-            .and_then(Connection::begin)
+        let mut tx = context
+            .state
+            .store
+            .begin()
             .await
-            .context(error::DBError {
+            .context(error::DBProvideError {
                 msg: "could not initiate transaction",
             })?;
 
-        let resp = ProvideData::create_index(&mut tx as &mut sqlx::PgConnection, &input)
+        let entity = tx
+            .update_project_quota(&id, &quota)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not update project quota",
+            })?;
+
+        let usage = tx
+            .get_project_usage(&entity.id)
             .await
             .context(error::DBProvideError {
-                msg: "Could not create index",
+                msg: "Could not get project usage",
             })?;
 
-        tx.commit().await.context(error::DBError {
-            msg: "could not commit create index transaction.",
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit update project transaction.",
         })?;
 
-        let environment = Index::from(resp);
-        Ok(SingleIndexResponseBody::from(environment))
+        Ok(SingleProjectResponseBody::from(Project::new(entity, usage)))
     }
     .await
 }
+
+/// Retrieve a project, and its current usage, locking the project until the end of
+/// the transaction.
+async fn lock_project_with_usage(
+    tx: &mut dyn Transaction,
+    id: &Uuid,
+) -> Result<(db::ProjectEntity, db::UsageEntity), error::Error> {
+    let project = tx.lock_project(id).await.context(error::DBProvideError {
+        msg: format!("Could not get project {}", id),
+    })?;
+
+    let usage = tx
+        .get_project_usage(id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get project usage",
+        })?;
+
+    Ok((project, usage))
+}
+
+/// Make sure the project has room for one more environment. The environment must be
+/// recorded in the same transaction, for the check to hold.
+async fn check_environment_quota(
+    tx: &mut dyn Transaction,
+    input: &db::InputEnvironmentEntity,
+) -> Result<(), error::Error> {
+    let (project, usage) = lock_project_with_usage(tx, &input.project).await?;
+
+    if let Some(max) = project.max_environments {
+        if usage.environments + 1 > max {
+            return Err(error::Error::QuotaExceeded {
+                project: project.name,
+                msg: format!("{} environments out of {}", usage.environments, max),
+            });
+        }
+    }
+
+    if let Some(max) = project.max_memory {
+        if usage.memory + input.memory > max {
+            return Err(error::Error::QuotaExceeded {
+                project: project.name,
+                msg: format!(
+                    "{} MiB used, {} MiB requested, out of {} MiB",
+                    usage.memory, input.memory, max
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Make sure the project owning the environment has room for one more index. The
+/// index must be recorded in the same transaction, for the check to hold.
+async fn check_index_quota(
+    tx: &mut dyn Transaction,
+    environment: &Environment,
+) -> Result<(), error::Error> {
+    let (project, usage) = lock_project_with_usage(tx, &environment.project).await?;
+
+    if let Some(max) = project.max_indexes {
+        if usage.indexes + 1 > max {
+            return Err(error::Error::QuotaExceeded {
+                project: project.name,
+                msg: format!("{} indexes out of {}", usage.indexes, max),
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,572 @@
+//! The queries and mutations of the service, shared by the GraphQL and REST APIs. Each
+//! checks the caller's role, and mutations are audited, and refused while shutting
+//! down.
+
+use opentelemetry::trace::SpanKind;
+use serde_json::json;
+use slog::info;
+use uuid::Uuid;
+
+use crate::api::gql::Context;
+use crate::api::manifest::{self, Manifest};
+use crate::api::model;
+use crate::audit;
+use crate::auth::Role;
+use crate::error;
+use crate::telemetry;
+
+/// Returns all the environments
+pub async fn environments(
+    context: &Context,
+) -> Result<model::MultiEnvironmentsResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for environments");
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query environments",
+        vec![],
+        model::list_environments(context),
+    )
+    .await
+}
+
+/// Returns an environment, with its indexes
+pub async fn environment(
+    id: Uuid,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for environment '{}'", id);
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query environment",
+        vec![],
+        model::get_environment_by_id(id, context),
+    )
+    .await
+}
+
+/// Returns the last lines logged by the services of an environment
+pub async fn environment_logs(
+    id: Uuid,
+    service: Option<String>,
+    tail: Option<i32>,
+    context: &Context,
+) -> Result<model::EnvironmentLogsResponseBody, error::Error> {
+    context.authorize(Role::Operator)?;
+    info!(context.logger, "Request for environment '{}' logs", id);
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query environmentLogs",
+        vec![],
+        model::environment_logs(id, service, tail, context),
+    )
+    .await
+}
+
+/// Returns the index types, with their data sources, and the regions indexes can cover
+pub async fn index_types(context: &Context) -> Result<model::IndexTypesResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for index types");
+    telemetry::in_span(SpanKind::Internal, "query indexTypes", vec![], async {
+        Ok(model::list_index_types(context))
+    })
+    .await
+}
+
+/// Returns the source data of indexes cached on the host, most recently used first
+pub async fn cached_artifacts(
+    context: &Context,
+) -> Result<model::MultiCachedArtifactsResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for cached artifacts");
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query cachedArtifacts",
+        vec![],
+        model::list_cached_artifacts(context),
+    )
+    .await
+}
+
+/// Returns the refreshes of an index, most recent first
+pub async fn index_refreshes(
+    environment: Uuid,
+    index: Uuid,
+    context: &Context,
+) -> Result<model::MultiIndexRefreshesResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for refreshes of index {}", index);
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query indexRefreshes",
+        vec![],
+        model::list_index_refreshes(environment, index, context),
+    )
+    .await
+}
+
+/// Returns the versions an index can be rolled back to, most recent first
+pub async fn index_versions(
+    environment: Uuid,
+    index: Uuid,
+    context: &Context,
+) -> Result<model::MultiIndexVersionsResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for versions of index {}", index);
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query indexVersions",
+        vec![],
+        model::list_index_versions(environment, index, context),
+    )
+    .await
+}
+
+/// Returns the list of snapshots
+pub async fn snapshots(
+    context: &Context,
+) -> Result<model::MultiSnapshotsResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for snapshots");
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query snapshots",
+        vec![],
+        model::list_snapshots(context),
+    )
+    .await
+}
+
+/// Returns the list of projects, with their quotas and current usage
+pub async fn projects(context: &Context) -> Result<model::MultiProjectsResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for projects");
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query projects",
+        vec![],
+        model::list_projects(context),
+    )
+    .await
+}
+
+/// Returns entries of the audit log, most recent first
+pub async fn audit_log(
+    filter: model::AuditFilterBody,
+    context: &Context,
+) -> Result<model::MultiAuditEntriesResponseBody, error::Error> {
+    context.authorize(Role::Admin)?;
+    info!(context.logger, "Request for audit log");
+    telemetry::in_span(
+        SpanKind::Internal,
+        "query auditLog",
+        vec![],
+        model::list_audit_entries(filter, context),
+    )
+    .await
+}
+
+/// Returns the changes applying a manifest would make, with the deletions pruning
+/// would make if requested
+pub async fn diff_manifest(
+    manifest: String,
+    prune: bool,
+    context: &Context,
+) -> Result<manifest::ManifestChangesResponseBody, error::Error> {
+    context.authorize(Role::Viewer)?;
+    info!(context.logger, "Request for manifest diff");
+    telemetry::in_span(SpanKind::Internal, "query diffManifest", vec![], async {
+        manifest::diff(Manifest::parse(&manifest)?, prune, context).await
+    })
+    .await
+}
+
+pub async fn create_environment(
+    env: model::EnvironmentRequestBody,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "createEnvironment",
+        &json!({ "env": &env }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' creation", env.name
+        );
+        model::create_environment(env, context).await
+    })
+    .await
+}
+
+pub async fn delete_environment(
+    id: model::EnvironmentIdBody,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "deleteEnvironment",
+        &json!({ "id": &id }),
+    )
+    .environment(id.id)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' deletion", id.id
+        );
+        model::delete_environment(id, context).await
+    })
+    .await
+}
+
+pub async fn extend_environment(
+    id: model::EnvironmentIdBody,
+    ttl: i32,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "extendEnvironment",
+        &json!({ "id": &id, "ttl": &ttl }),
+    )
+    .environment(id.id)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' extension by {}s", id.id, ttl
+        );
+        model::extend_environment(id, ttl, context).await
+    })
+    .await
+}
+
+pub async fn stop_environment(
+    id: model::EnvironmentIdBody,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "stopEnvironment",
+        &json!({ "id": &id }),
+    )
+    .environment(id.id)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for environment '{}' stop", id.id);
+        model::stop_environment(id, context).await
+    })
+    .await
+}
+
+pub async fn start_environment(
+    id: model::EnvironmentIdBody,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "startEnvironment",
+        &json!({ "id": &id }),
+    )
+    .environment(id.id)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for environment '{}' start", id.id);
+        model::start_environment(id, context).await
+    })
+    .await
+}
+
+pub async fn restart_service(
+    environment: model::EnvironmentIdBody,
+    service: String,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "restartService",
+        &json!({ "environment": &environment, "service": &service }),
+    )
+    .environment(environment.id)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for service '{}' restart in environment '{}'", service, environment.id
+        );
+        model::restart_service(environment, service, context).await
+    })
+    .await
+}
+
+pub async fn snapshot_environment(
+    snapshot: model::SnapshotRequestBody,
+    context: &Context,
+) -> Result<model::SingleSnapshotResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "snapshotEnvironment",
+        &json!({ "snapshot": &snapshot }),
+    )
+    .environment(snapshot.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' snapshot", snapshot.environment
+        );
+        model::snapshot_environment(snapshot, context).await
+    })
+    .await
+}
+
+pub async fn restore_environment(
+    snapshot: Uuid,
+    env: model::EnvironmentRequestBody,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "restoreEnvironment",
+        &json!({ "snapshot": &snapshot, "env": &env }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' restoration from snapshot '{}'", env.name, snapshot
+        );
+        model::restore_environment(snapshot, env, context).await
+    })
+    .await
+}
+
+pub async fn clone_environment(
+    source: Uuid,
+    env: model::EnvironmentRequestBody,
+    overrides: Option<model::CloneOverridesBody>,
+    context: &Context,
+) -> Result<model::SingleEnvironmentResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "cloneEnvironment",
+        &json!({ "source": &source, "env": &env, "overrides": &overrides }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for environment '{}' clone as '{}'", source, env.name
+        );
+        model::clone_environment(source, env, overrides, context).await
+    })
+    .await
+}
+
+pub async fn create_index(
+    index: model::IndexRequestBody,
+    context: &Context,
+) -> Result<model::SingleIndexResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "createIndex",
+        &json!({ "index": &index }),
+    )
+    .environment(index.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for index creation");
+        model::create_index(index, context).await
+    })
+    .await
+}
+
+pub async fn retry_index(
+    index: model::IndexIdBody,
+    context: &Context,
+) -> Result<model::SingleIndexResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "retryIndex",
+        &json!({ "index": &index }),
+    )
+    .environment(index.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for index '{}' retry", index.index);
+        model::retry_index(index, context).await
+    })
+    .await
+}
+
+pub async fn schedule_index_refresh(
+    schedule: model::ScheduleRequestBody,
+    context: &Context,
+) -> Result<model::MultiIndexesResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "scheduleIndexRefresh",
+        &json!({ "schedule": &schedule }),
+    )
+    .environment(schedule.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for index refresh schedule");
+        model::schedule_indexes(schedule, context).await
+    })
+    .await
+}
+
+pub async fn refresh_index(
+    index: model::IndexIdBody,
+    context: &Context,
+) -> Result<model::SingleIndexRefreshResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "refreshIndex",
+        &json!({ "index": &index }),
+    )
+    .environment(index.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for index '{}' refresh", index.index
+        );
+        model::refresh_index(index, context).await
+    })
+    .await
+}
+
+pub async fn rollback_index(
+    rollback: model::RollbackRequestBody,
+    context: &Context,
+) -> Result<model::SingleIndexResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "rollbackIndex",
+        &json!({ "rollback": &rollback }),
+    )
+    .environment(rollback.environment)
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(
+            context.logger,
+            "Request for index '{}' rollback", rollback.index
+        );
+        model::rollback_index(rollback, context).await
+    })
+    .await
+}
+
+pub async fn create_project(
+    project: model::ProjectRequestBody,
+    context: &Context,
+) -> Result<model::SingleProjectResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "createProject",
+        &json!({ "project": &project }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Admin)?;
+        info!(
+            context.logger,
+            "Request for project '{}' creation", project.name
+        );
+        model::create_project(project, context).await
+    })
+    .await
+}
+
+pub async fn update_project_quota(
+    id: Uuid,
+    quota: model::QuotaRequestBody,
+    context: &Context,
+) -> Result<model::SingleProjectResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "updateProjectQuota",
+        &json!({ "id": &id, "quota": &quota }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Admin)?;
+        info!(context.logger, "Request for project '{}' quota update", id);
+        model::update_project_quota(id, quota, context).await
+    })
+    .await
+}
+
+pub async fn apply_manifest(
+    manifest: String,
+    context: &Context,
+) -> Result<manifest::ManifestChangesResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "applyManifest",
+        &json!({ "manifest": &manifest }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for manifest application");
+        manifest::apply(Manifest::parse(&manifest)?, context).await
+    })
+    .await
+}
+
+pub async fn prune_manifest(
+    manifest: String,
+    context: &Context,
+) -> Result<manifest::ManifestChangesResponseBody, error::Error> {
+    audit::Operation::mutation(
+        &context.state,
+        context.actor(),
+        "pruneManifest",
+        &json!({ "manifest": &manifest }),
+    )
+    .run(async move {
+        let _in_flight = context.state.shutdown.enter()?;
+        context.authorize(Role::Operator)?;
+        info!(context.logger, "Request for manifest pruning");
+        manifest::prune(Manifest::parse(&manifest)?, context).await
+    })
+    .await
+}
@@ -0,0 +1,1091 @@
+//! REST API, for clients which cannot easily speak GraphQL. Its endpoints run the same
+//! operations, and return the same bodies, as the GraphQL queries and mutations. They
+//! are described by an OpenAPI document, served at /api/v1/openapi.json.
+
+use opentelemetry::trace::{FutureExt as _, SpanKind, StatusCode as SpanStatus, TraceContextExt};
+use opentelemetry::KeyValue;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{ObjectValidation, Schema};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use slog::debug;
+use std::future::Future;
+use std::sync::Arc;
+use uuid::Uuid;
+use warp::filters::BoxedFilter;
+use warp::http::{HeaderMap, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::api::gql::Context;
+use crate::api::{manifest, model, operations};
+use crate::db::model::ProvideError;
+use crate::error;
+use crate::logging;
+use crate::telemetry;
+
+/// A REST request: its context, and the trace context propagated by the client.
+struct Request {
+    context: Context,
+    trace: opentelemetry::Context,
+}
+
+/// The request body to extend an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ExtendBody {
+    /// Seconds added to the expiry of the environment, or to now if it has none
+    ttl: i32,
+}
+
+/// The request body to clone an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct CloneBody {
+    env: model::EnvironmentRequestBody,
+    overrides: Option<model::CloneOverridesBody>,
+}
+
+/// The request body to create an index in an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct IndexBody {
+    index_type: String,
+    data_source: String,
+    regions: Vec<String>,
+    /// Return an available index of the environment with the same signature, if
+    /// there is one, instead of creating a new one.
+    reuse: Option<bool>,
+}
+
+/// The request body to schedule the refreshes of the indexes of an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ScheduleBody {
+    /// The index to schedule, all the indexes of the environment if none is given
+    index: Option<Uuid>,
+    /// A cron expression in UTC, such as '0 3 * * 0'. The refreshes stop if none is
+    /// given
+    schedule: Option<String>,
+}
+
+/// The request body to roll back an index.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RollbackBody {
+    /// The id in the twerg of a previous version, the most recent one if none is given
+    version: Option<i32>,
+}
+
+/// The request body to snapshot an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SnapshotBody {
+    name: String,
+}
+
+/// The query parameters to read the logs of an environment.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct LogsQuery {
+    /// Only return the logs of this service
+    service: Option<String>,
+    /// The number of lines returned per service, 100 by default
+    tail: Option<i32>,
+}
+
+/// The request body of the manifest endpoints.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ManifestBody {
+    /// The manifest, in YAML
+    manifest: String,
+}
+
+/// The query parameters of a manifest diff.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct DiffQuery {
+    /// Also list the deletions pruning the manifest would make
+    prune: Option<bool>,
+}
+
+/// The response body of a failed request.
+#[derive(Debug, Serialize, JsonSchema)]
+struct ErrorBody {
+    error: String,
+}
+
+/// The routes of the REST API, under /api/v1. The context of each request is given by
+/// the filter, which authenticates it.
+pub fn routes(
+    context: BoxedFilter<(Context,)>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let request = context
+        .and(warp::header::headers_cloned())
+        .map(|context: Context, headers: HeaderMap| Request {
+            context,
+            trace: telemetry::remote_context(&headers),
+        })
+        .boxed();
+
+    let environments = warp::get()
+        .and(warp::path!("api" / "v1" / "environments"))
+        .and(request.clone())
+        .and_then(|request: Request| {
+            respond(
+                request,
+                "environments",
+                StatusCode::OK,
+                |context| async move { operations::environments(&context).await },
+            )
+        });
+
+    let create_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "environments"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|request: Request, env: model::EnvironmentRequestBody| {
+            respond(
+                request,
+                "createEnvironment",
+                StatusCode::CREATED,
+                |context| async move { operations::create_environment(env, &context).await },
+            )
+        });
+
+    let environment = warp::get()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid))
+        .and(request.clone())
+        .and_then(|id: Uuid, request: Request| {
+            respond(
+                request,
+                "environment",
+                StatusCode::OK,
+                move |context| async move { operations::environment(id, &context).await },
+            )
+        });
+
+    let delete_environment = warp::delete()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid))
+        .and(request.clone())
+        .and_then(|id: Uuid, request: Request| {
+            respond(
+                request,
+                "deleteEnvironment",
+                StatusCode::OK,
+                move |context| async move {
+                    let id = model::EnvironmentIdBody { id };
+                    operations::delete_environment(id, &context).await
+                },
+            )
+        });
+
+    let extend_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid / "extend"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|id: Uuid, request: Request, body: ExtendBody| {
+            respond(
+                request,
+                "extendEnvironment",
+                StatusCode::OK,
+                move |context| async move {
+                    let id = model::EnvironmentIdBody { id };
+                    operations::extend_environment(id, body.ttl, &context).await
+                },
+            )
+        });
+
+    let stop_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid / "stop"))
+        .and(request.clone())
+        .and_then(|id: Uuid, request: Request| {
+            respond(
+                request,
+                "stopEnvironment",
+                StatusCode::OK,
+                move |context| async move {
+                    let id = model::EnvironmentIdBody { id };
+                    operations::stop_environment(id, &context).await
+                },
+            )
+        });
+
+    let start_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid / "start"))
+        .and(request.clone())
+        .and_then(|id: Uuid, request: Request| {
+            respond(
+                request,
+                "startEnvironment",
+                StatusCode::OK,
+                move |context| async move {
+                    let id = model::EnvironmentIdBody { id };
+                    operations::start_environment(id, &context).await
+                },
+            )
+        });
+
+    let restart_service = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "services" / String / "restart"
+        ))
+        .and(request.clone())
+        .and_then(|id: Uuid, service: String, request: Request| {
+            respond(
+                request,
+                "restartService",
+                StatusCode::OK,
+                move |context| async move {
+                    let environment = model::EnvironmentIdBody { id };
+                    operations::restart_service(environment, service, &context).await
+                },
+            )
+        });
+
+    let environment_logs = warp::get()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid / "logs"))
+        .and(request.clone())
+        .and(warp::query::<LogsQuery>())
+        .and_then(|id: Uuid, request: Request, query: LogsQuery| {
+            respond(
+                request,
+                "environmentLogs",
+                StatusCode::OK,
+                move |context| async move {
+                    operations::environment_logs(id, query.service, query.tail, &context).await
+                },
+            )
+        });
+
+    let clone_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "environments" / Uuid / "clone"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|source: Uuid, request: Request, body: CloneBody| {
+            respond(
+                request,
+                "cloneEnvironment",
+                StatusCode::CREATED,
+                move |context| async move {
+                    operations::clone_environment(source, body.env, body.overrides, &context).await
+                },
+            )
+        });
+
+    let indexes = warp::get()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes"
+        ))
+        .and(request.clone())
+        .and_then(|id: Uuid, request: Request| {
+            respond(
+                request,
+                "indexes",
+                StatusCode::OK,
+                move |context| async move {
+                    let environment = operations::environment(id, &context).await?;
+                    let indexes = environment.env.map(|env| env.indexes).unwrap_or_default();
+                    Ok::<_, error::Error>(model::MultiIndexesResponseBody::from(indexes))
+                },
+            )
+        });
+
+    let create_index = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes"
+        ))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|environment: Uuid, request: Request, body: IndexBody| {
+            respond(
+                request,
+                "createIndex",
+                StatusCode::CREATED,
+                move |context| async move {
+                    let IndexBody {
+                        index_type,
+                        data_source,
+                        regions,
+                        reuse,
+                    } = body;
+                    let index = model::IndexRequestBody {
+                        environment,
+                        index_type,
+                        data_source,
+                        regions,
+                        reuse,
+                    };
+                    operations::create_index(index, &context).await
+                },
+            )
+        });
+
+    let retry_index = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes" / Uuid / "retry"
+        ))
+        .and(request.clone())
+        .and_then(|environment: Uuid, index: Uuid, request: Request| {
+            respond(
+                request,
+                "retryIndex",
+                StatusCode::OK,
+                move |context| async move {
+                    let index = model::IndexIdBody { environment, index };
+                    operations::retry_index(index, &context).await
+                },
+            )
+        });
+
+    let schedule_index_refresh = warp::put()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "schedule"
+        ))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|environment: Uuid, request: Request, body: ScheduleBody| {
+            respond(
+                request,
+                "scheduleIndexRefresh",
+                StatusCode::OK,
+                move |context| async move {
+                    let schedule = model::ScheduleRequestBody {
+                        environment,
+                        index: body.index,
+                        schedule: body.schedule,
+                    };
+                    operations::schedule_index_refresh(schedule, &context).await
+                },
+            )
+        });
+
+    let index_refreshes = warp::get()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes" / Uuid / "refreshes"
+        ))
+        .and(request.clone())
+        .and_then(|environment: Uuid, index: Uuid, request: Request| {
+            respond(
+                request,
+                "indexRefreshes",
+                StatusCode::OK,
+                move |context| async move {
+                    operations::index_refreshes(environment, index, &context).await
+                },
+            )
+        });
+
+    let index_versions = warp::get()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes" / Uuid / "versions"
+        ))
+        .and(request.clone())
+        .and_then(|environment: Uuid, index: Uuid, request: Request| {
+            respond(
+                request,
+                "indexVersions",
+                StatusCode::OK,
+                move |context| async move {
+                    operations::index_versions(environment, index, &context).await
+                },
+            )
+        });
+
+    let refresh_index = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes" / Uuid / "refresh"
+        ))
+        .and(request.clone())
+        .and_then(|environment: Uuid, index: Uuid, request: Request| {
+            respond(
+                request,
+                "refreshIndex",
+                StatusCode::CREATED,
+                move |context| async move {
+                    let index = model::IndexIdBody { environment, index };
+                    operations::refresh_index(index, &context).await
+                },
+            )
+        });
+
+    let rollback_index = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "indexes" / Uuid / "rollback"
+        ))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(
+            |environment: Uuid, index: Uuid, request: Request, body: RollbackBody| {
+                respond(
+                    request,
+                    "rollbackIndex",
+                    StatusCode::OK,
+                    move |context| async move {
+                        let rollback = model::RollbackRequestBody {
+                            environment,
+                            index,
+                            version: body.version,
+                        };
+                        operations::rollback_index(rollback, &context).await
+                    },
+                )
+            },
+        );
+
+    let index_types = warp::get()
+        .and(warp::path!("api" / "v1" / "index-types"))
+        .and(request.clone())
+        .and_then(|request: Request| {
+            respond(
+                request,
+                "indexTypes",
+                StatusCode::OK,
+                |context| async move { operations::index_types(&context).await },
+            )
+        });
+
+    let cached_artifacts = warp::get()
+        .and(warp::path!("api" / "v1" / "cache" / "artifacts"))
+        .and(request.clone())
+        .and_then(|request: Request| {
+            respond(
+                request,
+                "cachedArtifacts",
+                StatusCode::OK,
+                |context| async move { operations::cached_artifacts(&context).await },
+            )
+        });
+
+    let snapshot_environment = warp::post()
+        .and(warp::path!(
+            "api" / "v1" / "environments" / Uuid / "snapshots"
+        ))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|environment: Uuid, request: Request, body: SnapshotBody| {
+            respond(
+                request,
+                "snapshotEnvironment",
+                StatusCode::CREATED,
+                move |context| async move {
+                    let snapshot = model::SnapshotRequestBody {
+                        environment,
+                        name: body.name,
+                    };
+                    operations::snapshot_environment(snapshot, &context).await
+                },
+            )
+        });
+
+    let snapshots = warp::get()
+        .and(warp::path!("api" / "v1" / "snapshots"))
+        .and(request.clone())
+        .and_then(|request: Request| {
+            respond(request, "snapshots", StatusCode::OK, |context| async move {
+                operations::snapshots(&context).await
+            })
+        });
+
+    let restore_environment = warp::post()
+        .and(warp::path!("api" / "v1" / "snapshots" / Uuid / "restore"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(
+            |snapshot: Uuid, request: Request, env: model::EnvironmentRequestBody| {
+                respond(
+                    request,
+                    "restoreEnvironment",
+                    StatusCode::CREATED,
+                    move |context| async move {
+                        operations::restore_environment(snapshot, env, &context).await
+                    },
+                )
+            },
+        );
+
+    let projects = warp::get()
+        .and(warp::path!("api" / "v1" / "projects"))
+        .and(request.clone())
+        .and_then(|request: Request| {
+            respond(request, "projects", StatusCode::OK, |context| async move {
+                operations::projects(&context).await
+            })
+        });
+
+    let create_project = warp::post()
+        .and(warp::path!("api" / "v1" / "projects"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|request: Request, project: model::ProjectRequestBody| {
+            respond(
+                request,
+                "createProject",
+                StatusCode::CREATED,
+                |context| async move { operations::create_project(project, &context).await },
+            )
+        });
+
+    let update_project_quota = warp::put()
+        .and(warp::path!("api" / "v1" / "projects" / Uuid / "quota"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(
+            |id: Uuid, request: Request, quota: model::QuotaRequestBody| {
+                respond(
+                    request,
+                    "updateProjectQuota",
+                    StatusCode::OK,
+                    move |context| async move {
+                        operations::update_project_quota(id, quota, &context).await
+                    },
+                )
+            },
+        );
+
+    let audit_log = warp::get()
+        .and(warp::path!("api" / "v1" / "audit"))
+        .and(request)
+        .and(warp::query::<model::AuditFilterBody>())
+        .and_then(|request: Request, filter: model::AuditFilterBody| {
+            respond(request, "auditLog", StatusCode::OK, |context| async move {
+                operations::audit_log(filter, &context).await
+            })
+        });
+
+    let diff_manifest = warp::post()
+        .and(warp::path!("api" / "v1" / "manifests" / "diff"))
+        .and(request.clone())
+        .and(warp::query::<DiffQuery>())
+        .and(warp::body::json())
+        .and_then(|request: Request, query: DiffQuery, body: ManifestBody| {
+            respond(
+                request,
+                "diffManifest",
+                StatusCode::OK,
+                move |context| async move {
+                    let prune = query.prune.unwrap_or(false);
+                    operations::diff_manifest(body.manifest, prune, &context).await
+                },
+            )
+        });
+
+    let apply_manifest = warp::post()
+        .and(warp::path!("api" / "v1" / "manifests" / "apply"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|request: Request, body: ManifestBody| {
+            respond(
+                request,
+                "applyManifest",
+                StatusCode::OK,
+                |context| async move { operations::apply_manifest(body.manifest, &context).await },
+            )
+        });
+
+    let prune_manifest = warp::post()
+        .and(warp::path!("api" / "v1" / "manifests" / "prune"))
+        .and(request.clone())
+        .and(warp::body::json())
+        .and_then(|request: Request, body: ManifestBody| {
+            respond(
+                request,
+                "pruneManifest",
+                StatusCode::OK,
+                |context| async move { operations::prune_manifest(body.manifest, &context).await },
+            )
+        });
+
+    let document = Arc::new(openapi());
+    let openapi_json = warp::get()
+        .and(warp::path!("api" / "v1" / "openapi.json"))
+        .map(move || warp::reply::json(document.as_ref()));
+
+    environments
+        .or(create_environment)
+        .or(environment)
+        .or(delete_environment)
+        .or(extend_environment)
+        .or(stop_environment)
+        .or(start_environment)
+        .or(restart_service)
+        .or(environment_logs)
+        .or(clone_environment)
+        .or(indexes)
+        .or(create_index)
+        .or(retry_index)
+        .or(schedule_index_refresh)
+        .or(index_refreshes)
+        .or(index_versions)
+        .or(refresh_index)
+        .or(rollback_index)
+        .or(index_types)
+        .or(cached_artifacts)
+        .or(snapshot_environment)
+        .or(snapshots)
+        .or(restore_environment)
+        .or(projects)
+        .or(create_project)
+        .or(update_project_quota)
+        .or(audit_log)
+        .or(diff_manifest)
+        .or(apply_manifest)
+        .or(prune_manifest)
+        .or(openapi_json)
+}
+
+/// Run an operation in a span, child of the client's trace, and reply with the
+/// response body and the given status code, or with the error.
+async fn respond<T, F, H>(
+    request: Request,
+    operation: &'static str,
+    code: StatusCode,
+    handler: H,
+) -> Result<Response, Rejection>
+where
+    T: Serialize,
+    F: Future<Output = Result<T, error::Error>>,
+    H: FnOnce(Context) -> F,
+{
+    let Request { context, trace } = request;
+    let request_id = context.request_id.clone();
+    let logger = context.logger.clone();
+
+    debug!(logger, "Executing {}", operation);
+    let span = telemetry::start(
+        &trace,
+        SpanKind::Server,
+        &format!("rest {}", operation),
+        vec![KeyValue::new("request_id", request_id.clone())],
+    );
+    let result = handler(context).with_context(span.clone()).await;
+    let reply = match result {
+        Ok(body) => warp::reply::with_status(warp::reply::json(&body), code),
+        Err(err) => {
+            debug!(logger, "Failed {}: {}", operation, err);
+            span.span().set_status(SpanStatus::Error, err.to_string());
+            let body = ErrorBody {
+                error: err.to_string(),
+            };
+            warp::reply::with_status(warp::reply::json(&body), status_code(&err))
+        }
+    };
+    span.span().end();
+    debug!(logger, "Executed {}", operation);
+
+    Ok(
+        warp::reply::with_header(reply, logging::REQUEST_ID_HEADER, request_id.as_str())
+            .into_response(),
+    )
+}
+
+/// The status code of the response to a failed operation.
+fn status_code(err: &error::Error) -> StatusCode {
+    match err {
+        error::Error::Unauthenticated { .. } => StatusCode::UNAUTHORIZED,
+        error::Error::Unauthorized { .. } => StatusCode::FORBIDDEN,
+        error::Error::Environment { .. }
+        | error::Error::DBProvideError {
+            source: ProvideError::NotFound,
+            ..
+        } => StatusCode::NOT_FOUND,
+        error::Error::QuotaExceeded { .. }
+        | error::Error::DBProvideError {
+            source: ProvideError::UniqueViolation { .. },
+            ..
+        } => StatusCode::CONFLICT,
+        error::Error::MiscError { .. }
+        | error::Error::YAMLError { .. }
+        | error::Error::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
+        error::Error::TwergError { .. }
+        | error::Error::ReqwestError { .. }
+        | error::Error::DockerError { .. } => StatusCode::BAD_GATEWAY,
+        error::Error::ShuttingDown { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// An endpoint of the REST API, as documented.
+struct Endpoint {
+    method: &'static str,
+    /// Relative to /api/v1, with its parameters in braces.
+    path: &'static str,
+    operation: &'static str,
+    summary: &'static str,
+    code: StatusCode,
+    query: Vec<Value>,
+    request: Option<Schema>,
+    response: Schema,
+}
+
+impl Endpoint {
+    fn new(
+        method: &'static str,
+        path: &'static str,
+        operation: &'static str,
+        summary: &'static str,
+        response: Schema,
+    ) -> Self {
+        Endpoint {
+            method,
+            path,
+            operation,
+            summary,
+            code: StatusCode::OK,
+            query: Vec::new(),
+            request: None,
+            response,
+        }
+    }
+
+    /// The endpoint creates a resource.
+    fn created(mut self) -> Self {
+        self.code = StatusCode::CREATED;
+        self
+    }
+
+    fn request(mut self, schema: Schema) -> Self {
+        self.request = Some(schema);
+        self
+    }
+
+    fn query(mut self, parameters: Vec<Value>) -> Self {
+        self.query = parameters;
+        self
+    }
+}
+
+/// The endpoints served by `routes`.
+fn endpoints(gen: &mut SchemaGenerator) -> Vec<Endpoint> {
+    vec![
+        Endpoint::new(
+            "get",
+            "/environments",
+            "environments",
+            "List the environments",
+            gen.subschema_for::<model::MultiEnvironmentsResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments",
+            "createEnvironment",
+            "Create an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        )
+        .request(gen.subschema_for::<model::EnvironmentRequestBody>())
+        .created(),
+        Endpoint::new(
+            "get",
+            "/environments/{id}",
+            "environment",
+            "Get an environment, with its indexes",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        ),
+        Endpoint::new(
+            "delete",
+            "/environments/{id}",
+            "deleteEnvironment",
+            "Delete an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/extend",
+            "extendEnvironment",
+            "Push back the expiry of an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        )
+        .request(gen.subschema_for::<ExtendBody>()),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/stop",
+            "stopEnvironment",
+            "Stop the containers of an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/start",
+            "startEnvironment",
+            "Start the containers of an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/services/{service}/restart",
+            "restartService",
+            "Restart a service of an environment",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        ),
+        Endpoint::new(
+            "get",
+            "/environments/{id}/logs",
+            "environmentLogs",
+            "Read the last lines logged by the services of an environment",
+            gen.subschema_for::<model::EnvironmentLogsResponseBody>(),
+        )
+        .query(query_parameters::<LogsQuery>(gen)),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/clone",
+            "cloneEnvironment",
+            "Create an environment with the configuration and data of another",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        )
+        .request(gen.subschema_for::<CloneBody>())
+        .created(),
+        Endpoint::new(
+            "get",
+            "/environments/{id}/indexes",
+            "indexes",
+            "List the indexes of an environment",
+            gen.subschema_for::<model::MultiIndexesResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/indexes",
+            "createIndex",
+            "Create an index in an environment",
+            gen.subschema_for::<model::SingleIndexResponseBody>(),
+        )
+        .request(gen.subschema_for::<IndexBody>())
+        .created(),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/indexes/{index}/retry",
+            "retryIndex",
+            "Submit an index whose build failed to its twerg again",
+            gen.subschema_for::<model::SingleIndexResponseBody>(),
+        ),
+        Endpoint::new(
+            "put",
+            "/environments/{id}/schedule",
+            "scheduleIndexRefresh",
+            "Set or clear the schedule on which indexes of an environment are refreshed",
+            gen.subschema_for::<model::MultiIndexesResponseBody>(),
+        )
+        .request(gen.subschema_for::<ScheduleBody>()),
+        Endpoint::new(
+            "get",
+            "/environments/{id}/indexes/{index}/refreshes",
+            "indexRefreshes",
+            "List the refreshes of an index, most recent first",
+            gen.subschema_for::<model::MultiIndexRefreshesResponseBody>(),
+        ),
+        Endpoint::new(
+            "get",
+            "/environments/{id}/indexes/{index}/versions",
+            "indexVersions",
+            "List the versions an index can be rolled back to, most recent first",
+            gen.subschema_for::<model::MultiIndexVersionsResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/indexes/{index}/refresh",
+            "refreshIndex",
+            "Start a refresh of an index now, whatever its schedule",
+            gen.subschema_for::<model::SingleIndexRefreshResponseBody>(),
+        )
+        .created(),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/indexes/{index}/rollback",
+            "rollbackIndex",
+            "Serve a previous version of an index again, and discard the current one",
+            gen.subschema_for::<model::SingleIndexResponseBody>(),
+        )
+        .request(gen.subschema_for::<RollbackBody>()),
+        Endpoint::new(
+            "get",
+            "/index-types",
+            "indexTypes",
+            "List the index types, with their data sources, and the regions of indexes",
+            gen.subschema_for::<model::IndexTypesResponseBody>(),
+        ),
+        Endpoint::new(
+            "get",
+            "/cache/artifacts",
+            "cachedArtifacts",
+            "List the source data of indexes cached on the host",
+            gen.subschema_for::<model::MultiCachedArtifactsResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/environments/{id}/snapshots",
+            "snapshotEnvironment",
+            "Snapshot the volumes of an environment",
+            gen.subschema_for::<model::SingleSnapshotResponseBody>(),
+        )
+        .request(gen.subschema_for::<SnapshotBody>())
+        .created(),
+        Endpoint::new(
+            "get",
+            "/snapshots",
+            "snapshots",
+            "List the snapshots",
+            gen.subschema_for::<model::MultiSnapshotsResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/snapshots/{id}/restore",
+            "restoreEnvironment",
+            "Create an environment from a snapshot",
+            gen.subschema_for::<model::SingleEnvironmentResponseBody>(),
+        )
+        .request(gen.subschema_for::<model::EnvironmentRequestBody>())
+        .created(),
+        Endpoint::new(
+            "get",
+            "/projects",
+            "projects",
+            "List the projects, with their quotas and current usage",
+            gen.subschema_for::<model::MultiProjectsResponseBody>(),
+        ),
+        Endpoint::new(
+            "post",
+            "/projects",
+            "createProject",
+            "Create a project",
+            gen.subschema_for::<model::SingleProjectResponseBody>(),
+        )
+        .request(gen.subschema_for::<model::ProjectRequestBody>())
+        .created(),
+        Endpoint::new(
+            "put",
+            "/projects/{id}/quota",
+            "updateProjectQuota",
+            "Update the quotas of a project",
+            gen.subschema_for::<model::SingleProjectResponseBody>(),
+        )
+        .request(gen.subschema_for::<model::QuotaRequestBody>()),
+        Endpoint::new(
+            "get",
+            "/audit",
+            "auditLog",
+            "List entries of the audit log, most recent first",
+            gen.subschema_for::<model::MultiAuditEntriesResponseBody>(),
+        )
+        .query(query_parameters::<model::AuditFilterBody>(gen)),
+        Endpoint::new(
+            "post",
+            "/manifests/diff",
+            "diffManifest",
+            "List the changes applying a manifest would make",
+            gen.subschema_for::<manifest::ManifestChangesResponseBody>(),
+        )
+        .request(gen.subschema_for::<ManifestBody>())
+        .query(query_parameters::<DiffQuery>(gen)),
+        Endpoint::new(
+            "post",
+            "/manifests/apply",
+            "applyManifest",
+            "Create the environments and indexes of a manifest, and recreate the \
+             environments whose configuration changed",
+            gen.subschema_for::<manifest::ManifestChangesResponseBody>(),
+        )
+        .request(gen.subschema_for::<ManifestBody>()),
+        Endpoint::new(
+            "post",
+            "/manifests/prune",
+            "pruneManifest",
+            "Delete the environments and indexes a manifest does not describe",
+            gen.subschema_for::<manifest::ManifestChangesResponseBody>(),
+        )
+        .request(gen.subschema_for::<ManifestBody>()),
+    ]
+}
+
+/// The OpenAPI document describing the REST API, with the schemas of its bodies
+/// generated from their types.
+pub fn openapi() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let endpoints = endpoints(&mut gen);
+    let error = gen.subschema_for::<ErrorBody>();
+
+    let mut paths = serde_json::Map::new();
+    for endpoint in endpoints {
+        let mut parameters = path_parameters(endpoint.path);
+        parameters.extend(endpoint.query);
+
+        let mut responses = serde_json::Map::new();
+        responses.insert(
+            endpoint.code.as_u16().to_string(),
+            json!({
+                "description": endpoint.code.canonical_reason().unwrap_or_default(),
+                "content": { "application/json": { "schema": endpoint.response } },
+            }),
+        );
+        responses.insert(
+            String::from("default"),
+            json!({
+                "description": "The operation failed",
+                "content": { "application/json": { "schema": &error } },
+            }),
+        );
+
+        let mut operation = json!({
+            "operationId": endpoint.operation,
+            "summary": endpoint.summary,
+            "parameters": parameters,
+            "responses": responses,
+        });
+        if let Some(request) = endpoint.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } },
+            });
+        }
+
+        paths.entry(endpoint.path).or_insert_with(|| json!({}))[endpoint.method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Nidavellir",
+            "description": "Provisions twerg environments, and the indexes they serve",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "A JWT, or an API token",
+                },
+            },
+        },
+        "security": [{ "bearer": [] }],
+    })
+}
+
+/// The parameters in braces in the path of an endpoint. Services are given by name,
+/// everything else by id.
+fn path_parameters(path: &str) -> Vec<Value> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            let schema = match name {
+                "service" => json!({ "type": "string" }),
+                _ => json!({ "type": "string", "format": "uuid" }),
+            };
+            json!({ "name": name, "in": "path", "required": true, "schema": schema })
+        })
+        .collect()
+}
+
+/// The query parameters given by the fields of a type.
+fn query_parameters<T: JsonSchema>(gen: &mut SchemaGenerator) -> Vec<Value> {
+    let ObjectValidation {
+        properties,
+        required,
+        ..
+    } = *gen.root_schema_for::<T>().schema.object.unwrap_or_default();
+    properties
+        .into_iter()
+        .map(|(name, schema)| {
+            json!({
+                "name": &name,
+                "in": "query",
+                "required": required.contains(&name),
+                "schema": schema,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,158 @@
+//! Audit log of mutations and container operations: who did what, to which
+//! environment or index, how it went and how long it took. Operations are traced in
+//! spans, and container operations are also timed in the metrics.
+
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use serde::Serialize;
+use slog::warn;
+use snafu::ResultExt;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::db::model::{AuditKind, AuditOutcome, InputAuditEntity, ProvideData};
+use crate::error;
+use crate::state::State;
+use crate::telemetry;
+
+/// The actor of operations started by the service itself.
+pub const SYSTEM_ACTOR: &str = "nidavellir";
+
+/// Results which identify the environment or index an operation affected.
+pub trait Affects {
+    fn environment(&self) -> Option<Uuid> {
+        None
+    }
+
+    fn index(&self) -> Option<Uuid> {
+        None
+    }
+}
+
+impl Affects for () {}
+
+impl Affects for u16 {}
+
+impl Affects for Vec<String> {}
+
+/// An operation to be recorded in the audit log.
+pub struct Operation<'a> {
+    state: &'a State,
+    entry: InputAuditEntity,
+}
+
+impl<'a> Operation<'a> {
+    /// A GraphQL mutation, with its arguments.
+    pub fn mutation<A: Serialize>(
+        state: &'a State,
+        actor: &str,
+        name: &str,
+        arguments: &A,
+    ) -> Self {
+        Operation::new(state, actor, AuditKind::Mutation, name, arguments)
+    }
+
+    /// An operation on containers, networks or volumes, with its arguments.
+    pub fn docker<A: Serialize>(state: &'a State, actor: &str, name: &str, arguments: &A) -> Self {
+        Operation::new(state, actor, AuditKind::Docker, name, arguments)
+    }
+
+    fn new<A: Serialize>(
+        state: &'a State,
+        actor: &str,
+        kind: AuditKind,
+        name: &str,
+        arguments: &A,
+    ) -> Self {
+        Operation {
+            state,
+            entry: InputAuditEntity {
+                actor: String::from(actor),
+                kind,
+                operation: String::from(name),
+                arguments: serde_json::to_value(arguments).unwrap_or_default(),
+                environment: None,
+                index: None,
+                outcome: AuditOutcome::Success,
+                error: None,
+                duration: 0,
+            },
+        }
+    }
+
+    /// The environment affected, when known before the operation is run.
+    pub fn environment(mut self, environment: Uuid) -> Self {
+        self.entry.environment = Some(environment);
+        self
+    }
+
+    /// Run the operation, and record its outcome. Failing to record it is logged,
+    /// and does not change the result of the operation.
+    pub async fn run<T, F>(self, operation: F) -> Result<T, error::Error>
+    where
+        T: Affects,
+        F: Future<Output = Result<T, error::Error>>,
+    {
+        let Operation { state, mut entry } = self;
+
+        let span = match entry.kind {
+            AuditKind::Mutation => format!("mutation {}", entry.operation),
+            AuditKind::Docker => format!("docker {}", entry.operation),
+        };
+
+        let start = Instant::now();
+        let result = telemetry::in_span(
+            SpanKind::Internal,
+            &span,
+            vec![KeyValue::new("actor", entry.actor.clone())],
+            operation,
+        )
+        .await;
+        let elapsed = start.elapsed();
+        entry.duration = i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX);
+
+        if entry.kind == AuditKind::Docker {
+            state
+                .metrics
+                .observe_docker(&entry.operation, result.is_ok(), elapsed);
+        }
+
+        match &result {
+            Ok(value) => {
+                entry.environment = entry.environment.or_else(|| value.environment());
+                entry.index = value.index();
+            }
+            Err(err) => {
+                entry.outcome = AuditOutcome::Failure;
+                entry.error = Some(err.to_string());
+            }
+        }
+
+        if let Err(err) = record(state, &entry).await {
+            warn!(
+                state.logger,
+                "Could not record {} by {} in the audit log: {}", entry.operation, entry.actor, err
+            );
+        }
+
+        result
+    }
+}
+
+async fn record(state: &State, entry: &InputAuditEntity) -> Result<(), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    tx.create_audit_entry(entry)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not create audit entry",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit create audit entry transaction.",
+    })
+}
@@ -0,0 +1,188 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use juniper::GraphQLEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+use std::fmt;
+
+use crate::error;
+use crate::settings::{ApiToken, Settings};
+
+/// The roles a caller can be granted. They are ordered, so that a role
+/// grants all the permissions of the roles below it.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, GraphQLEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can list and inspect environments and indexes.
+    Viewer,
+    /// Can create environments and indexes, and delete their own environments.
+    Operator,
+    /// Can do everything.
+    Admin,
+}
+
+/// The claims we expect in a JWT.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: Role,
+    pub exp: usize,
+}
+
+/// Who is making a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub role: Role,
+}
+
+impl Identity {
+    /// Returns true if this identity is allowed to act on a resource owned by `owner`
+    /// with the given role.
+    pub fn can_manage(&self, owner: &str) -> bool {
+        self.role >= Role::Admin || (self.role >= Role::Operator && self.subject == owner)
+    }
+}
+
+/// Validates the credentials found in the authorization header. Both JWT
+/// (HS256 or RS256) and static API tokens are accepted as bearer tokens.
+#[derive(Clone)]
+pub struct Authenticator {
+    key: DecodingKey<'static>,
+    validation: Validation,
+    tokens: Vec<KnownToken>,
+}
+
+/// An API token from the settings, with its decoded digest.
+#[derive(Clone)]
+struct KnownToken {
+    identity: Identity,
+    digest: Vec<u8>,
+}
+
+impl KnownToken {
+    fn new(token: &ApiToken) -> Result<Self, error::Error> {
+        let digest = hex::decode(&token.digest)
+            .ok()
+            .filter(|digest| digest.len() == Sha256::output_size())
+            .ok_or_else(|| error::Error::MiscError {
+                msg: format!(
+                    "The API token digest of {} is not a hex encoded SHA-256",
+                    token.subject
+                ),
+            })?;
+        Ok(KnownToken {
+            identity: Identity {
+                subject: token.subject.clone(),
+                role: token.role,
+            },
+            digest,
+        })
+    }
+}
+
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authenticator")
+            .field("algorithms", &self.validation.algorithms)
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+impl Authenticator {
+    pub fn new(settings: &Settings) -> Result<Self, error::Error> {
+        let auth = &settings.auth;
+        let (algorithm, key) = match auth.algorithm.as_str() {
+            "HS256" => {
+                let secret = auth.secret.as_ref().ok_or(error::Error::MiscError {
+                    msg: String::from(
+                        "HS256 authentication requires auth.secret (APP_AUTH__SECRET)",
+                    ),
+                })?;
+                (
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(secret.as_bytes()).into_static(),
+                )
+            }
+            "RS256" => {
+                let path = auth.public_key.as_ref().ok_or(error::Error::MiscError {
+                    msg: String::from(
+                        "RS256 authentication requires auth.public_key (APP_AUTH__PUBLIC_KEY)",
+                    ),
+                })?;
+                let pem = std::fs::read(path).context(error::IOError {
+                    msg: format!("Could not read public key at {}", path),
+                })?;
+                let key = DecodingKey::from_rsa_pem(&pem)
+                    .context(error::JWTError {
+                        msg: format!("Could not parse public key at {}", path),
+                    })?
+                    .into_static();
+                (Algorithm::RS256, key)
+            }
+            algorithm => {
+                return Err(error::Error::MiscError {
+                    msg: format!("Unsupported JWT algorithm {}", algorithm),
+                })
+            }
+        };
+
+        let tokens = auth
+            .tokens
+            .iter()
+            .map(KnownToken::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Authenticator {
+            key,
+            validation: Validation::new(algorithm),
+            tokens,
+        })
+    }
+
+    /// Returns the identity carried by the authorization header, if any.
+    /// Missing credentials yield `None`, invalid credentials an error.
+    pub fn authenticate(&self, header: Option<&str>) -> Result<Option<Identity>, error::Error> {
+        let header = match header {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let token = header
+            .strip_prefix("Bearer ")
+            .ok_or(error::Error::Unauthenticated {
+                msg: String::from("Expected a bearer token"),
+            })?
+            .trim();
+
+        // A JWT starts with a base64 encoded JSON header, which an API token does not.
+        if decode_header(token).is_ok() {
+            let data = decode::<Claims>(token, &self.key, &self.validation).map_err(|err| {
+                error::Error::Unauthenticated {
+                    msg: format!("Invalid JWT ({})", err),
+                }
+            })?;
+            Ok(Some(Identity {
+                subject: data.claims.sub,
+                role: data.claims.role,
+            }))
+        } else {
+            let digest = Sha256::digest(token.as_bytes());
+            self.tokens
+                .iter()
+                .find(|known| constant_time_eq(&known.digest, &digest))
+                .map(|known| Some(known.identity.clone()))
+                .ok_or(error::Error::Unauthenticated {
+                    msg: String::from("Unknown API token"),
+                })
+        }
+    }
+}
+
+/// Compare two digests in a time which does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,324 @@
+//! A cache of the source data of indexes, on the host. A source file is downloaded once
+//! for a data source, a region and an upstream version, and named by the digest of its
+//! content. The cache is mounted read-only in the services ingesting source data, and
+//! twergs are given the path of the file to ingest. Files which have not been used for
+//! a while are evicted, and then the least recently used ones, to bound the cache size.
+
+use chrono::{Duration, Utc};
+use reqwest::header::{HeaderMap, ETAG, LAST_MODIFIED};
+use sha2::{Digest, Sha256};
+use slog::{debug, error, info, warn, Logger};
+use snafu::ResultExt;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::db::model::{CachedArtifactEntity, InputCachedArtifactEntity, ProvideData};
+use crate::docker::volumes::{VolumeConfig, VolumeType};
+use crate::error;
+use crate::settings;
+use crate::state::State;
+
+/// Spawn a task which periodically evicts files from the cache, until the service
+/// shuts down.
+pub fn spawn_evictor(state: State) {
+    tokio::spawn(async move {
+        let period = std::time::Duration::from_secs(state.settings.cache.interval);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _in_flight = match state.shutdown.enter() {
+                Ok(guard) => guard,
+                Err(_) => break,
+            };
+            if let Err(err) = evict(&state).await {
+                error!(state.logger, "Cache eviction failed: {}", err);
+            }
+        }
+    });
+}
+
+/// The bind mount of the cache, in the containers of the services ingesting source data.
+pub fn volume(settings: &settings::Cache) -> VolumeConfig {
+    VolumeConfig {
+        typ: VolumeType::Bind,
+        source: settings.directory.clone(),
+        target: settings.target.clone(),
+        read_only: true,
+    }
+}
+
+/// The path of an artifact in the containers the cache is mounted in.
+pub fn target_path(settings: &settings::Cache, artifact: &CachedArtifactEntity) -> String {
+    format!(
+        "{}/{}",
+        settings.target.trim_end_matches('/'),
+        artifact.digest
+    )
+}
+
+/// The path of a cached file on the host.
+fn host_path(settings: &settings::Cache, digest: &str) -> PathBuf {
+    Path::new(&settings.directory).join(digest)
+}
+
+/// Return the artifact of a data source for a region, at its current upstream version,
+/// downloading it unless it is cached. Data sources without a URL in the settings are
+/// not cached.
+pub async fn fetch(
+    state: &State,
+    data_source: &str,
+    region: &str,
+    logger: &Logger,
+) -> Result<Option<CachedArtifactEntity>, error::Error> {
+    let settings = &state.settings.cache;
+    let url = match settings.sources.get(data_source) {
+        Some(url) => url.replace("{region}", region),
+        None => return Ok(None),
+    };
+
+    let client = reqwest::Client::new();
+    let head = client
+        .head(&url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .context(error::ReqwestError {
+            msg: format!("Could not check the version of {}", url),
+        })?;
+    let version = upstream_version(head.headers());
+
+    if let Some(version) = &version {
+        let mut tx = state.store.begin().await.context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+        let cached = tx
+            .find_cached_artifact(data_source, region, version)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not find cached artifact",
+            })?;
+
+        // The file may have been removed from the host behind our back.
+        let cached = cached.filter(|artifact| host_path(settings, &artifact.digest).exists());
+        if let Some(artifact) = cached {
+            let artifact =
+                tx.touch_cached_artifact(&artifact.id)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not touch cached artifact",
+                    })?;
+            tx.commit().await.context(error::DBProvideError {
+                msg: "could not commit transaction",
+            })?;
+            debug!(
+                logger,
+                "Using cached {} for {} {}", artifact.digest, data_source, region
+            );
+            return Ok(Some(artifact));
+        }
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit transaction",
+        })?;
+    }
+
+    info!(logger, "Downloading {} into the cache", url);
+    let (digest, size) = download(&client, &url, settings).await?;
+    // Without an upstream version, the content is its own version.
+    let version = version.unwrap_or_else(|| digest.clone());
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let artifact = tx
+        .create_cached_artifact(&InputCachedArtifactEntity {
+            data_source: String::from(data_source),
+            region: String::from(region),
+            version,
+            digest,
+            size,
+            url,
+        })
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not record cached artifact",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    Ok(Some(artifact))
+}
+
+/// The path, in twergs, of the source data of an index's first region, which twergs
+/// ingest. The data is downloaded into the cache if needed. When it cannot be, twergs
+/// download it themselves.
+pub async fn source_file(
+    state: &State,
+    data_source: &str,
+    regions: &[String],
+    logger: &Logger,
+) -> Option<String> {
+    let region = regions.first()?;
+    match fetch(state, data_source, region, logger).await {
+        Ok(artifact) => artifact.map(|artifact| target_path(&state.settings.cache, &artifact)),
+        Err(err) => {
+            warn!(
+                logger,
+                "Could not cache {} for {}: {}", data_source, region, err
+            );
+            None
+        }
+    }
+}
+
+/// The version of a source file, as given by its server.
+fn upstream_version(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(ETAG)
+        .or_else(|| headers.get(LAST_MODIFIED))
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Download a file into the cache, and return the digest naming it, with its size.
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    settings: &settings::Cache,
+) -> Result<(String, i64), error::Error> {
+    tokio::fs::create_dir_all(&settings.directory)
+        .await
+        .context(error::TokioIOError {
+            msg: format!("Could not create cache directory {}", settings.directory),
+        })?;
+
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .context(error::ReqwestError {
+            msg: format!("Could not download {}", url),
+        })?;
+
+    // The file is named by its digest once complete, so that a partial download is
+    // never used.
+    let partial = Path::new(&settings.directory).join(format!(".partial-{}", Uuid::new_v4()));
+    let written = async {
+        let mut file = tokio::fs::File::create(&partial)
+            .await
+            .context(error::TokioIOError {
+                msg: format!("Could not create {}", partial.display()),
+            })?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        while let Some(chunk) = response.chunk().await.context(error::ReqwestError {
+            msg: format!("Could not download {}", url),
+        })? {
+            hasher.update(&chunk);
+            size += chunk.len();
+            file.write_all(&chunk).await.context(error::TokioIOError {
+                msg: format!("Could not write {}", partial.display()),
+            })?;
+        }
+        file.sync_all().await.context(error::TokioIOError {
+            msg: format!("Could not write {}", partial.display()),
+        })?;
+        Ok::<_, error::Error>((hex::encode(hasher.finalize()), size))
+    }
+    .await;
+
+    let (digest, size) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(err);
+        }
+    };
+
+    tokio::fs::rename(&partial, host_path(settings, &digest))
+        .await
+        .context(error::TokioIOError {
+            msg: format!("Could not store {} in the cache", url),
+        })?;
+
+    Ok((digest, i64::try_from(size).unwrap_or(i64::MAX)))
+}
+
+/// Evict the artifacts which have not been used for `max_age`, and then the least
+/// recently used ones, until the cache fits in `max_size`. Return the evicted artifacts.
+pub async fn evict(state: &State) -> Result<Vec<CachedArtifactEntity>, error::Error> {
+    let settings = &state.settings.cache;
+    let max_age = i64::try_from(settings.max_age).unwrap_or(i64::MAX);
+    let unused_since = Utc::now() - Duration::seconds(max_age);
+    let max_size = settings.max_size.saturating_mul(1024 * 1024);
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let artifacts = tx
+        .get_all_cached_artifacts()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get cached artifacts",
+        })?;
+
+    let mut kept = HashSet::new();
+    let mut size = 0u64;
+    let mut evicted = Vec::new();
+    for artifact in artifacts {
+        // Artifacts sharing a file account for it once.
+        let added = if kept.contains(&artifact.digest) {
+            0
+        } else {
+            u64::try_from(artifact.size).unwrap_or_default()
+        };
+        if artifact.used_at < unused_since || size + added > max_size {
+            let deleted = tx.delete_cached_artifact(&artifact.id).await;
+            evicted.push(deleted.context(error::DBProvideError {
+                msg: "Could not delete cached artifact",
+            })?);
+        } else {
+            size += added;
+            kept.insert(artifact.digest);
+        }
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    for artifact in evicted.iter() {
+        if kept.contains(&artifact.digest) {
+            continue;
+        }
+        info!(
+            state.logger,
+            "Evicting {} {} version {} from the cache",
+            artifact.data_source,
+            artifact.region,
+            artifact.version
+        );
+        match tokio::fs::remove_file(host_path(settings, &artifact.digest)).await {
+            Ok(()) => {}
+            // Evicted artifacts may share a file.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(error::Error::TokioIOError {
+                    msg: format!("Could not remove {} from the cache", artifact.digest),
+                    source: err,
+                })
+            }
+        }
+    }
+
+    Ok(evicted)
+}
@@ -0,0 +1,98 @@
+//! A client of the REST API of a Nidavellir server.
+
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use nidavellir::error;
+
+/// The response body of a failed request.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    /// The URL of the REST API of the server, ending with /api/v1.
+    url: String,
+    /// A JWT, or an API token.
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(server: &str, token: Option<&str>) -> Self {
+        Client {
+            client: reqwest::Client::new(),
+            url: format!("{}/api/v1", server.trim_end_matches('/')),
+            token: token.map(String::from),
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, error::Error> {
+        self.send(self.request(Method::GET, path)).await
+    }
+
+    /// Get a resource, with query parameters.
+    pub async fn get_with<Q, T>(&self, path: &str, query: &Q) -> Result<T, error::Error>
+    where
+        Q: Serialize,
+        T: DeserializeOwned,
+    {
+        self.send(self.request(Method::GET, path).query(query))
+            .await
+    }
+
+    pub async fn post<B, T>(&self, path: &str, body: &B) -> Result<T, error::Error>
+    where
+        B: Serialize,
+        T: DeserializeOwned,
+    {
+        self.send(self.request(Method::POST, path).json(body)).await
+    }
+
+    pub async fn put<B, T>(&self, path: &str, body: &B) -> Result<T, error::Error>
+    where
+        B: Serialize,
+        T: DeserializeOwned,
+    {
+        self.send(self.request(Method::PUT, path).json(body)).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T, error::Error> {
+        self.send(self.request(Method::DELETE, path)).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self
+            .client
+            .request(method, &format!("{}/{}", self.url, path));
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    /// Send a request, and return its response body, or the error reported by the
+    /// server.
+    async fn send<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<T, error::Error> {
+        let response = builder.send().await.context(error::ReqwestError {
+            msg: format!("Could not reach {}", self.url),
+        })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return response.json().await.context(error::ReqwestError {
+                msg: String::from("Could not read the response"),
+            });
+        }
+
+        let msg = match response.json::<ErrorBody>().await {
+            Ok(body) => format!("{} ({})", body.error, status),
+            Err(_) => status.to_string(),
+        };
+        Err(error::Error::MiscError { msg })
+    }
+}
@@ -0,0 +1,476 @@
+//! Command line client of a Nidavellir server, to manage environments and indexes
+//! through the REST API rather than with GraphQL queries.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+use snafu::ResultExt;
+use uuid::Uuid;
+
+mod client;
+mod output;
+
+use client::Client;
+use nidavellir::api::manifest::ManifestChangesResponseBody;
+use nidavellir::api::model;
+use nidavellir::error;
+use output::Format;
+
+#[tokio::main]
+async fn main() {
+    let matches = app().get_matches();
+    if let Err(err) = run(&matches).await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("nidavellir")
+        .about("Manage the environments and indexes of a Nidavellir server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("server")
+                .value_name("URL")
+                .short("s")
+                .long("server")
+                .env("NIDAVELLIR_URL")
+                .default_value("http://localhost:7654")
+                .global(true)
+                .help("URL of the server"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .value_name("TOKEN")
+                .long("token")
+                .env("NIDAVELLIR_TOKEN")
+                .hide_env_values(true)
+                .global(true)
+                .help("JWT or API token authenticating the requests"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .value_name("FORMAT")
+                .short("o")
+                .long("output")
+                .possible_values(&["table", "json"])
+                .default_value("table")
+                .global(true)
+                .help("Output format"),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Manage environments")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List the environments"))
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show an environment, with its services and indexes")
+                        .arg(id("ENV", "Id of the environment")),
+                )
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create an environment")
+                        .arg(
+                            Arg::with_name("name")
+                                .value_name("NAME")
+                                .long("name")
+                                .required(true)
+                                .help("Name of the environment"),
+                        )
+                        .arg(
+                            Arg::with_name("project")
+                                .value_name("PROJECT")
+                                .long("project")
+                                .required(true)
+                                .help("Id of the project of the environment"),
+                        )
+                        .arg(
+                            Arg::with_name("ttl")
+                                .value_name("SECONDS")
+                                .long("ttl")
+                                .help("Time to live of the environment"),
+                        )
+                        .arg(
+                            Arg::with_name("template")
+                                .value_name("TEMPLATE")
+                                .long("template")
+                                .help("Twerg configuration template, as named in the settings"),
+                        )
+                        .arg(
+                            Arg::with_name("reuse")
+                                .long("reuse")
+                                .help("Return the identical running environment of that name"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("delete")
+                        .about("Delete an environment")
+                        .arg(id("ENV", "Id of the environment")),
+                )
+                .subcommand(
+                    SubCommand::with_name("logs")
+                        .about("Show the last lines logged by the services of an environment")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(
+                            Arg::with_name("service")
+                                .value_name("SERVICE")
+                                .long("service")
+                                .help("Only show the logs of this service"),
+                        )
+                        .arg(
+                            Arg::with_name("tail")
+                                .value_name("LINES")
+                                .long("tail")
+                                .help("Number of lines shown per service [default: 100]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Manage the indexes of environments")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the indexes of an environment")
+                        .arg(id("ENV", "Id of the environment")),
+                )
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create an index in an environment")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(
+                            Arg::with_name("type")
+                                .value_name("TYPE")
+                                .long("type")
+                                .required(true)
+                                .help("Type of the index, see index types"),
+                        )
+                        .arg(
+                            Arg::with_name("source")
+                                .value_name("SOURCE")
+                                .long("source")
+                                .required(true)
+                                .help("Data source of the index, see index types"),
+                        )
+                        .arg(
+                            Arg::with_name("region")
+                                .value_name("REGION")
+                                .long("region")
+                                .required(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Region covered by the index"),
+                        )
+                        .arg(
+                            Arg::with_name("reuse")
+                                .long("reuse")
+                                .help("Return an available index with the same signature"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("types")
+                        .about("List the index types, their data sources, and the regions"),
+                )
+                .subcommand(
+                    SubCommand::with_name("retry")
+                        .about("Submit an index whose build failed to its twerg again")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(id("INDEX", "Id of the index")),
+                )
+                .subcommand(
+                    SubCommand::with_name("schedule")
+                        .about("Refresh indexes on a cron schedule, in UTC, or stop")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(
+                            Arg::with_name("index")
+                                .value_name("INDEX")
+                                .long("index")
+                                .help("Only schedule this index [default: all the indexes]"),
+                        )
+                        .arg(
+                            Arg::with_name("cron")
+                                .value_name("CRON")
+                                .long("cron")
+                                .help("Cron expression, eg '0 3 * * 0' [default: never]"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("refresh")
+                        .about("Start a refresh of an index now, whatever its schedule")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(id("INDEX", "Id of the index")),
+                )
+                .subcommand(
+                    SubCommand::with_name("refreshes")
+                        .about("List the refreshes of an index, most recent first")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(id("INDEX", "Id of the index")),
+                )
+                .subcommand(
+                    SubCommand::with_name("versions")
+                        .about("List the versions an index can be rolled back to")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(id("INDEX", "Id of the index")),
+                )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Serve a previous version of an index again")
+                        .arg(id("ENV", "Id of the environment"))
+                        .arg(id("INDEX", "Id of the index"))
+                        .arg(
+                            Arg::with_name("version")
+                                .value_name("VERSION")
+                                .long("version")
+                                .help("Twerg id of the version [default: the most recent]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Inspect the source data of indexes cached by the server")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the cached artifacts, most recently used first"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("manifest")
+                .about("Converge environments to a YAML manifest")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("diff")
+                        .about("List the changes applying the manifest would make")
+                        .arg(id("FILE", "Path of the manifest"))
+                        .arg(
+                            Arg::with_name("prune")
+                                .long("prune")
+                                .help("Also list the deletions pruning the manifest would make"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("apply")
+                        .about("Create missing environments and indexes, and recreate changed ones")
+                        .arg(id("FILE", "Path of the manifest")),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Delete the environments and indexes absent from the manifest")
+                        .arg(id("FILE", "Path of the manifest")),
+                ),
+        )
+}
+
+/// A positional argument identifying a resource.
+fn id(name: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name).required(true).help(help)
+}
+
+async fn run(matches: &ArgMatches<'_>) -> Result<(), error::Error> {
+    // Global arguments are propagated to the subcommands, so they are read from the
+    // innermost one, wherever they were given.
+    let (command, action, args) = match matches.subcommand() {
+        (command, Some(matches)) => match matches.subcommand() {
+            (action, Some(args)) => (command, action, args),
+            _ => return Err(unrecognized()),
+        },
+        _ => return Err(unrecognized()),
+    };
+
+    let client = Client::new(
+        args.value_of("server").unwrap_or_default(),
+        args.value_of("token"),
+    );
+    let format = Format::new(args.value_of("output").unwrap_or("table"))?;
+
+    match (command, action) {
+        ("env", "list") => {
+            let body: model::MultiEnvironmentsResponseBody = client.get("environments").await?;
+            output::print(format, &body, |body| output::environments(&body.envs))
+        }
+        ("env", "show") => {
+            let id = uuid(args, "ENV")?;
+            let body: model::SingleEnvironmentResponseBody =
+                client.get(&format!("environments/{}", id)).await?;
+            output::print(format, &body, |body| {
+                body.env
+                    .as_ref()
+                    .map(output::environment)
+                    .unwrap_or_default()
+            })
+        }
+        ("env", "create") => {
+            let ttl = match args.value_of("ttl") {
+                Some(ttl) => Some(ttl.parse::<i32>().map_err(|_| error::Error::MiscError {
+                    msg: format!("Invalid ttl {}, expecting a number of seconds", ttl),
+                })?),
+                None => None,
+            };
+            let env = model::EnvironmentRequestBody {
+                name: String::from(args.value_of("name").unwrap_or_default()),
+                project: uuid(args, "project")?,
+                ttl,
+                expires_at: None,
+                reuse: Some(args.is_present("reuse")),
+                template: args.value_of("template").map(String::from),
+            };
+            let body: model::SingleEnvironmentResponseBody =
+                client.post("environments", &env).await?;
+            output::print(format, &body, |body| output::environments(&body.env))
+        }
+        ("env", "delete") => {
+            let id = uuid(args, "ENV")?;
+            let body: model::SingleEnvironmentResponseBody =
+                client.delete(&format!("environments/{}", id)).await?;
+            output::print(format, &body, |body| output::environments(&body.env))
+        }
+        ("env", "logs") => {
+            let id = uuid(args, "ENV")?;
+            let query = ["service", "tail"]
+                .iter()
+                .filter_map(|name| Some((*name, args.value_of(name)?)))
+                .collect::<Vec<_>>();
+            let body: model::EnvironmentLogsResponseBody = client
+                .get_with(&format!("environments/{}/logs", id), &query)
+                .await?;
+            output::print(format, &body, output::logs)
+        }
+        ("index", "list") => {
+            let id = uuid(args, "ENV")?;
+            let body: model::MultiIndexesResponseBody =
+                client.get(&format!("environments/{}/indexes", id)).await?;
+            output::print(format, &body, |body| output::indexes(&body.indexes))
+        }
+        ("index", "create") => {
+            let id = uuid(args, "ENV")?;
+            let index = json!({
+                "index_type": args.value_of("type"),
+                "data_source": args.value_of("source"),
+                "regions": args.values_of("region").map(|regions| regions.collect::<Vec<_>>()),
+                "reuse": args.is_present("reuse"),
+            });
+            let body: model::SingleIndexResponseBody = client
+                .post(&format!("environments/{}/indexes", id), &index)
+                .await?;
+            output::print(format, &body, |body| output::indexes(&body.index))
+        }
+        ("index", "types") => {
+            let body: model::IndexTypesResponseBody = client.get("index-types").await?;
+            output::print(format, &body, output::index_types)
+        }
+        ("index", "retry") => {
+            let id = uuid(args, "ENV")?;
+            let index = uuid(args, "INDEX")?;
+            let body: model::SingleIndexResponseBody = client
+                .post(
+                    &format!("environments/{}/indexes/{}/retry", id, index),
+                    &json!({}),
+                )
+                .await?;
+            output::print(format, &body, |body| output::indexes(&body.index))
+        }
+        ("index", "schedule") => {
+            let id = uuid(args, "ENV")?;
+            let index = match args.value_of("index") {
+                Some(_) => Some(uuid(args, "index")?),
+                None => None,
+            };
+            let schedule = json!({
+                "index": index,
+                "schedule": args.value_of("cron"),
+            });
+            let body: model::MultiIndexesResponseBody = client
+                .put(&format!("environments/{}/schedule", id), &schedule)
+                .await?;
+            output::print(format, &body, |body| output::indexes(&body.indexes))
+        }
+        ("index", "refresh") => {
+            let id = uuid(args, "ENV")?;
+            let index = uuid(args, "INDEX")?;
+            let body: model::SingleIndexRefreshResponseBody = client
+                .post(
+                    &format!("environments/{}/indexes/{}/refresh", id, index),
+                    &json!({}),
+                )
+                .await?;
+            output::print(format, &body, |body| output::refreshes(&body.refresh))
+        }
+        ("index", "refreshes") => {
+            let id = uuid(args, "ENV")?;
+            let index = uuid(args, "INDEX")?;
+            let body: model::MultiIndexRefreshesResponseBody = client
+                .get(&format!("environments/{}/indexes/{}/refreshes", id, index))
+                .await?;
+            output::print(format, &body, |body| output::refreshes(&body.refreshes))
+        }
+        ("index", "versions") => {
+            let id = uuid(args, "ENV")?;
+            let index = uuid(args, "INDEX")?;
+            let body: model::MultiIndexVersionsResponseBody = client
+                .get(&format!("environments/{}/indexes/{}/versions", id, index))
+                .await?;
+            output::print(format, &body, |body| output::versions(&body.versions))
+        }
+        ("index", "rollback") => {
+            let id = uuid(args, "ENV")?;
+            let index = uuid(args, "INDEX")?;
+            let version = match args.value_of("version") {
+                Some(id) => Some(id.parse::<i32>().map_err(|_| error::Error::MiscError {
+                    msg: format!("Invalid version {}", id),
+                })?),
+                None => None,
+            };
+            let body: model::SingleIndexResponseBody = client
+                .post(
+                    &format!("environments/{}/indexes/{}/rollback", id, index),
+                    &json!({ "version": version }),
+                )
+                .await?;
+            output::print(format, &body, |body| output::indexes(&body.index))
+        }
+        ("cache", "list") => {
+            let body: model::MultiCachedArtifactsResponseBody =
+                client.get("cache/artifacts").await?;
+            output::print(format, &body, |body| output::artifacts(&body.artifacts))
+        }
+        ("manifest", "diff") => {
+            let manifest = json!({ "manifest": read_manifest(args)? });
+            let path = if args.is_present("prune") {
+                "manifests/diff?prune=true"
+            } else {
+                "manifests/diff"
+            };
+            let body: ManifestChangesResponseBody = client.post(path, &manifest).await?;
+            output::print(format, &body, |body| output::changes(&body.changes))
+        }
+        ("manifest", action) => {
+            let manifest = json!({ "manifest": read_manifest(args)? });
+            let body: ManifestChangesResponseBody = client
+                .post(&format!("manifests/{}", action), &manifest)
+                .await?;
+            output::print(format, &body, |body| output::changes(&body.changes))
+        }
+        _ => Err(unrecognized()),
+    }
+}
+
+fn read_manifest(args: &ArgMatches) -> Result<String, error::Error> {
+    let path = args.value_of("FILE").unwrap_or_default();
+    std::fs::read_to_string(path).context(error::IOError {
+        msg: format!("Could not read manifest {}", path),
+    })
+}
+
+fn uuid(args: &ArgMatches, name: &str) -> Result<Uuid, error::Error> {
+    let value = args.value_of(name).unwrap_or_default();
+    Uuid::parse_str(value).map_err(|_| error::Error::MiscError {
+        msg: format!("Invalid id {}", value),
+    })
+}
+
+fn unrecognized() -> error::Error {
+    error::Error::MiscError {
+        msg: String::from("Unrecognized subcommand"),
+    }
+}
@@ -0,0 +1,259 @@
+//! Rendering of the responses of the server, as tables for people, or as JSON for
+//! scripts.
+
+use serde::Serialize;
+use snafu::ResultExt;
+use std::fmt;
+
+use nidavellir::api::manifest::ManifestChange;
+use nidavellir::api::model;
+use nidavellir::error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+impl Format {
+    pub fn new(name: &str) -> Result<Self, error::Error> {
+        match name {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(error::Error::MiscError {
+                msg: format!("Unknown output format {}", name),
+            }),
+        }
+    }
+}
+
+/// Print a response body, either as JSON, or as rendered by `text`.
+pub fn print<T, F>(format: Format, body: &T, text: F) -> Result<(), error::Error>
+where
+    T: Serialize,
+    F: FnOnce(&T) -> String,
+{
+    match format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(body).context(error::JSONError {
+                msg: String::from("Could not serialize response"),
+            })?;
+            println!("{}", json);
+        }
+        Format::Table => print!("{}", text(body)),
+    }
+    Ok(())
+}
+
+/// Rows of text, aligned in columns.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Table {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut widths = self.headers.iter().map(|h| h.len()).collect::<Vec<_>>();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let headers = self.headers.iter().map(|h| String::from(*h));
+        let lines = std::iter::once(headers.collect::<Vec<_>>()).chain(self.rows.iter().cloned());
+        for line in lines {
+            let cells = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn environments<'a>(envs: impl IntoIterator<Item = &'a model::Environment>) -> String {
+    let mut table = Table::new(vec![
+        "ID", "NAME", "PROJECT", "OWNER", "STATE", "PORT", "INDEXES", "EXPIRES",
+    ]);
+    for env in envs {
+        table.row(vec![
+            env.id.to_string(),
+            env.name.clone(),
+            env.project.to_string(),
+            env.owner.clone(),
+            format!("{:?}", env.state),
+            env.port.to_string(),
+            env.indexes.len().to_string(),
+            env.expires_at
+                .map(|expires_at| expires_at.to_rfc3339())
+                .unwrap_or_else(|| String::from("never")),
+        ]);
+    }
+    table.to_string()
+}
+
+/// An environment, followed by its services and its indexes.
+pub fn environment(env: &model::Environment) -> String {
+    let mut services = Table::new(vec!["SERVICE", "IMAGE", "DIGEST"]);
+    for service in &env.services {
+        services.row(vec![
+            service.name.clone(),
+            service.image.clone(),
+            service.digest.clone().unwrap_or_default(),
+        ]);
+    }
+
+    format!(
+        "{}\n{}\n{}",
+        environments(Some(env)),
+        services,
+        indexes(&env.indexes)
+    )
+}
+
+pub fn indexes<'a>(indexes: impl IntoIterator<Item = &'a model::Index>) -> String {
+    let mut table = Table::new(vec![
+        "ID", "TYPE", "SOURCE", "REGIONS", "STATUS", "UPDATED", "SCHEDULE",
+    ]);
+    for index in indexes {
+        table.row(vec![
+            index.id.to_string(),
+            index.index_type.clone(),
+            index.data_source.clone(),
+            index.regions.join(","),
+            format!("{:?}", index.status),
+            index.updated_at.to_rfc3339(),
+            index.schedule.clone().unwrap_or_default(),
+        ]);
+    }
+    table.to_string()
+}
+
+pub fn refreshes<'a>(refreshes: impl IntoIterator<Item = &'a model::IndexRefresh>) -> String {
+    let mut table = Table::new(vec![
+        "ID", "STARTED", "DURATION", "OUTCOME", "STATUS", "ERROR",
+    ]);
+    for refresh in refreshes {
+        table.row(vec![
+            refresh.id.to_string(),
+            refresh.started_at.to_rfc3339(),
+            refresh
+                .duration
+                .map(|duration| format!("{:.1}s", f64::from(duration) / 1000.0))
+                .unwrap_or_default(),
+            refresh
+                .outcome
+                .map(|outcome| format!("{:?}", outcome))
+                .unwrap_or_else(|| String::from("Running")),
+            refresh
+                .status
+                .as_ref()
+                .map(|status| format!("{:?}", status))
+                .unwrap_or_default(),
+            refresh.error.clone().unwrap_or_default(),
+        ]);
+    }
+    table.to_string()
+}
+
+pub fn versions(versions: &[model::IndexVersion]) -> String {
+    let mut table = Table::new(vec!["VERSION", "RETIRED"]);
+    for version in versions {
+        table.row(vec![
+            version.twerg_id.to_string(),
+            version.retired_at.to_rfc3339(),
+        ]);
+    }
+    table.to_string()
+}
+
+/// The index types, followed by the regions.
+pub fn index_types(body: &model::IndexTypesResponseBody) -> String {
+    let mut table = Table::new(vec!["TYPE", "SOURCES", "DESCRIPTION"]);
+    for index_type in &body.index_types {
+        table.row(vec![
+            index_type.name.clone(),
+            index_type.data_sources.join(","),
+            index_type.description.clone(),
+        ]);
+    }
+    format!("{}\nRegions: {}\n", table, body.regions.join(","))
+}
+
+/// The lines logged by services, each prefixed by its service.
+pub fn logs(logs: &model::EnvironmentLogsResponseBody) -> String {
+    let width = logs
+        .services
+        .iter()
+        .map(|service| service.service.len())
+        .max()
+        .unwrap_or_default();
+    logs.services
+        .iter()
+        .flat_map(|service| {
+            service
+                .lines
+                .iter()
+                .map(move |line| format!("{:width$} | {}\n", service.service, line, width = width))
+        })
+        .collect()
+}
+
+pub fn artifacts(artifacts: &[model::CachedArtifact]) -> String {
+    let mut table = Table::new(vec![
+        "SOURCE", "REGION", "VERSION", "DIGEST", "SIZE", "USED",
+    ]);
+    for artifact in artifacts {
+        table.row(vec![
+            artifact.data_source.clone(),
+            artifact.region.clone(),
+            artifact.version.clone(),
+            artifact.digest.chars().take(12).collect(),
+            format!("{:.1} MiB", artifact.size / 1024.0 / 1024.0),
+            artifact.used_at.to_rfc3339(),
+        ]);
+    }
+    table.to_string()
+}
+
+pub fn changes(changes: &[ManifestChange]) -> String {
+    if changes.is_empty() {
+        return String::from("No changes\n");
+    }
+    let mut table = Table::new(vec!["ACTION", "ENVIRONMENT", "INDEX"]);
+    for change in changes {
+        table.row(vec![
+            format!("{:?}", change.action),
+            change.environment.clone(),
+            change
+                .index
+                .as_ref()
+                .map(|index| {
+                    format!(
+                        "{} {} {}",
+                        index.index_type,
+                        index.data_source,
+                        index.regions.join(",")
+                    )
+                })
+                .unwrap_or_default(),
+        ]);
+    }
+    table.to_string()
+}
@@ -0,0 +1,822 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+use super::model::{self, ProvideError, ProvideResult};
+use super::{Store, Transaction};
+
+/// The content of a memory store.
+#[derive(Debug, Clone, Default)]
+struct Data {
+    environments: Vec<model::EnvironmentEntity>,
+    /// Indexes, with the environment they belong to.
+    indexes: Vec<(Uuid, model::IndexEntity)>,
+    snapshots: Vec<model::SnapshotEntity>,
+    projects: Vec<model::ProjectEntity>,
+    audit: Vec<model::AuditEntity>,
+    operations: Vec<model::OperationEntity>,
+    cached_artifacts: Vec<model::CachedArtifactEntity>,
+    index_refreshes: Vec<model::IndexRefreshEntity>,
+    index_versions: Vec<model::IndexVersionEntity>,
+    /// Environments whose owners have been warned of their expiry.
+    warned: HashSet<Uuid>,
+}
+
+/// A store kept in memory, for tests. A transaction works on a copy of the data,
+/// which replaces the store's data when it is committed. As with SQLite, transactions
+/// are serialized: the store is locked until the transaction ends, so that concurrent
+/// ones cannot overwrite each other's changes.
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    data: Arc<Mutex<Data>>,
+}
+
+impl MemoryStore {
+    /// A new store, with a default project, as in databases.
+    pub fn new() -> Self {
+        let now = Utc::now();
+        let data = Data {
+            projects: vec![model::ProjectEntity {
+                id: Uuid::new_v4(),
+                name: String::from("default"),
+                max_environments: None,
+                max_indexes: None,
+                max_memory: None,
+                created_at: now,
+                updated_at: now,
+            }],
+            ..Default::default()
+        };
+        MemoryStore {
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn begin(&self) -> ProvideResult<Box<dyn Transaction>> {
+        let store = self.data.clone().lock_owned().await;
+        let data = store.clone();
+        Ok(Box::new(MemoryTransaction { store, data }))
+    }
+
+    async fn close(&self) {}
+
+    async fn ping(&self) -> ProvideResult<()> {
+        Ok(())
+    }
+}
+
+pub struct MemoryTransaction {
+    store: OwnedMutexGuard<Data>,
+    data: Data,
+}
+
+#[async_trait]
+impl Transaction for MemoryTransaction {
+    async fn commit(self: Box<Self>) -> ProvideResult<()> {
+        let MemoryTransaction { mut store, data } = *self;
+        *store = data;
+        Ok(())
+    }
+}
+
+impl MemoryTransaction {
+    fn environment_mut(&mut self, id: &Uuid) -> ProvideResult<&mut model::EnvironmentEntity> {
+        self.data
+            .environments
+            .iter_mut()
+            .find(|environment| environment.id == *id)
+            .ok_or(ProvideError::NotFound)
+    }
+
+    fn project_mut(&mut self, id: &Uuid) -> ProvideResult<&mut model::ProjectEntity> {
+        self.data
+            .projects
+            .iter_mut()
+            .find(|project| project.id == *id)
+            .ok_or(ProvideError::NotFound)
+    }
+
+    fn index_mut(&mut self, id: &Uuid) -> ProvideResult<&mut model::IndexEntity> {
+        self.data
+            .indexes
+            .iter_mut()
+            .map(|(_, index)| index)
+            .find(|index| index.id == *id)
+            .ok_or(ProvideError::NotFound)
+    }
+
+    fn environments_where<P>(&self, predicate: P) -> Vec<model::EnvironmentEntity>
+    where
+        P: Fn(&model::EnvironmentEntity) -> bool,
+    {
+        self.data
+            .environments
+            .iter()
+            .filter(|environment| predicate(environment))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether the entry matches all the criteria of the filter, its limit aside.
+fn audit_matches(filter: &model::AuditFilterEntity, entry: &model::AuditEntity) -> bool {
+    filter
+        .actor
+        .as_ref()
+        .map_or(true, |actor| *actor == entry.actor)
+        && filter.kind.map_or(true, |kind| kind == entry.kind)
+        && filter
+            .operation
+            .as_ref()
+            .map_or(true, |operation| *operation == entry.operation)
+        && filter
+            .environment
+            .map_or(true, |id| Some(id) == entry.environment)
+        && filter.index.map_or(true, |id| Some(id) == entry.index)
+        && filter
+            .outcome
+            .map_or(true, |outcome| outcome == entry.outcome)
+        && filter.since.map_or(true, |since| entry.created_at >= since)
+        && filter.until.map_or(true, |until| entry.created_at < until)
+}
+
+#[async_trait]
+impl model::ProvideData for MemoryTransaction {
+    async fn get_all_environments(&mut self) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        let mut environments = self.environments_where(|_| true);
+        environments.sort_by_key(|environment| environment.created_at);
+        Ok(environments)
+    }
+
+    async fn get_environment_indexes(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexEntity>> {
+        Ok(self
+            .data
+            .indexes
+            .iter()
+            .filter(|(owner, _)| owner == environment)
+            .map(|(_, index)| index.clone())
+            .collect())
+    }
+
+    async fn create_environment(
+        &mut self,
+        environment: &model::InputEnvironmentEntity,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        if self
+            .data
+            .environments
+            .iter()
+            .any(|existing| existing.name == environment.name)
+        {
+            return Err(ProvideError::UniqueViolation {
+                details: format!("Key (name)=({}) already exists.", environment.name),
+            });
+        }
+        let now = Utc::now();
+        let entity = model::EnvironmentEntity {
+            id: Uuid::new_v4(),
+            name: environment.name.clone(),
+            signature: environment.signature.clone(),
+            port: environment.port,
+            owner: environment.owner.clone(),
+            project: environment.project,
+            memory: environment.memory,
+            expires_at: environment.expires_at,
+            state: model::EnvironmentState::Running,
+            services: environment.services.clone(),
+            config: environment.config.clone(),
+            indexes: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        self.data.environments.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn delete_environment(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        let position = self
+            .data
+            .environments
+            .iter()
+            .position(|existing| existing.id == *environment)
+            .ok_or(ProvideError::NotFound)?;
+        let entity = self.data.environments.remove(position);
+        self.data.indexes.retain(|(owner, _)| owner != environment);
+        let indexes: HashSet<Uuid> = self
+            .data
+            .indexes
+            .iter()
+            .map(|(_, index)| index.id)
+            .collect();
+        self.data
+            .index_refreshes
+            .retain(|refresh| indexes.contains(&refresh.index));
+        self.data
+            .index_versions
+            .retain(|version| indexes.contains(&version.index));
+        self.data.warned.remove(environment);
+        for snapshot in self.data.snapshots.iter_mut() {
+            if snapshot.environment == Some(*environment) {
+                snapshot.environment = None;
+            }
+        }
+        Ok(entity)
+    }
+
+    async fn create_index(
+        &mut self,
+        index: &model::InputIndexEntity,
+    ) -> ProvideResult<model::IndexEntity> {
+        self.environment_mut(&index.environment)?;
+        let now = Utc::now();
+        let entity = model::IndexEntity {
+            id: Uuid::new_v4(),
+            index_type: index.index_type.clone(),
+            data_source: index.data_source.clone(),
+            regions: index.regions.clone(),
+            signature: index.signature.clone(),
+            status: model::IndexStatus::NotAvailable,
+            created_at: now,
+            updated_at: now,
+            twerg_id: index.twerg_id,
+            schedule: None,
+            next_refresh_at: None,
+        };
+        self.data.indexes.push((index.environment, entity.clone()));
+        Ok(entity)
+    }
+
+    async fn copy_environment_indexes(
+        &mut self,
+        source: &Uuid,
+        target: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexEntity>> {
+        let now = Utc::now();
+        let copies: Vec<model::IndexEntity> = self
+            .get_environment_indexes(source)
+            .await?
+            .into_iter()
+            .map(|index| model::IndexEntity {
+                id: Uuid::new_v4(),
+                created_at: now,
+                updated_at: now,
+                ..index
+            })
+            .collect();
+        self.data
+            .indexes
+            .extend(copies.iter().map(|index| (*target, index.clone())));
+        Ok(copies)
+    }
+
+    async fn delete_index(
+        &mut self,
+        environment: &Uuid,
+        index: &Uuid,
+    ) -> ProvideResult<model::IndexEntity> {
+        let position = self
+            .data
+            .indexes
+            .iter()
+            .position(|(owner, existing)| owner == environment && existing.id == *index)
+            .ok_or(ProvideError::NotFound)?;
+        let (_, entity) = self.data.indexes.remove(position);
+        self.data
+            .index_refreshes
+            .retain(|refresh| refresh.index != entity.id);
+        self.data
+            .index_versions
+            .retain(|version| version.index != entity.id);
+        Ok(entity)
+    }
+
+    async fn schedule_index(
+        &mut self,
+        index: &Uuid,
+        schedule: Option<&str>,
+        next_refresh_at: Option<&DateTime<Utc>>,
+    ) -> ProvideResult<model::IndexEntity> {
+        let entity = self.index_mut(index)?;
+        entity.schedule = schedule.map(String::from);
+        entity.next_refresh_at = next_refresh_at.cloned();
+        Ok(entity.clone())
+    }
+
+    async fn swap_index(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+        status: &model::IndexStatus,
+    ) -> ProvideResult<model::IndexEntity> {
+        let entity = self.index_mut(index)?;
+        entity.twerg_id = Some(twerg_id);
+        entity.status = status.clone();
+        entity.updated_at = Utc::now();
+        Ok(entity.clone())
+    }
+
+    async fn get_due_indexes(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<model::ScheduledIndexEntity>> {
+        let mut indexes: Vec<model::ScheduledIndexEntity> = self
+            .data
+            .indexes
+            .iter()
+            .filter_map(
+                |(environment, index)| match (&index.schedule, index.next_refresh_at) {
+                    (Some(schedule), Some(next_refresh_at)) if next_refresh_at <= *before => {
+                        Some(model::ScheduledIndexEntity {
+                            environment: *environment,
+                            id: index.id,
+                            schedule: schedule.clone(),
+                            next_refresh_at,
+                        })
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+        indexes.sort_by_key(|index| index.next_refresh_at);
+        Ok(indexes)
+    }
+
+    async fn get_environment_by_id(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        self.environment_mut(environment)
+            .map(|entity| entity.clone())
+    }
+
+    async fn extend_environment(
+        &mut self,
+        environment: &Uuid,
+        expires_at: &DateTime<Utc>,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        self.data.warned.remove(environment);
+        let entity = self.environment_mut(environment)?;
+        entity.expires_at = Some(*expires_at);
+        entity.updated_at = Utc::now();
+        Ok(entity.clone())
+    }
+
+    async fn get_expiring_environments(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        let warned = self.data.warned.clone();
+        let mut environments = self.environments_where(|environment| {
+            environment.expires_at.map_or(false, |at| at <= *before)
+                && !warned.contains(&environment.id)
+        });
+        environments.sort_by_key(|environment| environment.expires_at);
+        Ok(environments)
+    }
+
+    async fn get_expired_environments(&mut self) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        let now = Utc::now();
+        let mut environments = self
+            .environments_where(|environment| environment.expires_at.map_or(false, |at| at <= now));
+        environments.sort_by_key(|environment| environment.expires_at);
+        Ok(environments)
+    }
+
+    async fn mark_environment_warned(&mut self, environment: &Uuid) -> ProvideResult<()> {
+        self.data.warned.insert(*environment);
+        Ok(())
+    }
+
+    async fn update_environment_state(
+        &mut self,
+        environment: &Uuid,
+        state: &model::EnvironmentState,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        let entity = self.environment_mut(environment)?;
+        entity.state = *state;
+        entity.updated_at = Utc::now();
+        Ok(entity.clone())
+    }
+
+    async fn create_snapshot(
+        &mut self,
+        snapshot: &model::InputSnapshotEntity,
+    ) -> ProvideResult<model::SnapshotEntity> {
+        let entity = model::SnapshotEntity {
+            id: snapshot.id,
+            name: snapshot.name.clone(),
+            environment: Some(snapshot.environment),
+            environment_name: snapshot.environment_name.clone(),
+            path: snapshot.path.clone(),
+            volumes: snapshot.volumes.clone(),
+            created_at: Utc::now(),
+        };
+        self.data.snapshots.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn get_all_snapshots(&mut self) -> ProvideResult<Vec<model::SnapshotEntity>> {
+        let mut snapshots = self.data.snapshots.clone();
+        snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(snapshots)
+    }
+
+    async fn get_snapshot_by_id(
+        &mut self,
+        snapshot: &Uuid,
+    ) -> ProvideResult<model::SnapshotEntity> {
+        self.data
+            .snapshots
+            .iter()
+            .find(|existing| existing.id == *snapshot)
+            .cloned()
+            .ok_or(ProvideError::NotFound)
+    }
+
+    async fn get_all_projects(&mut self) -> ProvideResult<Vec<model::ProjectEntity>> {
+        let mut projects = self.data.projects.clone();
+        projects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(projects)
+    }
+
+    async fn get_project_by_id(&mut self, project: &Uuid) -> ProvideResult<model::ProjectEntity> {
+        self.project_mut(project).map(|entity| entity.clone())
+    }
+
+    async fn lock_project(&mut self, project: &Uuid) -> ProvideResult<model::ProjectEntity> {
+        self.get_project_by_id(project).await
+    }
+
+    async fn create_project(
+        &mut self,
+        project: &model::InputProjectEntity,
+    ) -> ProvideResult<model::ProjectEntity> {
+        if self
+            .data
+            .projects
+            .iter()
+            .any(|existing| existing.name == project.name)
+        {
+            return Err(ProvideError::UniqueViolation {
+                details: format!("Key (name)=({}) already exists.", project.name),
+            });
+        }
+        let now = Utc::now();
+        let entity = model::ProjectEntity {
+            id: Uuid::new_v4(),
+            name: project.name.clone(),
+            max_environments: project.quota.max_environments,
+            max_indexes: project.quota.max_indexes,
+            max_memory: project.quota.max_memory,
+            created_at: now,
+            updated_at: now,
+        };
+        self.data.projects.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn update_project_quota(
+        &mut self,
+        project: &Uuid,
+        quota: &model::QuotaEntity,
+    ) -> ProvideResult<model::ProjectEntity> {
+        let entity = self.project_mut(project)?;
+        entity.max_environments = quota.max_environments;
+        entity.max_indexes = quota.max_indexes;
+        entity.max_memory = quota.max_memory;
+        entity.updated_at = Utc::now();
+        Ok(entity.clone())
+    }
+
+    async fn get_project_usage(&mut self, project: &Uuid) -> ProvideResult<model::UsageEntity> {
+        let environments = self.environments_where(|environment| environment.project == *project);
+        let ids: HashSet<Uuid> = environments
+            .iter()
+            .map(|environment| environment.id)
+            .collect();
+        let indexes = self
+            .data
+            .indexes
+            .iter()
+            .filter(|(owner, _)| ids.contains(owner))
+            .count();
+        Ok(model::UsageEntity {
+            environments: environments.len() as i32,
+            indexes: indexes as i32,
+            memory: environments
+                .iter()
+                .map(|environment| environment.memory)
+                .sum(),
+        })
+    }
+
+    async fn create_audit_entry(
+        &mut self,
+        entry: &model::InputAuditEntity,
+    ) -> ProvideResult<model::AuditEntity> {
+        let entity = model::AuditEntity {
+            id: Uuid::new_v4(),
+            actor: entry.actor.clone(),
+            kind: entry.kind,
+            operation: entry.operation.clone(),
+            arguments: entry.arguments.clone(),
+            environment: entry.environment,
+            index: entry.index,
+            outcome: entry.outcome,
+            error: entry.error.clone(),
+            duration: entry.duration,
+            created_at: Utc::now(),
+        };
+        self.data.audit.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn get_audit_entries(
+        &mut self,
+        filter: &model::AuditFilterEntity,
+    ) -> ProvideResult<Vec<model::AuditEntity>> {
+        let limit = usize::try_from(filter.limit).unwrap_or(0);
+        Ok(self
+            .data
+            .audit
+            .iter()
+            .rev()
+            .filter(|entry| audit_matches(filter, entry))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_operation(
+        &mut self,
+        operation: &model::InputOperationEntity,
+    ) -> ProvideResult<model::OperationEntity> {
+        let entity = model::OperationEntity {
+            id: Uuid::new_v4(),
+            kind: operation.kind,
+            actor: operation.actor.clone(),
+            name: operation.name.clone(),
+            arguments: operation.arguments.clone(),
+            started_at: Utc::now(),
+        };
+        self.data.operations.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn update_operation(
+        &mut self,
+        operation: &Uuid,
+        arguments: &serde_json::Value,
+    ) -> ProvideResult<model::OperationEntity> {
+        let entity = self
+            .data
+            .operations
+            .iter_mut()
+            .find(|existing| existing.id == *operation)
+            .ok_or(ProvideError::NotFound)?;
+        entity.arguments = arguments.clone();
+        Ok(entity.clone())
+    }
+
+    async fn delete_operation(
+        &mut self,
+        operation: &Uuid,
+    ) -> ProvideResult<model::OperationEntity> {
+        let position = self
+            .data
+            .operations
+            .iter()
+            .position(|existing| existing.id == *operation)
+            .ok_or(ProvideError::NotFound)?;
+        Ok(self.data.operations.remove(position))
+    }
+
+    async fn get_all_operations(&mut self) -> ProvideResult<Vec<model::OperationEntity>> {
+        Ok(self.data.operations.clone())
+    }
+
+    async fn get_all_cached_artifacts(
+        &mut self,
+    ) -> ProvideResult<Vec<model::CachedArtifactEntity>> {
+        let mut artifacts = self.data.cached_artifacts.clone();
+        artifacts.sort_by(|a, b| b.used_at.cmp(&a.used_at));
+        Ok(artifacts)
+    }
+
+    async fn find_cached_artifact(
+        &mut self,
+        data_source: &str,
+        region: &str,
+        version: &str,
+    ) -> ProvideResult<Option<model::CachedArtifactEntity>> {
+        Ok(self
+            .data
+            .cached_artifacts
+            .iter()
+            .find(|artifact| {
+                artifact.data_source == data_source
+                    && artifact.region == region
+                    && artifact.version == version
+            })
+            .cloned())
+    }
+
+    async fn create_cached_artifact(
+        &mut self,
+        artifact: &model::InputCachedArtifactEntity,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        let now = Utc::now();
+        let existing = self.data.cached_artifacts.iter_mut().find(|existing| {
+            existing.data_source == artifact.data_source
+                && existing.region == artifact.region
+                && existing.version == artifact.version
+        });
+        if let Some(existing) = existing {
+            existing.digest = artifact.digest.clone();
+            existing.size = artifact.size;
+            existing.url = artifact.url.clone();
+            existing.used_at = now;
+            return Ok(existing.clone());
+        }
+
+        let entity = model::CachedArtifactEntity {
+            id: Uuid::new_v4(),
+            data_source: artifact.data_source.clone(),
+            region: artifact.region.clone(),
+            version: artifact.version.clone(),
+            digest: artifact.digest.clone(),
+            size: artifact.size,
+            url: artifact.url.clone(),
+            created_at: now,
+            used_at: now,
+        };
+        self.data.cached_artifacts.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn touch_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        let existing = self
+            .data
+            .cached_artifacts
+            .iter_mut()
+            .find(|existing| existing.id == *artifact)
+            .ok_or(ProvideError::NotFound)?;
+        existing.used_at = Utc::now();
+        Ok(existing.clone())
+    }
+
+    async fn delete_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        let position = self
+            .data
+            .cached_artifacts
+            .iter()
+            .position(|existing| existing.id == *artifact)
+            .ok_or(ProvideError::NotFound)?;
+        Ok(self.data.cached_artifacts.remove(position))
+    }
+
+    async fn get_index_refreshes(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let mut refreshes: Vec<model::IndexRefreshEntity> = self
+            .data
+            .index_refreshes
+            .iter()
+            .filter(|refresh| refresh.index == *index)
+            .cloned()
+            .collect();
+        refreshes.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        Ok(refreshes)
+    }
+
+    async fn create_index_refresh(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<model::IndexRefreshEntity> {
+        self.index_mut(index)?;
+        let entity = model::IndexRefreshEntity {
+            id: Uuid::new_v4(),
+            index: *index,
+            twerg_id: None,
+            status: None,
+            outcome: None,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+        self.data.index_refreshes.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn finish_index_refresh(
+        &mut self,
+        refresh: &Uuid,
+        finished: &model::FinishedIndexRefreshEntity,
+    ) -> ProvideResult<model::IndexRefreshEntity> {
+        let existing = self
+            .data
+            .index_refreshes
+            .iter_mut()
+            .find(|existing| existing.id == *refresh)
+            .ok_or(ProvideError::NotFound)?;
+        existing.twerg_id = finished.twerg_id;
+        existing.status = finished.status.clone();
+        existing.outcome = Some(finished.outcome);
+        existing.error = finished.error.clone();
+        existing.finished_at = Some(Utc::now());
+        Ok(existing.clone())
+    }
+
+    async fn abandon_index_refreshes(
+        &mut self,
+        error: &str,
+    ) -> ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let now = Utc::now();
+        let abandoned = self
+            .data
+            .index_refreshes
+            .iter_mut()
+            .filter(|refresh| refresh.finished_at.is_none())
+            .map(|refresh| {
+                refresh.outcome = Some(model::AuditOutcome::Failure);
+                refresh.error = Some(String::from(error));
+                refresh.finished_at = Some(now);
+                refresh.clone()
+            })
+            .collect();
+        Ok(abandoned)
+    }
+
+    async fn get_index_versions(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexVersionEntity>> {
+        let mut versions: Vec<model::IndexVersionEntity> = self
+            .data
+            .index_versions
+            .iter()
+            .filter(|version| version.index == *index)
+            .cloned()
+            .collect();
+        versions.sort_by(|a, b| b.retired_at.cmp(&a.retired_at));
+        Ok(versions)
+    }
+
+    async fn create_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<model::IndexVersionEntity> {
+        self.index_mut(index)?;
+        self.data
+            .index_versions
+            .retain(|version| !(version.index == *index && version.twerg_id == twerg_id));
+        let entity = model::IndexVersionEntity {
+            index: *index,
+            twerg_id,
+            retired_at: Utc::now(),
+        };
+        self.data.index_versions.push(entity.clone());
+        Ok(entity)
+    }
+
+    async fn delete_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<model::IndexVersionEntity> {
+        let position = self
+            .data
+            .index_versions
+            .iter()
+            .position(|version| version.index == *index && version.twerg_id == twerg_id)
+            .ok_or(ProvideError::NotFound)?;
+        Ok(self.data.index_versions.remove(position))
+    }
+}
@@ -1,10 +1,89 @@
 use async_trait::async_trait;
+use slog::Logger;
+use std::fmt;
+use std::sync::Arc;
 
+pub mod memory;
 pub mod model;
 pub mod pg;
+pub mod sqlite;
+pub mod traced;
 
+use crate::error;
+use model::{ProvideData, ProvideResult};
+
+/// Where environments, indexes, snapshots and projects are stored.
+#[async_trait]
+pub trait Store: fmt::Debug + Send + Sync {
+    /// Start a transaction. Its changes are visible to others once committed.
+    async fn begin(&self) -> ProvideResult<Box<dyn Transaction>>;
+
+    /// Close the connections to the store.
+    async fn close(&self);
+
+    /// Check the store answers queries.
+    async fn ping(&self) -> ProvideResult<()>;
+
+    /// The migrations of the store's schema, for stores whose schema is migrated
+    /// rather than created when they are opened.
+    async fn migrations(&self) -> ProvideResult<Option<Migrations>> {
+        Ok(None)
+    }
+
+    /// The connections of the store's pool, if it has one.
+    fn connections(&self) -> Option<Connections> {
+        None
+    }
+}
+
+/// The latest migration applied to a store, and the one expected by this version
+/// of the service.
+#[derive(Debug, Clone)]
+pub struct Migrations {
+    pub applied: Option<String>,
+    pub expected: String,
+}
+
+/// The usage of a pool of connections.
+#[derive(Debug, Clone, Copy)]
+pub struct Connections {
+    /// Connections opened, idle or in use
+    pub open: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+/// A transaction on a store, giving access to its data.
 #[async_trait]
-pub trait Db {
-    type Conn;
-    async fn conn(&self) -> Result<Self::Conn, sqlx::Error>;
+pub trait Transaction: ProvideData + Send {
+    async fn commit(self: Box<Self>) -> ProvideResult<()>;
+}
+
+/// Open the store at the given url. Its scheme selects the implementation:
+/// 'postgres://' for a Postgres database, 'sqlite://' for an SQLite file, and
+/// 'memory://' for a store kept in memory, lost when the process exits. Calls to the
+/// store are traced.
+pub async fn connect(url: &str, logger: &Logger) -> Result<Arc<dyn Store>, error::Error> {
+    let scheme = url.split(':').next().unwrap_or_default();
+    let store: Arc<dyn Store> = match scheme {
+        "postgres" | "postgresql" => Arc::new(pg::PgStore::connect(url, logger).await?),
+        "sqlite" => Arc::new(sqlite::SqliteStore::connect(url, logger).await?),
+        "memory" => Arc::new(memory::MemoryStore::new()),
+        _ => {
+            return Err(error::Error::MiscError {
+                msg: format!("Unsupported database url scheme '{}'", scheme),
+            })
+        }
+    };
+    Ok(Arc::new(traced::TracedStore::new(store)))
+}
+
+/// Prepare the store at the given url. Postgres databases are migrated, other stores
+/// create their schema when they are opened.
+pub async fn init(url: &str, logger: &Logger) -> Result<(), error::Error> {
+    if url.starts_with("postgres") {
+        pg::init_db(url, logger.clone()).await
+    } else {
+        connect(url, logger).await.map(|_| ())
+    }
 }
@@ -1,14 +1,16 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::convert::TryFrom;
 use uuid::Uuid;
 
 pub type EntityId = Uuid;
 
-#[derive(Debug, Clone, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(rename = "index_status")]
 #[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum IndexStatus {
     NotAvailable,
     DownloadingInProgress,
@@ -25,6 +27,80 @@ pub enum IndexStatus {
     Available,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename = "environment_state")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentState {
+    Running,
+    Stopped,
+    PartiallyRunning,
+}
+
+/// What an audit entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename = "audit_kind")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A GraphQL mutation
+    Mutation,
+    /// An operation on containers, networks or volumes
+    Docker,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename = "audit_outcome")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// What an operation in progress is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename = "operation_kind")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    /// Creating the containers of an environment, until it is recorded
+    ProvisionEnvironment,
+    /// Requesting an index from a twerg, until it is recorded
+    CreateIndex,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UlimitEntity {
+    pub name: String,
+    pub soft: i32,
+    pub hard: i32,
+}
+
+/// The resource limits applied to a service container.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LimitsEntity {
+    pub memory: Option<i32>,
+    pub memory_reservation: Option<i32>,
+    pub cpu_shares: Option<i32>,
+    pub cpu_quota: Option<i32>,
+    pub cpu_period: Option<i32>,
+    pub pids_limit: Option<i32>,
+    pub ulimits: Vec<UlimitEntity>,
+    pub restart_policy: Option<String>,
+    pub restart_max_retries: Option<i32>,
+}
+
+/// A service of an environment, stored as JSON alongside the environment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceEntity {
+    pub name: String,
+    pub image: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    pub limits: LimitsEntity,
+}
+
 /// An environment stored in the database
 #[derive(Debug, Clone)]
 pub struct EnvironmentEntity {
@@ -32,6 +108,14 @@ pub struct EnvironmentEntity {
     pub name: String,
     pub signature: String,
     pub port: i32,
+    pub owner: String,
+    pub project: EntityId,
+    pub memory: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub state: EnvironmentState,
+    pub services: Vec<ServiceEntity>,
+    /// The configuration the services were created from
+    pub config: serde_json::Value,
     pub indexes: Vec<IndexEntity>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -42,6 +126,13 @@ pub struct EnvironmentEntity {
 pub struct InputEnvironmentEntity {
     pub name: String,
     pub port: i32,
+    pub owner: String,
+    pub project: EntityId,
+    pub memory: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub services: Vec<ServiceEntity>,
+    pub config: serde_json::Value,
+    pub signature: String,
 }
 
 /// An index stored in the database
@@ -55,6 +146,11 @@ pub struct IndexEntity {
     pub status: IndexStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// The id of the index in the twerg, which serves requests
+    pub twerg_id: Option<i32>,
+    /// The cron schedule on which the index is refreshed, if any
+    pub schedule: Option<String>,
+    pub next_refresh_at: Option<DateTime<Utc>>,
 }
 
 /// The input data necessary to create an index.
@@ -64,6 +160,211 @@ pub struct InputIndexEntity {
     pub index_type: String,
     pub data_source: String,
     pub regions: Vec<String>,
+    pub signature: String,
+    pub twerg_id: Option<i32>,
+}
+
+/// An index with a schedule, due for a refresh.
+#[derive(Debug, Clone)]
+pub struct ScheduledIndexEntity {
+    pub environment: EntityId,
+    pub id: EntityId,
+    pub schedule: String,
+    pub next_refresh_at: DateTime<Utc>,
+}
+
+/// A refresh of an index, running until it is finished.
+#[derive(Debug, Clone)]
+pub struct IndexRefreshEntity {
+    pub id: EntityId,
+    pub index: EntityId,
+    /// The id of the index built in the twerg
+    pub twerg_id: Option<i32>,
+    /// The last status of the index built
+    pub status: Option<IndexStatus>,
+    pub outcome: Option<AuditOutcome>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// The input data necessary to finish a refresh.
+#[derive(Debug, Clone)]
+pub struct FinishedIndexRefreshEntity {
+    pub twerg_id: Option<i32>,
+    pub status: Option<IndexStatus>,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+}
+
+/// A version of an index which served requests before the current one, and which the
+/// index can be rolled back to.
+#[derive(Debug, Clone)]
+pub struct IndexVersionEntity {
+    pub index: EntityId,
+    /// The id of the version in the twerg
+    pub twerg_id: i32,
+    /// When the version stopped serving requests
+    pub retired_at: DateTime<Utc>,
+}
+
+/// A snapshot of the volumes of an environment, stored in the database.
+#[derive(Debug, Clone)]
+pub struct SnapshotEntity {
+    pub id: EntityId,
+    pub name: String,
+    /// The environment the snapshot was taken from, if it still exists
+    pub environment: Option<EntityId>,
+    pub environment_name: String,
+    /// The directory holding the volume archives
+    pub path: String,
+    pub volumes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The input data necessary to record a snapshot.
+#[derive(Debug, Clone)]
+pub struct InputSnapshotEntity {
+    pub id: EntityId,
+    pub name: String,
+    pub environment: EntityId,
+    pub environment_name: String,
+    pub path: String,
+    pub volumes: Vec<String>,
+}
+
+/// Source data cached on the host, as downloaded for a data source and a region, at an
+/// upstream version.
+#[derive(Debug, Clone)]
+pub struct CachedArtifactEntity {
+    pub id: EntityId,
+    pub data_source: String,
+    pub region: String,
+    pub version: String,
+    /// The hex encoded SHA-256 of the content, which names the cached file
+    pub digest: String,
+    /// In bytes
+    pub size: i64,
+    /// Where the content was downloaded from
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    pub used_at: DateTime<Utc>,
+}
+
+/// The input data necessary to record a cached artifact.
+#[derive(Debug, Clone)]
+pub struct InputCachedArtifactEntity {
+    pub data_source: String,
+    pub region: String,
+    pub version: String,
+    pub digest: String,
+    pub size: i64,
+    pub url: String,
+}
+
+/// A project stored in the database. A project owns environments, and
+/// its quotas bound the resources they use. A `None` quota is unlimited.
+#[derive(Debug, Clone)]
+pub struct ProjectEntity {
+    pub id: EntityId,
+    pub name: String,
+    pub max_environments: Option<i32>,
+    pub max_indexes: Option<i32>,
+    pub max_memory: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The quotas of a project.
+#[derive(Debug, Clone)]
+pub struct QuotaEntity {
+    pub max_environments: Option<i32>,
+    pub max_indexes: Option<i32>,
+    pub max_memory: Option<i32>,
+}
+
+/// The input data necessary to create a project.
+#[derive(Debug, Clone)]
+pub struct InputProjectEntity {
+    pub name: String,
+    pub quota: QuotaEntity,
+}
+
+/// What a project's environments currently use.
+#[derive(Debug, Clone)]
+pub struct UsageEntity {
+    pub environments: i32,
+    pub indexes: i32,
+    pub memory: i32,
+}
+
+/// An entry of the audit log. Entries are never modified.
+#[derive(Debug, Clone)]
+pub struct AuditEntity {
+    pub id: EntityId,
+    pub actor: String,
+    pub kind: AuditKind,
+    pub operation: String,
+    pub arguments: serde_json::Value,
+    /// The environment affected, which may since have been deleted
+    pub environment: Option<EntityId>,
+    /// The index affected, which may since have been deleted
+    pub index: Option<EntityId>,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+    /// In milliseconds
+    pub duration: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The input data necessary to record an audit entry.
+#[derive(Debug, Clone)]
+pub struct InputAuditEntity {
+    pub actor: String,
+    pub kind: AuditKind,
+    pub operation: String,
+    pub arguments: serde_json::Value,
+    pub environment: Option<EntityId>,
+    pub index: Option<EntityId>,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+    pub duration: i64,
+}
+
+/// Criteria selecting audit entries. A `None` criterion matches any entry.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilterEntity {
+    pub actor: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub operation: Option<String>,
+    pub environment: Option<EntityId>,
+    pub index: Option<EntityId>,
+    pub outcome: Option<AuditOutcome>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: i64,
+}
+
+/// An operation in progress, removed once it completes.
+#[derive(Debug, Clone)]
+pub struct OperationEntity {
+    pub id: EntityId,
+    pub kind: OperationKind,
+    pub actor: String,
+    /// The name of the environment the operation applies to
+    pub name: String,
+    /// The request, needed to resume the operation
+    pub arguments: serde_json::Value,
+    pub started_at: DateTime<Utc>,
+}
+
+/// The input data necessary to record an operation in progress.
+#[derive(Debug, Clone)]
+pub struct InputOperationEntity {
+    pub kind: OperationKind,
+    pub actor: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
 }
 
 // From sqlx realworld example
@@ -85,10 +386,189 @@ pub trait ProvideData {
 
     async fn create_index(&mut self, index: &InputIndexEntity) -> ProvideResult<IndexEntity>;
 
+    /// Copy the indexes of an environment to another one, keeping their status.
+    async fn copy_environment_indexes(
+        &mut self,
+        source: &Uuid,
+        target: &Uuid,
+    ) -> ProvideResult<Vec<IndexEntity>>;
+
+    /// Remove an index from the catalog of an environment.
+    async fn delete_index(
+        &mut self,
+        environment: &Uuid,
+        index: &Uuid,
+    ) -> ProvideResult<IndexEntity>;
+
+    /// Set the schedule of an index, and when it is next refreshed. Without a schedule,
+    /// the index is not refreshed.
+    async fn schedule_index(
+        &mut self,
+        index: &Uuid,
+        schedule: Option<&str>,
+        next_refresh_at: Option<&DateTime<Utc>>,
+    ) -> ProvideResult<IndexEntity>;
+
+    /// Replace the index served by the twerg with the one given.
+    async fn swap_index(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+        status: &IndexStatus,
+    ) -> ProvideResult<IndexEntity>;
+
+    /// Indexes with a schedule, due for a refresh before the given date, earliest first.
+    async fn get_due_indexes(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<ScheduledIndexEntity>>;
+
     async fn get_environment_by_id(
         &mut self,
         environment: &Uuid,
     ) -> ProvideResult<EnvironmentEntity>;
+
+    async fn extend_environment(
+        &mut self,
+        environment: &Uuid,
+        expires_at: &DateTime<Utc>,
+    ) -> ProvideResult<EnvironmentEntity>;
+
+    /// Environments expiring before the given date, whose owners have not been warned yet.
+    async fn get_expiring_environments(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<EnvironmentEntity>>;
+
+    async fn get_expired_environments(&mut self) -> ProvideResult<Vec<EnvironmentEntity>>;
+
+    async fn mark_environment_warned(&mut self, environment: &Uuid) -> ProvideResult<()>;
+
+    async fn update_environment_state(
+        &mut self,
+        environment: &Uuid,
+        state: &EnvironmentState,
+    ) -> ProvideResult<EnvironmentEntity>;
+
+    async fn create_snapshot(
+        &mut self,
+        snapshot: &InputSnapshotEntity,
+    ) -> ProvideResult<SnapshotEntity>;
+
+    async fn get_all_snapshots(&mut self) -> ProvideResult<Vec<SnapshotEntity>>;
+
+    async fn get_snapshot_by_id(&mut self, snapshot: &Uuid) -> ProvideResult<SnapshotEntity>;
+
+    async fn get_all_projects(&mut self) -> ProvideResult<Vec<ProjectEntity>>;
+
+    async fn get_project_by_id(&mut self, project: &Uuid) -> ProvideResult<ProjectEntity>;
+
+    /// Retrieve a project, locked until the end of the transaction, so that its usage
+    /// can be checked against its quota and then changed without a concurrent
+    /// transaction doing the same. Stores which serialize their transactions only
+    /// retrieve it.
+    async fn lock_project(&mut self, project: &Uuid) -> ProvideResult<ProjectEntity>;
+
+    async fn create_project(
+        &mut self,
+        project: &InputProjectEntity,
+    ) -> ProvideResult<ProjectEntity>;
+
+    async fn update_project_quota(
+        &mut self,
+        project: &Uuid,
+        quota: &QuotaEntity,
+    ) -> ProvideResult<ProjectEntity>;
+
+    async fn get_project_usage(&mut self, project: &Uuid) -> ProvideResult<UsageEntity>;
+
+    async fn create_audit_entry(&mut self, entry: &InputAuditEntity) -> ProvideResult<AuditEntity>;
+
+    /// Entries matching the filter, most recent first.
+    async fn get_audit_entries(
+        &mut self,
+        filter: &AuditFilterEntity,
+    ) -> ProvideResult<Vec<AuditEntity>>;
+
+    async fn create_operation(
+        &mut self,
+        operation: &InputOperationEntity,
+    ) -> ProvideResult<OperationEntity>;
+
+    /// Replace the arguments of an operation in progress, eg with what it created so
+    /// far, so that it is recovered from where it was interrupted.
+    async fn update_operation(
+        &mut self,
+        operation: &Uuid,
+        arguments: &serde_json::Value,
+    ) -> ProvideResult<OperationEntity>;
+
+    async fn delete_operation(&mut self, operation: &Uuid) -> ProvideResult<OperationEntity>;
+
+    /// Operations in progress, oldest first.
+    async fn get_all_operations(&mut self) -> ProvideResult<Vec<OperationEntity>>;
+
+    /// Cached artifacts, most recently used first.
+    async fn get_all_cached_artifacts(&mut self) -> ProvideResult<Vec<CachedArtifactEntity>>;
+
+    async fn find_cached_artifact(
+        &mut self,
+        data_source: &str,
+        region: &str,
+        version: &str,
+    ) -> ProvideResult<Option<CachedArtifactEntity>>;
+
+    /// Record an artifact, replacing the one of the same data source, region and
+    /// version, if any.
+    async fn create_cached_artifact(
+        &mut self,
+        artifact: &InputCachedArtifactEntity,
+    ) -> ProvideResult<CachedArtifactEntity>;
+
+    /// Record that an artifact was used, now.
+    async fn touch_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<CachedArtifactEntity>;
+
+    async fn delete_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<CachedArtifactEntity>;
+
+    /// The refreshes of an index, most recent first.
+    async fn get_index_refreshes(&mut self, index: &Uuid)
+        -> ProvideResult<Vec<IndexRefreshEntity>>;
+
+    async fn create_index_refresh(&mut self, index: &Uuid) -> ProvideResult<IndexRefreshEntity>;
+
+    async fn finish_index_refresh(
+        &mut self,
+        refresh: &Uuid,
+        finished: &FinishedIndexRefreshEntity,
+    ) -> ProvideResult<IndexRefreshEntity>;
+
+    /// Fail the refreshes which are not finished, and return them.
+    async fn abandon_index_refreshes(
+        &mut self,
+        error: &str,
+    ) -> ProvideResult<Vec<IndexRefreshEntity>>;
+
+    /// The previous versions of an index, most recently retired first.
+    async fn get_index_versions(&mut self, index: &Uuid) -> ProvideResult<Vec<IndexVersionEntity>>;
+
+    /// Keep a version of an index, retired now.
+    async fn create_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<IndexVersionEntity>;
+
+    async fn delete_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<IndexVersionEntity>;
 }
 
 pub type ProvideResult<T> = Result<T, ProvideError>;
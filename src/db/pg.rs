@@ -1,24 +1,27 @@
 use async_trait::async_trait;
-// use chrono::{DateTime, Utc};
+use chrono::{DateTime, Utc};
 use slog::{debug, info, o, Logger};
 use snafu::ResultExt;
 use sqlx::error::DatabaseError;
 use sqlx::pool::PoolConnection;
 use sqlx::postgres::{PgError, PgQueryAs, PgRow};
 use sqlx::row::{FromRow, Row};
-use sqlx::{PgConnection, PgPool};
+use sqlx::types::Json;
+use sqlx::{Connection, PgConnection, PgPool};
 use std::convert::TryFrom;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 
 use super::model;
-use super::Db;
+use super::{Connections, Migrations, Store, Transaction};
 use crate::error;
 
 /// The row here should match the information in the return_environment_type
 impl<'c> FromRow<'c, PgRow<'c>> for model::EnvironmentEntity {
     fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        let services: Json<Vec<model::ServiceEntity>> = row.get(11);
+        let config: Json<serde_json::Value> = row.get(12);
         Ok(model::EnvironmentEntity {
             id: row.get(0),
             name: row.get(1),
@@ -27,6 +30,106 @@ impl<'c> FromRow<'c, PgRow<'c>> for model::EnvironmentEntity {
             port: row.get(3),
             created_at: row.get(4),
             updated_at: row.get(5),
+            owner: row.get(6),
+            project: row.get(7),
+            memory: row.get(8),
+            expires_at: row.get(9),
+            state: row.get(10),
+            services: services.0,
+            config: config.0,
+        })
+    }
+}
+
+/// The row here should match the information in the return_snapshot_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::SnapshotEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::SnapshotEntity {
+            id: row.get(0),
+            name: row.get(1),
+            environment: row.get(2),
+            environment_name: row.get(3),
+            path: row.get(4),
+            volumes: row.get(5),
+            created_at: row.get(6),
+        })
+    }
+}
+
+/// The row here should match the information in the return_cached_artifact_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::CachedArtifactEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::CachedArtifactEntity {
+            id: row.get(0),
+            data_source: row.get(1),
+            region: row.get(2),
+            version: row.get(3),
+            digest: row.get(4),
+            size: row.get(5),
+            url: row.get(6),
+            created_at: row.get(7),
+            used_at: row.get(8),
+        })
+    }
+}
+
+/// The row here should match the information in the return_audit_entry_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::AuditEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        let arguments: Json<serde_json::Value> = row.get(4);
+        Ok(model::AuditEntity {
+            id: row.get(0),
+            actor: row.get(1),
+            kind: row.get(2),
+            operation: row.get(3),
+            arguments: arguments.0,
+            environment: row.get(5),
+            index: row.get(6),
+            outcome: row.get(7),
+            error: row.get(8),
+            duration: row.get(9),
+            created_at: row.get(10),
+        })
+    }
+}
+
+/// The row here should match the information in the return_operation_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::OperationEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        let arguments: Json<serde_json::Value> = row.get(4);
+        Ok(model::OperationEntity {
+            id: row.get(0),
+            kind: row.get(1),
+            actor: row.get(2),
+            name: row.get(3),
+            arguments: arguments.0,
+            started_at: row.get(5),
+        })
+    }
+}
+
+/// The row here should match the information in the return_project_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::ProjectEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::ProjectEntity {
+            id: row.get(0),
+            name: row.get(1),
+            max_environments: row.get(2),
+            max_indexes: row.get(3),
+            max_memory: row.get(4),
+            created_at: row.get(5),
+            updated_at: row.get(6),
+        })
+    }
+}
+
+/// The row here should match the information in the return_usage_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::UsageEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::UsageEntity {
+            environments: row.get(0),
+            indexes: row.get(1),
+            memory: row.get(2),
         })
     }
 }
@@ -43,14 +146,140 @@ impl<'c> FromRow<'c, PgRow<'c>> for model::IndexEntity {
             status: row.get(5),
             created_at: row.get(6),
             updated_at: row.get(7),
+            twerg_id: row.get(8),
+            schedule: row.get(9),
+            next_refresh_at: row.get(10),
+        })
+    }
+}
+
+/// The row here should match the information in the return_scheduled_index_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::ScheduledIndexEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::ScheduledIndexEntity {
+            environment: row.get(0),
+            id: row.get(1),
+            schedule: row.get(2),
+            next_refresh_at: row.get(3),
+        })
+    }
+}
+
+/// The row here should match the information in the return_index_refresh_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::IndexRefreshEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::IndexRefreshEntity {
+            id: row.get(0),
+            index: row.get(1),
+            twerg_id: row.get(2),
+            status: row.get(3),
+            outcome: row.get(4),
+            error: row.get(5),
+            started_at: row.get(6),
+            finished_at: row.get(7),
+        })
+    }
+}
+
+/// The row here should match the information in the return_index_version_type
+impl<'c> FromRow<'c, PgRow<'c>> for model::IndexVersionEntity {
+    fn from_row(row: &PgRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::IndexVersionEntity {
+            index: row.get(0),
+            twerg_id: row.get(1),
+            retired_at: row.get(2),
+        })
+    }
+}
+
+/// The latest migration in 'migrations', which the queries of this module rely on.
+/// It must be updated with each new migration.
+pub const SCHEMA_VERSION: &str = "2021-02-15-090000_operation_progress";
+
+/// A store in a Postgres database, with its schema managed by migrations.
+#[derive(Debug, Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub async fn connect(db_url: &str, logger: &Logger) -> Result<Self, error::Error> {
+        let pool = PgPool::builder()
+            .max_size(5)
+            .build(db_url)
+            .await
+            .context(error::DBError {
+                msg: format!("Could not connect to {}", db_url),
+            })?;
+
+        let row: (String,) = sqlx::query_as("SELECT version()")
+            .fetch_one(&pool)
+            .await
+            .context(error::DBError {
+                msg: format!("Could not test database version for {}", db_url),
+            })?;
+
+        info!(logger, "db version: {:?}", row.0);
+
+        Ok(PgStore { pool })
+    }
+}
+
+#[async_trait]
+impl Store for PgStore {
+    async fn begin(&self) -> model::ProvideResult<Box<dyn Transaction>> {
+        let conn = self.pool.acquire().await?;
+        let tx = conn.begin().await?;
+        Ok(Box::new(PgTransaction { tx }))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn ping(&self) -> model::ProvideResult<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn migrations(&self) -> model::ProvideResult<Option<Migrations>> {
+        // Migrations are applied by movine, which records them in its own table.
+        let applied: Option<(String,)> =
+            sqlx::query_as("SELECT name FROM movine_migrations ORDER BY name DESC LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(Some(Migrations {
+            applied: applied.map(|row| row.0),
+            expected: String::from(SCHEMA_VERSION),
+        }))
+    }
+
+    fn connections(&self) -> Option<Connections> {
+        Some(Connections {
+            open: self.pool.size(),
+            idle: u32::try_from(self.pool.idle()).unwrap_or(u32::MAX),
+            max: self.pool.max_size(),
         })
     }
 }
 
-/// Open a connection to a database
-pub async fn connect(db_url: &str) -> sqlx::Result<PgPool> {
-    let pool = PgPool::new(db_url).await?;
-    Ok(pool)
+pub struct PgTransaction {
+    tx: sqlx::Transaction<PoolConnection<PgConnection>>,
+}
+
+impl PgTransaction {
+    fn conn(&mut self) -> &mut PgConnection {
+        &mut self.tx
+    }
+}
+
+#[async_trait]
+impl Transaction for PgTransaction {
+    async fn commit(self: Box<Self>) -> model::ProvideResult<()> {
+        self.tx.commit().await?;
+        Ok(())
+    }
 }
 
 impl TryFrom<&PgError> for model::ProvideError {
@@ -77,22 +306,13 @@ impl TryFrom<&PgError> for model::ProvideError {
 }
 
 #[async_trait]
-impl Db for PgPool {
-    type Conn = PoolConnection<PgConnection>;
-
-    async fn conn(&self) -> Result<Self::Conn, sqlx::Error> {
-        self.acquire().await
-    }
-}
-
-#[async_trait]
-impl model::ProvideData for PgConnection {
+impl model::ProvideData for PgTransaction {
     async fn get_all_environments(
         &mut self,
     ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
         let environments: Vec<model::EnvironmentEntity> =
             sqlx::query_as(r#"SELECT * FROM list_environments()"#)
-                .fetch_all(self)
+                .fetch_all(self.conn())
                 .await?;
 
         Ok(environments)
@@ -105,7 +325,7 @@ impl model::ProvideData for PgConnection {
         let indexes: Vec<model::IndexEntity> =
             sqlx::query_as(r#"SELECT * FROM list_environment_indexes($1)"#)
                 .bind(environment)
-                .fetch_all(self)
+                .fetch_all(self.conn())
                 .await?;
 
         Ok(indexes)
@@ -116,10 +336,19 @@ impl model::ProvideData for PgConnection {
         env: &model::InputEnvironmentEntity,
     ) -> model::ProvideResult<model::EnvironmentEntity> {
         let environment: model::EnvironmentEntity =
-            sqlx::query_as("SELECT * FROM create_environment($1::TEXT, $2::INTEGER)")
-                .bind(&env.name)
-                .bind(&env.port)
-                .fetch_one(self)
+            sqlx::query_as(
+                "SELECT * FROM create_environment($1::TEXT, $2::INTEGER, $3::TEXT, $4::UUID, $5::INTEGER, $6::TIMESTAMPTZ, $7::JSONB, $8::JSONB, $9::TEXT)",
+            )
+            .bind(&env.name)
+            .bind(&env.port)
+            .bind(&env.owner)
+            .bind(&env.project)
+            .bind(&env.memory)
+            .bind(&env.expires_at)
+            .bind(Json(&env.services))
+            .bind(Json(&env.config))
+            .bind(&env.signature)
+                .fetch_one(self.conn())
                 .await?;
 
         Ok(environment)
@@ -132,7 +361,7 @@ impl model::ProvideData for PgConnection {
         let environment: model::EnvironmentEntity =
             sqlx::query_as("SELECT * FROM delete_environment($1::UUID)")
                 .bind(&env)
-                .fetch_one(self)
+                .fetch_one(self.conn())
                 .await?;
 
         Ok(environment)
@@ -141,18 +370,98 @@ impl model::ProvideData for PgConnection {
     async fn create_index(
         &mut self,
         index: &model::InputIndexEntity,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        let index: model::IndexEntity = sqlx::query_as(
+            "SELECT * FROM create_index($1::UUID, $2::TEXT, $3::TEXT, $4::TEXT[], $5::TEXT, $6::INTEGER)",
+        )
+        .bind(&index.environment)
+        .bind(&index.index_type)
+        .bind(&index.data_source)
+        .bind(&index.regions)
+        .bind(&index.signature)
+        .bind(&index.twerg_id)
+        .fetch_one(self.conn())
+        .await?;
+        Ok(index)
+    }
+
+    async fn copy_environment_indexes(
+        &mut self,
+        source: &model::EntityId,
+        target: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexEntity>> {
+        let indexes: Vec<model::IndexEntity> =
+            sqlx::query_as("SELECT * FROM copy_environment_indexes($1::UUID, $2::UUID)")
+                .bind(&source)
+                .bind(&target)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(indexes)
+    }
+
+    async fn delete_index(
+        &mut self,
+        environment: &model::EntityId,
+        index: &model::EntityId,
     ) -> model::ProvideResult<model::IndexEntity> {
         let index: model::IndexEntity =
-            sqlx::query_as("SELECT * FROM create_index($1::UUID, $2::TEXT, $3::TEXT, $4::TEXT[])")
-                .bind(&index.environment)
-                .bind(&index.index_type)
-                .bind(&index.data_source)
-                .bind(&index.regions)
-                .fetch_one(self)
+            sqlx::query_as("SELECT * FROM delete_index($1::UUID, $2::UUID)")
+                .bind(&environment)
+                .bind(&index)
+                .fetch_one(self.conn())
                 .await?;
+
+        Ok(index)
+    }
+
+    async fn schedule_index(
+        &mut self,
+        index: &model::EntityId,
+        schedule: Option<&str>,
+        next_refresh_at: Option<&DateTime<Utc>>,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        let index: model::IndexEntity =
+            sqlx::query_as("SELECT * FROM schedule_index($1::UUID, $2::TEXT, $3::TIMESTAMPTZ)")
+                .bind(&index)
+                .bind(schedule)
+                .bind(next_refresh_at.cloned())
+                .fetch_one(self.conn())
+                .await?;
+
         Ok(index)
     }
 
+    async fn swap_index(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+        status: &model::IndexStatus,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        let index: model::IndexEntity =
+            sqlx::query_as("SELECT * FROM swap_index($1::UUID, $2::INTEGER, $3::index_status)")
+                .bind(&index)
+                .bind(twerg_id)
+                .bind(status)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(index)
+    }
+
+    async fn get_due_indexes(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> model::ProvideResult<Vec<model::ScheduledIndexEntity>> {
+        let indexes: Vec<model::ScheduledIndexEntity> =
+            sqlx::query_as("SELECT * FROM list_due_indexes($1::TIMESTAMPTZ)")
+                .bind(before)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(indexes)
+    }
+
     async fn get_environment_by_id(
         &mut self,
         id: &model::EntityId,
@@ -160,11 +469,469 @@ impl model::ProvideData for PgConnection {
         let environment: model::EnvironmentEntity =
             sqlx::query_as("SELECT * FROM get_environment_by_id($1::UUID)")
                 .bind(&id)
-                .fetch_one(self)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(environment)
+    }
+
+    async fn extend_environment(
+        &mut self,
+        id: &model::EntityId,
+        expires_at: &DateTime<Utc>,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        let environment: model::EnvironmentEntity =
+            sqlx::query_as("SELECT * FROM extend_environment($1::UUID, $2::TIMESTAMPTZ)")
+                .bind(&id)
+                .bind(&expires_at)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(environment)
+    }
+
+    async fn get_expiring_environments(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
+        let environments: Vec<model::EnvironmentEntity> =
+            sqlx::query_as("SELECT * FROM list_expiring_environments($1::TIMESTAMPTZ)")
+                .bind(&before)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(environments)
+    }
+
+    async fn get_expired_environments(
+        &mut self,
+    ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
+        let environments: Vec<model::EnvironmentEntity> =
+            sqlx::query_as(r#"SELECT * FROM list_expired_environments()"#)
+                .fetch_all(self.conn())
                 .await?;
 
+        Ok(environments)
+    }
+
+    async fn mark_environment_warned(&mut self, id: &model::EntityId) -> model::ProvideResult<()> {
+        sqlx::query("SELECT mark_environment_warned($1::UUID)")
+            .bind(&id)
+            .execute(self.conn())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_environment_state(
+        &mut self,
+        id: &model::EntityId,
+        state: &model::EnvironmentState,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        let environment: model::EnvironmentEntity = sqlx::query_as(
+            "SELECT * FROM update_environment_state($1::UUID, $2::environment_state)",
+        )
+        .bind(&id)
+        .bind(state)
+        .fetch_one(self.conn())
+        .await?;
+
         Ok(environment)
     }
+
+    async fn create_snapshot(
+        &mut self,
+        snapshot: &model::InputSnapshotEntity,
+    ) -> model::ProvideResult<model::SnapshotEntity> {
+        let snapshot: model::SnapshotEntity = sqlx::query_as(
+            "SELECT * FROM create_snapshot($1::UUID, $2::TEXT, $3::UUID, $4::TEXT, $5::TEXT, $6::TEXT[])",
+        )
+        .bind(&snapshot.id)
+        .bind(&snapshot.name)
+        .bind(&snapshot.environment)
+        .bind(&snapshot.environment_name)
+        .bind(&snapshot.path)
+        .bind(&snapshot.volumes)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    async fn get_all_snapshots(&mut self) -> model::ProvideResult<Vec<model::SnapshotEntity>> {
+        let snapshots: Vec<model::SnapshotEntity> =
+            sqlx::query_as(r#"SELECT * FROM list_snapshots()"#)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(snapshots)
+    }
+
+    async fn get_snapshot_by_id(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::SnapshotEntity> {
+        let snapshot: model::SnapshotEntity =
+            sqlx::query_as("SELECT * FROM get_snapshot_by_id($1::UUID)")
+                .bind(&id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(snapshot)
+    }
+
+    async fn get_all_projects(&mut self) -> model::ProvideResult<Vec<model::ProjectEntity>> {
+        let projects: Vec<model::ProjectEntity> =
+            sqlx::query_as(r#"SELECT * FROM list_projects()"#)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(projects)
+    }
+
+    async fn get_project_by_id(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        let project: model::ProjectEntity =
+            sqlx::query_as("SELECT * FROM get_project_by_id($1::UUID)")
+                .bind(&id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(project)
+    }
+
+    async fn lock_project(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        let project: model::ProjectEntity = sqlx::query_as("SELECT * FROM lock_project($1::UUID)")
+            .bind(&id)
+            .fetch_one(self.conn())
+            .await?;
+
+        Ok(project)
+    }
+
+    async fn create_project(
+        &mut self,
+        project: &model::InputProjectEntity,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        let project: model::ProjectEntity = sqlx::query_as(
+            "SELECT * FROM create_project($1::TEXT, $2::INTEGER, $3::INTEGER, $4::INTEGER)",
+        )
+        .bind(&project.name)
+        .bind(&project.quota.max_environments)
+        .bind(&project.quota.max_indexes)
+        .bind(&project.quota.max_memory)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(project)
+    }
+
+    async fn update_project_quota(
+        &mut self,
+        id: &model::EntityId,
+        quota: &model::QuotaEntity,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        let project: model::ProjectEntity = sqlx::query_as(
+            "SELECT * FROM update_project_quota($1::UUID, $2::INTEGER, $3::INTEGER, $4::INTEGER)",
+        )
+        .bind(&id)
+        .bind(&quota.max_environments)
+        .bind(&quota.max_indexes)
+        .bind(&quota.max_memory)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(project)
+    }
+
+    async fn get_project_usage(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::UsageEntity> {
+        let usage: model::UsageEntity = sqlx::query_as("SELECT * FROM get_project_usage($1::UUID)")
+            .bind(&id)
+            .fetch_one(self.conn())
+            .await?;
+
+        Ok(usage)
+    }
+
+    async fn create_audit_entry(
+        &mut self,
+        entry: &model::InputAuditEntity,
+    ) -> model::ProvideResult<model::AuditEntity> {
+        let entry: model::AuditEntity = sqlx::query_as(
+            "SELECT * FROM create_audit_entry($1::TEXT, $2::audit_kind, $3::TEXT, $4::JSONB, $5::UUID, $6::UUID, $7::audit_outcome, $8::TEXT, $9::BIGINT)",
+        )
+        .bind(&entry.actor)
+        .bind(&entry.kind)
+        .bind(&entry.operation)
+        .bind(Json(&entry.arguments))
+        .bind(&entry.environment)
+        .bind(&entry.index)
+        .bind(&entry.outcome)
+        .bind(&entry.error)
+        .bind(entry.duration)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(entry)
+    }
+
+    async fn get_audit_entries(
+        &mut self,
+        filter: &model::AuditFilterEntity,
+    ) -> model::ProvideResult<Vec<model::AuditEntity>> {
+        let entries: Vec<model::AuditEntity> = sqlx::query_as(
+            "SELECT * FROM list_audit_entries($1::TEXT, $2::audit_kind, $3::TEXT, $4::UUID, $5::UUID, $6::audit_outcome, $7::TIMESTAMPTZ, $8::TIMESTAMPTZ, $9::BIGINT)",
+        )
+        .bind(&filter.actor)
+        .bind(&filter.kind)
+        .bind(&filter.operation)
+        .bind(&filter.environment)
+        .bind(&filter.index)
+        .bind(&filter.outcome)
+        .bind(&filter.since)
+        .bind(&filter.until)
+        .bind(filter.limit)
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn create_operation(
+        &mut self,
+        operation: &model::InputOperationEntity,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        let operation: model::OperationEntity = sqlx::query_as(
+            "SELECT * FROM create_operation($1::operation_kind, $2::TEXT, $3::TEXT, $4::JSONB)",
+        )
+        .bind(&operation.kind)
+        .bind(&operation.actor)
+        .bind(&operation.name)
+        .bind(Json(&operation.arguments))
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(operation)
+    }
+
+    async fn update_operation(
+        &mut self,
+        id: &model::EntityId,
+        arguments: &serde_json::Value,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        let operation: model::OperationEntity =
+            sqlx::query_as("SELECT * FROM update_operation($1::UUID, $2::JSONB)")
+                .bind(&id)
+                .bind(Json(arguments))
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(operation)
+    }
+
+    async fn delete_operation(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        let operation: model::OperationEntity =
+            sqlx::query_as("SELECT * FROM delete_operation($1::UUID)")
+                .bind(&id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(operation)
+    }
+
+    async fn get_all_operations(&mut self) -> model::ProvideResult<Vec<model::OperationEntity>> {
+        let operations: Vec<model::OperationEntity> =
+            sqlx::query_as("SELECT * FROM list_operations()")
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(operations)
+    }
+
+    async fn get_all_cached_artifacts(
+        &mut self,
+    ) -> model::ProvideResult<Vec<model::CachedArtifactEntity>> {
+        let artifacts: Vec<model::CachedArtifactEntity> =
+            sqlx::query_as("SELECT * FROM list_cached_artifacts()")
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(artifacts)
+    }
+
+    async fn find_cached_artifact(
+        &mut self,
+        data_source: &str,
+        region: &str,
+        version: &str,
+    ) -> model::ProvideResult<Option<model::CachedArtifactEntity>> {
+        let artifact: Option<model::CachedArtifactEntity> =
+            sqlx::query_as("SELECT * FROM find_cached_artifact($1::TEXT, $2::TEXT, $3::TEXT)")
+                .bind(data_source)
+                .bind(region)
+                .bind(version)
+                .fetch_optional(self.conn())
+                .await?;
+
+        Ok(artifact)
+    }
+
+    async fn create_cached_artifact(
+        &mut self,
+        artifact: &model::InputCachedArtifactEntity,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let artifact: model::CachedArtifactEntity = sqlx::query_as(
+            "SELECT * FROM create_cached_artifact($1::TEXT, $2::TEXT, $3::TEXT, $4::TEXT, $5::BIGINT, $6::TEXT)",
+        )
+        .bind(&artifact.data_source)
+        .bind(&artifact.region)
+        .bind(&artifact.version)
+        .bind(&artifact.digest)
+        .bind(artifact.size)
+        .bind(&artifact.url)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(artifact)
+    }
+
+    async fn touch_cached_artifact(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let artifact: model::CachedArtifactEntity =
+            sqlx::query_as("SELECT * FROM touch_cached_artifact($1::UUID)")
+                .bind(&id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(artifact)
+    }
+
+    async fn delete_cached_artifact(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let artifact: model::CachedArtifactEntity =
+            sqlx::query_as("SELECT * FROM delete_cached_artifact($1::UUID)")
+                .bind(&id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(artifact)
+    }
+
+    async fn get_index_refreshes(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let refreshes: Vec<model::IndexRefreshEntity> =
+            sqlx::query_as("SELECT * FROM list_index_refreshes($1::UUID)")
+                .bind(&index)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(refreshes)
+    }
+
+    async fn create_index_refresh(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<model::IndexRefreshEntity> {
+        let refresh: model::IndexRefreshEntity =
+            sqlx::query_as("SELECT * FROM create_index_refresh($1::UUID)")
+                .bind(&index)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(refresh)
+    }
+
+    async fn finish_index_refresh(
+        &mut self,
+        refresh: &model::EntityId,
+        finished: &model::FinishedIndexRefreshEntity,
+    ) -> model::ProvideResult<model::IndexRefreshEntity> {
+        let refresh: model::IndexRefreshEntity = sqlx::query_as(
+            "SELECT * FROM finish_index_refresh($1::UUID, $2::INTEGER, $3::index_status, $4::audit_outcome, $5::TEXT)",
+        )
+        .bind(&refresh)
+        .bind(&finished.twerg_id)
+        .bind(&finished.status)
+        .bind(&finished.outcome)
+        .bind(&finished.error)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(refresh)
+    }
+
+    async fn abandon_index_refreshes(
+        &mut self,
+        error: &str,
+    ) -> model::ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let refreshes: Vec<model::IndexRefreshEntity> =
+            sqlx::query_as("SELECT * FROM abandon_index_refreshes($1::TEXT)")
+                .bind(error)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(refreshes)
+    }
+
+    async fn get_index_versions(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexVersionEntity>> {
+        let versions: Vec<model::IndexVersionEntity> =
+            sqlx::query_as("SELECT * FROM list_index_versions($1::UUID)")
+                .bind(&index)
+                .fetch_all(self.conn())
+                .await?;
+
+        Ok(versions)
+    }
+
+    async fn create_index_version(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+    ) -> model::ProvideResult<model::IndexVersionEntity> {
+        let version: model::IndexVersionEntity =
+            sqlx::query_as("SELECT * FROM create_index_version($1::UUID, $2::INTEGER)")
+                .bind(&index)
+                .bind(twerg_id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(version)
+    }
+
+    async fn delete_index_version(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+    ) -> model::ProvideResult<model::IndexVersionEntity> {
+        let version: model::IndexVersionEntity =
+            sqlx::query_as("SELECT * FROM delete_index_version($1::UUID, $2::INTEGER)")
+                .bind(&index)
+                .bind(twerg_id)
+                .fetch_one(self.conn())
+                .await?;
+
+        Ok(version)
+    }
 }
 
 pub async fn init_db(conn_str: &str, logger: Logger) -> Result<(), error::Error> {
@@ -0,0 +1,1254 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use slog::{info, Logger};
+use snafu::ResultExt;
+use sqlx::pool::PoolConnection;
+use sqlx::row::{FromRow, Row};
+use sqlx::sqlite::{SqliteConnection, SqlitePool, SqliteQueryAs, SqliteRow};
+use sqlx::{Connection, Executor};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+use super::model;
+use super::{Connections, Store, Transaction};
+use crate::error;
+
+const SCHEMA: &str = include_str!("sqlite.sql");
+
+/// Changes to the tables of `SCHEMA`, applied in order to stores created before them.
+/// The number of upgrades applied is the `user_version` of the database. Upgrades are
+/// only ever appended.
+const UPGRADES: &[&str] = &[include_str!("sqlite/01_index_refresh.sql")];
+
+/// Foreign keys are enforced per connection, and only outside of transactions.
+const FOREIGN_KEYS: &str = "PRAGMA foreign_keys = ON";
+
+const ENVIRONMENT_COLUMNS: &str = "id, name, signature, port, owner, project, memory, \
+     expires_at, state, services, config, created_at, updated_at";
+
+const INDEX_COLUMNS: &str = "id, index_type, data_source, regions, signature, status, \
+     created_at, updated_at, twerg_id, schedule, next_refresh_at";
+
+const INDEX_REFRESH_COLUMNS: &str =
+    "id, index_id, twerg_id, status, outcome, error, started_at, finished_at";
+
+const INDEX_VERSION_COLUMNS: &str = "index_id, twerg_id, retired_at";
+
+const SNAPSHOT_COLUMNS: &str = "id, name, environment, environment_name, path, volumes, created_at";
+
+const AUDIT_COLUMNS: &str = "id, actor, kind, operation, arguments, environment_id, index_id, \
+     outcome, error, duration, created_at";
+
+const OPERATION_COLUMNS: &str = "id, kind, actor, name, arguments, started_at";
+
+const CACHED_ARTIFACT_COLUMNS: &str =
+    "id, data_source, region, version, digest, size, url, created_at, used_at";
+
+const PROJECT_COLUMNS: &str =
+    "id, name, max_environments, max_indexes, max_memory, created_at, updated_at";
+
+fn decode_error<E>(err: E) -> sqlx::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    sqlx::Error::Decode(Box::new(err))
+}
+
+fn uuid(text: String) -> Result<Uuid, sqlx::Error> {
+    Uuid::parse_str(&text).map_err(decode_error)
+}
+
+fn datetime(text: String) -> Result<DateTime<Utc>, sqlx::Error> {
+    DateTime::parse_from_rfc3339(&text)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(decode_error)
+}
+
+/// Dates are stored with a fixed precision, so that they can be compared as text.
+fn timestamp(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn from_json<T: DeserializeOwned>(text: String) -> Result<T, sqlx::Error> {
+    serde_json::from_str(&text).map_err(decode_error)
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Enumerations are stored as their serialized name.
+fn from_variant<T: DeserializeOwned>(text: String) -> Result<T, sqlx::Error> {
+    serde_json::from_value(serde_json::Value::String(text)).map_err(decode_error)
+}
+
+fn to_variant<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::EnvironmentEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::EnvironmentEntity {
+            id: uuid(row.get(0))?,
+            name: row.get(1),
+            signature: row.get(2),
+            port: row.get(3),
+            owner: row.get(4),
+            project: uuid(row.get(5))?,
+            memory: row.get(6),
+            expires_at: row.get::<Option<String>, _>(7).map(datetime).transpose()?,
+            state: from_variant(row.get(8))?,
+            services: from_json(row.get(9))?,
+            config: from_json(row.get(10))?,
+            indexes: Vec::new(),
+            created_at: datetime(row.get(11))?,
+            updated_at: datetime(row.get(12))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::IndexEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::IndexEntity {
+            id: uuid(row.get(0))?,
+            index_type: row.get(1),
+            data_source: row.get(2),
+            regions: from_json(row.get(3))?,
+            signature: row.get(4),
+            status: from_variant(row.get(5))?,
+            created_at: datetime(row.get(6))?,
+            updated_at: datetime(row.get(7))?,
+            twerg_id: row.get(8),
+            schedule: row.get(9),
+            next_refresh_at: row.get::<Option<String>, _>(10).map(datetime).transpose()?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::ScheduledIndexEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::ScheduledIndexEntity {
+            environment: uuid(row.get(0))?,
+            id: uuid(row.get(1))?,
+            schedule: row.get(2),
+            next_refresh_at: datetime(row.get(3))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::IndexVersionEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::IndexVersionEntity {
+            index: uuid(row.get(0))?,
+            twerg_id: row.get(1),
+            retired_at: datetime(row.get(2))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::IndexRefreshEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::IndexRefreshEntity {
+            id: uuid(row.get(0))?,
+            index: uuid(row.get(1))?,
+            twerg_id: row.get(2),
+            status: row
+                .get::<Option<String>, _>(3)
+                .map(from_variant)
+                .transpose()?,
+            outcome: row
+                .get::<Option<String>, _>(4)
+                .map(from_variant)
+                .transpose()?,
+            error: row.get(5),
+            started_at: datetime(row.get(6))?,
+            finished_at: row.get::<Option<String>, _>(7).map(datetime).transpose()?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::SnapshotEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::SnapshotEntity {
+            id: uuid(row.get(0))?,
+            name: row.get(1),
+            environment: row.get::<Option<String>, _>(2).map(uuid).transpose()?,
+            environment_name: row.get(3),
+            path: row.get(4),
+            volumes: from_json(row.get(5))?,
+            created_at: datetime(row.get(6))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::ProjectEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::ProjectEntity {
+            id: uuid(row.get(0))?,
+            name: row.get(1),
+            max_environments: row.get(2),
+            max_indexes: row.get(3),
+            max_memory: row.get(4),
+            created_at: datetime(row.get(5))?,
+            updated_at: datetime(row.get(6))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::AuditEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::AuditEntity {
+            id: uuid(row.get(0))?,
+            actor: row.get(1),
+            kind: from_variant(row.get(2))?,
+            operation: row.get(3),
+            arguments: from_json(row.get(4))?,
+            environment: row.get::<Option<String>, _>(5).map(uuid).transpose()?,
+            index: row.get::<Option<String>, _>(6).map(uuid).transpose()?,
+            outcome: from_variant(row.get(7))?,
+            error: row.get(8),
+            duration: row.get(9),
+            created_at: datetime(row.get(10))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::OperationEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::OperationEntity {
+            id: uuid(row.get(0))?,
+            kind: from_variant(row.get(1))?,
+            actor: row.get(2),
+            name: row.get(3),
+            arguments: from_json(row.get(4))?,
+            started_at: datetime(row.get(5))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::CachedArtifactEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::CachedArtifactEntity {
+            id: uuid(row.get(0))?,
+            data_source: row.get(1),
+            region: row.get(2),
+            version: row.get(3),
+            digest: row.get(4),
+            size: row.get(5),
+            url: row.get(6),
+            created_at: datetime(row.get(7))?,
+            used_at: datetime(row.get(8))?,
+        })
+    }
+}
+
+impl<'c> FromRow<'c, SqliteRow<'c>> for model::UsageEntity {
+    fn from_row(row: &SqliteRow<'c>) -> Result<Self, sqlx::Error> {
+        Ok(model::UsageEntity {
+            environments: row.get(0),
+            indexes: row.get(1),
+            memory: row.get(2),
+        })
+    }
+}
+
+/// A store in an SQLite file, for a single developer. Its schema is created when
+/// it is opened.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn connect(db_url: &str, logger: &Logger) -> Result<Self, error::Error> {
+        // SQLite has a single writer, so transactions are serialized on a single
+        // connection rather than failing on a locked database.
+        let pool = SqlitePool::builder()
+            .max_size(1)
+            .build(db_url)
+            .await
+            .context(error::DBError {
+                msg: format!("Could not open {}", db_url),
+            })?;
+
+        let mut conn = pool.acquire().await.context(error::DBError {
+            msg: format!("Could not connect to {}", db_url),
+        })?;
+
+        conn.execute(FOREIGN_KEYS).await.context(error::DBError {
+            msg: format!("Could not enforce foreign keys in {}", db_url),
+        })?;
+
+        for statement in statements(SCHEMA) {
+            conn.execute(statement).await.context(error::DBError {
+                msg: format!("Could not create schema in {}", db_url),
+            })?;
+        }
+
+        let version: i32 = sqlx::query("PRAGMA user_version")
+            .fetch_one(&mut *conn)
+            .await
+            .context(error::DBError {
+                msg: format!("Could not read schema version of {}", db_url),
+            })?
+            .get(0);
+
+        for (applied, upgrade) in UPGRADES.iter().enumerate().skip(version as usize) {
+            let mut tx = conn.begin().await.context(error::DBError {
+                msg: format!("Could not upgrade schema of {}", db_url),
+            })?;
+            for statement in statements(upgrade) {
+                tx.execute(statement).await.context(error::DBError {
+                    msg: format!("Could not upgrade schema of {}", db_url),
+                })?;
+            }
+            tx.execute(format!("PRAGMA user_version = {}", applied + 1).as_str())
+                .await
+                .context(error::DBError {
+                    msg: format!("Could not upgrade schema of {}", db_url),
+                })?;
+            conn = tx.commit().await.context(error::DBError {
+                msg: format!("Could not upgrade schema of {}", db_url),
+            })?;
+            info!(
+                logger,
+                "Upgraded SQLite store {} to version {}",
+                db_url,
+                applied + 1
+            );
+        }
+
+        info!(logger, "Opened SQLite store {}", db_url);
+
+        Ok(SqliteStore { pool })
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn begin(&self) -> model::ProvideResult<Box<dyn Transaction>> {
+        // The pool may have opened a new connection since the last transaction.
+        let mut conn = self.pool.acquire().await?;
+        conn.execute(FOREIGN_KEYS).await?;
+        let tx = conn.begin().await?;
+        Ok(Box::new(SqliteTransaction { tx }))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn ping(&self) -> model::ProvideResult<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn connections(&self) -> Option<Connections> {
+        Some(Connections {
+            open: self.pool.size(),
+            idle: u32::try_from(self.pool.idle()).unwrap_or(u32::MAX),
+            max: self.pool.max_size(),
+        })
+    }
+}
+
+/// The statements of a script. Statements end with a semicolon at the end of a line
+/// followed by an empty line, triggers having semicolons in their body.
+fn statements(script: &str) -> impl Iterator<Item = &str> {
+    script
+        .split(";\n\n")
+        .map(|statement| statement.trim().trim_end_matches(';'))
+        .filter(|statement| !statement.is_empty())
+}
+
+pub struct SqliteTransaction {
+    tx: sqlx::Transaction<PoolConnection<SqliteConnection>>,
+}
+
+impl SqliteTransaction {
+    fn conn(&mut self) -> &mut SqliteConnection {
+        &mut self.tx
+    }
+
+    async fn environment(&mut self, id: &Uuid) -> model::ProvideResult<model::EnvironmentEntity> {
+        let environment: model::EnvironmentEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM environments WHERE id = ?",
+            ENVIRONMENT_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(environment)
+    }
+
+    async fn project(&mut self, id: &Uuid) -> model::ProvideResult<model::ProjectEntity> {
+        let project: model::ProjectEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM projects WHERE id = ?",
+            PROJECT_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(project)
+    }
+
+    async fn cached_artifact(
+        &mut self,
+        id: &Uuid,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let artifact: model::CachedArtifactEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM cached_artifacts WHERE id = ?",
+            CACHED_ARTIFACT_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(artifact)
+    }
+
+    async fn index(&mut self, id: &Uuid) -> model::ProvideResult<model::IndexEntity> {
+        let index: model::IndexEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM indexes WHERE id = ?",
+            INDEX_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(index)
+    }
+
+    async fn index_refresh(
+        &mut self,
+        id: &Uuid,
+    ) -> model::ProvideResult<model::IndexRefreshEntity> {
+        let refresh: model::IndexRefreshEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM index_refreshes WHERE id = ?",
+            INDEX_REFRESH_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(refresh)
+    }
+
+    async fn index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> model::ProvideResult<model::IndexVersionEntity> {
+        let version: model::IndexVersionEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM index_versions WHERE index_id = ? AND twerg_id = ?",
+            INDEX_VERSION_COLUMNS
+        ))
+        .bind(index.to_string())
+        .bind(twerg_id)
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(version)
+    }
+}
+
+#[async_trait]
+impl Transaction for SqliteTransaction {
+    async fn commit(self: Box<Self>) -> model::ProvideResult<()> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl model::ProvideData for SqliteTransaction {
+    async fn get_all_environments(
+        &mut self,
+    ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
+        let environments: Vec<model::EnvironmentEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM environments ORDER BY created_at",
+            ENVIRONMENT_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(environments)
+    }
+
+    async fn get_environment_indexes(
+        &mut self,
+        environment: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexEntity>> {
+        let indexes: Vec<model::IndexEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM indexes WHERE environment = ? ORDER BY created_at",
+            INDEX_COLUMNS
+        ))
+        .bind(environment.to_string())
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(indexes)
+    }
+
+    async fn create_environment(
+        &mut self,
+        env: &model::InputEnvironmentEntity,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        let id = Uuid::new_v4();
+        let now = timestamp(&Utc::now());
+        sqlx::query(
+            "INSERT INTO environments (id, name, signature, port, owner, project, memory, \
+             expires_at, services, config, created_at, updated_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id.to_string())
+        .bind(&env.name)
+        .bind(&env.signature)
+        .bind(env.port)
+        .bind(&env.owner)
+        .bind(env.project.to_string())
+        .bind(env.memory)
+        .bind(env.expires_at.as_ref().map(timestamp))
+        .bind(to_json(&env.services))
+        .bind(to_json(&env.config))
+        .bind(&now)
+        .bind(&now)
+        .execute(self.conn())
+        .await?;
+
+        self.environment(&id).await
+    }
+
+    async fn delete_environment(
+        &mut self,
+        env: &model::EntityId,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        let environment = self.environment(env).await?;
+
+        sqlx::query("DELETE FROM indexes WHERE environment = ?")
+            .bind(env.to_string())
+            .execute(self.conn())
+            .await?;
+        sqlx::query("UPDATE snapshots SET environment = NULL WHERE environment = ?")
+            .bind(env.to_string())
+            .execute(self.conn())
+            .await?;
+        sqlx::query("DELETE FROM environments WHERE id = ?")
+            .bind(env.to_string())
+            .execute(self.conn())
+            .await?;
+
+        Ok(environment)
+    }
+
+    async fn create_index(
+        &mut self,
+        index: &model::InputIndexEntity,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        let id = Uuid::new_v4();
+        let now = timestamp(&Utc::now());
+        sqlx::query(
+            "INSERT INTO indexes (id, environment, index_type, data_source, regions, signature, \
+             twerg_id, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id.to_string())
+        .bind(index.environment.to_string())
+        .bind(&index.index_type)
+        .bind(&index.data_source)
+        .bind(to_json(&index.regions))
+        .bind(&index.signature)
+        .bind(index.twerg_id)
+        .bind(&now)
+        .bind(&now)
+        .execute(self.conn())
+        .await?;
+
+        self.index(&id).await
+    }
+
+    async fn copy_environment_indexes(
+        &mut self,
+        source: &model::EntityId,
+        target: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexEntity>> {
+        let now = timestamp(&Utc::now());
+        let indexes = self.get_environment_indexes(source).await?;
+        for index in indexes.iter() {
+            sqlx::query(
+                "INSERT INTO indexes (id, environment, index_type, data_source, regions, \
+                 signature, status, twerg_id, schedule, next_refresh_at, created_at, \
+                 updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(target.to_string())
+            .bind(&index.index_type)
+            .bind(&index.data_source)
+            .bind(to_json(&index.regions))
+            .bind(&index.signature)
+            .bind(to_variant(&index.status))
+            .bind(index.twerg_id)
+            .bind(&index.schedule)
+            .bind(index.next_refresh_at.as_ref().map(timestamp))
+            .bind(&now)
+            .bind(&now)
+            .execute(self.conn())
+            .await?;
+        }
+
+        self.get_environment_indexes(target).await
+    }
+
+    async fn delete_index(
+        &mut self,
+        environment: &model::EntityId,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        let entity: model::IndexEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM indexes WHERE id = ? AND environment = ?",
+            INDEX_COLUMNS
+        ))
+        .bind(index.to_string())
+        .bind(environment.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        sqlx::query("DELETE FROM indexes WHERE id = ?")
+            .bind(index.to_string())
+            .execute(self.conn())
+            .await?;
+
+        Ok(entity)
+    }
+
+    async fn schedule_index(
+        &mut self,
+        index: &model::EntityId,
+        schedule: Option<&str>,
+        next_refresh_at: Option<&DateTime<Utc>>,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        sqlx::query("UPDATE indexes SET schedule = ?, next_refresh_at = ? WHERE id = ?")
+            .bind(schedule)
+            .bind(next_refresh_at.map(timestamp))
+            .bind(index.to_string())
+            .execute(self.conn())
+            .await?;
+
+        self.index(index).await
+    }
+
+    async fn swap_index(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+        status: &model::IndexStatus,
+    ) -> model::ProvideResult<model::IndexEntity> {
+        sqlx::query("UPDATE indexes SET twerg_id = ?, status = ?, updated_at = ? WHERE id = ?")
+            .bind(twerg_id)
+            .bind(to_variant(status))
+            .bind(timestamp(&Utc::now()))
+            .bind(index.to_string())
+            .execute(self.conn())
+            .await?;
+
+        self.index(index).await
+    }
+
+    async fn get_due_indexes(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> model::ProvideResult<Vec<model::ScheduledIndexEntity>> {
+        let indexes: Vec<model::ScheduledIndexEntity> = sqlx::query_as(
+            "SELECT environment, id, schedule, next_refresh_at FROM indexes \
+             WHERE schedule IS NOT NULL AND next_refresh_at <= ? \
+             ORDER BY next_refresh_at",
+        )
+        .bind(timestamp(before))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(indexes)
+    }
+
+    async fn get_environment_by_id(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        self.environment(id).await
+    }
+
+    async fn extend_environment(
+        &mut self,
+        id: &model::EntityId,
+        expires_at: &DateTime<Utc>,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        sqlx::query(
+            "UPDATE environments SET expires_at = ?, expiry_warned = 0, updated_at = ? \
+             WHERE id = ?",
+        )
+        .bind(timestamp(expires_at))
+        .bind(timestamp(&Utc::now()))
+        .bind(id.to_string())
+        .execute(self.conn())
+        .await?;
+
+        self.environment(id).await
+    }
+
+    async fn get_expiring_environments(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
+        let environments: Vec<model::EnvironmentEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM environments \
+             WHERE expires_at IS NOT NULL AND expires_at <= ? AND NOT expiry_warned \
+             ORDER BY expires_at",
+            ENVIRONMENT_COLUMNS
+        ))
+        .bind(timestamp(before))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(environments)
+    }
+
+    async fn get_expired_environments(
+        &mut self,
+    ) -> model::ProvideResult<Vec<model::EnvironmentEntity>> {
+        let environments: Vec<model::EnvironmentEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM environments \
+             WHERE expires_at IS NOT NULL AND expires_at <= ? \
+             ORDER BY expires_at",
+            ENVIRONMENT_COLUMNS
+        ))
+        .bind(timestamp(&Utc::now()))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(environments)
+    }
+
+    async fn mark_environment_warned(&mut self, id: &model::EntityId) -> model::ProvideResult<()> {
+        sqlx::query("UPDATE environments SET expiry_warned = 1 WHERE id = ?")
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_environment_state(
+        &mut self,
+        id: &model::EntityId,
+        state: &model::EnvironmentState,
+    ) -> model::ProvideResult<model::EnvironmentEntity> {
+        sqlx::query("UPDATE environments SET state = ?, updated_at = ? WHERE id = ?")
+            .bind(to_variant(state))
+            .bind(timestamp(&Utc::now()))
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        self.environment(id).await
+    }
+
+    async fn create_snapshot(
+        &mut self,
+        snapshot: &model::InputSnapshotEntity,
+    ) -> model::ProvideResult<model::SnapshotEntity> {
+        sqlx::query(
+            "INSERT INTO snapshots (id, name, environment, environment_name, path, volumes, \
+             created_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(snapshot.id.to_string())
+        .bind(&snapshot.name)
+        .bind(snapshot.environment.to_string())
+        .bind(&snapshot.environment_name)
+        .bind(&snapshot.path)
+        .bind(to_json(&snapshot.volumes))
+        .bind(timestamp(&Utc::now()))
+        .execute(self.conn())
+        .await?;
+
+        self.get_snapshot_by_id(&snapshot.id).await
+    }
+
+    async fn get_all_snapshots(&mut self) -> model::ProvideResult<Vec<model::SnapshotEntity>> {
+        let snapshots: Vec<model::SnapshotEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM snapshots ORDER BY created_at DESC",
+            SNAPSHOT_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(snapshots)
+    }
+
+    async fn get_snapshot_by_id(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::SnapshotEntity> {
+        let snapshot: model::SnapshotEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM snapshots WHERE id = ?",
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    async fn get_all_projects(&mut self) -> model::ProvideResult<Vec<model::ProjectEntity>> {
+        let projects: Vec<model::ProjectEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM projects ORDER BY name",
+            PROJECT_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(projects)
+    }
+
+    async fn get_project_by_id(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        self.project(id).await
+    }
+
+    async fn lock_project(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        self.project(id).await
+    }
+
+    async fn create_project(
+        &mut self,
+        project: &model::InputProjectEntity,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        let id = Uuid::new_v4();
+        let now = timestamp(&Utc::now());
+        sqlx::query(
+            "INSERT INTO projects (id, name, max_environments, max_indexes, max_memory, \
+             created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id.to_string())
+        .bind(&project.name)
+        .bind(project.quota.max_environments)
+        .bind(project.quota.max_indexes)
+        .bind(project.quota.max_memory)
+        .bind(&now)
+        .bind(&now)
+        .execute(self.conn())
+        .await?;
+
+        self.project(&id).await
+    }
+
+    async fn update_project_quota(
+        &mut self,
+        id: &model::EntityId,
+        quota: &model::QuotaEntity,
+    ) -> model::ProvideResult<model::ProjectEntity> {
+        sqlx::query(
+            "UPDATE projects SET max_environments = ?, max_indexes = ?, max_memory = ?, \
+             updated_at = ? WHERE id = ?",
+        )
+        .bind(quota.max_environments)
+        .bind(quota.max_indexes)
+        .bind(quota.max_memory)
+        .bind(timestamp(&Utc::now()))
+        .bind(id.to_string())
+        .execute(self.conn())
+        .await?;
+
+        self.project(id).await
+    }
+
+    async fn get_project_usage(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::UsageEntity> {
+        let usage: model::UsageEntity = sqlx::query_as(
+            "SELECT \
+             (SELECT COUNT(*) FROM environments WHERE project = ?1), \
+             (SELECT COUNT(*) FROM indexes i JOIN environments e ON i.environment = e.id \
+              WHERE e.project = ?1), \
+             (SELECT COALESCE(SUM(memory), 0) FROM environments WHERE project = ?1)",
+        )
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(usage)
+    }
+
+    async fn create_audit_entry(
+        &mut self,
+        entry: &model::InputAuditEntity,
+    ) -> model::ProvideResult<model::AuditEntity> {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO audit_entries (id, actor, kind, operation, arguments, environment_id, \
+             index_id, outcome, error, duration, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id.to_string())
+        .bind(&entry.actor)
+        .bind(to_variant(&entry.kind))
+        .bind(&entry.operation)
+        .bind(to_json(&entry.arguments))
+        .bind(entry.environment.map(|id| id.to_string()))
+        .bind(entry.index.map(|id| id.to_string()))
+        .bind(to_variant(&entry.outcome))
+        .bind(&entry.error)
+        .bind(entry.duration)
+        .bind(timestamp(&Utc::now()))
+        .execute(self.conn())
+        .await?;
+
+        let entry: model::AuditEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM audit_entries WHERE id = ?",
+            AUDIT_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(entry)
+    }
+
+    async fn get_audit_entries(
+        &mut self,
+        filter: &model::AuditFilterEntity,
+    ) -> model::ProvideResult<Vec<model::AuditEntity>> {
+        let entries: Vec<model::AuditEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM audit_entries \
+             WHERE (?1 IS NULL OR actor = ?1) \
+             AND (?2 IS NULL OR kind = ?2) \
+             AND (?3 IS NULL OR operation = ?3) \
+             AND (?4 IS NULL OR environment_id = ?4) \
+             AND (?5 IS NULL OR index_id = ?5) \
+             AND (?6 IS NULL OR outcome = ?6) \
+             AND (?7 IS NULL OR created_at >= ?7) \
+             AND (?8 IS NULL OR created_at < ?8) \
+             ORDER BY created_at DESC LIMIT ?9",
+            AUDIT_COLUMNS
+        ))
+        .bind(&filter.actor)
+        .bind(filter.kind.as_ref().map(to_variant))
+        .bind(&filter.operation)
+        .bind(filter.environment.map(|id| id.to_string()))
+        .bind(filter.index.map(|id| id.to_string()))
+        .bind(filter.outcome.as_ref().map(to_variant))
+        .bind(filter.since.as_ref().map(timestamp))
+        .bind(filter.until.as_ref().map(timestamp))
+        .bind(filter.limit)
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn create_operation(
+        &mut self,
+        operation: &model::InputOperationEntity,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO operations (id, kind, actor, name, arguments, started_at) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id.to_string())
+        .bind(to_variant(&operation.kind))
+        .bind(&operation.actor)
+        .bind(&operation.name)
+        .bind(to_json(&operation.arguments))
+        .bind(timestamp(&Utc::now()))
+        .execute(self.conn())
+        .await?;
+
+        let operation: model::OperationEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM operations WHERE id = ?",
+            OPERATION_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(operation)
+    }
+
+    async fn update_operation(
+        &mut self,
+        id: &model::EntityId,
+        arguments: &serde_json::Value,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        sqlx::query("UPDATE operations SET arguments = ? WHERE id = ?")
+            .bind(to_json(arguments))
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        let operation: model::OperationEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM operations WHERE id = ?",
+            OPERATION_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        Ok(operation)
+    }
+
+    async fn delete_operation(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::OperationEntity> {
+        let operation: model::OperationEntity = sqlx::query_as(&format!(
+            "SELECT {} FROM operations WHERE id = ?",
+            OPERATION_COLUMNS
+        ))
+        .bind(id.to_string())
+        .fetch_one(self.conn())
+        .await?;
+
+        sqlx::query("DELETE FROM operations WHERE id = ?")
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        Ok(operation)
+    }
+
+    async fn get_all_operations(&mut self) -> model::ProvideResult<Vec<model::OperationEntity>> {
+        let operations: Vec<model::OperationEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM operations ORDER BY started_at",
+            OPERATION_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(operations)
+    }
+
+    async fn get_all_cached_artifacts(
+        &mut self,
+    ) -> model::ProvideResult<Vec<model::CachedArtifactEntity>> {
+        let artifacts: Vec<model::CachedArtifactEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM cached_artifacts ORDER BY used_at DESC",
+            CACHED_ARTIFACT_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(artifacts)
+    }
+
+    async fn find_cached_artifact(
+        &mut self,
+        data_source: &str,
+        region: &str,
+        version: &str,
+    ) -> model::ProvideResult<Option<model::CachedArtifactEntity>> {
+        let artifact: Option<model::CachedArtifactEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM cached_artifacts WHERE data_source = ? AND region = ? AND version = ?",
+            CACHED_ARTIFACT_COLUMNS
+        ))
+        .bind(data_source)
+        .bind(region)
+        .bind(version)
+        .fetch_optional(self.conn())
+        .await?;
+
+        Ok(artifact)
+    }
+
+    async fn create_cached_artifact(
+        &mut self,
+        artifact: &model::InputCachedArtifactEntity,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let now = timestamp(&Utc::now());
+        sqlx::query(
+            "INSERT INTO cached_artifacts (id, data_source, region, version, digest, size, url, \
+             created_at, used_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (data_source, region, version) DO UPDATE SET digest = excluded.digest, \
+             size = excluded.size, url = excluded.url, used_at = excluded.used_at",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&artifact.data_source)
+        .bind(&artifact.region)
+        .bind(&artifact.version)
+        .bind(&artifact.digest)
+        .bind(artifact.size)
+        .bind(&artifact.url)
+        .bind(&now)
+        .bind(&now)
+        .execute(self.conn())
+        .await?;
+
+        self.find_cached_artifact(&artifact.data_source, &artifact.region, &artifact.version)
+            .await?
+            .ok_or(model::ProvideError::NotFound)
+    }
+
+    async fn touch_cached_artifact(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        sqlx::query("UPDATE cached_artifacts SET used_at = ? WHERE id = ?")
+            .bind(timestamp(&Utc::now()))
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        self.cached_artifact(id).await
+    }
+
+    async fn delete_cached_artifact(
+        &mut self,
+        id: &model::EntityId,
+    ) -> model::ProvideResult<model::CachedArtifactEntity> {
+        let artifact = self.cached_artifact(id).await?;
+
+        sqlx::query("DELETE FROM cached_artifacts WHERE id = ?")
+            .bind(id.to_string())
+            .execute(self.conn())
+            .await?;
+
+        Ok(artifact)
+    }
+
+    async fn get_index_refreshes(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let refreshes: Vec<model::IndexRefreshEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM index_refreshes WHERE index_id = ? ORDER BY started_at DESC",
+            INDEX_REFRESH_COLUMNS
+        ))
+        .bind(index.to_string())
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(refreshes)
+    }
+
+    async fn create_index_refresh(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<model::IndexRefreshEntity> {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO index_refreshes (id, index_id, started_at) VALUES (?, ?, ?)")
+            .bind(id.to_string())
+            .bind(index.to_string())
+            .bind(timestamp(&Utc::now()))
+            .execute(self.conn())
+            .await?;
+
+        self.index_refresh(&id).await
+    }
+
+    async fn finish_index_refresh(
+        &mut self,
+        refresh: &model::EntityId,
+        finished: &model::FinishedIndexRefreshEntity,
+    ) -> model::ProvideResult<model::IndexRefreshEntity> {
+        sqlx::query(
+            "UPDATE index_refreshes SET twerg_id = ?, status = ?, outcome = ?, error = ?, \
+             finished_at = ? WHERE id = ?",
+        )
+        .bind(finished.twerg_id)
+        .bind(finished.status.as_ref().map(to_variant))
+        .bind(to_variant(&finished.outcome))
+        .bind(&finished.error)
+        .bind(timestamp(&Utc::now()))
+        .bind(refresh.to_string())
+        .execute(self.conn())
+        .await?;
+
+        self.index_refresh(refresh).await
+    }
+
+    async fn abandon_index_refreshes(
+        &mut self,
+        error: &str,
+    ) -> model::ProvideResult<Vec<model::IndexRefreshEntity>> {
+        let unfinished: Vec<model::IndexRefreshEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM index_refreshes WHERE finished_at IS NULL",
+            INDEX_REFRESH_COLUMNS
+        ))
+        .fetch_all(self.conn())
+        .await?;
+
+        let mut abandoned = Vec::new();
+        for refresh in unfinished {
+            let finished = model::FinishedIndexRefreshEntity {
+                twerg_id: refresh.twerg_id,
+                status: refresh.status,
+                outcome: model::AuditOutcome::Failure,
+                error: Some(String::from(error)),
+            };
+            abandoned.push(self.finish_index_refresh(&refresh.id, &finished).await?);
+        }
+
+        Ok(abandoned)
+    }
+
+    async fn get_index_versions(
+        &mut self,
+        index: &model::EntityId,
+    ) -> model::ProvideResult<Vec<model::IndexVersionEntity>> {
+        let versions: Vec<model::IndexVersionEntity> = sqlx::query_as(&format!(
+            "SELECT {} FROM index_versions WHERE index_id = ? ORDER BY retired_at DESC",
+            INDEX_VERSION_COLUMNS
+        ))
+        .bind(index.to_string())
+        .fetch_all(self.conn())
+        .await?;
+
+        Ok(versions)
+    }
+
+    async fn create_index_version(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+    ) -> model::ProvideResult<model::IndexVersionEntity> {
+        sqlx::query(
+            "INSERT INTO index_versions (index_id, twerg_id, retired_at) VALUES (?, ?, ?) \
+             ON CONFLICT (index_id, twerg_id) DO UPDATE SET retired_at = excluded.retired_at",
+        )
+        .bind(index.to_string())
+        .bind(twerg_id)
+        .bind(timestamp(&Utc::now()))
+        .execute(self.conn())
+        .await?;
+
+        self.index_version(index, twerg_id).await
+    }
+
+    async fn delete_index_version(
+        &mut self,
+        index: &model::EntityId,
+        twerg_id: i32,
+    ) -> model::ProvideResult<model::IndexVersionEntity> {
+        let version = self.index_version(index, twerg_id).await?;
+
+        sqlx::query("DELETE FROM index_versions WHERE index_id = ? AND twerg_id = ?")
+            .bind(index.to_string())
+            .bind(twerg_id)
+            .execute(self.conn())
+            .await?;
+
+        Ok(version)
+    }
+}
@@ -0,0 +1,136 @@
+-- Schema of SQLite stores, created when the store is opened. It mirrors the Postgres
+-- schema built by the migrations. Identifiers are stored as text, dates as RFC 3339
+-- text in UTC with a fixed precision, so that they compare as text, and arrays and
+-- structures as JSON text.
+--
+-- Stores created before a table changed are upgraded by the scripts of `UPGRADES` in
+-- sqlite.rs, so the statements here must not change the tables which already exist.
+--
+-- Statements are separated by empty lines.
+
+CREATE TABLE IF NOT EXISTS projects (
+  id TEXT PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  max_environments INTEGER,
+  max_indexes INTEGER,
+  max_memory INTEGER,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS environments (
+  id TEXT PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  signature TEXT NOT NULL,
+  port INTEGER NOT NULL,
+  owner TEXT NOT NULL,
+  project TEXT NOT NULL REFERENCES projects(id),
+  memory INTEGER NOT NULL DEFAULT 0,
+  expires_at TEXT,
+  expiry_warned INTEGER NOT NULL DEFAULT 0,
+  state TEXT NOT NULL DEFAULT 'running',
+  services TEXT NOT NULL DEFAULT '[]',
+  config TEXT NOT NULL DEFAULT '[]',
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS environments_signature_idx ON environments (project, signature);
+
+CREATE TABLE IF NOT EXISTS indexes (
+  id TEXT PRIMARY KEY,
+  environment TEXT NOT NULL REFERENCES environments(id) ON DELETE CASCADE,
+  index_type TEXT NOT NULL,
+  data_source TEXT NOT NULL,
+  regions TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'not_available',
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS index_refreshes (
+  id TEXT PRIMARY KEY,
+  index_id TEXT NOT NULL REFERENCES indexes(id) ON DELETE CASCADE,
+  twerg_id INTEGER,
+  status TEXT,
+  outcome TEXT,
+  error TEXT,
+  started_at TEXT NOT NULL,
+  finished_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS index_versions (
+  index_id TEXT NOT NULL REFERENCES indexes(id) ON DELETE CASCADE,
+  twerg_id INTEGER NOT NULL,
+  retired_at TEXT NOT NULL,
+  PRIMARY KEY (index_id, twerg_id)
+);
+
+CREATE TABLE IF NOT EXISTS snapshots (
+  id TEXT PRIMARY KEY,
+  name TEXT NOT NULL,
+  environment TEXT REFERENCES environments(id) ON DELETE SET NULL,
+  environment_name TEXT NOT NULL,
+  path TEXT NOT NULL,
+  volumes TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audit_entries (
+  id TEXT PRIMARY KEY,
+  actor TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  operation TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  environment_id TEXT,
+  index_id TEXT,
+  outcome TEXT NOT NULL,
+  error TEXT,
+  duration INTEGER NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_entries_created_at_idx ON audit_entries (created_at);
+
+CREATE TRIGGER IF NOT EXISTS audit_entries_no_update
+BEFORE UPDATE ON audit_entries
+BEGIN
+  SELECT RAISE(ABORT, 'audit entries cannot be modified');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_entries_no_delete
+BEFORE DELETE ON audit_entries
+BEGIN
+  SELECT RAISE(ABORT, 'audit entries cannot be modified');
+END;
+
+CREATE TABLE IF NOT EXISTS operations (
+  id TEXT PRIMARY KEY,
+  kind TEXT NOT NULL,
+  actor TEXT NOT NULL,
+  name TEXT NOT NULL,
+  arguments TEXT NOT NULL,
+  started_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS cached_artifacts (
+  id TEXT PRIMARY KEY,
+  data_source TEXT NOT NULL,
+  region TEXT NOT NULL,
+  version TEXT NOT NULL,
+  digest TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  used_at TEXT NOT NULL,
+  UNIQUE (data_source, region, version)
+);
+
+INSERT OR IGNORE INTO projects (id, name, created_at, updated_at)
+VALUES (
+  '00000000-0000-0000-0000-000000000000',
+  'default',
+  strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+  strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+);
@@ -0,0 +1,7 @@
+-- Scheduled refreshes of indexes, and the index served by the twerg.
+
+ALTER TABLE indexes ADD COLUMN twerg_id INTEGER;
+
+ALTER TABLE indexes ADD COLUMN schedule TEXT;
+
+ALTER TABLE indexes ADD COLUMN next_refresh_at TEXT;
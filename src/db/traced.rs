@@ -0,0 +1,447 @@
+//! A store tracing each of its calls in a span.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use opentelemetry::trace::SpanKind;
+use std::future::Future;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::model::{self, ProvideResult};
+use super::{Connections, Migrations, Store, Transaction};
+use crate::telemetry;
+
+/// Traces the calls to the store it wraps.
+#[derive(Debug)]
+pub struct TracedStore {
+    store: Arc<dyn Store>,
+}
+
+impl TracedStore {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        TracedStore { store }
+    }
+}
+
+#[async_trait]
+impl Store for TracedStore {
+    async fn begin(&self) -> ProvideResult<Box<dyn Transaction>> {
+        let tx = traced("begin", self.store.begin()).await?;
+        Ok(Box::new(TracedTransaction { tx }))
+    }
+
+    async fn close(&self) {
+        self.store.close().await
+    }
+
+    async fn ping(&self) -> ProvideResult<()> {
+        traced("ping", self.store.ping()).await
+    }
+
+    async fn migrations(&self) -> ProvideResult<Option<Migrations>> {
+        traced("migrations", self.store.migrations()).await
+    }
+
+    fn connections(&self) -> Option<Connections> {
+        self.store.connections()
+    }
+}
+
+/// Traces the calls to the transaction it wraps.
+pub struct TracedTransaction {
+    tx: Box<dyn Transaction>,
+}
+
+/// Run a call to the store in a span.
+async fn traced<T, F>(name: &str, call: F) -> ProvideResult<T>
+where
+    F: Future<Output = ProvideResult<T>>,
+{
+    telemetry::in_span(SpanKind::Client, &format!("db {}", name), vec![], call).await
+}
+
+#[async_trait]
+impl Transaction for TracedTransaction {
+    async fn commit(self: Box<Self>) -> ProvideResult<()> {
+        traced("commit", self.tx.commit()).await
+    }
+}
+
+#[async_trait]
+impl model::ProvideData for TracedTransaction {
+    async fn get_all_environments(&mut self) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        traced("get_all_environments", self.tx.get_all_environments()).await
+    }
+
+    async fn get_environment_indexes(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexEntity>> {
+        traced(
+            "get_environment_indexes",
+            self.tx.get_environment_indexes(environment),
+        )
+        .await
+    }
+
+    async fn create_environment(
+        &mut self,
+        environment: &model::InputEnvironmentEntity,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        traced(
+            "create_environment",
+            self.tx.create_environment(environment),
+        )
+        .await
+    }
+
+    async fn delete_environment(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        traced(
+            "delete_environment",
+            self.tx.delete_environment(environment),
+        )
+        .await
+    }
+
+    async fn create_index(
+        &mut self,
+        index: &model::InputIndexEntity,
+    ) -> ProvideResult<model::IndexEntity> {
+        traced("create_index", self.tx.create_index(index)).await
+    }
+
+    async fn copy_environment_indexes(
+        &mut self,
+        source: &Uuid,
+        target: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexEntity>> {
+        traced(
+            "copy_environment_indexes",
+            self.tx.copy_environment_indexes(source, target),
+        )
+        .await
+    }
+
+    async fn delete_index(
+        &mut self,
+        environment: &Uuid,
+        index: &Uuid,
+    ) -> ProvideResult<model::IndexEntity> {
+        traced("delete_index", self.tx.delete_index(environment, index)).await
+    }
+
+    async fn schedule_index(
+        &mut self,
+        index: &Uuid,
+        schedule: Option<&str>,
+        next_refresh_at: Option<&DateTime<Utc>>,
+    ) -> ProvideResult<model::IndexEntity> {
+        traced(
+            "schedule_index",
+            self.tx.schedule_index(index, schedule, next_refresh_at),
+        )
+        .await
+    }
+
+    async fn swap_index(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+        status: &model::IndexStatus,
+    ) -> ProvideResult<model::IndexEntity> {
+        traced("swap_index", self.tx.swap_index(index, twerg_id, status)).await
+    }
+
+    async fn get_due_indexes(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<model::ScheduledIndexEntity>> {
+        traced("get_due_indexes", self.tx.get_due_indexes(before)).await
+    }
+
+    async fn get_environment_by_id(
+        &mut self,
+        environment: &Uuid,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        traced(
+            "get_environment_by_id",
+            self.tx.get_environment_by_id(environment),
+        )
+        .await
+    }
+
+    async fn extend_environment(
+        &mut self,
+        environment: &Uuid,
+        expires_at: &DateTime<Utc>,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        traced(
+            "extend_environment",
+            self.tx.extend_environment(environment, expires_at),
+        )
+        .await
+    }
+
+    async fn get_expiring_environments(
+        &mut self,
+        before: &DateTime<Utc>,
+    ) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        traced(
+            "get_expiring_environments",
+            self.tx.get_expiring_environments(before),
+        )
+        .await
+    }
+
+    async fn get_expired_environments(&mut self) -> ProvideResult<Vec<model::EnvironmentEntity>> {
+        traced(
+            "get_expired_environments",
+            self.tx.get_expired_environments(),
+        )
+        .await
+    }
+
+    async fn mark_environment_warned(&mut self, environment: &Uuid) -> ProvideResult<()> {
+        traced(
+            "mark_environment_warned",
+            self.tx.mark_environment_warned(environment),
+        )
+        .await
+    }
+
+    async fn update_environment_state(
+        &mut self,
+        environment: &Uuid,
+        state: &model::EnvironmentState,
+    ) -> ProvideResult<model::EnvironmentEntity> {
+        traced(
+            "update_environment_state",
+            self.tx.update_environment_state(environment, state),
+        )
+        .await
+    }
+
+    async fn create_snapshot(
+        &mut self,
+        snapshot: &model::InputSnapshotEntity,
+    ) -> ProvideResult<model::SnapshotEntity> {
+        traced("create_snapshot", self.tx.create_snapshot(snapshot)).await
+    }
+
+    async fn get_all_snapshots(&mut self) -> ProvideResult<Vec<model::SnapshotEntity>> {
+        traced("get_all_snapshots", self.tx.get_all_snapshots()).await
+    }
+
+    async fn get_snapshot_by_id(
+        &mut self,
+        snapshot: &Uuid,
+    ) -> ProvideResult<model::SnapshotEntity> {
+        traced("get_snapshot_by_id", self.tx.get_snapshot_by_id(snapshot)).await
+    }
+
+    async fn get_all_projects(&mut self) -> ProvideResult<Vec<model::ProjectEntity>> {
+        traced("get_all_projects", self.tx.get_all_projects()).await
+    }
+
+    async fn get_project_by_id(&mut self, project: &Uuid) -> ProvideResult<model::ProjectEntity> {
+        traced("get_project_by_id", self.tx.get_project_by_id(project)).await
+    }
+
+    async fn lock_project(&mut self, project: &Uuid) -> ProvideResult<model::ProjectEntity> {
+        traced("lock_project", self.tx.lock_project(project)).await
+    }
+
+    async fn create_project(
+        &mut self,
+        project: &model::InputProjectEntity,
+    ) -> ProvideResult<model::ProjectEntity> {
+        traced("create_project", self.tx.create_project(project)).await
+    }
+
+    async fn update_project_quota(
+        &mut self,
+        project: &Uuid,
+        quota: &model::QuotaEntity,
+    ) -> ProvideResult<model::ProjectEntity> {
+        traced(
+            "update_project_quota",
+            self.tx.update_project_quota(project, quota),
+        )
+        .await
+    }
+
+    async fn get_project_usage(&mut self, project: &Uuid) -> ProvideResult<model::UsageEntity> {
+        traced("get_project_usage", self.tx.get_project_usage(project)).await
+    }
+
+    async fn create_audit_entry(
+        &mut self,
+        entry: &model::InputAuditEntity,
+    ) -> ProvideResult<model::AuditEntity> {
+        traced("create_audit_entry", self.tx.create_audit_entry(entry)).await
+    }
+
+    async fn get_audit_entries(
+        &mut self,
+        filter: &model::AuditFilterEntity,
+    ) -> ProvideResult<Vec<model::AuditEntity>> {
+        traced("get_audit_entries", self.tx.get_audit_entries(filter)).await
+    }
+
+    async fn create_operation(
+        &mut self,
+        operation: &model::InputOperationEntity,
+    ) -> ProvideResult<model::OperationEntity> {
+        traced("create_operation", self.tx.create_operation(operation)).await
+    }
+
+    async fn update_operation(
+        &mut self,
+        operation: &Uuid,
+        arguments: &serde_json::Value,
+    ) -> ProvideResult<model::OperationEntity> {
+        traced(
+            "update_operation",
+            self.tx.update_operation(operation, arguments),
+        )
+        .await
+    }
+
+    async fn delete_operation(
+        &mut self,
+        operation: &Uuid,
+    ) -> ProvideResult<model::OperationEntity> {
+        traced("delete_operation", self.tx.delete_operation(operation)).await
+    }
+
+    async fn get_all_operations(&mut self) -> ProvideResult<Vec<model::OperationEntity>> {
+        traced("get_all_operations", self.tx.get_all_operations()).await
+    }
+
+    async fn get_all_cached_artifacts(
+        &mut self,
+    ) -> ProvideResult<Vec<model::CachedArtifactEntity>> {
+        traced(
+            "get_all_cached_artifacts",
+            self.tx.get_all_cached_artifacts(),
+        )
+        .await
+    }
+
+    async fn find_cached_artifact(
+        &mut self,
+        data_source: &str,
+        region: &str,
+        version: &str,
+    ) -> ProvideResult<Option<model::CachedArtifactEntity>> {
+        traced(
+            "find_cached_artifact",
+            self.tx.find_cached_artifact(data_source, region, version),
+        )
+        .await
+    }
+
+    async fn create_cached_artifact(
+        &mut self,
+        artifact: &model::InputCachedArtifactEntity,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        traced(
+            "create_cached_artifact",
+            self.tx.create_cached_artifact(artifact),
+        )
+        .await
+    }
+
+    async fn touch_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        traced(
+            "touch_cached_artifact",
+            self.tx.touch_cached_artifact(artifact),
+        )
+        .await
+    }
+
+    async fn delete_cached_artifact(
+        &mut self,
+        artifact: &Uuid,
+    ) -> ProvideResult<model::CachedArtifactEntity> {
+        traced(
+            "delete_cached_artifact",
+            self.tx.delete_cached_artifact(artifact),
+        )
+        .await
+    }
+
+    async fn get_index_refreshes(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexRefreshEntity>> {
+        traced("get_index_refreshes", self.tx.get_index_refreshes(index)).await
+    }
+
+    async fn create_index_refresh(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<model::IndexRefreshEntity> {
+        traced("create_index_refresh", self.tx.create_index_refresh(index)).await
+    }
+
+    async fn finish_index_refresh(
+        &mut self,
+        refresh: &Uuid,
+        finished: &model::FinishedIndexRefreshEntity,
+    ) -> ProvideResult<model::IndexRefreshEntity> {
+        traced(
+            "finish_index_refresh",
+            self.tx.finish_index_refresh(refresh, finished),
+        )
+        .await
+    }
+
+    async fn abandon_index_refreshes(
+        &mut self,
+        error: &str,
+    ) -> ProvideResult<Vec<model::IndexRefreshEntity>> {
+        traced(
+            "abandon_index_refreshes",
+            self.tx.abandon_index_refreshes(error),
+        )
+        .await
+    }
+
+    async fn get_index_versions(
+        &mut self,
+        index: &Uuid,
+    ) -> ProvideResult<Vec<model::IndexVersionEntity>> {
+        traced("get_index_versions", self.tx.get_index_versions(index)).await
+    }
+
+    async fn create_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<model::IndexVersionEntity> {
+        traced(
+            "create_index_version",
+            self.tx.create_index_version(index, twerg_id),
+        )
+        .await
+    }
+
+    async fn delete_index_version(
+        &mut self,
+        index: &Uuid,
+        twerg_id: i32,
+    ) -> ProvideResult<model::IndexVersionEntity> {
+        traced(
+            "delete_index_version",
+            self.tx.delete_index_version(index, twerg_id),
+        )
+        .await
+    }
+}
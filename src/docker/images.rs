@@ -0,0 +1,251 @@
+use bollard::auth::DockerCredentials;
+use bollard::image::CreateImageOptions;
+use bollard::service::BuildInfo;
+use bollard::Docker;
+use futures::stream::TryStreamExt;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use slog::{trace, Logger};
+use snafu::ResultExt;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+use super::{traced, ServiceConfig};
+use crate::error;
+use crate::events::{self, Event, EventKind};
+use crate::settings::{DockerRegistry, Settings};
+use crate::telemetry;
+
+/// When to pull the image of a service.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    Always,
+    IfNotPresent,
+    Never,
+}
+
+impl Default for PullPolicy {
+    fn default() -> Self {
+        PullPolicy::Always
+    }
+}
+
+/// An image ready to be used by a container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedImage {
+    /// The reference containers should be created from. It is pinned by digest
+    /// when the digest is known.
+    pub reference: String,
+    pub digest: Option<String>,
+}
+
+pub fn credentials(registry: &DockerRegistry) -> Option<DockerCredentials> {
+    registry
+        .username
+        .as_ref()
+        .map(|username| DockerCredentials {
+            username: Some(username.clone()),
+            password: registry.password.clone(),
+            serveraddress: Some(registry.url.clone()),
+            ..Default::default()
+        })
+}
+
+/// Make the images of a twerg's services available, according to their pull policy,
+/// and return them by service. The progress of pulls is published as events.
+pub async fn resolve_images(
+    name: &str,
+    config: &[ServiceConfig],
+    settings: &Settings,
+    events: &broadcast::Sender<Event>,
+    logger: &Logger,
+) -> Result<HashMap<String, ResolvedImage>, error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    let mut resolved = HashMap::new();
+    for service in config {
+        let image_name = service.image(&settings.registry.url);
+        // Only status changes are published, not the progress of each layer.
+        let on_progress = |info: &BuildInfo| {
+            if let (Some(status), None) = (&info.status, &info.progress) {
+                events::publish(
+                    events,
+                    Event::new(
+                        None,
+                        name,
+                        EventKind::ImagePull,
+                        format!("{}: {}", image_name, status),
+                    ),
+                );
+            }
+        };
+        let image = resolve_image(
+            &docker,
+            &image_name,
+            service.docker.pull,
+            &settings.registry,
+            on_progress,
+            logger,
+        )
+        .await?;
+        resolved.insert(service.service.clone(), image);
+    }
+
+    Ok(resolved)
+}
+
+/// Make sure the image is available according to the pull policy, and pin it by digest.
+pub async fn resolve_image<F>(
+    docker: &Docker,
+    image_name: &str,
+    policy: PullPolicy,
+    registry: &DockerRegistry,
+    on_progress: F,
+    logger: &Logger,
+) -> Result<ResolvedImage, error::Error>
+where
+    F: Fn(&BuildInfo),
+{
+    let present = traced(
+        "inspect_image",
+        image_name,
+        docker.inspect_image(image_name),
+    )
+    .await
+    .is_ok();
+
+    let pull = match policy {
+        PullPolicy::Always => true,
+        PullPolicy::IfNotPresent => !present,
+        PullPolicy::Never if !present => {
+            return Err(error::Error::MiscError {
+                msg: format!(
+                    "Image {} is not present, and its pull policy is never",
+                    image_name
+                ),
+            })
+        }
+        PullPolicy::Never => false,
+    };
+    if pull {
+        create_image(
+            docker,
+            image_name,
+            credentials(registry),
+            on_progress,
+            logger,
+        )
+        .await?;
+    } else {
+        trace!(logger, "Using local image {}", image_name);
+    }
+
+    let image = traced(
+        "inspect_image",
+        image_name,
+        docker.inspect_image(image_name),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not inspect image {}", image_name),
+    })?;
+
+    // A repo digest looks like 'localhost:5000/bragi@sha256:...'. We want the one
+    // matching the repository of the image.
+    let repository = image_repository(image_name);
+    let repo_digest = image
+        .repo_digests
+        .unwrap_or_default()
+        .into_iter()
+        .find(|repo_digest| repo_digest.starts_with(&format!("{}@", repository)));
+
+    Ok(match repo_digest {
+        Some(repo_digest) => ResolvedImage {
+            digest: repo_digest.split('@').nth(1).map(String::from),
+            reference: repo_digest,
+        },
+        // Images built locally have no repo digest, we use them by name.
+        None => ResolvedImage {
+            reference: String::from(image_name),
+            digest: None,
+        },
+    })
+}
+
+/// Pull an image, reporting progress as it goes. Errors reported by docker in
+/// the progress stream are returned as errors.
+pub async fn create_image<F>(
+    docker: &Docker,
+    image_name: &str,
+    credentials: Option<DockerCredentials>,
+    on_progress: F,
+    logger: &Logger,
+) -> Result<(), error::Error>
+where
+    F: Fn(&BuildInfo),
+{
+    trace!(logger, "Creating docker image {}", image_name);
+    let options = Some(CreateImageOptions {
+        from_image: &image_name[..],
+        ..Default::default()
+    });
+
+    let pull = docker
+        .create_image(options, None, credentials)
+        .map_err(|err| error::Error::DockerError {
+            msg: format!("Could not create image {}", image_name),
+            source: err,
+        })
+        .try_fold(0usize, |count, info| {
+            let res = match (&info.error, &info.error_detail) {
+                (Some(err), _) => Err(error::Error::MiscError {
+                    msg: format!("Could not pull image {}: {}", image_name, err),
+                }),
+                (None, Some(detail)) if detail.message.is_some() => Err(error::Error::MiscError {
+                    msg: format!(
+                        "Could not pull image {}: {}",
+                        image_name,
+                        detail.message.clone().unwrap_or_default()
+                    ),
+                }),
+                _ => {
+                    on_progress(&info);
+                    Ok(count + 1)
+                }
+            };
+            futures::future::ready(res)
+        });
+    let count = telemetry::in_span(
+        SpanKind::Client,
+        "docker create_image",
+        vec![KeyValue::new("docker.object", image_name.to_owned())],
+        pull,
+    )
+    .await?;
+
+    if count == 0 {
+        return Err(error::Error::MiscError {
+            msg: format!("Pulling image {} returned no information", image_name),
+        });
+    }
+
+    trace!(logger, "Successfully created docker image {}", image_name);
+    Ok(())
+}
+
+/// The repository part of an image name, that is without its tag.
+pub fn image_repository(image_name: &str) -> &str {
+    // The tag follows the last colon, unless that colon belongs to the registry's port.
+    match image_name.rfind(':') {
+        Some(idx) if !image_name[idx..].contains('/') => &image_name[..idx],
+        _ => image_name,
+    }
+}
+
+pub fn format_image(registry: &str, name: &str, tag: &str) -> String {
+    format!("{}/{}:{}", registry, name, tag)
+}
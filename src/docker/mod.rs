@@ -1,10 +1,14 @@
-use bollard::container::{Config, CreateContainerOptions, NetworkingConfig, StartContainerOptions};
-use bollard::image::CreateImageOptions;
+use bollard::container::{
+    Config, CreateContainerOptions, InspectContainerOptions, LogsOptions, NetworkingConfig,
+    RemoveContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions,
+};
 use bollard::network::{CreateNetworkOptions, ListNetworksOptions};
-use bollard::service::{BuildInfo, EndpointSettings, HostConfig, Ipam, PortBinding};
+use bollard::service::{EndpointSettings, HostConfig, Ipam, PortBinding};
 use bollard::Docker;
 use futures::future;
 use futures::stream::{self, StreamExt, TryStreamExt};
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
 use slog::{error, trace, Logger};
 use snafu::ResultExt;
 use std::net::TcpListener;
@@ -15,38 +19,96 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::default::Default;
+use std::future::Future;
 
+use tokio::sync::broadcast;
+
+use crate::cache;
 use crate::error;
+use crate::events::{self, Event, EventKind};
 use crate::settings::Settings;
+use crate::telemetry;
+
+pub mod images;
+pub mod resources;
+pub mod volumes;
 
-#[derive(Debug, Serialize, Deserialize)]
+use images::{PullPolicy, ResolvedImage};
+use resources::ResourcesConfig;
+use volumes::VolumeConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DockerConfig {
-    image: String,
-    tag: String,
+    pub image: String,
+    pub tag: String,
+    #[serde(default)]
+    pub pull: PullPolicy,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
-    addr_base: String,
-    addr_suffix: u16,
-    id: Option<String>,
+    pub addr_base: String,
+    pub addr_suffix: u16,
+    pub id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceConfig {
-    service: String,
-    docker: DockerConfig,
-    network: NetworkConfig,
-    envs: Option<Vec<String>>,
-    ports: Option<HashMap<String, Option<String>>>, // Internal, External
+    pub service: String,
+    pub docker: DockerConfig,
+    pub network: NetworkConfig,
+    pub envs: Option<Vec<String>>,
+    pub ports: Option<HashMap<String, Option<String>>>, // Internal, External
+    pub resources: Option<ResourcesConfig>,
+    pub volumes: Option<Vec<VolumeConfig>>,
 }
 
-/// Create a twerg, and return the twerg's frontend port
+impl ServiceConfig {
+    /// The name of the service's image, in the given registry.
+    pub fn image(&self, registry: &str) -> String {
+        images::format_image(registry, &self.docker.image, &self.docker.tag)
+    }
+}
+
+/// Read and validate the configuration of the services making up a twerg.
 pub async fn get_config(
     settings: &Settings,
     logger: &Logger,
 ) -> Result<Vec<ServiceConfig>, error::Error> {
-    let config = settings.twerg.config.clone();
+    read_config(&settings.twerg.config, logger).await
+}
+
+/// Read and validate the configuration of a template named in the settings, or the
+/// default configuration when no template is given.
+pub async fn get_template_config(
+    settings: &Settings,
+    template: Option<&str>,
+    logger: &Logger,
+) -> Result<Vec<ServiceConfig>, error::Error> {
+    match template {
+        None => get_config(settings, logger).await,
+        Some(template) => match settings.twerg.templates.get(template) {
+            Some(path) => read_config(path, logger).await,
+            None => Err(error::Error::MiscError {
+                msg: format!("Unknown template {}", template),
+            }),
+        },
+    }
+}
+
+/// The configuration a twerg was created from, as recorded with its environment.
+pub fn stored_config(
+    name: &str,
+    config: &serde_json::Value,
+) -> Result<Vec<ServiceConfig>, error::Error> {
+    serde_json::from_value(config.clone()).context(error::JSONError {
+        msg: format!("Could not deserialize services configuration of {}", name),
+    })
+}
+
+async fn read_config(path: &str, logger: &Logger) -> Result<Vec<ServiceConfig>, error::Error> {
+    trace!(logger, "Reading twerg configuration at {}", path);
+    let config = String::from(path);
     let mut file = File::open(&config).await.context(error::TokioIOError {
         msg: format!("Could not open twerg configuration at {}", config),
     })?;
@@ -56,18 +118,32 @@ pub async fn get_config(
         .context(error::TokioIOError {
             msg: format!("Could not read twerg configuration at {}", config),
         })?;
-    serde_json::from_str(&config).context(error::JSONError {
+    let config: Vec<ServiceConfig> = serde_json::from_str(&config).context(error::JSONError {
         msg: format!("Could not deserialize {}", config),
-    })
+    })?;
+
+    for service in config.iter() {
+        if let Some(resources) = &service.resources {
+            resources.validate(&service.service)?;
+        }
+    }
+
+    Ok(config)
 }
 
+/// Create a twerg from its configuration, with the images previously resolved for
+/// its services, and return the twerg's frontend port. If a snapshot directory is
+/// given, the twerg's volumes are restored from it before the services are started.
+/// Progress is published as events.
 pub async fn create_twerg(
     name: &str,
+    config: Vec<ServiceConfig>,
+    images: &HashMap<String, ResolvedImage>,
+    restore: Option<&str>,
     settings: &Settings,
+    events: &broadcast::Sender<Event>,
     logger: &Logger,
 ) -> Result<u16, error::Error> {
-    let config = get_config(settings, &logger).await?;
-
     let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
         msg: String::from("Could not connect to docker"),
     })?;
@@ -84,25 +160,305 @@ pub async fn create_twerg(
 
     let network_id = create_network(&docker, name, network_base.as_str(), &logger).await?;
 
-    stream::iter(config.into_iter().map(|c| Ok(c)))
-        .try_for_each(|mut config| {
-            config.network.id = Some(network_id.clone());
-            // For nginx, we bind the external port to port 80. So we add a "80:{external port}"
-            // binding.
-            if config.service == "nginx" {
-                // FIXME Hardcoded internal port number
-                let mut ports = HashMap::new();
-                let external_port = format!("{}", port);
-                ports.insert(String::from("80"), Some(external_port));
-                config.ports = Some(ports);
-            }
-            launch_service(&docker, &name, config, &logger)
-        })
-        .await?;
+    volumes::create_volumes(&docker, name, &config, &logger).await?;
+
+    if let Some(snapshot_dir) = restore {
+        for volume in volumes::volume_names(&config) {
+            volumes::restore_volume(
+                &docker,
+                name,
+                &volume,
+                snapshot_dir,
+                &settings.snapshots.image,
+                &logger,
+            )
+            .await?;
+        }
+    }
+
+    for mut config in config {
+        config.network.id = Some(network_id.clone());
+        // For nginx, we bind the external port to port 80. So we add a "80:{external port}"
+        // binding.
+        if config.service == "nginx" {
+            // FIXME Hardcoded internal port number
+            let mut ports = HashMap::new();
+            let external_port = format!("{}", port);
+            ports.insert(String::from("80"), Some(external_port));
+            config.ports = Some(ports);
+        }
+        // Services ingesting source data read it from the cache.
+        if settings.cache.services.contains(&config.service) {
+            config
+                .volumes
+                .get_or_insert_with(Vec::new)
+                .push(cache::volume(&settings.cache));
+        }
+        let service = config.service.clone();
+        let image = images
+            .get(&service)
+            .ok_or_else(|| error::Error::MiscError {
+                msg: format!("No image resolved for service {}", service),
+            })?;
+        launch_service(&docker, &name, config, &image.reference, &logger).await?;
+        events::publish(
+            events,
+            Event::new(
+                None,
+                name,
+                EventKind::Provisioning,
+                format!("Started service {} from {}", service, image.reference),
+            ),
+        );
+    }
 
     Ok(port)
 }
 
+/// Destroy a twerg created from the given configuration: remove its containers, its
+/// network and its volumes. Those already gone, eg removed by hand or never created
+/// by an interrupted creation, are skipped, so that the twerg can be forgotten all
+/// the same.
+pub async fn delete_twerg(
+    name: &str,
+    config: &[ServiceConfig],
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    trace!(logger, "Connected to docker");
+
+    for service in config {
+        let container_name = format_container(&service.service, name);
+        skip_not_found(remove_container(&docker, &container_name, logger).await)?;
+    }
+
+    skip_not_found(remove_network(&docker, name, logger).await)?;
+
+    volumes::remove_existing_volumes(&docker, name, config, logger).await
+}
+
+/// Ignore the error of docker answering that an object does not exist.
+fn skip_not_found(result: Result<(), error::Error>) -> Result<(), error::Error> {
+    match result {
+        Err(error::Error::DockerError {
+            source: bollard::errors::Error::DockerResponseNotFoundError { .. },
+            ..
+        }) => Ok(()),
+        result => result,
+    }
+}
+
+/// Archive the volumes of a twerg in the snapshot directory, and return their names.
+/// Running services are stopped during the snapshot, so that the data is consistent,
+/// and started again afterwards.
+pub async fn snapshot_twerg(
+    name: &str,
+    config: &[ServiceConfig],
+    snapshot_dir: &str,
+    settings: &Settings,
+    logger: &Logger,
+) -> Result<Vec<String>, error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    let mut running = Vec::new();
+    for service in config.iter() {
+        let container_name = format_container(&service.service, name);
+        if is_container_running(&docker, &container_name).await? {
+            stop_container(&docker, &container_name, &logger).await?;
+            running.push(container_name);
+        }
+    }
+
+    let names = volumes::volume_names(config);
+    let mut result = Ok(());
+    for volume in names.iter() {
+        result = volumes::archive_volume(
+            &docker,
+            name,
+            volume,
+            snapshot_dir,
+            &settings.snapshots.image,
+            &logger,
+        )
+        .await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    // Whatever happened, we restart what was running, without losing the
+    // archive error if the restart fails too.
+    let mut restarted = Ok(());
+    for container_name in running {
+        let started = start_container(&docker, &container_name, &logger).await;
+        if restarted.is_ok() {
+            restarted = started;
+        }
+    }
+
+    match (result, restarted) {
+        (Ok(()), Ok(())) => Ok(names),
+        (Err(err), Ok(())) | (Ok(()), Err(err)) => Err(err),
+        (Err(archive), Err(restart)) => Err(error::Error::MiscError {
+            msg: format!(
+                "Could not snapshot {}: {}, nor restart it: {}",
+                name, archive, restart
+            ),
+        }),
+    }
+}
+
+/// Stop all the containers of a twerg which are running. Containers are kept,
+/// so their data and port bindings are preserved.
+pub async fn stop_twerg(
+    name: &str,
+    config: &[ServiceConfig],
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    for service in config {
+        let container_name = format_container(&service.service, name);
+        if is_container_running(&docker, &container_name).await? {
+            stop_container(&docker, &container_name, &logger).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Start all the containers of a twerg which are not running, in the order of the
+/// twerg configuration.
+pub async fn start_twerg(
+    name: &str,
+    config: &[ServiceConfig],
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    for service in config {
+        let container_name = format_container(&service.service, name);
+        if !is_container_running(&docker, &container_name).await? {
+            start_container(&docker, &container_name, &logger).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Restart a single service of a twerg.
+pub async fn restart_service(
+    name: &str,
+    config: &[ServiceConfig],
+    service: &str,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    if !config.iter().any(|c| c.service == service) {
+        return Err(error::Error::MiscError {
+            msg: format!("Unknown service {} in twerg {}", service, name),
+        });
+    }
+
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    let container_name = format_container(service, name);
+    trace!(logger, "Restarting container {}", container_name);
+    traced(
+        "restart_container",
+        &container_name,
+        docker.restart_container(&container_name, None::<RestartContainerOptions>),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not restart container {}", container_name),
+    })
+}
+
+/// Return the last `tail` lines logged by the container of a service of a twerg,
+/// on both stdout and stderr.
+pub async fn service_logs(
+    name: &str,
+    service: &str,
+    tail: u32,
+    logger: &Logger,
+) -> Result<Vec<String>, error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    let container_name = format_container(service, name);
+    trace!(logger, "Reading logs of container {}", container_name);
+    let options = LogsOptions {
+        stdout: true,
+        stderr: true,
+        tail: tail.to_string(),
+        ..Default::default()
+    };
+    let output = traced(
+        "logs",
+        &container_name,
+        docker
+            .logs(&container_name, Some(options))
+            .try_collect::<Vec<_>>(),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not read logs of container {}", container_name),
+    })?;
+
+    Ok(output
+        .into_iter()
+        .map(|line| line.to_string().trim_end().to_string())
+        .collect())
+}
+
+/// Check the docker daemon answers.
+pub async fn ping() -> Result<(), error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    traced("ping", "", docker.ping())
+        .await
+        .context(error::DockerError {
+            msg: String::from("Could not ping docker"),
+        })?;
+
+    Ok(())
+}
+
+/// Returns the number of running containers in a twerg, and the number of
+/// services it is made of.
+pub async fn twerg_running_count(
+    name: &str,
+    config: &[ServiceConfig],
+) -> Result<(usize, usize), error::Error> {
+    let docker = Docker::connect_with_unix_defaults().context(error::DockerError {
+        msg: String::from("Could not connect to docker"),
+    })?;
+
+    let mut running = 0;
+    for service in config.iter() {
+        let container_name = format_container(&service.service, name);
+        if is_container_running(&docker, &container_name).await? {
+            running += 1;
+        }
+    }
+
+    Ok((running, config.len()))
+}
+
 /// Returns the first available port available after the base.
 /// We iterate over the range base..base+99, and try to create a TcpListener.
 /// If this fails, we try the next port.
@@ -140,8 +496,7 @@ pub async fn get_network_base(docker: &Docker) -> Result<String, error::Error> {
     let config = ListNetworksOptions::<String> {
         ..Default::default()
     };
-    let networks = docker
-        .list_networks(Some(config))
+    let networks = traced("list_networks", "", docker.list_networks(Some(config)))
         .await
         .context(error::DockerError {
             msg: String::from("Could not list networks"),
@@ -180,16 +535,16 @@ pub async fn get_network_base(docker: &Docker) -> Result<String, error::Error> {
     Ok(format!("172.{}", res))
 }
 
+/// Create and start the container of a service, from the given image.
 pub async fn launch_service(
     docker: &Docker,
     env_name: &str,
     config: ServiceConfig,
+    image_name: &str,
     logger: &Logger,
 ) -> Result<(), error::Error> {
-    let image_name = format_image(&config.docker.image, &config.docker.tag);
     let container_name = format_container(&config.service, env_name);
-    create_image(&docker, &image_name, &logger).await?;
-    create_container(&docker, &env_name, &config, &logger).await?;
+    create_container(&docker, &env_name, &config, image_name, &logger).await?;
     start_container(&docker, &container_name, &logger).await?;
     Ok(())
 }
@@ -198,13 +553,13 @@ pub async fn create_container(
     docker: &Docker,
     env_name: &str,
     config: &ServiceConfig,
+    image_name: &str,
     logger: &Logger,
 ) -> Result<(), error::Error> {
     let network_name = format_network(env_name);
-    let image_name = format_image(&config.docker.image, &config.docker.tag);
     let container_name = format_container(&config.service, env_name);
     let options = CreateContainerOptions {
-        name: String::from(container_name),
+        name: container_name.clone(),
     };
 
     let network_id = String::from(&config.network.id.clone().unwrap());
@@ -243,12 +598,18 @@ pub async fn create_container(
         port_bindings
     });
 
-    let host_config = HostConfig {
+    let mut host_config = HostConfig {
         port_bindings,
         network_mode: Some(String::from(network_name)),
         ..Default::default()
     };
 
+    if let Some(resources) = &config.resources {
+        resources.apply(&mut host_config);
+    }
+
+    host_config.mounts = volumes::mounts(env_name, &config);
+
     let exposed_ports = config.ports.clone().map(|ports| {
         let mut exposed_ports = HashMap::new();
         for (internal, _) in ports.iter() {
@@ -267,12 +628,15 @@ pub async fn create_container(
         exposed_ports,
         ..Default::default()
     };
-    let result = &docker
-        .create_container(Some(options), config)
-        .await
-        .context(error::DockerError {
-            msg: String::from("Could not create container"),
-        })?;
+    let result = &traced(
+        "create_container",
+        &container_name,
+        docker.create_container(Some(options), config),
+    )
+    .await
+    .context(error::DockerError {
+        msg: String::from("Could not create container"),
+    })?;
 
     assert_ne!(result.id.len(), 0);
     Ok(())
@@ -283,57 +647,74 @@ pub async fn start_container(
     container_name: &str,
     logger: &Logger,
 ) -> Result<(), error::Error> {
-    &docker
-        .start_container(container_name, None::<StartContainerOptions<String>>)
-        .await
-        .context(error::DockerError {
-            msg: String::from("Could not start container"),
-        })?;
+    traced(
+        "start_container",
+        container_name,
+        docker.start_container(container_name, None::<StartContainerOptions<String>>),
+    )
+    .await
+    .context(error::DockerError {
+        msg: String::from("Could not start container"),
+    })?;
 
     Ok(())
 }
 
-pub async fn create_image(
+pub async fn stop_container(
     docker: &Docker,
-    image_name: &str,
+    container_name: &str,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    trace!(logger, "Stopping container {}", container_name);
+    traced(
+        "stop_container",
+        container_name,
+        docker.stop_container(container_name, None::<StopContainerOptions>),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not stop container {}", container_name),
+    })
+}
+
+pub async fn is_container_running(
+    docker: &Docker,
+    container_name: &str,
+) -> Result<bool, error::Error> {
+    let info = traced(
+        "inspect_container",
+        container_name,
+        docker.inspect_container(container_name, None::<InspectContainerOptions>),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not inspect container {}", container_name),
+    })?;
+
+    Ok(info.state.and_then(|state| state.running).unwrap_or(false))
+}
+
+/// Remove a container, stopping it first if it is running.
+pub async fn remove_container(
+    docker: &Docker,
+    container_name: &str,
     logger: &Logger,
 ) -> Result<(), error::Error> {
-    trace!(logger, "Creating docker image {}", image_name);
-    let options = Some(CreateImageOptions {
-        from_image: &image_name[..],
+    trace!(logger, "Removing container {}", container_name);
+    let options = Some(RemoveContainerOptions {
+        force: true,
         ..Default::default()
     });
 
-    let result: Vec<BuildInfo> = docker
-        .create_image(options, None, None)
-        .try_collect()
-        .await
-        .context(error::DockerError {
-            msg: String::from("Could not create image"),
-        })?;
-
-    match result.len() {
-        0 => {
-            error!(logger, "Not expecting image creation return empty info");
-            // FIXME That's not Ok!
-            Ok(())
-        }
-        1 => {
-            // FIXME There are two fields that probably need to be defensively reported, error, and
-            // error_detail
-            trace!(
-                logger,
-                "Successfully created docker image {} => id {}",
-                image_name,
-                &result[0].id.clone().unwrap_or_else(|| String::from("NA"))
-            );
-            Ok(())
-        }
-        _ => {
-            // FIXME Not expecting more than one
-            Ok(())
-        }
-    }
+    traced(
+        "remove_container",
+        container_name,
+        docker.remove_container(container_name, options),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not remove container {}", container_name),
+    })
 }
 
 /// Creates a network, and returns its id
@@ -359,20 +740,24 @@ pub async fn create_network(
         String::from(env_name),
     );
 
+    let network_name = format_network(env_name);
     let options = CreateNetworkOptions {
-        name: format_network(env_name),
+        name: network_name.clone(),
         driver: String::from("bridge"),
         ipam,
         labels,
         ..Default::default()
     };
 
-    let result = docker
-        .create_network(options)
-        .await
-        .context(error::DockerError {
-            msg: String::from("Could not create network"),
-        })?;
+    let result = traced(
+        "create_network",
+        &network_name,
+        docker.create_network(options),
+    )
+    .await
+    .context(error::DockerError {
+        msg: String::from("Could not create network"),
+    })?;
 
     match result.id {
         Some(id) => {
@@ -388,15 +773,46 @@ pub async fn create_network(
     }
 }
 
-pub fn registry_http_addr() -> String {
-    format!(
-        "{}",
-        ::std::env::var("REGISTRY_HTTP_ADDR").unwrap_or_else(|_| "localhost:5000".to_string())
+/// Removes the network of an environment
+pub async fn remove_network(
+    docker: &Docker,
+    env_name: &str,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let network_name = format_network(env_name);
+    trace!(logger, "Removing network {}", network_name);
+    traced(
+        "remove_network",
+        &network_name,
+        docker.remove_network(&network_name),
     )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not remove network {}", network_name),
+    })
 }
 
-pub fn format_image(name: &str, tag: &str) -> String {
-    format!("{}/{}:{}", registry_http_addr(), name, tag)
+/// Run a docker API call in a span, naming the container, network, volume or image
+/// it is about, if any.
+pub(crate) async fn traced<T, F>(
+    operation: &str,
+    object: &str,
+    call: F,
+) -> Result<T, bollard::errors::Error>
+where
+    F: Future<Output = Result<T, bollard::errors::Error>>,
+{
+    let mut attributes = Vec::new();
+    if !object.is_empty() {
+        attributes.push(KeyValue::new("docker.object", object.to_owned()));
+    }
+    telemetry::in_span(
+        SpanKind::Client,
+        &format!("docker {}", operation),
+        attributes,
+        call,
+    )
+    .await
 }
 
 pub fn format_container(name: &str, env: &str) -> String {
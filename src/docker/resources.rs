@@ -0,0 +1,167 @@
+use bollard::service::{HostConfig, ResourcesUlimits, RestartPolicy, RestartPolicyNameEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+
+/// Docker refuses memory limits below 6MiB.
+const MIN_MEMORY: i32 = 6;
+
+/// The ulimits which can be set on a service.
+const ULIMITS: &[&str] = &["nofile", "memlock", "nproc", "core", "stack"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UlimitConfig {
+    pub name: String,
+    /// -1 means unlimited
+    pub soft: i32,
+    /// -1 means unlimited
+    pub hard: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicyConfig {
+    No,
+    Always,
+    UnlessStopped,
+    OnFailure,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestartConfig {
+    pub policy: RestartPolicyConfig,
+    /// Only meaningful with the on-failure policy
+    pub max_retries: Option<i32>,
+}
+
+/// Limits and reservations of the resources a service can use.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourcesConfig {
+    /// Memory limit, in MiB
+    pub memory: Option<i32>,
+    /// Memory soft limit, in MiB
+    pub memory_reservation: Option<i32>,
+    /// Relative CPU weight
+    pub cpu_shares: Option<i32>,
+    /// Microseconds of CPU time allowed per cpu_period
+    pub cpu_quota: Option<i32>,
+    /// CPU period, in microseconds
+    pub cpu_period: Option<i32>,
+    /// Maximum number of processes, -1 means unlimited
+    pub pids_limit: Option<i32>,
+    pub ulimits: Option<Vec<UlimitConfig>>,
+    pub restart: Option<RestartConfig>,
+}
+
+impl ResourcesConfig {
+    /// Check the resources are consistent, and acceptable by docker.
+    pub fn validate(&self, service: &str) -> Result<(), error::Error> {
+        let invalid = |msg: String| error::Error::MiscError {
+            msg: format!("Invalid resources for service {}: {}", service, msg),
+        };
+
+        if let Some(memory) = self.memory {
+            if memory < MIN_MEMORY {
+                return Err(invalid(format!(
+                    "memory limit {}MiB is below the {}MiB minimum",
+                    memory, MIN_MEMORY
+                )));
+            }
+        }
+        if let Some(reservation) = self.memory_reservation {
+            if reservation <= 0 {
+                return Err(invalid(format!("memory reservation {}MiB", reservation)));
+            }
+            if let Some(memory) = self.memory {
+                if reservation > memory {
+                    return Err(invalid(format!(
+                        "memory reservation {}MiB exceeds memory limit {}MiB",
+                        reservation, memory
+                    )));
+                }
+            }
+        }
+        if let Some(shares) = self.cpu_shares {
+            if shares < 2 {
+                return Err(invalid(format!("cpu shares {} is below 2", shares)));
+            }
+        }
+        if let Some(quota) = self.cpu_quota {
+            if quota < 1000 {
+                return Err(invalid(format!("cpu quota {}us is below 1ms", quota)));
+            }
+        }
+        if let Some(period) = self.cpu_period {
+            if period < 1000 || period > 1_000_000 {
+                return Err(invalid(format!(
+                    "cpu period {}us is not between 1ms and 1s",
+                    period
+                )));
+            }
+        }
+        if let Some(pids) = self.pids_limit {
+            if pids == 0 || pids < -1 {
+                return Err(invalid(format!("pids limit {}", pids)));
+            }
+        }
+        for ulimit in self.ulimits.iter().flatten() {
+            if !ULIMITS.contains(&ulimit.name.as_str()) {
+                return Err(invalid(format!(
+                    "unsupported ulimit {}, expecting one of {}",
+                    ulimit.name,
+                    ULIMITS.join(", ")
+                )));
+            }
+            let unlimited = |v: i32| v == -1;
+            if !unlimited(ulimit.hard) && (unlimited(ulimit.soft) || ulimit.soft > ulimit.hard) {
+                return Err(invalid(format!(
+                    "ulimit {} soft limit {} exceeds hard limit {}",
+                    ulimit.name, ulimit.soft, ulimit.hard
+                )));
+            }
+        }
+        if let Some(RestartConfig {
+            policy,
+            max_retries: Some(retries),
+        }) = &self.restart
+        {
+            if *policy != RestartPolicyConfig::OnFailure || *retries < 0 {
+                return Err(invalid(format!(
+                    "max retries {} requires a positive value and the on-failure policy",
+                    retries
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Translate the resources into docker's host configuration.
+    pub fn apply(&self, host_config: &mut HostConfig) {
+        let mib = |v: i32| i64::from(v) * 1024 * 1024;
+        host_config.memory = self.memory.map(mib);
+        host_config.memory_reservation = self.memory_reservation.map(mib);
+        host_config.cpu_shares = self.cpu_shares.map(i64::from);
+        host_config.cpu_quota = self.cpu_quota.map(i64::from);
+        host_config.cpu_period = self.cpu_period.map(i64::from);
+        host_config.pids_limit = self.pids_limit.map(i64::from);
+        host_config.ulimits = self.ulimits.as_ref().map(|ulimits| {
+            ulimits
+                .iter()
+                .map(|ulimit| ResourcesUlimits {
+                    name: Some(ulimit.name.clone()),
+                    soft: Some(i64::from(ulimit.soft)),
+                    hard: Some(i64::from(ulimit.hard)),
+                })
+                .collect()
+        });
+        host_config.restart_policy = self.restart.as_ref().map(|restart| RestartPolicy {
+            name: Some(match restart.policy {
+                RestartPolicyConfig::No => RestartPolicyNameEnum::NO,
+                RestartPolicyConfig::Always => RestartPolicyNameEnum::ALWAYS,
+                RestartPolicyConfig::UnlessStopped => RestartPolicyNameEnum::UNLESS_STOPPED,
+                RestartPolicyConfig::OnFailure => RestartPolicyNameEnum::ON_FAILURE,
+            }),
+            maximum_retry_count: restart.max_retries.map(i64::from),
+        });
+    }
+}
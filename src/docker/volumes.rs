@@ -0,0 +1,273 @@
+use bollard::container::{Config, CreateContainerOptions, WaitContainerOptions};
+use bollard::service::{HostConfig, Mount, MountTypeEnum};
+use bollard::volume::{CreateVolumeOptions, RemoveVolumeOptions};
+use bollard::Docker;
+use futures::stream::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use slog::{trace, Logger};
+use snafu::ResultExt;
+use std::collections::HashMap;
+
+use super::images::create_image;
+use super::{remove_container, start_container, traced, ServiceConfig};
+use crate::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeType {
+    /// A docker volume, created for each environment.
+    Volume,
+    /// A directory of the host.
+    Bind,
+}
+
+/// A volume mounted in a service container.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeConfig {
+    #[serde(rename = "type")]
+    pub typ: VolumeType,
+    /// The name of the volume, or the host path of a bind mount
+    pub source: String,
+    /// The path in the container
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// The names of the docker volumes used by a twerg. Several services may share a volume.
+pub fn volume_names(config: &[ServiceConfig]) -> Vec<String> {
+    let mut names = config
+        .iter()
+        .flat_map(|service| service.volumes.iter().flatten())
+        .filter(|volume| volume.typ == VolumeType::Volume)
+        .map(|volume| volume.source.clone())
+        .collect::<Vec<String>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Create the docker volumes of an environment, labelled so they can be traced back to it.
+pub async fn create_volumes(
+    docker: &Docker,
+    env_name: &str,
+    config: &[ServiceConfig],
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    for name in volume_names(config) {
+        let volume_name = format_volume(&name, env_name);
+        trace!(logger, "Creating volume {}", volume_name);
+        let mut labels = HashMap::new();
+        labels.insert("nidavellir.environment", env_name);
+        labels.insert("nidavellir.volume", name.as_str());
+        let options = CreateVolumeOptions {
+            name: volume_name.as_str(),
+            driver: "local",
+            labels,
+            ..Default::default()
+        };
+        traced("create_volume", &volume_name, docker.create_volume(options))
+            .await
+            .context(error::DockerError {
+                msg: format!("Could not create volume {}", volume_name),
+            })?;
+    }
+    Ok(())
+}
+
+/// Remove the volumes of an environment which exist.
+pub async fn remove_existing_volumes(
+    docker: &Docker,
+    env_name: &str,
+    config: &[ServiceConfig],
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    for name in volume_names(config) {
+        let volume_name = format_volume(&name, env_name);
+        trace!(logger, "Removing volume {}", volume_name);
+        let result = traced(
+            "remove_volume",
+            &volume_name,
+            docker.remove_volume(&volume_name, None::<RemoveVolumeOptions>),
+        )
+        .await;
+        match result {
+            Ok(()) | Err(bollard::errors::Error::DockerResponseNotFoundError { .. }) => {}
+            Err(err) => {
+                return Err(err).context(error::DockerError {
+                    msg: format!("Could not remove volume {}", volume_name),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The mounts of a service container.
+pub fn mounts(env_name: &str, config: &ServiceConfig) -> Option<Vec<Mount>> {
+    config.volumes.as_ref().map(|volumes| {
+        volumes
+            .iter()
+            .map(|volume| match volume.typ {
+                VolumeType::Volume => Mount {
+                    target: Some(volume.target.clone()),
+                    source: Some(format_volume(&volume.source, env_name)),
+                    typ: Some(MountTypeEnum::VOLUME),
+                    read_only: Some(volume.read_only),
+                    ..Default::default()
+                },
+                VolumeType::Bind => Mount {
+                    target: Some(volume.target.clone()),
+                    source: Some(volume.source.clone()),
+                    typ: Some(MountTypeEnum::BIND),
+                    read_only: Some(volume.read_only),
+                    ..Default::default()
+                },
+            })
+            .collect()
+    })
+}
+
+/// Archive the content of an environment's volume in the snapshot directory, as
+/// `{volume}.tar.gz`. The snapshot directory is a path on the docker host.
+pub async fn archive_volume(
+    docker: &Docker,
+    env_name: &str,
+    volume: &str,
+    snapshot_dir: &str,
+    image: &str,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let volume_name = format_volume(volume, env_name);
+    trace!(
+        logger,
+        "Archiving volume {} to {}",
+        volume_name,
+        snapshot_dir
+    );
+    run_helper(
+        docker,
+        &format!("{}_archive", volume_name),
+        image,
+        vec![
+            format!("{}:/volume:ro", volume_name),
+            format!("{}:/snapshot", snapshot_dir),
+        ],
+        vec![
+            String::from("tar"),
+            String::from("czf"),
+            format!("/snapshot/{}.tar.gz", volume),
+            String::from("-C"),
+            String::from("/volume"),
+            String::from("."),
+        ],
+        logger,
+    )
+    .await
+}
+
+/// Restore an archive made by `archive_volume` in an environment's volume.
+pub async fn restore_volume(
+    docker: &Docker,
+    env_name: &str,
+    volume: &str,
+    snapshot_dir: &str,
+    image: &str,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    let volume_name = format_volume(volume, env_name);
+    trace!(
+        logger,
+        "Restoring volume {} from {}",
+        volume_name,
+        snapshot_dir
+    );
+    run_helper(
+        docker,
+        &format!("{}_restore", volume_name),
+        image,
+        vec![
+            format!("{}:/volume", volume_name),
+            format!("{}:/snapshot:ro", snapshot_dir),
+        ],
+        vec![
+            String::from("tar"),
+            String::from("xzf"),
+            format!("/snapshot/{}.tar.gz", volume),
+            String::from("-C"),
+            String::from("/volume"),
+        ],
+        logger,
+    )
+    .await
+}
+
+/// Run a short lived container to completion, and remove it.
+async fn run_helper(
+    docker: &Docker,
+    container_name: &str,
+    image: &str,
+    binds: Vec<String>,
+    cmd: Vec<String>,
+    logger: &Logger,
+) -> Result<(), error::Error> {
+    if traced("inspect_image", image, docker.inspect_image(image))
+        .await
+        .is_err()
+    {
+        create_image(docker, image, None, |_| (), logger).await?;
+    }
+
+    let options = CreateContainerOptions {
+        name: String::from(container_name),
+    };
+    let config = Config {
+        image: Some(String::from(image)),
+        cmd: Some(cmd),
+        host_config: Some(HostConfig {
+            binds: Some(binds),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    traced(
+        "create_container",
+        container_name,
+        docker.create_container(Some(options), config),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not create container {}", container_name),
+    })?;
+
+    start_container(docker, container_name, logger).await?;
+
+    let results = traced(
+        "wait_container",
+        container_name,
+        docker
+            .wait_container(container_name, None::<WaitContainerOptions<String>>)
+            .try_collect::<Vec<_>>(),
+    )
+    .await
+    .context(error::DockerError {
+        msg: format!("Could not wait for container {}", container_name),
+    });
+
+    // The helper is removed whatever the outcome.
+    remove_container(docker, container_name, logger).await?;
+
+    match results?.last().map(|result| result.status_code) {
+        Some(0) => Ok(()),
+        Some(code) => Err(error::Error::MiscError {
+            msg: format!("Container {} exited with status {}", container_name, code),
+        }),
+        None => Err(error::Error::MiscError {
+            msg: format!("Container {} did not report its status", container_name),
+        }),
+    }
+}
+
+pub fn format_volume(name: &str, env: &str) -> String {
+    format!("{}_{}", env, name)
+}
@@ -1,4 +1,4 @@
-use juniper::{graphql_value, FieldError, IntoFieldError};
+use juniper::{graphql_value, FieldError, IntoFieldError, Object, Value};
 use snafu::Snafu;
 
 use crate::db::model::ProvideError;
@@ -45,6 +45,13 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("YAML Error: {} - {}", msg, source))]
+    #[snafu(visibility(pub))]
+    YAMLError {
+        msg: String,
+        source: serde_yaml::Error,
+    },
+
     #[snafu(display("DB Error: {} - {}", msg, source))]
     #[snafu(visibility(pub))]
     DBError { msg: String, source: sqlx::Error },
@@ -57,12 +64,54 @@ pub enum Error {
     #[snafu(visibility(pub))]
     ReqwestError { msg: String, source: reqwest::Error },
 
+    #[snafu(display("Twerg Error: {}", msg))]
+    #[snafu(visibility(pub))]
+    TwergError { msg: String },
+
     #[snafu(display("Docker Error: {} - {}", msg, source))]
     #[snafu(visibility(pub))]
     DockerError {
         msg: String,
         source: bollard::errors::Error,
     },
+
+    #[snafu(display("JWT Error: {} - {}", msg, source))]
+    #[snafu(visibility(pub))]
+    JWTError {
+        msg: String,
+        source: jsonwebtoken::errors::Error,
+    },
+
+    #[snafu(display("Metrics Error: {} - {}", msg, source))]
+    #[snafu(visibility(pub))]
+    MetricsError {
+        msg: String,
+        source: prometheus::Error,
+    },
+
+    #[snafu(display("Unauthenticated: {}", msg))]
+    #[snafu(visibility(pub))]
+    Unauthenticated { msg: String },
+
+    #[snafu(display("Unauthorized: {}", msg))]
+    #[snafu(visibility(pub))]
+    Unauthorized { msg: String },
+
+    #[snafu(display("Quota exceeded for project {}: {}", project, msg))]
+    #[snafu(visibility(pub))]
+    QuotaExceeded { project: String, msg: String },
+
+    #[snafu(display("Invalid {} '{}', expecting one of: {}", field, value, valid.join(", ")))]
+    #[snafu(visibility(pub))]
+    InvalidArgument {
+        field: String,
+        value: String,
+        valid: Vec<String>,
+    },
+
+    #[snafu(display("Shutting down: {}", msg))]
+    #[snafu(visibility(pub))]
+    ShuttingDown { msg: String },
 }
 
 impl IntoFieldError for Error {
@@ -115,6 +164,11 @@ impl IntoFieldError for Error {
                 FieldError::new("JSON Error", graphql_value!({ "internal_error": errmsg }))
             }
 
+            err @ Error::YAMLError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new("YAML Error", graphql_value!({ "internal_error": errmsg }))
+            }
+
             err @ Error::DBError { .. } => {
                 let errmsg = format!("{}", err);
                 FieldError::new("DB Error", graphql_value!({ "internal_error": errmsg }))
@@ -136,10 +190,72 @@ impl IntoFieldError for Error {
                 )
             }
 
+            err @ Error::TwergError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new("Twerg Error", graphql_value!({ "internal_error": errmsg }))
+            }
+
             err @ Error::DockerError { .. } => {
                 let errmsg = format!("{}", err);
                 FieldError::new("Docker Error", graphql_value!({ "internal_error": errmsg }))
             }
+
+            err @ Error::JWTError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new("JWT Error", graphql_value!({ "internal_error": errmsg }))
+            }
+
+            err @ Error::MetricsError { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Metrics Error",
+                    graphql_value!({ "internal_error": errmsg }),
+                )
+            }
+
+            err @ Error::Unauthenticated { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Unauthenticated",
+                    graphql_value!({ "internal_error": errmsg }),
+                )
+            }
+
+            err @ Error::Unauthorized { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new("Unauthorized", graphql_value!({ "internal_error": errmsg }))
+            }
+
+            err @ Error::QuotaExceeded { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Quota Exceeded",
+                    graphql_value!({ "internal_error": errmsg }),
+                )
+            }
+
+            err @ Error::InvalidArgument { .. } => {
+                let errmsg = format!("{}", err);
+                // The valid options are also given as a list, for clients to offer them.
+                let mut extensions = Object::with_capacity(3);
+                extensions.add_field("internal_error", Value::scalar(errmsg));
+                if let Error::InvalidArgument { field, valid, .. } = err {
+                    extensions.add_field("field", Value::scalar(field));
+                    extensions.add_field(
+                        "valid",
+                        Value::list(valid.into_iter().map(Value::scalar).collect()),
+                    );
+                }
+                FieldError::new("Invalid Argument", Value::object(extensions))
+            }
+
+            err @ Error::ShuttingDown { .. } => {
+                let errmsg = format!("{}", err);
+                FieldError::new(
+                    "Shutting Down",
+                    graphql_value!({ "internal_error": errmsg }),
+                )
+            }
         }
     }
 }
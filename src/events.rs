@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// The number of events kept for subscribers that lag behind.
+pub const EVENTS_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum EventKind {
+    /// The environment will expire soon.
+    ExpiryWarning,
+    /// The environment has expired, and is being torn down.
+    Expired,
+    /// An image is being pulled while provisioning the environment.
+    ImagePull,
+    /// A step of the environment's provisioning was completed.
+    Provisioning,
+    /// An index of the environment was refreshed on its schedule, or failed to be.
+    IndexRefresh,
+}
+
+/// Something that happened to an environment, broadcasted to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, GraphQLObject)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    /// The environment's id, unknown while it is being provisioned
+    pub environment: Option<Uuid>,
+    pub name: String,
+    pub kind: EventKind,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn new(environment: Option<Uuid>, name: &str, kind: EventKind, message: String) -> Self {
+        Event {
+            environment,
+            name: String::from(name),
+            kind,
+            message,
+            at: Utc::now(),
+        }
+    }
+}
+
+/// Publish an event. Having nobody listening is not an error.
+pub fn publish(sender: &broadcast::Sender<Event>, event: Event) {
+    let _ = sender.send(event);
+}
@@ -0,0 +1,141 @@
+use chrono::{Duration, Utc};
+use serde_json::json;
+use slog::{error, info, o};
+use snafu::ResultExt;
+use std::convert::TryFrom;
+
+use crate::audit;
+use crate::db::model::{EnvironmentEntity, ProvideData};
+use crate::docker;
+use crate::error;
+use crate::events::{self, Event, EventKind};
+use crate::state::State;
+
+/// Spawn a task which periodically warns about environments about to expire,
+/// and tears down those which have expired, until the service shuts down.
+pub fn spawn_sweeper(state: State) {
+    tokio::spawn(async move {
+        let period = std::time::Duration::from_secs(state.settings.expiry.interval);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            // Sweeps stop with the service, a sweep in progress is waited for.
+            let _in_flight = match state.shutdown.enter() {
+                Ok(guard) => guard,
+                Err(_) => break,
+            };
+            if let Err(err) = sweep(&state).await {
+                error!(state.logger, "Expiry sweep failed: {}", err);
+            }
+        }
+    });
+}
+
+/// Run a single sweep.
+pub async fn sweep(state: &State) -> Result<(), error::Error> {
+    let warning = i64::try_from(state.settings.expiry.warning).unwrap_or(i64::MAX);
+    let before = Utc::now() + Duration::seconds(warning);
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let expiring = tx
+        .get_expiring_environments(&before)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get expiring environments",
+        })?;
+
+    for environment in expiring.iter() {
+        let expires_at = environment.expires_at.unwrap_or_else(Utc::now);
+        info!(
+            state.logger,
+            "Environment {} expires at {}", environment.name, expires_at
+        );
+        events::publish(
+            &state.events,
+            Event::new(
+                Some(environment.id),
+                &environment.name,
+                EventKind::ExpiryWarning,
+                format!("Environment expires at {}", expires_at),
+            ),
+        );
+        tx.mark_environment_warned(&environment.id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not mark environment as warned",
+            })?;
+    }
+
+    let expired = tx
+        .get_expired_environments()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get expired environments",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    // One failed teardown should not prevent the others.
+    for environment in expired {
+        let logger = state
+            .logger
+            .new(o!("environment" => environment.name.clone()));
+        if let Err(err) = teardown(environment, state).await {
+            error!(logger, "Could not tear down expired environment: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the containers, the network and the catalog entry of an expired environment.
+async fn teardown(environment: EnvironmentEntity, state: &State) -> Result<(), error::Error> {
+    info!(
+        state.logger,
+        "Tearing down expired environment {}", environment.name
+    );
+    events::publish(
+        &state.events,
+        Event::new(
+            Some(environment.id),
+            &environment.name,
+            EventKind::Expired,
+            String::from("Environment has expired, and is being torn down"),
+        ),
+    );
+
+    let config = docker::stored_config(&environment.name, &environment.config)?;
+
+    audit::Operation::docker(
+        state,
+        audit::SYSTEM_ACTOR,
+        "delete_twerg",
+        &json!({ "name": &environment.name }),
+    )
+    .environment(environment.id)
+    .run(docker::delete_twerg(
+        &environment.name,
+        &config,
+        &state.logger,
+    ))
+    .await?;
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    tx.delete_environment(&environment.id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not delete environment",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit delete environment transaction.",
+    })
+}
@@ -0,0 +1,146 @@
+//! Readiness of the service: whether the store and docker can be reached, the
+//! store's schema is the one expected, and the service is not shutting down.
+
+use futures::future::join3;
+use serde::Serialize;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::docker;
+use crate::state::State;
+
+/// How long a check may take before it is considered failed, below the usual
+/// timeout of orchestrator probes.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Failed,
+}
+
+/// The result of a single check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn ok(detail: Option<String>) -> Self {
+        Check {
+            status: Status::Ok,
+            detail,
+        }
+    }
+
+    fn failed(detail: String) -> Self {
+        Check {
+            status: Status::Failed,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Checks {
+    pub database: Check,
+    pub docker: Check,
+    pub migrations: Check,
+    pub shutdown: Check,
+}
+
+/// The readiness of the service, ready when all the checks are ok.
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: Checks,
+}
+
+/// Run all the checks, concurrently.
+pub async fn readiness(state: &State) -> Readiness {
+    let (database, docker, migrations) = join3(
+        with_timeout(check_database(state)),
+        with_timeout(check_docker()),
+        with_timeout(check_migrations(state)),
+    )
+    .await;
+
+    let checks = Checks {
+        database,
+        docker,
+        migrations,
+        shutdown: check_shutdown(state),
+    };
+    let status = if [
+        &checks.database,
+        &checks.docker,
+        &checks.migrations,
+        &checks.shutdown,
+    ]
+    .iter()
+    .all(|check| check.status == Status::Ok)
+    {
+        Status::Ok
+    } else {
+        Status::Failed
+    };
+
+    Readiness { status, checks }
+}
+
+async fn with_timeout<F: Future<Output = Check>>(check: F) -> Check {
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| {
+            Check::failed(format!(
+                "No answer within {} seconds",
+                CHECK_TIMEOUT.as_secs()
+            ))
+        })
+}
+
+async fn check_database(state: &State) -> Check {
+    match state.store.ping().await {
+        Ok(()) => Check::ok(None),
+        Err(err) => Check::failed(format!("{}", err)),
+    }
+}
+
+async fn check_docker() -> Check {
+    match docker::ping().await {
+        Ok(()) => Check::ok(None),
+        Err(err) => Check::failed(format!("{}", err)),
+    }
+}
+
+async fn check_migrations(state: &State) -> Check {
+    match state.store.migrations().await {
+        Ok(None) => Check::ok(Some(String::from("Schema created with the store"))),
+        Ok(Some(migrations)) => match migrations.applied {
+            Some(applied) if applied == migrations.expected => Check::ok(Some(applied)),
+            Some(applied) => Check::failed(format!(
+                "Latest migration applied is {}, expecting {}",
+                applied, migrations.expected
+            )),
+            None => Check::failed(format!(
+                "No migration applied, expecting {}",
+                migrations.expected
+            )),
+        },
+        Err(err) => Check::failed(format!("{}", err)),
+    }
+}
+
+fn check_shutdown(state: &State) -> Check {
+    if state.shutdown.is_draining() {
+        Check::failed(format!(
+            "Shutting down, {} mutations in flight",
+            state.shutdown.in_flight()
+        ))
+    } else {
+        Check::ok(None)
+    }
+}
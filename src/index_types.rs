@@ -0,0 +1,94 @@
+//! The index types twergs know how to build, with the data sources each can be built
+//! from. Index requests are checked against them, and against the regions of the
+//! settings, before anything is forwarded to a twerg.
+
+use crate::error;
+use crate::settings;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndexType {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Data sources, in order of preference.
+    pub data_sources: &'static [&'static str],
+}
+
+pub const INDEX_TYPES: &[IndexType] = &[
+    IndexType {
+        name: "admins",
+        description: "Administrative regions",
+        data_sources: &["cosmogony", "osm"],
+    },
+    IndexType {
+        name: "streets",
+        description: "Streets",
+        data_sources: &["osm"],
+    },
+    IndexType {
+        name: "addresses",
+        description: "Addresses",
+        data_sources: &["bano", "openaddresses", "osm"],
+    },
+    IndexType {
+        name: "pois",
+        description: "Points of interest",
+        data_sources: &["osm"],
+    },
+    IndexType {
+        name: "public_transport",
+        description: "Public transport stops",
+        data_sources: &["ntfs"],
+    },
+];
+
+/// Check that the index type is known, that it can be built from the data source, and
+/// that there are regions, all of them known.
+pub fn validate(
+    index_type: &str,
+    data_source: &str,
+    regions: &[String],
+    settings: &settings::Indexes,
+) -> Result<(), error::Error> {
+    let known = INDEX_TYPES
+        .iter()
+        .find(|known| known.name == index_type)
+        .ok_or_else(|| {
+            invalid(
+                "index type",
+                index_type,
+                INDEX_TYPES.iter().map(|known| known.name),
+            )
+        })?;
+
+    let supported = known
+        .data_sources
+        .iter()
+        .any(|source| *source == data_source);
+    if !supported {
+        return Err(invalid(
+            &format!("data source for {} indexes", index_type),
+            data_source,
+            known.data_sources.iter().copied(),
+        ));
+    }
+
+    if regions.is_empty() {
+        return Err(invalid("regions", "[]", settings.regions.iter()));
+    }
+
+    match regions
+        .iter()
+        .find(|region| !settings.regions.contains(region))
+    {
+        Some(region) => Err(invalid("region", region, settings.regions.iter())),
+        None => Ok(()),
+    }
+}
+
+fn invalid<T: ToString>(field: &str, value: &str, valid: impl Iterator<Item = T>) -> error::Error {
+    error::Error::InvalidArgument {
+        field: String::from(field),
+        value: String::from(value),
+        valid: valid.map(|value| value.to_string()).collect(),
+    }
+}
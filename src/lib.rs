@@ -1,8 +1,21 @@
 pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod cache;
 pub mod db;
 pub mod docker;
 pub mod error;
+pub mod events;
+pub mod expiry;
+pub mod health;
+pub mod index_types;
+pub mod logging;
+pub mod metrics;
+pub mod refresh;
+pub mod schedule;
 pub mod settings;
+pub mod shutdown;
+pub mod signature;
 pub mod state;
+pub mod telemetry;
 pub mod twerg;
-pub mod utils;
@@ -0,0 +1,88 @@
+//! The root logger, built from the settings, and the ids correlating the logs of a
+//! request with those of the docker and twerg calls it triggers.
+
+use slog::{o, Drain, Level, Logger};
+use snafu::ResultExt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::error;
+use crate::settings::{LogFormat, Logging};
+
+/// Header carrying the id of a request, taken from the client when supplied, and
+/// forwarded to twergs.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Build the root logger.
+pub fn logger(settings: &Logging) -> Result<Logger, error::Error> {
+    let level = Level::from_str(&settings.level).map_err(|_| error::Error::MiscError {
+        msg: format!("Unknown log level '{}'", settings.level),
+    })?;
+
+    let logger = match (settings.format, settings.output.as_str()) {
+        (LogFormat::Term, "stdout") => {
+            let decorator = slog_term::TermDecorator::new().stdout().build();
+            root(slog_term::FullFormat::new(decorator).build(), level)
+        }
+        (LogFormat::Term, "stderr") => {
+            let decorator = slog_term::TermDecorator::new().stderr().build();
+            root(slog_term::FullFormat::new(decorator).build(), level)
+        }
+        (LogFormat::Term, path) => {
+            let decorator = slog_term::PlainDecorator::new(open(path)?);
+            root(slog_term::FullFormat::new(decorator).build(), level)
+        }
+        (LogFormat::Json, output) => {
+            let writer: Box<dyn Write + Send> = match output {
+                "stdout" => Box::new(io::stdout()),
+                "stderr" => Box::new(io::stderr()),
+                path => Box::new(open(path)?),
+            };
+            root(
+                slog_json::Json::new(writer).add_default_keys().build(),
+                level,
+            )
+        }
+    };
+
+    Ok(logger)
+}
+
+fn root<D>(drain: D, level: Level) -> Logger
+where
+    D: Drain<Ok = (), Err = io::Error> + Send + 'static,
+{
+    let drain = drain.fuse().filter_level(level).fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    Logger::root(drain, o!())
+}
+
+fn open(path: &str) -> Result<std::fs::File, error::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(error::IOError {
+            msg: format!("Could not open log file {}", path),
+        })
+}
+
+/// The id of a request: the one supplied by the client, if it is printable and not
+/// too long, or a new one.
+pub fn request_id(supplied: Option<&str>) -> String {
+    match supplied {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic()) =>
+        {
+            String::from(id)
+        }
+        _ => Uuid::new_v4().to_string(),
+    }
+}
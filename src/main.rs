@@ -1,10 +1,11 @@
 use clap::{App, Arg, SubCommand};
-use slog::{o, warn, Drain};
 
 mod init;
 mod server;
 
 use nidavellir::error;
+use nidavellir::logging;
+use nidavellir::settings::Settings;
 
 #[tokio::main]
 async fn main() -> Result<(), error::Error> {
@@ -37,28 +38,21 @@ async fn main() -> Result<(), error::Error> {
                 .version("0.1")
                 .author("Matthieu Paindavoine <matt@area403.org>"),
         )
-        .subcommand(
-            SubCommand::with_name("test")
-                .about("Test Something")
-                .version("0.1")
-                .author("Matthieu Paindavoine <matt@area403.org>"),
-        )
         .get_matches();
 
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::FullFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
-    let logger = slog::Logger::root(drain, o!());
-
     match matches.subcommand() {
-        ("run", Some(sm)) => server::run(sm, logger).await,
-        ("init", Some(sm)) => init::init(sm, logger).await,
-        // ("test", Some(sm)) => test::test(sm, logger).await,
-        _ => {
-            warn!(logger, "Unrecognized subcommand");
-            Err(error::Error::MiscError {
-                msg: String::from("Unrecognized subcommand"),
-            })
+        ("run", Some(sm)) => {
+            let settings = Settings::new(sm)?;
+            let logger = logging::logger(&settings.logging)?;
+            server::run(settings, logger).await
+        }
+        ("init", Some(sm)) => {
+            let settings = Settings::new(sm)?;
+            let logger = logging::logger(&settings.logging)?;
+            init::init(settings, logger).await
         }
+        _ => Err(error::Error::MiscError {
+            msg: String::from("Unrecognized subcommand"),
+        }),
     }
 }
@@ -0,0 +1,266 @@
+//! Prometheus metrics, exported by the '/metrics' endpoint.
+//!
+//! Counters and histograms are updated as requests and operations complete. Gauges
+//! describing the content of the store are computed when the metrics are gathered.
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use serde::Serialize;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::db::model::ProvideData;
+use crate::db::Store;
+use crate::error;
+
+/// Buckets, in seconds, for operations on containers, which take from a fraction of a
+/// second to pull images and start twergs.
+const SLOW_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    graphql_requests: IntCounterVec,
+    graphql_duration: HistogramVec,
+    docker_duration: HistogramVec,
+    docker_failures: IntCounterVec,
+    provisioning_duration: Histogram,
+    store_connections: IntGaugeVec,
+    environments: IntGaugeVec,
+    indexes: IntGaugeVec,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, error::Error> {
+        let registry = Registry::new_custom(Some(String::from("nidavellir")), None).context(
+            error::MetricsError {
+                msg: "Could not create registry",
+            },
+        )?;
+
+        let graphql_requests = IntCounterVec::new(
+            Opts::new("graphql_requests_total", "GraphQL requests"),
+            &["operation", "outcome"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create graphql_requests_total",
+        })?;
+
+        let graphql_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "graphql_request_duration_seconds",
+                "Duration of GraphQL requests",
+            ),
+            &["operation"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create graphql_request_duration_seconds",
+        })?;
+
+        let docker_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "docker_operation_duration_seconds",
+                "Duration of operations on containers",
+            )
+            .buckets(SLOW_BUCKETS.to_vec()),
+            &["operation"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create docker_operation_duration_seconds",
+        })?;
+
+        let docker_failures = IntCounterVec::new(
+            Opts::new(
+                "docker_operation_failures_total",
+                "Failed operations on containers",
+            ),
+            &["operation"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create docker_operation_failures_total",
+        })?;
+
+        let provisioning_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "provisioning_duration_seconds",
+                "Duration of environment provisioning, from image resolution to running twerg",
+            )
+            .buckets(SLOW_BUCKETS.to_vec()),
+        )
+        .context(error::MetricsError {
+            msg: "Could not create provisioning_duration_seconds",
+        })?;
+
+        let store_connections = IntGaugeVec::new(
+            Opts::new("store_connections", "Connections of the store's pool"),
+            &["state"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create store_connections",
+        })?;
+
+        let environments = IntGaugeVec::new(
+            Opts::new("environments", "Environments by state"),
+            &["state"],
+        )
+        .context(error::MetricsError {
+            msg: "Could not create environments",
+        })?;
+
+        let indexes = IntGaugeVec::new(Opts::new("indexes", "Indexes by status"), &["status"])
+            .context(error::MetricsError {
+                msg: "Could not create indexes",
+            })?;
+
+        let metrics = Metrics {
+            registry,
+            graphql_requests,
+            graphql_duration,
+            docker_duration,
+            docker_failures,
+            provisioning_duration,
+            store_connections,
+            environments,
+            indexes,
+        };
+
+        metrics.register()?;
+
+        Ok(metrics)
+    }
+
+    fn register(&self) -> Result<(), error::Error> {
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(self.graphql_requests.clone()),
+            Box::new(self.graphql_duration.clone()),
+            Box::new(self.docker_duration.clone()),
+            Box::new(self.docker_failures.clone()),
+            Box::new(self.provisioning_duration.clone()),
+            Box::new(self.store_connections.clone()),
+            Box::new(self.environments.clone()),
+            Box::new(self.indexes.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .context(error::MetricsError {
+                    msg: "Could not register metric",
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Record a GraphQL request, given its operation name.
+    pub fn observe_graphql(&self, operation: &str, success: bool, duration: Duration) {
+        let outcome = if success { "success" } else { "failure" };
+        self.graphql_requests
+            .with_label_values(&[operation, outcome])
+            .inc();
+        self.graphql_duration
+            .with_label_values(&[operation])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record an operation on containers.
+    pub fn observe_docker(&self, operation: &str, success: bool, duration: Duration) {
+        self.docker_duration
+            .with_label_values(&[operation])
+            .observe(duration.as_secs_f64());
+        if !success {
+            self.docker_failures.with_label_values(&[operation]).inc();
+        }
+    }
+
+    /// Record the provisioning of an environment.
+    pub fn observe_provisioning(&self, duration: Duration) {
+        self.provisioning_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Update the gauges from the store, and return all the metrics in the
+    /// Prometheus text format.
+    pub async fn gather(&self, store: &dyn Store) -> Result<String, error::Error> {
+        if let Some(connections) = store.connections() {
+            let gauge = |state: &str, value: u32| {
+                self.store_connections
+                    .with_label_values(&[state])
+                    .set(i64::from(value))
+            };
+            gauge("open", connections.open);
+            gauge("idle", connections.idle);
+            gauge("max", connections.max);
+        }
+
+        let mut tx = store.begin().await.context(error::DBProvideError {
+            msg: "could not initiate transaction",
+        })?;
+
+        let environments = tx
+            .get_all_environments()
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not get all them environments",
+            })?;
+
+        let mut states: HashMap<String, i64> = HashMap::new();
+        let mut statuses: HashMap<String, i64> = HashMap::new();
+        for environment in environments {
+            *states.entry(label(&environment.state)).or_default() += 1;
+            let indexes = tx.get_environment_indexes(&environment.id).await.context(
+                error::DBProvideError {
+                    msg: "Could not get environment indexes",
+                },
+            )?;
+            for index in indexes {
+                *statuses.entry(label(&index.status)).or_default() += 1;
+            }
+        }
+
+        tx.commit().await.context(error::DBProvideError {
+            msg: "could not commit transaction",
+        })?;
+
+        // States and statuses no longer present are removed, rather than left at
+        // their previous count.
+        self.environments.reset();
+        for (state, count) in states {
+            self.environments
+                .with_label_values(&[state.as_str()])
+                .set(count);
+        }
+        self.indexes.reset();
+        for (status, count) in statuses {
+            self.indexes
+                .with_label_values(&[status.as_str()])
+                .set(count);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context(error::MetricsError {
+                msg: "Could not encode metrics",
+            })?;
+
+        String::from_utf8(buffer).map_err(|err| error::Error::MiscError {
+            msg: format!("Metrics are not valid UTF-8: {}", err),
+        })
+    }
+}
+
+/// The name of an enumeration's variant, as stored, eg 'indexing_in_progress'.
+fn label<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::from("unknown"),
+    }
+}
@@ -0,0 +1,495 @@
+//! Scheduled refreshes of indexes. When an index with a schedule is due, it is built
+//! again in its twerg, from fresh source data, and the new index replaces the one serving
+//! requests once it is available. Until then, or if the build fails, the previous index
+//! is served. Each refresh is recorded, with its outcome.
+//!
+//! Twergs serve each index through an alias, which is only pointed at a new version once
+//! it has passed validation. The versions it replaces are kept, up to the number given
+//! in the settings, so that the index can be rolled back to them.
+
+use chrono::Utc;
+use slog::{error, info, o, warn, Logger};
+use snafu::ResultExt;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::api::model::IndexRequestBody;
+use crate::cache;
+use crate::db::model::{
+    AuditOutcome, EnvironmentEntity, FinishedIndexRefreshEntity, IndexEntity, IndexRefreshEntity,
+    IndexStatus, ProvideData,
+};
+use crate::db::Transaction;
+use crate::error;
+use crate::events::{self, Event, EventKind};
+use crate::schedule::Schedule;
+use crate::state::State;
+use crate::twerg::client::Client;
+
+/// Spawn a task which periodically starts the refreshes of the indexes which are due,
+/// until the service shuts down.
+pub fn spawn_refresher(state: State) {
+    tokio::spawn(async move {
+        // Refreshes are not resumed after a restart, they are failed, and the next
+        // ones are run on schedule.
+        if let Err(err) = abandon(&state).await {
+            error!(state.logger, "Could not abandon index refreshes: {}", err);
+        }
+
+        let period = Duration::from_secs(state.settings.refresh.interval);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _in_flight = match state.shutdown.enter() {
+                Ok(guard) => guard,
+                Err(_) => break,
+            };
+            if let Err(err) = start_due(&state).await {
+                error!(state.logger, "Could not start index refreshes: {}", err);
+            }
+        }
+    });
+}
+
+/// Fail the refreshes interrupted by a restart.
+async fn abandon(state: &State) -> Result<(), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let abandoned = tx
+        .abandon_index_refreshes("Interrupted by a restart of the service")
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not abandon index refreshes",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    for refresh in abandoned {
+        info!(
+            state.logger,
+            "Refresh {} of index {} was interrupted", refresh.id, refresh.index
+        );
+    }
+    Ok(())
+}
+
+/// Start the refreshes of the indexes which are due, in the background, and schedule
+/// their next ones. An index still being refreshed is skipped. Return the refreshes
+/// started.
+pub async fn start_due(state: &State) -> Result<Vec<IndexRefreshEntity>, error::Error> {
+    let now = Utc::now();
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let due = tx
+        .get_due_indexes(&now)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get indexes due for a refresh",
+        })?;
+
+    let mut started = Vec::new();
+    for index in due {
+        // Schedules are checked when they are set, this is only in case the rules change.
+        let next = match index.schedule.parse::<Schedule>() {
+            Ok(schedule) => schedule.next_after(&now),
+            Err(err) => {
+                error!(
+                    state.logger,
+                    "Dropping schedule of index {}: {}", index.id, err
+                );
+                None
+            }
+        };
+        tx.schedule_index(
+            &index.id,
+            next.map(|_| index.schedule.as_str()),
+            next.as_ref(),
+        )
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not schedule index refresh",
+        })?;
+
+        match record(&mut *tx, &index.id).await? {
+            Some(refresh) => started.push((index.environment, refresh)),
+            None => info!(
+                state.logger,
+                "Index {} is still being refreshed, skipping", index.id
+            ),
+        }
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    for (environment, refresh) in started.iter() {
+        spawn(state, environment, refresh);
+    }
+
+    Ok(started.into_iter().map(|(_, refresh)| refresh).collect())
+}
+
+/// Start the refresh of an index now, in the background, whatever its schedule. Return
+/// the refresh started, or an error if the index is still being refreshed.
+pub async fn start(
+    state: &State,
+    environment: &Uuid,
+    index: &Uuid,
+) -> Result<IndexRefreshEntity, error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let refresh = record(&mut *tx, index).await?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    let refresh = refresh.ok_or_else(|| error::Error::MiscError {
+        msg: format!("Index {} is still being refreshed", index),
+    })?;
+    spawn(state, environment, &refresh);
+    Ok(refresh)
+}
+
+/// Record a new refresh of an index, unless it is still being refreshed, in which case
+/// there is nothing recorded, and None is returned.
+async fn record(
+    tx: &mut dyn Transaction,
+    index: &Uuid,
+) -> Result<Option<IndexRefreshEntity>, error::Error> {
+    let refreshes = tx
+        .get_index_refreshes(index)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get index refreshes",
+        })?;
+    if refreshes
+        .iter()
+        .any(|refresh| refresh.finished_at.is_none())
+    {
+        return Ok(None);
+    }
+
+    let refresh = tx
+        .create_index_refresh(index)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not record index refresh",
+        })?;
+    Ok(Some(refresh))
+}
+
+/// Run a refresh in the background.
+fn spawn(state: &State, environment: &Uuid, refresh: &IndexRefreshEntity) {
+    let state = state.clone();
+    let environment = *environment;
+    let refresh = refresh.clone();
+    // The refresh was started by a mutation or the refresher, both in flight, and is
+    // waited for as they are when the service shuts down.
+    let in_flight = state.shutdown.hold();
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+        if let Err(err) = run(&state, &environment, refresh).await {
+            error!(state.logger, "Index refresh failed: {}", err);
+        }
+    });
+}
+
+/// Run a refresh, swap the index in if it is built, and record the outcome. Failing to
+/// build the index is an outcome, not an error.
+async fn run(
+    state: &State,
+    environment: &Uuid,
+    refresh: IndexRefreshEntity,
+) -> Result<IndexRefreshEntity, error::Error> {
+    let logger = state.logger.new(o!("index" => refresh.index.to_string()));
+    let started = Instant::now();
+
+    let mut finished = FinishedIndexRefreshEntity {
+        twerg_id: None,
+        status: None,
+        outcome: AuditOutcome::Success,
+        error: None,
+    };
+
+    let (environment, index) = match find(state, environment, &refresh.index).await {
+        Ok(found) => found,
+        Err(err) => {
+            // The refresh is finished anyway, so that the next ones are not skipped.
+            finished.outcome = AuditOutcome::Failure;
+            finished.error = Some(err.to_string());
+            finish(state, &refresh, &finished).await?;
+            return Err(err);
+        }
+    };
+    info!(
+        logger,
+        "Refreshing index in environment {}", environment.name
+    );
+
+    let published = async {
+        let version = build(state, &environment, &index, &mut finished, &logger).await?;
+        publish(
+            state,
+            &state.twerg,
+            &environment,
+            &index,
+            version,
+            true,
+            &logger,
+        )
+        .await
+    }
+    .await;
+    if let Err(err) = published {
+        error!(logger, "Could not refresh index: {}", err);
+        finished.outcome = AuditOutcome::Failure;
+        finished.error = Some(err.to_string());
+    }
+
+    let refresh = finish(state, &refresh, &finished).await?;
+
+    let message = match &refresh.error {
+        None => format!(
+            "Index {} {} {} was refreshed in {:?}",
+            index.index_type,
+            index.data_source,
+            index.regions.join(","),
+            started.elapsed()
+        ),
+        Some(err) => format!(
+            "Index {} {} {} could not be refreshed: {}",
+            index.index_type,
+            index.data_source,
+            index.regions.join(","),
+            err
+        ),
+    };
+    info!(logger, "{}", message);
+    events::publish(
+        &state.events,
+        Event::new(
+            Some(environment.id),
+            &environment.name,
+            EventKind::IndexRefresh,
+            message,
+        ),
+    );
+
+    Ok(refresh)
+}
+
+/// Record the outcome of a refresh.
+async fn finish(
+    state: &State,
+    refresh: &IndexRefreshEntity,
+    finished: &FinishedIndexRefreshEntity,
+) -> Result<IndexRefreshEntity, error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let refresh = tx
+        .finish_index_refresh(&refresh.id, finished)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not record index refresh",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    Ok(refresh)
+}
+
+/// The environment of an index, and the index.
+async fn find(
+    state: &State,
+    environment: &Uuid,
+    index: &Uuid,
+) -> Result<(EnvironmentEntity, IndexEntity), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let entity = tx
+        .get_environment_by_id(environment)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment",
+        })?;
+
+    let indexes = tx
+        .get_environment_indexes(environment)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment indexes",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    let index = indexes
+        .into_iter()
+        .find(|candidate| candidate.id == *index)
+        .ok_or_else(|| error::Error::MiscError {
+            msg: format!("Index {} not found in environment {}", index, entity.name),
+        })?;
+
+    Ok((entity, index))
+}
+
+/// Build a new version of the index in the twerg, and wait until it is available,
+/// recording its progress in `finished`. Return the id of the version in the twerg.
+async fn build(
+    state: &State,
+    environment: &EnvironmentEntity,
+    index: &IndexEntity,
+    finished: &mut FinishedIndexRefreshEntity,
+    logger: &Logger,
+) -> Result<i32, error::Error> {
+    let request = IndexRequestBody {
+        environment: environment.id,
+        index_type: index.index_type.clone(),
+        data_source: index.data_source.clone(),
+        regions: index.regions.clone(),
+        reuse: None,
+    };
+
+    let file = cache::source_file(state, &index.data_source, &index.regions, logger).await;
+    let endpoint = state.twerg.endpoint(&environment.name, environment.port);
+    let id = state
+        .twerg
+        .create_index(&endpoint, &request, file.as_deref(), logger)
+        .await?;
+    finished.twerg_id = Some(id);
+
+    let settings = &state.settings.refresh;
+    let deadline = Instant::now() + Duration::from_secs(settings.timeout);
+    loop {
+        let status = state.twerg.index_status(&endpoint, id, logger).await?;
+        finished.status = Some(status.clone());
+        match status {
+            IndexStatus::Available => return Ok(id),
+            IndexStatus::DownloadingError
+            | IndexStatus::ProcessingError
+            | IndexStatus::IndexingError
+            | IndexStatus::ValidationError => {
+                return Err(error::Error::TwergError {
+                    msg: format!("Index {} failed with status {:?}", id, status),
+                })
+            }
+            _ => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(error::Error::TwergError {
+                msg: format!(
+                    "Index {} is not available after {} seconds",
+                    id, settings.timeout
+                ),
+            });
+        }
+        tokio::time::delay_for(Duration::from_secs(settings.poll)).await;
+    }
+}
+
+/// Serve an available version of an index, pointing the alias of the twerg at it. The
+/// version it replaces is kept for rollbacks if `retain` is set, and deleted from the
+/// twerg otherwise, as are the oldest versions beyond those kept. Return the index.
+pub async fn publish(
+    state: &State,
+    twerg: &Client,
+    environment: &EnvironmentEntity,
+    index: &IndexEntity,
+    version: i32,
+    retain: bool,
+    logger: &Logger,
+) -> Result<IndexEntity, error::Error> {
+    let endpoint = twerg.endpoint(&environment.name, environment.port);
+    twerg.publish_index(&endpoint, version, logger).await?;
+
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let published = tx
+        .swap_index(&index.id, version, &IndexStatus::Available)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not swap index",
+        })?;
+
+    let mut versions = tx
+        .get_index_versions(&index.id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get index versions",
+        })?;
+
+    // The version published is no longer a previous one.
+    if let Some(position) = versions.iter().position(|kept| kept.twerg_id == version) {
+        let kept = versions.remove(position);
+        tx.delete_index_version(&index.id, kept.twerg_id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not delete index version",
+            })?;
+    }
+
+    let mut discarded = Vec::new();
+    match index.twerg_id.filter(|replaced| *replaced != version) {
+        Some(replaced) if retain => {
+            let kept = tx.create_index_version(&index.id, replaced).await.context(
+                error::DBProvideError {
+                    msg: "Could not keep index version",
+                },
+            )?;
+            versions.insert(0, kept);
+        }
+        Some(replaced) => discarded.push(replaced),
+        None => {}
+    }
+
+    for pruned in versions.iter().skip(state.settings.refresh.versions) {
+        tx.delete_index_version(&index.id, pruned.twerg_id)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not delete index version",
+            })?;
+        discarded.push(pruned.twerg_id);
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    info!(
+        logger,
+        "Published version {} of index {}", version, index.id
+    );
+
+    // The versions no longer served are only taking space, failing to delete them is
+    // not worth failing the publication.
+    for id in discarded {
+        if let Err(err) = twerg.delete_index(&endpoint, id, logger).await {
+            warn!(
+                logger,
+                "Could not delete version {} of index {}: {}", id, index.id, err
+            );
+        }
+    }
+
+    Ok(published)
+}
@@ -0,0 +1,143 @@
+//! Cron schedules of five fields: minutes, hours, days of the month, months and days of
+//! the week, always in UTC. A field is `*`, a value, a range such as `1-5`, either with
+//! a step such as `*/15`, or a comma separated list of those. Days of the week run from
+//! 0, Sunday, to 6, and 7 is also Sunday. As with cron, when both the days of the month
+//! and the days of the week are restricted, a day matching either is on the schedule; a
+//! field starting with `*`, eg `*/2`, does not restrict them.
+
+use chrono::{Date, DateTime, Datelike, Duration, Timelike, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    /// Indexed by the day of the month, from 1
+    days: Vec<bool>,
+    /// Indexed by the month, from 1
+    months: Vec<bool>,
+    /// Indexed by the number of days from Sunday
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// The first minute strictly after the given date which is on the schedule. None if
+    /// there is none, eg for February 30th.
+    pub fn next_after(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        // Every day of every month, February 29th included, comes within four years.
+        for _ in 0..(4 * 366) {
+            if self.on_day(&date) {
+                let (first_hour, first_minute) = if date == start.date() {
+                    (start.hour(), start.minute())
+                } else {
+                    (0, 0)
+                };
+                for hour in (first_hour..24).filter(|hour| self.hours[*hour as usize]) {
+                    let from = if hour == first_hour { first_minute } else { 0 };
+                    if let Some(minute) = (from..60).find(|minute| self.minutes[*minute as usize]) {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn on_day(&self, date: &Date<Utc>) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        let day = if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day && self.months[date.month() as usize]
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = error::Error;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let invalid = || error::Error::InvalidArgument {
+            field: String::from("schedule"),
+            value: String::from(expression),
+            valid: vec![String::from(
+                "minute hour day-of-month month day-of-week, eg '0 3 * * 0'",
+            )],
+        };
+
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+
+        let (minutes, _) = field(fields[0], 0, 59).ok_or_else(invalid)?;
+        let (hours, _) = field(fields[1], 0, 23).ok_or_else(invalid)?;
+        let (days, days_restricted) = field(fields[2], 1, 31).ok_or_else(invalid)?;
+        let (months, _) = field(fields[3], 1, 12).ok_or_else(invalid)?;
+        let (mut weekdays, weekdays_restricted) = field(fields[4], 0, 7).ok_or_else(invalid)?;
+        weekdays[0] |= weekdays[7];
+        weekdays.truncate(7);
+
+        let schedule = Schedule {
+            expression: fields.join(" "),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            days_restricted,
+            weekdays_restricted,
+        };
+
+        // A schedule which never comes is a mistake.
+        match schedule.next_after(&Utc::now()) {
+            Some(_) => Ok(schedule),
+            None => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// The values allowed by a field, indexed from 0, and whether the field restricts them,
+/// that is, whether it does not start with `*`.
+fn field(text: &str, min: u32, max: u32) -> Option<(Vec<bool>, bool)> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in text.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(slash) => (&part[..slash], part[slash + 1..].parse::<u32>().ok()?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(dash) = range.find('-') {
+            (range[..dash].parse().ok()?, range[dash + 1..].parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // As in cron, a single value with a step runs to the end of the field.
+            (value, if step > 1 { max } else { value })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Some((allowed, !text.starts_with('*')))
+}
@@ -1,19 +1,42 @@
-use clap::ArgMatches;
+use futures::FutureExt;
+use juniper::http::GraphQLBatchRequest;
+use juniper_graphql_ws::ConnectionConfig;
 use juniper_warp::playground_filter;
-use slog::{debug, info, Logger};
+use juniper_warp::subscriptions::serve_graphql_ws;
+use opentelemetry::trace::{FutureExt as _, SpanKind, StatusCode as SpanStatus, TraceContextExt};
+use opentelemetry::KeyValue;
+use slog::{debug, info, warn, Logger};
 use snafu::ResultExt;
+use std::convert::Infallible;
 use std::net::ToSocketAddrs;
-use warp::{self, Filter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use warp::http::{HeaderMap, StatusCode};
+use warp::{self, Filter, Rejection, Reply};
 
-use nidavellir::api::gql;
+use nidavellir::api::{gql, rest};
+use nidavellir::cache;
 use nidavellir::error;
+use nidavellir::expiry;
+use nidavellir::health;
+use nidavellir::logging;
+use nidavellir::refresh;
 use nidavellir::settings::Settings;
+use nidavellir::shutdown;
 use nidavellir::state::State;
+use nidavellir::telemetry;
 
-#[allow(clippy::needless_lifetimes)]
-pub async fn run<'a>(matches: &ArgMatches<'a>, logger: Logger) -> Result<(), error::Error> {
-    let settings = Settings::new(matches)?;
+/// How long connections are given to close once the server stops.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub async fn run(settings: Settings, logger: Logger) -> Result<(), error::Error> {
+    let _telemetry = telemetry::init(&settings.telemetry)?;
     let state = State::new(&settings, &logger).await?;
+    shutdown::recover(&state).await?;
+    expiry::spawn_sweeper(state.clone());
+    cache::spawn_evictor(state.clone());
+    refresh::spawn_refresher(state.clone());
     run_server(state).await
 }
 
@@ -21,32 +44,187 @@ pub async fn run_server(state: State) -> Result<(), error::Error> {
     // We keep a copy of the logger before the context takes ownership of it.
     debug!(state.logger, "Entering server");
     let state1 = state.clone();
-    let qm_state1 = warp::any().map(move || gql::Context {
-        state: state1.clone(),
-    });
+    let qm_state1 = warp::any()
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>(logging::REQUEST_ID_HEADER))
+        .and_then(
+            move |authorization: Option<String>, request_id: Option<String>| {
+                let state = state1.clone();
+                async move {
+                    match state.auth.authenticate(authorization.as_deref()) {
+                        Ok(identity) => {
+                            let request_id = logging::request_id(request_id.as_deref());
+                            Ok(gql::Context::new(state, identity, request_id))
+                        }
+                        Err(err) => {
+                            warn!(state.logger, "Rejecting request: {}", err);
+                            Err(warp::reject::custom(Unauthenticated {
+                                msg: format!("{}", err),
+                            }))
+                        }
+                    }
+                }
+            },
+        );
+
+    let qm_state1 = qm_state1.boxed();
+
+    let root_node = Arc::new(gql::schema());
 
-    let qm_schema = gql::schema();
+    // GraphQL requests are executed here, rather than with juniper_warp's filter,
+    // to time them by operation.
+    let graphql_root_node = root_node.clone();
     let graphql = warp::post()
         .and(warp::path("graphql"))
-        .and(juniper_warp::make_graphql_filter(
-            qm_schema,
-            qm_state1.boxed(),
-        ));
+        .and(qm_state1.clone())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and_then(
+            move |context: gql::Context, headers: HeaderMap, request: GraphQLBatchRequest| {
+                let root_node = graphql_root_node.clone();
+                async move {
+                    let operation = operation_name(&request);
+                    debug!(context.logger, "Executing {}", operation);
+                    let span = telemetry::start(
+                        &telemetry::remote_context(&headers),
+                        SpanKind::Server,
+                        &format!("graphql {}", operation),
+                        vec![KeyValue::new("request_id", context.request_id.clone())],
+                    );
+                    let start = Instant::now();
+                    let response = request
+                        .execute(&root_node, &context)
+                        .with_context(span.clone())
+                        .await;
+                    let elapsed = start.elapsed();
+                    if !response.is_ok() {
+                        span.span()
+                            .set_status(SpanStatus::Error, String::from("GraphQL errors"));
+                    }
+                    span.span().end();
+                    context
+                        .state
+                        .metrics
+                        .observe_graphql(&operation, response.is_ok(), elapsed);
+                    debug!(context.logger, "Executed {} in {:?}", operation, elapsed);
+                    let code = if response.is_ok() {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+                    let mut body = serde_json::to_value(&response).unwrap_or_default();
+                    add_request_id(&mut body, &context.request_id);
+                    Ok::<_, Rejection>(warp::reply::with_header(
+                        warp::reply::with_status(warp::reply::json(&body), code),
+                        logging::REQUEST_ID_HEADER,
+                        context.request_id.as_str(),
+                    ))
+                }
+            },
+        );
+
+    // The REST API, for clients which cannot speak GraphQL.
+    let rest = rest::routes(qm_state1.clone());
+
+    let ws_logger = state.logger.clone();
+    let subscriptions = warp::path("subscriptions")
+        .and(warp::ws())
+        .and(qm_state1)
+        .map(move |ws: warp::ws::Ws, context: gql::Context| {
+            let root_node = root_node.clone();
+            let logger = ws_logger.clone();
+            ws.on_upgrade(move |websocket| async move {
+                serve_graphql_ws(websocket, root_node, ConnectionConfig::new(context))
+                    .map(move |res| {
+                        if let Err(err) = res {
+                            warn!(logger, "Websocket error: {}", err);
+                        }
+                    })
+                    .await
+            })
+        })
+        .map(|reply| warp::reply::with_header(reply, "Sec-WebSocket-Protocol", "graphql-ws"));
+
+    let metrics_state = state.clone();
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and_then(move || {
+            let state = metrics_state.clone();
+            async move {
+                match state.metrics.gather(state.store.as_ref()).await {
+                    Ok(body) => Ok(warp::reply::with_header(
+                        body,
+                        "content-type",
+                        prometheus::TEXT_FORMAT,
+                    )),
+                    Err(err) => {
+                        warn!(state.logger, "Could not gather metrics: {}", err);
+                        Err(warp::reject::custom(Unavailable {
+                            msg: format!("{}", err),
+                        }))
+                    }
+                }
+            }
+        });
+
+    // Probes for orchestrators: the process is alive as long as it answers, and ready
+    // when it can reach its store and docker.
+    let healthz = warp::get()
+        .and(warp::path("healthz"))
+        .and(warp::path::end())
+        .map(|| warp::reply::json(&serde_json::json!({ "status": "ok" })));
+
+    let readyz_state = state.clone();
+    let readyz = warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .and_then(move || {
+            let state = readyz_state.clone();
+            async move {
+                let readiness = health::readiness(&state).await;
+                let code = if readiness.status == health::Status::Ok {
+                    StatusCode::OK
+                } else {
+                    warn!(state.logger, "Not ready: {:?}", readiness.checks);
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&readiness),
+                    code,
+                ))
+            }
+        });
 
     let playground = warp::get()
         .and(warp::path("playground"))
         .and(playground_filter("/graphql", Some("/subscriptions")));
 
     let cors = warp::cors()
-        .allow_any_origin()
-        .allow_methods(vec!["GET", "POST"])
+        .allow_origins(
+            state
+                .settings
+                .service
+                .cors_origins
+                .iter()
+                .map(String::as_str),
+        )
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
         .allow_headers(vec!["content-type", "authorization"])
-        .allow_any_origin()
         .build();
 
     let log = warp::log("nidavellir::graphql");
 
-    let routes = playground.or(graphql).with(cors).with(log);
+    let routes = healthz
+        .or(readyz)
+        .or(playground)
+        .or(metrics)
+        .or(graphql)
+        .or(rest)
+        .or(subscriptions)
+        .recover(handle_rejection)
+        .with(cors)
+        .with(log);
 
     let host = state.settings.service.host;
     let port = state.settings.service.port;
@@ -61,8 +239,100 @@ pub async fn run_server(state: State) -> Result<(), error::Error> {
             msg: String::from("Cannot resolve addr"),
         })?;
 
+    // The server stops accepting connections once mutations in flight are drained,
+    // or the deadline has passed. Operations still in progress are then recovered at
+    // the next start.
+    let (stop, stopped) = oneshot::channel::<()>();
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, async move {
+        stopped.await.ok();
+    });
+    let server = tokio::spawn(server);
+
     info!(state.logger, "Serving Nidavellir");
-    warp::serve(routes).run(addr).await;
+    shutdown::signal_received().await?;
+
+    let deadline = Duration::from_secs(state.settings.shutdown.deadline);
+    info!(
+        state.logger,
+        "Shutting down, draining {} mutations in flight",
+        state.shutdown.in_flight()
+    );
+    if state.shutdown.drain(deadline).await {
+        info!(state.logger, "Mutations in flight drained");
+    } else {
+        warn!(
+            state.logger,
+            "Interrupting {} mutations still in flight after {} seconds",
+            state.shutdown.in_flight(),
+            deadline.as_secs()
+        );
+    }
+
+    stop.send(()).ok();
+    // Open connections, such as subscriptions, are not waited for.
+    if tokio::time::timeout(CLOSE_TIMEOUT, server).await.is_err() {
+        warn!(state.logger, "Closing connections still open");
+    }
 
     Ok(())
 }
+
+/// Rejection used when the credentials supplied with a request are invalid.
+#[derive(Debug)]
+struct Unauthenticated {
+    msg: String,
+}
+
+impl warp::reject::Reject for Unauthenticated {}
+
+/// Rejection used when a resource cannot be served, eg the store is unreachable.
+#[derive(Debug)]
+struct Unavailable {
+    msg: String,
+}
+
+impl warp::reject::Reject for Unavailable {}
+
+/// The name of the operation of a request, to label its metrics.
+fn operation_name(request: &GraphQLBatchRequest) -> String {
+    match request {
+        GraphQLBatchRequest::Single(request) => {
+            String::from(request.operation_name().unwrap_or("anonymous"))
+        }
+        GraphQLBatchRequest::Batch(_) => String::from("batch"),
+    }
+}
+
+/// Return the request id in the extensions of the response, or of each response of
+/// a batch.
+fn add_request_id(body: &mut serde_json::Value, request_id: &str) {
+    match body {
+        serde_json::Value::Array(responses) => {
+            for response in responses {
+                add_request_id(response, request_id);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            fields.insert(
+                String::from("extensions"),
+                serde_json::json!({ "requestId": request_id }),
+            );
+        }
+        _ => {}
+    }
+}
+
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (code, msg) = if let Some(Unauthenticated { msg }) = err.find() {
+        (StatusCode::UNAUTHORIZED, msg.clone())
+    } else if let Some(Unavailable { msg }) = err.find() {
+        (StatusCode::SERVICE_UNAVAILABLE, msg.clone())
+    } else if err.is_not_found() {
+        (StatusCode::NOT_FOUND, String::from("Not Found"))
+    } else {
+        (StatusCode::BAD_REQUEST, format!("{:?}", err))
+    };
+
+    let json = warp::reply::json(&serde_json::json!({ "error": msg }));
+    Ok(warp::reply::with_status(json, code))
+}
@@ -2,8 +2,10 @@ use clap::ArgMatches;
 use config::{Config, Environment, File};
 use serde::Deserialize;
 use snafu::ResultExt;
+use std::collections::HashMap;
 use std::env;
 
+use super::auth::Role;
 use super::error;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -12,11 +14,45 @@ pub struct Twerg {
     pub base: u16,
     /// Twerg configuration file
     pub config: String,
+    /// Other twerg configuration files, by template name, which environments can be
+    /// created from
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+    /// Memory, in MiB, accounted against project quotas for each service
+    /// without a memory limit
+    pub memory: i32,
+    pub client: TwergClient,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwergClient {
+    /// GraphQL endpoint of twergs, where '{name}' and '{port}' are replaced by the
+    /// environment's name and port.
+    pub url: String,
+    /// GraphQL endpoints of the twergs of some environments, by environment name, in
+    /// place of `url`. They are templates as well.
+    #[serde(default)]
+    pub urls: HashMap<String, String>,
+    /// Request timeout, in seconds
+    pub timeout: u64,
+    /// Number of times a failed request is retried
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds. It doubles with each retry.
+    pub backoff: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Indexes {
+    /// Identifiers of the regions indexes can be requested for, eg 'fr'
+    pub regions: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DockerRegistry {
+    /// Address of the registry images are pulled from, eg 'localhost:5000'
     pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -28,6 +64,116 @@ pub struct Database {
 pub struct Service {
     pub host: String,
     pub port: u16,
+    /// Origins allowed to make cross origin requests
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiToken {
+    /// Name recorded as the owner of what is created with this token
+    pub subject: String,
+    pub role: Role,
+    /// Hex encoded SHA-256 digest of the token
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Auth {
+    /// JWT signing algorithm, either HS256 or RS256
+    pub algorithm: String,
+    /// Shared secret, for HS256
+    pub secret: Option<String>,
+    /// Path to the PEM encoded public key, for RS256
+    pub public_key: Option<String>,
+    /// Static API tokens
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Expiry {
+    /// Seconds between two sweeps for expired environments
+    pub interval: u64,
+    /// Seconds before expiry at which a warning is emitted
+    pub warning: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Refresh {
+    /// Seconds between two checks for indexes due for a refresh
+    pub interval: u64,
+    /// Seconds between two queries of the status of an index being built
+    pub poll: u64,
+    /// Seconds after which a refresh which has not completed is failed
+    pub timeout: u64,
+    /// Number of previous versions of an index kept for rollbacks
+    pub versions: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, colored on a terminal
+    Term,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Logging {
+    pub format: LogFormat,
+    /// Minimum level logged, eg 'info' or 'debug'
+    pub level: String,
+    /// 'stdout', 'stderr', or the path of a file logs are appended to
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Telemetry {
+    /// Whether spans are exported
+    pub enabled: bool,
+    /// OTLP endpoint of the collector spans are exported to
+    pub endpoint: String,
+    /// Name of the service, as reported to the collector
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Shutdown {
+    /// Seconds given to mutations in flight to complete, once a termination signal
+    /// is received
+    pub deadline: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snapshots {
+    /// Directory where snapshots are archived. It must be the same path on the
+    /// docker host and for this service.
+    pub directory: String,
+    /// Image used to archive and restore volumes, it must provide tar
+    pub image: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Cache {
+    /// Directory where the source data of indexes is cached. It must be the same path
+    /// on the docker host and for this service.
+    pub directory: String,
+    /// Where the cache is mounted, read-only, in the containers of `services`
+    pub target: String,
+    /// Services ingesting source data, which the cache is mounted in
+    pub services: Vec<String>,
+    /// Size of the cache, in MiB, above which the least recently used files are evicted
+    pub max_size: u64,
+    /// Seconds after which a file which has not been used is evicted
+    pub max_age: u64,
+    /// Seconds between two evictions
+    pub interval: u64,
+    /// URLs of source files by data source, where '{region}' is replaced by the region.
+    /// Data sources without a URL are not cached, twergs download them.
+    #[serde(default)]
+    pub sources: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,8 +182,18 @@ pub struct Settings {
     pub testing: bool,
     pub mode: String,
     pub twerg: Twerg,
+    pub indexes: Indexes,
     pub database: Database,
     pub service: Service,
+    pub logging: Logging,
+    pub telemetry: Telemetry,
+    pub auth: Auth,
+    pub expiry: Expiry,
+    pub refresh: Refresh,
+    pub shutdown: Shutdown,
+    pub snapshots: Snapshots,
+    pub cache: Cache,
+    pub registry: DockerRegistry,
 }
 
 // TODO Parameterize the config directory
@@ -69,25 +225,48 @@ impl Settings {
             })?;
 
         // Add in settings from the environment (with a prefix of APP)
-        // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-        s.merge(Environment::with_prefix("app"))
+        // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key, and
+        // `APP_AUTH__SECRET=...` the `auth.secret` key
+        s.merge(Environment::with_prefix("app").separator("__"))
             .context(error::ConfigError {
                 msg: String::from("Could not merge configuration from environment variables"),
             })?;
 
         // Now we take care of the database.url, which can be had from environment variables.
+        // Its scheme selects the store, see db::connect.
         let key = match mode.as_str() {
             "testing" => "DATABASE_TEST_URL",
             _ => "DATABASE_URL",
         };
 
-        let db_url = env::var(key).context(error::EnvVarError {
-            msg: format!("Could not get env var {}", key),
-        })?;
+        if let Ok(db_url) = env::var(key) {
+            s.set("database.url", db_url).context(error::ConfigError {
+                msg: String::from("Could not set database url from environment variable"),
+            })?;
+        }
 
-        s.set("database.url", db_url).context(error::ConfigError {
-            msg: String::from("Could not set database url from environment variable"),
-        })?;
+        // Logging can also be configured from environment variables, eg to log JSON
+        // in a container.
+        for (var, key) in &[
+            ("LOG_FORMAT", "logging.format"),
+            ("LOG_LEVEL", "logging.level"),
+            ("LOG_OUTPUT", "logging.output"),
+        ] {
+            if let Ok(value) = env::var(var) {
+                s.set(key, value).context(error::ConfigError {
+                    msg: format!("Could not set {} from environment variable", key),
+                })?;
+            }
+        }
+
+        // The collector's endpoint, under its standard name. Setting it enables the export.
+        if let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            s.set("telemetry.endpoint", endpoint)
+                .and_then(|s| s.set("telemetry.enabled", true))
+                .context(error::ConfigError {
+                    msg: String::from("Could not set telemetry endpoint from environment variable"),
+                })?;
+        }
 
         let m = matches.into();
         if let Some(m) = m {
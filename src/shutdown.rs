@@ -0,0 +1,462 @@
+//! Graceful shutdown. Once a termination signal is received, mutations are refused,
+//! and those in flight are given a deadline to complete.
+//!
+//! Provisioning environments and creating indexes are recorded in the store while
+//! they are in progress, so that those interrupted anyway are found at the next
+//! start: a partially created twerg is removed, and an index request is resumed.
+
+use serde::Serialize;
+use serde_json::json;
+use slog::{info, warn};
+use snafu::ResultExt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::api::gql::Context;
+use crate::api::model::{self, IndexRequestBody};
+use crate::audit;
+use crate::auth::{Identity, Role};
+use crate::db::model::{
+    InputIndexEntity, InputOperationEntity, OperationEntity, OperationKind, ProvideData,
+};
+use crate::docker::{self, ServiceConfig};
+use crate::error;
+use crate::state::State;
+
+/// Tracks the mutations in flight, and whether new ones are still accepted.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    /// Notified when the last mutation in flight completes.
+    idle: Notify,
+}
+
+/// A mutation in flight, until it is dropped.
+#[derive(Debug)]
+pub struct Guard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Admit a mutation, unless the service is shutting down.
+    pub fn enter(&self) -> Result<Guard, error::Error> {
+        // The mutation is counted before the flag is checked, so that it is either
+        // refused, or waited for.
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard {
+            inner: self.inner.clone(),
+        };
+        if self.is_draining() {
+            return Err(error::Error::ShuttingDown {
+                msg: String::from("No new mutation is accepted"),
+            });
+        }
+        Ok(guard)
+    }
+
+    /// Count background work started by a mutation, eg a refresh, until the guard is
+    /// dropped. Unlike `enter`, it is admitted while draining, as the mutation which
+    /// started it was.
+    pub fn hold(&self) -> Guard {
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        Guard {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new mutations, and wait for those in flight to complete, for at most
+    /// the deadline. Returns whether they all completed.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        self.inner.draining.store(true, Ordering::SeqCst);
+        let idle = async {
+            while self.in_flight() > 0 {
+                self.inner.idle.notified().await;
+            }
+        };
+        tokio::time::timeout(deadline, idle).await.is_ok()
+    }
+}
+
+/// Wait for SIGTERM or SIGINT.
+pub async fn signal_received() -> Result<(), error::Error> {
+    let mut terminate = signal(SignalKind::terminate()).context(error::IOError {
+        msg: String::from("Could not listen to SIGTERM"),
+    })?;
+    let mut interrupt = signal(SignalKind::interrupt()).context(error::IOError {
+        msg: String::from("Could not listen to SIGINT"),
+    })?;
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = interrupt.recv() => {},
+    }
+    Ok(())
+}
+
+/// An operation recorded in the store while it is in progress.
+pub struct Journal<'a> {
+    state: &'a State,
+    operation: InputOperationEntity,
+}
+
+impl<'a> Journal<'a> {
+    /// The provisioning of an environment, with its services configuration and the
+    /// snapshot restored, if any.
+    pub fn provisioning(
+        state: &'a State,
+        actor: &str,
+        name: &str,
+        config: &[ServiceConfig],
+        restore: Option<&str>,
+    ) -> Self {
+        let arguments = json!({ "config": config, "restore": restore });
+        Journal::new(
+            state,
+            OperationKind::ProvisionEnvironment,
+            actor,
+            name,
+            &arguments,
+        )
+    }
+
+    /// The creation of an index in the given environment.
+    pub fn index(state: &'a State, actor: &str, name: &str, request: &IndexRequestBody) -> Self {
+        Journal::new(state, OperationKind::CreateIndex, actor, name, request)
+    }
+
+    fn new<A: Serialize>(
+        state: &'a State,
+        kind: OperationKind,
+        actor: &str,
+        name: &str,
+        arguments: &A,
+    ) -> Self {
+        Journal {
+            state,
+            operation: InputOperationEntity {
+                kind,
+                actor: String::from(actor),
+                name: String::from(name),
+                arguments: serde_json::to_value(arguments).unwrap_or_default(),
+            },
+        }
+    }
+
+    /// Run the operation, recorded until it completes, successfully or not. If it is
+    /// interrupted, the record is left for `recover`.
+    pub async fn run<T, F>(self, operation: F) -> Result<T, error::Error>
+    where
+        F: Future<Output = Result<T, error::Error>>,
+    {
+        self.run_with(|_| operation).await
+    }
+
+    /// Run the operation like `run`, letting it record its progress, so that it is
+    /// recovered from where it was interrupted.
+    pub async fn run_with<T, F, Fut>(self, operation: F) -> Result<T, error::Error>
+    where
+        F: FnOnce(Progress<'a>) -> Fut,
+        Fut: Future<Output = Result<T, error::Error>>,
+    {
+        let Journal {
+            state,
+            operation: input,
+        } = self;
+
+        let recorded = create_operation(state, &input).await?;
+        let progress = Progress {
+            state,
+            id: recorded.id,
+            arguments: recorded.arguments.clone(),
+        };
+
+        let result = operation(progress).await;
+
+        if let Err(err) = delete_operation(state, &recorded.id).await {
+            warn!(
+                state.logger,
+                "Could not remove {:?} of {} from the operations in progress: {}",
+                recorded.kind,
+                recorded.name,
+                err
+            );
+        }
+
+        result
+    }
+}
+
+/// The progress of an operation in progress, recorded with its arguments.
+pub struct Progress<'a> {
+    state: &'a State,
+    id: Uuid,
+    arguments: serde_json::Value,
+}
+
+impl Progress<'_> {
+    /// Record a step of the operation, eg the id of what it created, under the given
+    /// key of its arguments.
+    pub async fn record<V: Serialize>(&mut self, key: &str, value: &V) -> Result<(), error::Error> {
+        self.arguments[key] = serde_json::to_value(value).unwrap_or_default();
+        update_operation(self.state, &self.id, &self.arguments)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Roll back or resume the operations interrupted when the service last stopped.
+/// Each is then forgotten, whether it could be recovered or not.
+pub async fn recover(state: &State) -> Result<(), error::Error> {
+    let operations = get_all_operations(state).await?;
+
+    for operation in operations {
+        info!(
+            state.logger,
+            "Recovering {:?} of {}, interrupted since {}",
+            operation.kind,
+            operation.name,
+            operation.started_at
+        );
+        let result = match operation.kind {
+            OperationKind::ProvisionEnvironment => roll_back_provisioning(state, &operation).await,
+            OperationKind::CreateIndex => resume_index(state, &operation).await,
+        };
+        if let Err(err) = result {
+            warn!(
+                state.logger,
+                "Could not recover {:?} of {}: {}", operation.kind, operation.name, err
+            );
+        }
+        delete_operation(state, &operation.id).await?;
+    }
+
+    Ok(())
+}
+
+/// Remove what was created of the twerg, unless the environment was recorded, in
+/// which case only the removal of the operation was interrupted.
+async fn roll_back_provisioning(
+    state: &State,
+    operation: &OperationEntity,
+) -> Result<(), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let environments = tx
+        .get_all_environments()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get all them environments",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    if environments
+        .iter()
+        .any(|environment| environment.name == operation.name)
+    {
+        return Ok(());
+    }
+
+    let config: Vec<ServiceConfig> = serde_json::from_value(operation.arguments["config"].clone())
+        .context(error::JSONError {
+            msg: String::from("Could not deserialize services configuration"),
+        })?;
+
+    audit::Operation::docker(
+        state,
+        audit::SYSTEM_ACTOR,
+        "remove_partial_twerg",
+        &json!({ "name": &operation.name }),
+    )
+    .run(docker::delete_twerg(
+        &operation.name,
+        &config,
+        &state.logger,
+    ))
+    .await
+}
+
+/// Submit the index request again, on behalf of its original requester, unless the
+/// twerg accepted it already, in which case only its index is recorded.
+async fn resume_index(state: &State, operation: &OperationEntity) -> Result<(), error::Error> {
+    let request: IndexRequestBody =
+        serde_json::from_value(operation.arguments.clone()).context(error::JSONError {
+            msg: String::from("Could not deserialize index request"),
+        })?;
+    let submitted = operation.arguments["twerg_id"]
+        .as_i64()
+        .map(|twerg_id| twerg_id as i32);
+
+    let audited = audit::Operation::mutation(
+        state,
+        &operation.actor,
+        "createIndex",
+        &json!({ "index": &request, "resumed": true, "twergId": submitted }),
+    )
+    .environment(request.environment);
+
+    if let Some(twerg_id) = submitted {
+        return audited.run(record_index(state, request, twerg_id)).await;
+    }
+
+    // The operation's id stands for the request id, to find the logs of the resumed
+    // request. The request was authorized when it was made, so it is resumed as is.
+    let context = Context::new(
+        state.clone(),
+        Some(Identity {
+            subject: operation.actor.clone(),
+            role: Role::Admin,
+        }),
+        operation.id.to_string(),
+    );
+
+    audited
+        .run(model::create_index(request, &context))
+        .await
+        .map(|_| ())
+}
+
+/// Record the index the twerg accepted, unless its creation was only interrupted after
+/// it was recorded.
+async fn record_index(
+    state: &State,
+    request: IndexRequestBody,
+    twerg_id: i32,
+) -> Result<(), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let indexes = tx
+        .get_environment_indexes(&request.environment)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get environment indexes",
+        })?;
+
+    if !indexes.iter().any(|index| index.twerg_id == Some(twerg_id)) {
+        let input = InputIndexEntity {
+            twerg_id: Some(twerg_id),
+            ..InputIndexEntity::from(request)
+        };
+        tx.create_index(&input)
+            .await
+            .context(error::DBProvideError {
+                msg: "Could not create index",
+            })?;
+    }
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit create index transaction.",
+    })
+}
+
+async fn create_operation(
+    state: &State,
+    operation: &InputOperationEntity,
+) -> Result<OperationEntity, error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let operation = tx
+        .create_operation(operation)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not create operation",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit create operation transaction.",
+    })?;
+
+    Ok(operation)
+}
+
+async fn update_operation(
+    state: &State,
+    id: &Uuid,
+    arguments: &serde_json::Value,
+) -> Result<OperationEntity, error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let operation = tx
+        .update_operation(id, arguments)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not update operation",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit update operation transaction.",
+    })?;
+
+    Ok(operation)
+}
+
+async fn delete_operation(state: &State, id: &Uuid) -> Result<(), error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    tx.delete_operation(id)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not delete operation",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit delete operation transaction.",
+    })
+}
+
+async fn get_all_operations(state: &State) -> Result<Vec<OperationEntity>, error::Error> {
+    let mut tx = state.store.begin().await.context(error::DBProvideError {
+        msg: "could not initiate transaction",
+    })?;
+
+    let operations = tx
+        .get_all_operations()
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get operations",
+        })?;
+
+    tx.commit().await.context(error::DBProvideError {
+        msg: "could not commit transaction",
+    })?;
+
+    Ok(operations)
+}
@@ -0,0 +1,79 @@
+//! Signatures identify environments and indexes by their content, so that two
+//! identical requests can share the same result.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::docker::images::ResolvedImage;
+use crate::docker::ServiceConfig;
+
+/// What makes a service, as far as its signature is concerned.
+#[derive(Debug, Serialize)]
+struct ServiceContent<'a> {
+    image: &'a str,
+    envs: Vec<&'a str>,
+    ports: BTreeMap<&'a str, Option<&'a str>>,
+}
+
+/// What makes an index, as far as its signature is concerned.
+#[derive(Debug, Serialize)]
+struct IndexContent<'a> {
+    index_type: &'a str,
+    data_source: &'a str,
+    regions: Vec<&'a str>,
+}
+
+/// The signature of an environment, from its services configuration and the images
+/// they resolved to. Images are identified by their reference, which is pinned by
+/// digest when the digest is known. The order of services, variables and regions
+/// does not matter.
+pub fn environment_signature(
+    config: &[ServiceConfig],
+    images: &HashMap<String, ResolvedImage>,
+) -> String {
+    let services: BTreeMap<&str, ServiceContent> = config
+        .iter()
+        .map(|service| {
+            let image = images
+                .get(&service.service)
+                .map(|image| image.reference.as_str())
+                .unwrap_or(service.docker.image.as_str());
+            let mut envs: Vec<&str> = service.envs.iter().flatten().map(String::as_str).collect();
+            envs.sort_unstable();
+            let ports = service
+                .ports
+                .iter()
+                .flatten()
+                .map(|(internal, external)| (internal.as_str(), external.as_deref()))
+                .collect();
+            (
+                service.service.as_str(),
+                ServiceContent { image, envs, ports },
+            )
+        })
+        .collect();
+
+    digest(&services)
+}
+
+/// The signature of an index, from its type, data source and regions.
+pub fn index_signature(index_type: &str, data_source: &str, regions: &[String]) -> String {
+    let mut regions: Vec<&str> = regions.iter().map(String::as_str).collect();
+    regions.sort_unstable();
+    regions.dedup();
+
+    digest(&IndexContent {
+        index_type,
+        data_source,
+        regions,
+    })
+}
+
+/// The hex encoded SHA-256 of the JSON serialization of the content. Maps are
+/// ordered, so the serialization is deterministic.
+fn digest<T: Serialize>(content: &T) -> String {
+    // Serializing these structures cannot fail: all keys are strings.
+    let json = serde_json::to_vec(content).unwrap_or_default();
+    hex::encode(Sha256::digest(&json))
+}
@@ -1,47 +1,52 @@
+use crate::auth::Authenticator;
+use crate::db::{self, Store};
 use crate::error;
+use crate::events::{Event, EVENTS_CAPACITY};
+use crate::metrics::Metrics;
 use crate::settings::Settings;
-use slog::{info, o, Logger};
-use snafu::ResultExt;
-use sqlx::postgres::PgPool;
-use sqlx::postgres::PgQueryAs;
+use crate::shutdown::Shutdown;
+use crate::twerg::client::Client;
+use slog::{o, Logger};
+use std::sync::Arc;
+use tokio::sync::broadcast;
 
 #[derive(Clone, Debug)]
 pub struct State {
-    pub pool: PgPool,
+    pub store: Arc<dyn Store>,
     pub logger: Logger,
     pub settings: Settings,
+    pub auth: Authenticator,
+    pub events: broadcast::Sender<Event>,
+    pub twerg: Client,
+    pub metrics: Metrics,
+    pub shutdown: Shutdown,
 }
 
 impl State {
     pub async fn new(settings: &Settings, logger: &Logger) -> Result<Self, error::Error> {
-        let pool = PgPool::builder()
-            .max_size(5)
-            .build(&settings.database.url)
-            .await
-            .context(error::DBError {
-                msg: String::from("foo"),
-            })?;
-
-        let row: (String,) = sqlx::query_as("SELECT version()")
-            .fetch_one(&pool)
-            .await
-            .context(error::DBError {
-                msg: format!(
-                    "Could not test database version for {}",
-                    &settings.database.url,
-                ),
-            })?;
-
-        info!(logger, "db version: {:?}", row.0);
+        let store = db::connect(&settings.database.url, logger).await?;
+
+        let auth = Authenticator::new(settings)?;
+
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+
+        let twerg = Client::new(&settings.twerg.client)?;
+
+        let metrics = Metrics::new()?;
 
         let logger = logger.new(
             o!("host" => String::from(&settings.service.host), "port" => settings.service.port, "database" => String::from(&settings.database.url)),
         );
 
         Ok(Self {
-            pool,
+            store,
             logger,
             settings: settings.clone(),
+            auth,
+            events,
+            twerg,
+            metrics,
+            shutdown: Shutdown::default(),
         })
     }
 }
@@ -0,0 +1,101 @@
+//! Tracing with OpenTelemetry. GraphQL requests, resolvers, store calls, docker
+//! operations and twerg requests are traced in spans, exported with OTLP to a
+//! collector. The trace context is propagated to twergs, and taken from clients, with
+//! the W3C 'traceparent' header.
+
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace as sdktrace, Resource};
+use opentelemetry::trace::{FutureExt, SpanKind, StatusCode, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use warp::http::HeaderMap;
+
+use crate::error;
+use crate::settings;
+
+const TRACER: &str = "nidavellir";
+
+/// Propagate trace contexts, and export spans if enabled. Spans are exported until the
+/// returned guard is dropped.
+pub fn init(
+    settings: &settings::Telemetry,
+) -> Result<Option<opentelemetry_otlp::Uninstall>, error::Error> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    if !settings.enabled {
+        return Ok(None);
+    }
+
+    let resource = Resource::new(vec![KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]);
+
+    let (_, uninstall) = opentelemetry_otlp::new_pipeline()
+        .with_endpoint(&settings.endpoint)
+        .with_trace_config(sdktrace::config().with_resource(resource))
+        .install()
+        .map_err(|err| error::Error::MiscError {
+            msg: format!("Could not export traces to {}: {}", settings.endpoint, err),
+        })?;
+
+    Ok(Some(uninstall))
+}
+
+/// Start a span, child of the given context, and return the context holding it.
+pub fn start(parent: &Context, kind: SpanKind, name: &str, attributes: Vec<KeyValue>) -> Context {
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .with_parent_context(parent.clone())
+        .start(&tracer);
+    parent.with_span(span)
+}
+
+/// Run the future in a span, child of the current one. Its error, if it fails, is
+/// recorded in the span.
+pub async fn in_span<T, E, F>(
+    kind: SpanKind,
+    name: &str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let cx = start(&Context::current(), kind, name, attributes);
+    let result = future.with_context(cx.clone()).await;
+    if let Err(err) = &result {
+        cx.span().set_status(StatusCode::Error, err.to_string());
+    }
+    cx.span().end();
+    result
+}
+
+/// The headers propagating the current trace context, for outgoing requests.
+pub fn trace_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Context::current(), &mut headers)
+    });
+    headers
+}
+
+/// The trace context propagated by the headers of an incoming request.
+pub fn remote_context(headers: &HeaderMap) -> Context {
+    let headers: HashMap<String, String> = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| (name.as_str().to_owned(), value.to_owned()))
+        })
+        .collect();
+    global::get_text_map_propagator(|propagator| propagator.extract(&headers))
+}
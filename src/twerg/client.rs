@@ -1,75 +1,357 @@
-use futures::future::TryFutureExt;
-use slog::{info, Logger};
-use snafu::futures::try_future::TryFutureExt as SnafuTryFutureExt;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::KeyValue;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
 use snafu::ResultExt;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use crate::api::model;
+use crate::db::model::IndexStatus;
 use crate::error;
-use crate::utils::{construct_headers, get_service_url};
-
-// FIXME I'm not exactly sure what to retrieve from this function for Nidavellir...
-pub async fn create_index(
-    index: &model::IndexRequestBody,
-    port: i32,
-    logger: &Logger,
-) -> Result<i32, error::Error> {
-    let data = get_graphql_create_index(&index);
-    let url = get_service_url(port);
-    let client = reqwest::Client::new();
-    client
-        .post(&url)
-        .headers(construct_headers())
-        .body(data)
-        .send()
-        .context(error::ReqwestError {
-            msg: String::from("Could not request SingleIndexResponseBody"),
-        })
-        .and_then(|resp| {
-            resp.json::<serde_json::Value>()
-                .context(error::ReqwestError {
-                    msg: String::from("Could not deserialize SingleUserResponseBody"),
-                })
+use crate::logging::REQUEST_ID_HEADER;
+use crate::settings;
+use crate::telemetry;
+
+/// A GraphQL request, as sent to a twerg.
+#[derive(Debug, Serialize)]
+pub struct Request<'a, V> {
+    pub query: &'a str,
+    pub variables: V,
+}
+
+/// A GraphQL response, as received from a twerg.
+#[derive(Debug, Deserialize)]
+pub struct Response<T> {
+    pub data: Option<T>,
+    #[serde(default)]
+    pub errors: Vec<ResponseError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseError {
+    pub message: String,
+    #[serde(default)]
+    pub path: Vec<serde_json::Value>,
+}
+
+impl<T> Response<T> {
+    /// The data of the response, or its errors.
+    pub fn into_result(self) -> Result<T, error::Error> {
+        match (self.data, self.errors.is_empty()) {
+            (Some(data), true) => Ok(data),
+            (_, false) => Err(error::Error::TwergError {
+                msg: self
+                    .errors
+                    .iter()
+                    .map(|err| err.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            }),
+            (None, true) => Err(error::Error::TwergError {
+                msg: String::from("Response has neither data nor errors"),
+            }),
+        }
+    }
+}
+
+const CREATE_INDEX: &str =
+    "mutation createIndex($index: IndexRequestBody!) { createIndex(index: $index) { index { indexId } } }";
+
+#[derive(Debug, Serialize)]
+pub struct CreateIndexVariables<'a> {
+    pub index: IndexInput<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexInput<'a> {
+    pub index_type: &'a str,
+    pub data_source: &'a str,
+    pub region: &'a str,
+    /// The source data to ingest, in the cache mounted in the twerg. Twergs download
+    /// the data themselves when it is not cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateIndexData {
+    pub create_index: CreateIndexPayload,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateIndexPayload {
+    pub index: Option<IndexId>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexId {
+    pub index_id: i32,
+}
+
+const INDEX_STATUS: &str = "query index($id: Int!) { index(id: $id) { indexId status } }";
+
+#[derive(Debug, Serialize)]
+pub struct IndexIdVariables {
+    pub id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexStatusData {
+    pub index: Option<IndexState>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexState {
+    pub index_id: i32,
+    pub status: IndexStatus,
+}
+
+const PUBLISH_INDEX: &str =
+    "mutation publishIndex($id: Int!) { publishIndex(id: $id) { index { indexId } } }";
+
+const DELETE_INDEX: &str =
+    "mutation deleteIndex($id: Int!) { deleteIndex(id: $id) { index { indexId } } }";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishIndexData {
+    pub publish_index: CreateIndexPayload,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteIndexData {
+    pub delete_index: CreateIndexPayload,
+}
+
+/// A client for the GraphQL endpoints of twergs.
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    url: String,
+    /// Endpoints in place of `url`, by environment name.
+    urls: HashMap<String, String>,
+    retries: u32,
+    backoff: Duration,
+    /// Id of the request on behalf of which twergs are queried.
+    request_id: Option<String>,
+}
+
+impl Client {
+    pub fn new(settings: &settings::TwergClient) -> Result<Self, error::Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(settings.timeout))
+            .build()
+            .context(error::ReqwestError {
+                msg: String::from("Could not build twerg client"),
+            })?;
+
+        Ok(Client {
+            client,
+            url: settings.url.clone(),
+            urls: settings.urls.clone(),
+            retries: settings.retries,
+            backoff: Duration::from_millis(settings.backoff),
+            request_id: None,
         })
-        .and_then(|json| {
-            async move {
-                // This JSON contains two fields, data, and errors.
-                // So we test if data is null,
-                //   in which case we return the first error in the errors array,
-                // otherwise
-                //   we return the expected singleuserresponse
-                if json["data"].is_null() {
-                    let errors = json["errors"].as_array().expect("errors");
-                    let error = &errors.first().expect("at least one error");
-                    Err(error::Error::MiscError {
-                        msg: format!("{}", error),
-                    })
-                } else {
-                    let res = &json["data"]["createIndex"]["index"]["indexId"];
-                    let res = res.clone();
-                    serde_json::from_value(res).context(error::JSONError {
-                        msg: String::from("Cannot deserialize id from index creation"),
-                    })
+    }
+
+    /// A client sending the id of a request with each query, so that twergs can
+    /// correlate their logs with ours.
+    pub fn with_request_id(&self, request_id: &str) -> Self {
+        Client {
+            request_id: Some(String::from(request_id)),
+            ..self.clone()
+        }
+    }
+
+    /// The GraphQL endpoint of the twerg of an environment.
+    pub fn endpoint(&self, name: &str, port: i32) -> String {
+        self.urls
+            .get(name)
+            .unwrap_or(&self.url)
+            .replace("{name}", name)
+            .replace("{port}", &port.to_string())
+    }
+
+    /// Request the creation of an index in a twerg, and return the twerg's id for it.
+    /// The twerg ingests the given file, if any, which must be the source data of the
+    /// index's first region.
+    pub async fn create_index(
+        &self,
+        endpoint: &str,
+        index: &model::IndexRequestBody,
+        file: Option<&str>,
+        logger: &Logger,
+    ) -> Result<i32, error::Error> {
+        // FIXME Here we have a problem Houston.... Twerg has for now been designed for a single
+        // region (ie a String), whereas Nidavellir been done for Vec<String>.
+        let region = index
+            .regions
+            .first()
+            .ok_or_else(|| error::Error::MiscError {
+                msg: String::from("An index needs at least one region"),
+            })?;
+
+        let variables = CreateIndexVariables {
+            index: IndexInput {
+                index_type: &index.index_type,
+                data_source: &index.data_source,
+                region,
+                file,
+            },
+        };
+
+        let data: CreateIndexData = self
+            .query(endpoint, CREATE_INDEX, &variables, false, logger)
+            .await?;
+
+        data.create_index
+            .index
+            .map(|index| index.index_id)
+            .ok_or_else(|| error::Error::TwergError {
+                msg: String::from("Index creation returned no index"),
+            })
+    }
+
+    /// The status of an index in a twerg, as it goes through downloading, processing,
+    /// indexing and validation.
+    pub async fn index_status(
+        &self,
+        endpoint: &str,
+        id: i32,
+        logger: &Logger,
+    ) -> Result<IndexStatus, error::Error> {
+        let variables = IndexIdVariables { id };
+        let data: IndexStatusData = self
+            .query(endpoint, INDEX_STATUS, &variables, true, logger)
+            .await?;
+
+        data.index
+            .map(|index| index.status)
+            .ok_or_else(|| error::Error::TwergError {
+                msg: format!("Index {} not found", id),
+            })
+    }
+
+    /// Point the alias searched by the twerg for the type and region of an index at the
+    /// given version of the index, which must be available. The previous version stays
+    /// in the twerg until it is deleted.
+    pub async fn publish_index(
+        &self,
+        endpoint: &str,
+        id: i32,
+        logger: &Logger,
+    ) -> Result<(), error::Error> {
+        let variables = IndexIdVariables { id };
+        let data: PublishIndexData = self
+            .query(endpoint, PUBLISH_INDEX, &variables, true, logger)
+            .await?;
+
+        data.publish_index
+            .index
+            .map(|_| ())
+            .ok_or_else(|| error::Error::TwergError {
+                msg: format!("Index {} not found", id),
+            })
+    }
+
+    /// Delete a version of an index from a twerg. It must not be published.
+    pub async fn delete_index(
+        &self,
+        endpoint: &str,
+        id: i32,
+        logger: &Logger,
+    ) -> Result<(), error::Error> {
+        let variables = IndexIdVariables { id };
+        let data: DeleteIndexData = self
+            .query(endpoint, DELETE_INDEX, &variables, true, logger)
+            .await?;
+
+        data.delete_index
+            .index
+            .map(|_| ())
+            .ok_or_else(|| error::Error::TwergError {
+                msg: format!("Index {} not found", id),
+            })
+    }
+
+    /// Submit a GraphQL query. Requests failing because the twerg could not be
+    /// reached are retried with an exponential backoff. Those timing out, or failing
+    /// with a server error, may have been processed by the twerg, and are only retried
+    /// if the query is idempotent.
+    pub async fn query<V, T>(
+        &self,
+        endpoint: &str,
+        query: &str,
+        variables: &V,
+        idempotent: bool,
+        logger: &Logger,
+    ) -> Result<T, error::Error>
+    where
+        V: Serialize,
+        T: DeserializeOwned,
+    {
+        let request = Request { query, variables };
+        let mut attempt = 0;
+        loop {
+            let attributes = vec![
+                KeyValue::new("http.url", endpoint.to_owned()),
+                KeyValue::new("attempt", i64::from(attempt)),
+            ];
+            let result = telemetry::in_span(SpanKind::Client, "twerg query", attributes, async {
+                let mut builder = self.client.post(endpoint).json(&request);
+                if let Some(request_id) = &self.request_id {
+                    builder = builder.header(REQUEST_ID_HEADER, request_id.as_str());
+                }
+                // Within the span, so that the twerg's spans are its children.
+                for (name, value) in telemetry::trace_headers() {
+                    builder = builder.header(name.as_str(), value.as_str());
                 }
+                builder.send().await
+            })
+            .await;
+            let retryable = match &result {
+                Ok(resp) => idempotent && resp.status().is_server_error(),
+                Err(err) => err.is_connect() || (idempotent && err.is_timeout()),
+            };
+            if retryable && attempt < self.retries {
+                let delay = self.backoff * 2u32.pow(attempt);
+                warn!(
+                    logger,
+                    "Twerg request to {} failed, retrying in {:?}", endpoint, delay
+                );
+                tokio::time::delay_for(delay).await;
+                attempt += 1;
+                continue;
             }
-        })
-        .await
-}
-
-// This is a helper function which generates the GraphQL query for creating an index.
-pub fn get_graphql_create_index(index: &model::IndexRequestBody) -> String {
-    let query = r#" "mutation createIndex($index: IndexRequestBody!) { createIndex(index: $index) { index { indexId } } }" "#;
-    // FIXME Here we have a problem Houston.... Twerg has for now been designed for a single
-    // region (ie a String), whereas Nidavellir been done for Vec<String>.
-    let variables = format!(
-        r#"{{ "indexType": "{indexType}", "dataSource": "{dataSource}", "region": "{region}" }}"#,
-        indexType = index.index_type,
-        dataSource = index.data_source,
-        region = index.regions[0]
-    );
-    format!(
-        r#"{{ "query": {query}, "variables": {{ "index": {variables} }} }}"#,
-        query = query,
-        variables = variables
-    )
+
+            let resp =
+                result
+                    .and_then(|resp| resp.error_for_status())
+                    .context(error::ReqwestError {
+                        msg: format!("Could not query twerg at {}", endpoint),
+                    })?;
+
+            debug!(logger, "Twerg at {} answered {}", endpoint, resp.status());
+
+            let resp: Response<T> = resp.json().await.context(error::ReqwestError {
+                msg: format!("Could not deserialize response from twerg at {}", endpoint),
+            })?;
+
+            return resp.into_result();
+        }
+    }
 }
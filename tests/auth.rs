@@ -0,0 +1,110 @@
+//! Tests of the authentication of bearer tokens, either JWTs or static API tokens.
+
+use jsonwebtoken::{encode, EncodingKey, Header};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nidavellir::auth::{Authenticator, Claims, Identity, Role};
+use nidavellir::settings::{ApiToken, Settings};
+
+const API_TOKEN: &str = "ci-token";
+
+/// The testing settings, which sign JWTs with the 'testing' secret, and accept the
+/// given digest for the API token.
+fn authenticator(digest: &str) -> Result<Authenticator, String> {
+    env::set_var("RUN_MODE", "testing");
+    let mut settings = Settings::new(None).expect("testing settings");
+    settings.auth.tokens = vec![ApiToken {
+        subject: String::from("ci"),
+        role: Role::Operator,
+        digest: String::from(digest),
+    }];
+    Authenticator::new(&settings).map_err(|err| err.to_string())
+}
+
+fn api_token_digest() -> String {
+    hex::encode(Sha256::digest(API_TOKEN.as_bytes()))
+}
+
+fn jwt(subject: &str, role: Role) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_secs() as usize;
+    let claims = Claims {
+        sub: String::from(subject),
+        role,
+        exp: now + 600,
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"testing"),
+    )
+    .expect("JWT")
+}
+
+fn authenticate(auth: &Authenticator, token: &str) -> Result<Option<Identity>, String> {
+    auth.authenticate(Some(&format!("Bearer {}", token)))
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn api_tokens_are_matched_by_their_digest() {
+    let auth = authenticator(&api_token_digest()).expect("authenticator");
+    assert_eq!(
+        authenticate(&auth, API_TOKEN),
+        Ok(Some(Identity {
+            subject: String::from("ci"),
+            role: Role::Operator,
+        }))
+    );
+
+    let err = authenticate(&auth, "another-token").expect_err("an unknown token");
+    assert!(err.contains("Unknown API token"), "{}", err);
+
+    // Digests are compared as bytes, whatever the case of their hex encoding.
+    let auth = authenticator(&api_token_digest().to_uppercase()).expect("authenticator");
+    assert!(authenticate(&auth, API_TOKEN).is_ok());
+}
+
+#[test]
+fn api_token_digests_must_be_hex_encoded_sha256() {
+    let err = authenticator("not a digest").expect_err("an invalid digest");
+    assert!(err.contains("API token digest of ci"), "{}", err);
+
+    let truncated = &api_token_digest()[..32];
+    assert!(authenticator(truncated).is_err());
+}
+
+#[test]
+fn jwts_are_recognized_by_their_header() {
+    let auth = authenticator(&api_token_digest()).expect("authenticator");
+    assert_eq!(
+        authenticate(&auth, &jwt("alice", Role::Admin)),
+        Ok(Some(Identity {
+            subject: String::from("alice"),
+            role: Role::Admin,
+        }))
+    );
+
+    let mut tampered = jwt("alice", Role::Viewer);
+    tampered.push('x');
+    let err = authenticate(&auth, &tampered).expect_err("a tampered JWT");
+    assert!(err.contains("Invalid JWT"), "{}", err);
+
+    // Dots do not make a JWT.
+    let err = authenticate(&auth, "not.a.jwt").expect_err("an unknown token");
+    assert!(err.contains("Unknown API token"), "{}", err);
+}
+
+#[test]
+fn missing_credentials_are_anonymous() {
+    let auth = authenticator(&api_token_digest()).expect("authenticator");
+    assert_eq!(
+        auth.authenticate(None).map_err(|err| err.to_string()),
+        Ok(None)
+    );
+    assert!(auth.authenticate(Some(API_TOKEN)).is_err());
+}
@@ -0,0 +1,113 @@
+//! End to end tests of the command line client, run against the REST API. See `common`
+//! for the requirements.
+
+mod common;
+
+use serde_json::{json, Value};
+use std::process::Output;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use common::twerg::Reply;
+use common::{admin, TestContext};
+
+/// Run the client against a server.
+async fn nidavellir(server: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nidavellir"))
+        .env_remove("NIDAVELLIR_TOKEN")
+        .arg("--server")
+        .arg(server)
+        .args(args)
+        .output()
+        .await
+        .expect("nidavellir")
+}
+
+/// Run the client, expecting it to succeed, and return its output.
+async fn stdout(server: &str, args: &[&str]) -> String {
+    let output = nidavellir(server, args).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("utf-8 output")
+}
+
+#[tokio::test]
+async fn create_list_and_retry_indexes() {
+    let ctx = TestContext::new().await;
+    let server = ctx.serve_rest(Some(admin()));
+    let project = ctx.insert_project("cli").await;
+    let environment = ctx.insert_environment("twerg", project).await.to_string();
+
+    ctx.twerg.push(Reply::Index(1));
+    let output = stdout(
+        &server,
+        &[
+            "index",
+            "create",
+            &environment,
+            "--type",
+            "admins",
+            "--source",
+            "osm",
+            "--region",
+            "fr",
+            "--output",
+            "json",
+        ],
+    )
+    .await;
+    let created: Value = serde_json::from_str(&output).expect("json output");
+    assert_eq!(created["index"]["dataSource"], json!("osm"));
+    let index = created["index"]["id"]
+        .as_str()
+        .expect("index id")
+        .to_owned();
+
+    let output = stdout(&server, &["index", "list", &environment]).await;
+    let mut lines = output.lines();
+    assert!(lines.next().expect("header").starts_with("ID "));
+    let row = lines.next().expect("index row");
+    assert!(row.starts_with(&index), "{}", row);
+    assert!(row.contains("admins"), "{}", row);
+
+    // The failed index is built again in its place.
+    ctx.twerg
+        .progress(1, &["indexing_error"])
+        .push(Reply::Index(2));
+    let output = stdout(
+        &server,
+        &["-o", "json", "index", "retry", &environment, &index],
+    )
+    .await;
+    let retried: Value = serde_json::from_str(&output).expect("json output");
+    assert_eq!(retried["index"]["id"], json!(index));
+    assert_eq!(retried["index"]["twergId"], json!(2));
+    assert_eq!(retried["index"]["signature"], created["index"]["signature"]);
+    assert_eq!(ctx.twerg.deleted(), vec![1]);
+
+    let output = stdout(&server, &["index", "list", &environment, "-o", "json"]).await;
+    let indexes: Value = serde_json::from_str(&output).expect("json output");
+    assert_eq!(indexes["indexesCount"], json!(1));
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn errors_are_reported_on_stderr() {
+    let ctx = TestContext::new().await;
+    let server = ctx.serve_rest(Some(admin()));
+
+    let output = nidavellir(&server, &["env", "show", &Uuid::new_v4().to_string()]).await;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("404"), "{}", stderr);
+
+    let output = nidavellir(&server, &["env", "show", "not-an-id"]).await;
+    assert!(!output.status.success());
+
+    ctx.teardown().await;
+}
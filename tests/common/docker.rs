@@ -0,0 +1,177 @@
+//! A fake docker engine, running in process, which only knows about images.
+
+use bollard::Docker;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use warp::http::{Method, Response, StatusCode};
+use warp::path::FullPath;
+use warp::Filter;
+
+#[derive(Debug, Default)]
+struct Engine {
+    /// The images present, by name, with their repo digests.
+    images: HashMap<String, Vec<String>>,
+    /// The progress lines streamed by the next pull, if scripted.
+    pull: Option<Vec<Value>>,
+    /// The repo digests of the images pulled.
+    pulled_digests: Vec<String>,
+    /// The names of the images pulled, in order.
+    pulls: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockDocker {
+    pub addr: SocketAddr,
+    engine: Arc<Mutex<Engine>>,
+}
+
+impl MockDocker {
+    /// Start the mock on an ephemeral port. A pull succeeds with a single status
+    /// line, unless another progress is scripted.
+    pub fn start() -> Self {
+        let engine = Arc::new(Mutex::new(Engine::default()));
+
+        let state = engine.clone();
+        let route = warp::method()
+            .and(warp::path::full())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .map(move |method: Method, path: FullPath, query: String| {
+                respond(&state, &method, path.as_str(), &query)
+            });
+
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        MockDocker { addr, engine }
+    }
+
+    /// A client of the mock.
+    pub fn client(&self) -> Docker {
+        Docker::connect_with_http(
+            &format!("http://{}", self.addr),
+            10,
+            bollard::API_DEFAULT_VERSION,
+        )
+        .expect("docker client")
+    }
+
+    /// Make an image present, with the given repo digests.
+    pub fn image(&self, name: &str, repo_digests: &[&str]) -> &Self {
+        self.engine.lock().unwrap().images.insert(
+            String::from(name),
+            repo_digests
+                .iter()
+                .map(|digest| String::from(*digest))
+                .collect(),
+        );
+        self
+    }
+
+    /// Script the progress lines streamed by the next pull, and the repo digests of
+    /// the image pulled.
+    pub fn pull(&self, lines: Vec<Value>, repo_digests: &[&str]) -> &Self {
+        let mut engine = self.engine.lock().unwrap();
+        engine.pull = Some(lines);
+        engine.pulled_digests = repo_digests
+            .iter()
+            .map(|digest| String::from(*digest))
+            .collect();
+        self
+    }
+
+    /// The names of the images pulled so far.
+    pub fn pulls(&self) -> Vec<String> {
+        self.engine.lock().unwrap().pulls.clone()
+    }
+}
+
+fn respond(engine: &Mutex<Engine>, method: &Method, path: &str, query: &str) -> Response<String> {
+    let mut engine = engine.lock().unwrap();
+
+    if method == Method::POST && path.ends_with("/images/create") {
+        let name = url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "fromImage")
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default();
+        engine.pulls.push(name.clone());
+
+        let lines = engine
+            .pull
+            .take()
+            .unwrap_or_else(|| vec![json!({ "status": format!("Pulling {}", name) })]);
+        let failed = lines
+            .iter()
+            .any(|line| !line["error"].is_null() || !line["errorDetail"].is_null());
+        if !failed && !lines.is_empty() {
+            let digests = std::mem::take(&mut engine.pulled_digests);
+            engine.images.insert(name, digests);
+        }
+
+        let body = lines
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        return reply(StatusCode::OK, body);
+    }
+
+    if method == Method::GET && path.ends_with("/json") {
+        if let Some(start) = path.find("/images/") {
+            let name = decode(&path[start + "/images/".len()..path.len() - "/json".len()]);
+            return match engine.images.get(&name) {
+                Some(repo_digests) => reply(StatusCode::OK, inspection(&name, repo_digests)),
+                None => reply(
+                    StatusCode::NOT_FOUND,
+                    json!({ "message": format!("No such image: {}", name) }).to_string(),
+                ),
+            };
+        }
+    }
+
+    reply(
+        StatusCode::NOT_FOUND,
+        json!({ "message": "page not found" }).to_string(),
+    )
+}
+
+fn reply(status: StatusCode, body: String) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// Path segments may be percent encoded.
+fn decode(text: &str) -> String {
+    url::form_urlencoded::parse(text.as_bytes())
+        .map(|(key, _)| key.into_owned())
+        .collect()
+}
+
+/// The inspection of an image, as docker reports it.
+fn inspection(name: &str, repo_digests: &[String]) -> String {
+    json!({
+        "Id": "sha256:0123456789abcdef",
+        "RepoTags": [name],
+        "RepoDigests": repo_digests,
+        "Parent": "",
+        "Comment": "",
+        "Created": "2021-01-01T00:00:00Z",
+        "Container": "",
+        "ContainerConfig": {},
+        "DockerVersion": "19.03.13",
+        "Author": "",
+        "Config": {},
+        "Architecture": "amd64",
+        "Os": "linux",
+        "Size": 0,
+        "VirtualSize": 0,
+        "GraphDriver": { "Name": "overlay2", "Data": {} },
+        "RootFS": { "Type": "layers", "Layers": [] },
+        "Metadata": {},
+    })
+    .to_string()
+}
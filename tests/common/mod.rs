@@ -0,0 +1,228 @@
+//! Harness for end to end tests: the Nidavellir GraphQL schema, executed against a
+//! store, with twergs replaced by a mock.
+//!
+//! When DATABASE_TEST_URL gives a Postgres server, each test runs against a throwaway
+//! database, migrated with movine, which must be installed. Otherwise the tests run
+//! against a memory store.
+
+#![allow(dead_code)]
+
+use juniper::http::GraphQLRequest;
+use serde_json::{json, Value};
+use slog::{o, Logger};
+use sqlx::{Connect, Executor, PgConnection};
+use std::env;
+use url::Url;
+use uuid::Uuid;
+use warp::{Filter, Rejection, Reply};
+
+use nidavellir::api::gql::{schema, Context};
+use nidavellir::api::rest;
+use nidavellir::auth::{Identity, Role};
+use nidavellir::db::model::{InputEnvironmentEntity, InputProjectEntity, ProvideData, QuotaEntity};
+use nidavellir::db::pg;
+use nidavellir::settings::Settings;
+use nidavellir::state::State;
+use nidavellir::telemetry;
+
+pub mod docker;
+pub mod twerg;
+
+use twerg::MockTwerg;
+
+pub struct TestContext {
+    pub state: State,
+    pub twerg: MockTwerg,
+    /// The database server, and the database created on it, if any.
+    database: Option<(String, String)>,
+    /// Exports the spans of the test, if a collector is configured.
+    _telemetry: Option<opentelemetry_otlp::Uninstall>,
+}
+
+impl TestContext {
+    /// Create a store, and start a mock twerg.
+    pub async fn new() -> Self {
+        TestContext::with_settings(|_, _| {}).await
+    }
+
+    /// Create a store, and start a mock twerg, with the testing settings changed by
+    /// `configure`, eg to download sources from the mock.
+    pub async fn with_settings<F>(configure: F) -> Self
+    where
+        F: FnOnce(&mut Settings, &MockTwerg),
+    {
+        env::set_var("RUN_MODE", "testing");
+
+        let logger = Logger::root(slog::Discard, o!());
+
+        let (database_url, database) = match env::var("DATABASE_TEST_URL") {
+            Ok(server_url) => {
+                let (database_url, database) = create_database(&server_url, &logger).await;
+                (database_url, Some((server_url, database)))
+            }
+            Err(_) => (String::from("memory://"), None),
+        };
+
+        let twerg = MockTwerg::start();
+
+        let mut settings = Settings::new(None).expect("testing settings");
+        settings.database.url = database_url;
+        settings.twerg.client.url = format!("http://{}/mimir/graphql", twerg.addr);
+        let cache = env::temp_dir().join(format!("nidavellir-cache-{}", Uuid::new_v4()));
+        settings.cache.directory = cache.to_string_lossy().into_owned();
+        configure(&mut settings, &twerg);
+
+        // Spans are only exported when a collector is configured, but trace contexts
+        // are always propagated.
+        let telemetry = telemetry::init(&settings.telemetry).expect("telemetry");
+
+        let state = State::new(&settings, &logger).await.expect("state");
+
+        TestContext {
+            state,
+            twerg,
+            database,
+            _telemetry: telemetry,
+        }
+    }
+
+    /// Execute a GraphQL query as the given identity, and return the JSON response.
+    pub async fn execute(
+        &self,
+        query: &str,
+        variables: Value,
+        identity: Option<Identity>,
+    ) -> Value {
+        let request: GraphQLRequest = serde_json::from_value(json!({
+            "query": query,
+            "variables": variables,
+        }))
+        .expect("graphql request");
+
+        // Requests are all identified as 'test', see the mock twerg's request ids.
+        let context = Context::new(self.state.clone(), identity, String::from("test"));
+
+        let response = request.execute(&schema(), &context).await;
+        serde_json::to_value(&response).expect("graphql response")
+    }
+
+    /// Execute a GraphQL query as an administrator.
+    pub async fn execute_as_admin(&self, query: &str, variables: Value) -> Value {
+        self.execute(query, variables, Some(admin())).await
+    }
+
+    /// The routes of the REST API, with requests made as the given identity.
+    pub fn rest(
+        &self,
+        identity: Option<Identity>,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let state = self.state.clone();
+        let context = warp::any()
+            .map(move || Context::new(state.clone(), identity.clone(), String::from("test")))
+            .boxed();
+        rest::routes(context)
+    }
+
+    /// Serve the REST API on a free port, with requests made as the given identity, and
+    /// return its url.
+    pub fn serve_rest(&self, identity: Option<Identity>) -> String {
+        let (addr, server) = warp::serve(self.rest(identity)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", addr)
+    }
+
+    /// Record a project without quotas.
+    pub async fn insert_project(&self, name: &str) -> Uuid {
+        let mut tx = self.state.store.begin().await.expect("transaction");
+        let project = tx
+            .create_project(&InputProjectEntity {
+                name: String::from(name),
+                quota: QuotaEntity {
+                    max_environments: None,
+                    max_indexes: None,
+                    max_memory: None,
+                },
+            })
+            .await
+            .expect("project creation");
+        tx.commit().await.expect("commit");
+        project.id
+    }
+
+    /// Record an environment served by the mock twerg, without provisioning it.
+    pub async fn insert_environment(&self, name: &str, project: Uuid) -> Uuid {
+        let mut tx = self.state.store.begin().await.expect("transaction");
+        let environment = tx
+            .create_environment(&InputEnvironmentEntity {
+                name: String::from(name),
+                port: self.twerg.port(),
+                owner: String::from("tester"),
+                project,
+                memory: 0,
+                expires_at: None,
+                services: Vec::new(),
+                config: json!([]),
+                signature: format!("signature of {}", name),
+            })
+            .await
+            .expect("environment creation");
+        tx.commit().await.expect("commit");
+        environment.id
+    }
+
+    /// Close the connections, drop the database, if any, and remove the cache.
+    pub async fn teardown(self) {
+        self.state.store.close().await;
+        let _ = std::fs::remove_dir_all(&self.state.settings.cache.directory);
+        if let Some((server_url, database)) = self.database {
+            let mut conn = PgConnection::connect(&server_url)
+                .await
+                .expect("connection to the database server");
+            conn.execute(format!("DROP DATABASE {}", database).as_str())
+                .await
+                .expect("database removal");
+        }
+    }
+}
+
+/// Create a database on the given server, and migrate it. Returns its url and name.
+async fn create_database(server_url: &str, logger: &Logger) -> (String, String) {
+    let database = format!("nidavellir_test_{}", Uuid::new_v4().to_simple());
+    let mut conn = PgConnection::connect(server_url)
+        .await
+        .expect("connection to the database server");
+    conn.execute(format!("CREATE DATABASE {}", database).as_str())
+        .await
+        .expect("database creation");
+
+    let mut url = Url::parse(server_url).expect("database server url");
+    url.set_path(&database);
+    let database_url = url.to_string();
+
+    pg::migration_up(&database_url, logger)
+        .await
+        .expect("migrations");
+
+    (database_url, database)
+}
+
+pub fn admin() -> Identity {
+    Identity {
+        subject: String::from("tester"),
+        role: Role::Admin,
+    }
+}
+
+pub fn operator(subject: &str) -> Identity {
+    Identity {
+        subject: String::from(subject),
+        role: Role::Operator,
+    }
+}
+
+pub fn viewer() -> Identity {
+    Identity {
+        subject: String::from("viewer"),
+        role: Role::Viewer,
+    }
+}
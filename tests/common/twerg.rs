@@ -0,0 +1,256 @@
+//! A fake twerg GraphQL endpoint, running in process, with scripted responses.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use warp::http::header::{HeaderValue, ETAG};
+use warp::http::{Response, StatusCode};
+use warp::Filter;
+
+/// What the mock answers to the next request.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// The index was created with the given id.
+    Index(i32),
+    /// A GraphQL response with these errors, and no data.
+    Errors(Vec<String>),
+    /// An HTTP error, without body.
+    Status(u16),
+    /// The reply, after the delay.
+    Slow(Duration, Box<Reply>),
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    replies: VecDeque<Reply>,
+    progressions: HashMap<i32, VecDeque<String>>,
+    requests: Vec<Value>,
+    /// The request id header of each request.
+    request_ids: Vec<Option<String>>,
+    /// The trace context header of each request.
+    trace_parents: Vec<Option<String>>,
+    next_id: i32,
+    /// Source files, by name, with their version.
+    sources: HashMap<String, (String, Vec<u8>)>,
+    /// The names of the source files downloaded.
+    downloads: Vec<String>,
+    /// The ids of the indexes published, in order.
+    published: Vec<i32>,
+    /// The ids of the indexes deleted, in order.
+    deleted: Vec<i32>,
+    /// HTTP errors replied to the next requests, whatever they are.
+    failures: VecDeque<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockTwerg {
+    pub addr: SocketAddr,
+    script: Arc<Mutex<Script>>,
+}
+
+impl MockTwerg {
+    /// Start the mock on an ephemeral port. Requests without a scripted reply
+    /// create an index, with increasing ids.
+    pub fn start() -> Self {
+        let script = Arc::new(Mutex::new(Script::default()));
+
+        let state = script.clone();
+        let route = warp::post()
+            .and(warp::path!("mimir" / "graphql"))
+            .and(warp::header::optional::<String>("x-request-id"))
+            .and(warp::header::optional::<String>("traceparent"))
+            .and(warp::body::json())
+            .and_then(
+                move |request_id: Option<String>, trace_parent: Option<String>, body: Value| {
+                    let state = state.clone();
+                    async move {
+                        Ok::<_, Infallible>(respond(state, request_id, trace_parent, body).await)
+                    }
+                },
+            );
+
+        let state = script.clone();
+        let download = warp::get()
+            .and(warp::path!("sources" / String))
+            .map(move |name: String| source(&state, name, true));
+
+        let state = script.clone();
+        let head = warp::head()
+            .and(warp::path!("sources" / String))
+            .map(move |name: String| source(&state, name, false));
+
+        let (addr, server) =
+            warp::serve(route.or(download).or(head)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        MockTwerg { addr, script }
+    }
+
+    /// The port of the mock, to use as the environment's port.
+    pub fn port(&self) -> i32 {
+        i32::from(self.addr.port())
+    }
+
+    /// Script the reply to the next request.
+    pub fn push(&self, reply: Reply) -> &Self {
+        self.script.lock().unwrap().replies.push_back(reply);
+        self
+    }
+
+    /// Fail the next request, whatever it is, with an HTTP error.
+    pub fn fail(&self, status: u16) -> &Self {
+        self.script.lock().unwrap().failures.push_back(status);
+        self
+    }
+
+    /// Script the statuses successively reported for an index. The last one is
+    /// reported forever after.
+    pub fn progress(&self, index: i32, statuses: &[&str]) -> &Self {
+        self.script
+            .lock()
+            .unwrap()
+            .progressions
+            .insert(index, statuses.iter().map(|s| String::from(*s)).collect());
+        self
+    }
+
+    /// Serve a source file at /sources/{name}, with its version as ETag.
+    pub fn source(&self, name: &str, version: &str, content: &[u8]) -> &Self {
+        self.script.lock().unwrap().sources.insert(
+            String::from(name),
+            (String::from(version), content.to_vec()),
+        );
+        self
+    }
+
+    /// The names of the source files downloaded so far.
+    pub fn downloads(&self) -> Vec<String> {
+        self.script.lock().unwrap().downloads.clone()
+    }
+
+    /// The ids of the indexes published so far, the last one being served.
+    pub fn published(&self) -> Vec<i32> {
+        self.script.lock().unwrap().published.clone()
+    }
+
+    /// The ids of the indexes deleted so far.
+    pub fn deleted(&self) -> Vec<i32> {
+        self.script.lock().unwrap().deleted.clone()
+    }
+
+    /// The bodies of the requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.script.lock().unwrap().requests.clone()
+    }
+
+    /// The request ids received so far.
+    pub fn request_ids(&self) -> Vec<Option<String>> {
+        self.script.lock().unwrap().request_ids.clone()
+    }
+
+    /// The trace contexts received so far.
+    pub fn trace_parents(&self) -> Vec<Option<String>> {
+        self.script.lock().unwrap().trace_parents.clone()
+    }
+}
+
+async fn respond(
+    script: Arc<Mutex<Script>>,
+    request_id: Option<String>,
+    trace_parent: Option<String>,
+    body: Value,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    let reply = {
+        let mut script = script.lock().unwrap();
+        script.requests.push(body.clone());
+        script.request_ids.push(request_id);
+        script.trace_parents.push(trace_parent);
+        let query = body["query"].as_str().unwrap_or_default();
+        if let Some(status) = script.failures.pop_front() {
+            Reply::Status(status)
+        } else if query.contains("createIndex") {
+            script.replies.pop_front().unwrap_or_else(|| {
+                script.next_id += 1;
+                Reply::Index(script.next_id)
+            })
+        } else {
+            let id = body["variables"]["id"].as_i64().unwrap_or_default() as i32;
+            let index = json!({ "index": { "indexId": id } });
+            if query.contains("publishIndex") {
+                script.published.push(id);
+                return ok(json!({ "publishIndex": index }));
+            }
+            if query.contains("deleteIndex") {
+                script.deleted.push(id);
+                return ok(json!({ "deleteIndex": index }));
+            }
+            let status = match script.progressions.get_mut(&id) {
+                Some(statuses) if statuses.len() > 1 => statuses.pop_front(),
+                Some(statuses) => statuses.front().cloned(),
+                None => None,
+            };
+            return match status {
+                Some(status) => ok(json!({ "index": { "indexId": id, "status": status } })),
+                None => ok(json!({ "index": null })),
+            };
+        }
+    };
+
+    reply_with(reply).await
+}
+
+/// A source file, with its content if it is downloaded.
+fn source(script: &Arc<Mutex<Script>>, name: String, download: bool) -> Response<Vec<u8>> {
+    let mut script = script.lock().unwrap();
+    let (version, content) = match script.sources.get(&name) {
+        Some(source) => source.clone(),
+        None => {
+            let mut response = Response::new(Vec::new());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+    };
+    let mut response = if download {
+        script.downloads.push(name);
+        Response::new(content)
+    } else {
+        Response::new(Vec::new())
+    };
+    response
+        .headers_mut()
+        .insert(ETAG, HeaderValue::from_str(&version).expect("version"));
+    response
+}
+
+async fn reply_with(mut reply: Reply) -> warp::reply::WithStatus<warp::reply::Json> {
+    while let Reply::Slow(delay, next) = reply {
+        tokio::time::delay_for(delay).await;
+        reply = *next;
+    }
+
+    match reply {
+        Reply::Index(id) => ok(json!({ "createIndex": { "index": { "indexId": id } } })),
+        Reply::Errors(messages) => {
+            let errors: Vec<Value> = messages
+                .into_iter()
+                .map(|message| json!({ "message": message }))
+                .collect();
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "data": null, "errors": errors })),
+                StatusCode::OK,
+            )
+        }
+        Reply::Status(code) => warp::reply::with_status(
+            warp::reply::json(&Value::Null),
+            StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        ),
+        Reply::Slow(..) => unreachable!("slow replies are unwrapped above"),
+    }
+}
+
+fn ok(data: Value) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&json!({ "data": data })), StatusCode::OK)
+}
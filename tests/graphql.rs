@@ -0,0 +1,1640 @@
+//! End to end tests of the GraphQL schema. See `common` for the requirements.
+
+mod common;
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use opentelemetry::global;
+use opentelemetry::sdk::trace as sdktrace;
+use opentelemetry::trace::{FutureExt as _, SpanKind, TraceContextExt as _};
+use serde_json::{json, Value};
+use slog::{o, Logger};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use common::twerg::Reply;
+use common::{operator, viewer, TestContext};
+use nidavellir::api::gql::Context;
+use nidavellir::api::model::{self, EnvironmentState as ApiEnvironmentState};
+use nidavellir::cache;
+use nidavellir::db::model::{
+    EnvironmentState, IndexStatus, InputEnvironmentEntity, InputOperationEntity,
+    InputSnapshotEntity, OperationKind, ProvideData,
+};
+use nidavellir::events::EventKind;
+use nidavellir::expiry;
+use nidavellir::health;
+use nidavellir::refresh;
+use nidavellir::shutdown;
+use nidavellir::signature;
+use nidavellir::telemetry;
+
+const CREATE_PROJECT: &str = r#"
+    mutation createProject($project: ProjectRequestBody!) {
+        createProject(project: $project) {
+            project { id name quota { maxEnvironments maxIndexes maxMemory } }
+        }
+    }"#;
+
+const CREATE_INDEX: &str = r#"
+    mutation createIndex($index: IndexRequestBody!) {
+        createIndex(index: $index) {
+            index { id indexType dataSource regions signature status }
+        }
+    }"#;
+
+const CACHED_ARTIFACTS: &str = r#"
+    query {
+        cachedArtifacts { artifacts { dataSource region version digest size } artifactsCount }
+    }"#;
+
+const SCHEDULE_INDEX_REFRESH: &str = r#"
+    mutation scheduleIndexRefresh($schedule: ScheduleRequestBody!) {
+        scheduleIndexRefresh(schedule: $schedule) {
+            indexes { id twergId schedule nextRefreshAt } indexesCount
+        }
+    }"#;
+
+const INDEX_REFRESHES: &str = r#"
+    query indexRefreshes($environment: Uuid!, $index: Uuid!) {
+        indexRefreshes(environment: $environment, index: $index) {
+            refreshes { twergId status outcome error duration } refreshesCount
+        }
+    }"#;
+
+const RETRY_INDEX: &str = r#"
+    mutation retryIndex($index: IndexIdBody!) {
+        retryIndex(index: $index) { index { id twergId status } }
+    }"#;
+
+const REFRESH_INDEX: &str = r#"
+    mutation refreshIndex($index: IndexIdBody!) {
+        refreshIndex(index: $index) { refresh { id outcome } }
+    }"#;
+
+const INDEX_VERSIONS: &str = r#"
+    query indexVersions($environment: Uuid!, $index: Uuid!) {
+        indexVersions(environment: $environment, index: $index) {
+            versions { twergId } versionsCount
+        }
+    }"#;
+
+const ROLLBACK_INDEX: &str = r#"
+    mutation rollbackIndex($rollback: RollbackRequestBody!) {
+        rollbackIndex(rollback: $rollback) { index { id twergId status } }
+    }"#;
+
+const ENVIRONMENTS: &str = r#"
+    query {
+        environments { envs { id name indexes { id status } } envsCount }
+    }"#;
+
+async fn create_project(ctx: &TestContext, name: &str, max_indexes: Option<i32>) -> Uuid {
+    let resp = ctx
+        .execute_as_admin(
+            CREATE_PROJECT,
+            json!({ "project": { "name": name, "quota": { "maxIndexes": max_indexes } } }),
+        )
+        .await;
+    let id = resp["data"]["createProject"]["project"]["id"]
+        .as_str()
+        .unwrap_or_else(|| panic!("project creation: {}", resp));
+    Uuid::parse_str(id).expect("project id")
+}
+
+fn index_request(environment: Uuid, data_source: &str) -> Value {
+    json!({
+        "index": {
+            "environment": environment.to_string(),
+            "indexType": "admins",
+            "dataSource": data_source,
+            "regions": ["fr"],
+        }
+    })
+}
+
+const DIFF_MANIFEST: &str = r#"
+    query diffManifest($manifest: String!, $prune: Boolean) {
+        diffManifest(manifest: $manifest, prune: $prune) {
+            changes { action environment index { indexType dataSource regions } }
+            changesCount
+        }
+    }"#;
+
+const APPLY_MANIFEST: &str = r#"
+    mutation applyManifest($manifest: String!) {
+        applyManifest(manifest: $manifest) {
+            changes { action environment index { indexType dataSource regions } }
+            changesCount
+        }
+    }"#;
+
+const PRUNE_MANIFEST: &str = r#"
+    mutation pruneManifest($manifest: String!) {
+        pruneManifest(manifest: $manifest) {
+            changes { action environment index { indexType dataSource regions } }
+            changesCount
+        }
+    }"#;
+
+/// Refresh an index, and wait for the refresh to finish. Return the finished refresh.
+async fn refresh_index(ctx: &TestContext, environment: Uuid, index: &str) -> Value {
+    let resp = ctx
+        .execute_as_admin(
+            REFRESH_INDEX,
+            json!({ "index": { "environment": environment.to_string(), "index": index } }),
+        )
+        .await;
+    let started = &resp["data"]["refreshIndex"]["refresh"];
+    assert!(started["outcome"].is_null(), "{}", resp);
+
+    let ids = json!({ "environment": environment.to_string(), "index": index });
+    for _ in 0..100 {
+        let resp = ctx.execute_as_admin(INDEX_REFRESHES, ids.clone()).await;
+        let refresh = &resp["data"]["indexRefreshes"]["refreshes"][0];
+        if !refresh["outcome"].is_null() {
+            return refresh.clone();
+        }
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+    panic!("the refresh of index {} did not finish", index);
+}
+
+fn error_message(resp: &Value) -> String {
+    resp["errors"][0]["message"]
+        .as_str()
+        .unwrap_or_else(|| panic!("expected an error: {}", resp))
+        .to_string()
+}
+
+#[tokio::test]
+async fn create_project_and_list_it() {
+    let ctx = TestContext::new().await;
+
+    create_project(&ctx, "e2e", Some(2)).await;
+
+    let resp = ctx
+        .execute_as_admin("query { projects { projects { name } } }", json!({}))
+        .await;
+    let names: Vec<&str> = resp["data"]["projects"]["projects"]
+        .as_array()
+        .expect("projects")
+        .iter()
+        .filter_map(|project| project["name"].as_str())
+        .collect();
+    assert!(names.contains(&"e2e"), "{}", resp);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn create_index_in_twerg() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "indexes", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.twerg.push(Reply::Index(42));
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    let index = &resp["data"]["createIndex"]["index"];
+    assert_eq!(index["dataSource"], json!("osm"), "{}", resp);
+    assert_eq!(index["status"], json!("NOT_AVAILABLE"));
+    assert!(!index["signature"].as_str().unwrap_or_default().is_empty());
+
+    let requests = ctx.twerg.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0]["variables"]["index"],
+        json!({ "indexType": "admins", "dataSource": "osm", "region": "fr" })
+    );
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envs"][0]["indexes"]
+            .as_array()
+            .map(Vec::len),
+        Some(1)
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn twerg_requests_carry_the_request_id() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "correlation", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    assert_eq!(ctx.twerg.request_ids(), vec![Some(String::from("test"))]);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn twerg_requests_propagate_the_trace_context() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "tracing", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    // Spans are recorded, but not exported.
+    let _ = global::set_tracer_provider(sdktrace::TracerProvider::builder().build());
+    let cx = telemetry::start(
+        &opentelemetry::Context::current(),
+        SpanKind::Server,
+        "test",
+        vec![],
+    );
+    let trace_id = cx.span().span_context().trace_id().to_hex();
+
+    ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .with_context(cx)
+        .await;
+
+    let trace_parents = ctx.twerg.trace_parents();
+    assert_eq!(trace_parents.len(), 1);
+    let trace_parent = trace_parents[0].clone().expect("traceparent header");
+    assert!(trace_parent.contains(&trace_id), "{}", trace_parent);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn invalid_indexes_are_rejected_with_the_valid_options() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "validation", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "ntfs"))
+        .await;
+    assert_eq!(error_message(&resp), "Invalid Argument");
+    let extensions = &resp["errors"][0]["extensions"];
+    assert_eq!(extensions["field"], json!("data source for admins indexes"));
+    assert_eq!(extensions["valid"], json!(["cosmogony", "osm"]));
+
+    let mut request = index_request(environment, "osm");
+    request["index"]["indexType"] = json!("buildings");
+    let resp = ctx.execute_as_admin(CREATE_INDEX, request).await;
+    assert_eq!(error_message(&resp), "Invalid Argument");
+    let valid = &resp["errors"][0]["extensions"]["valid"];
+    assert!(
+        valid.as_array().map(Vec::len).unwrap_or_default() > 1,
+        "{}",
+        resp
+    );
+    assert!(resp.to_string().contains("public_transport"), "{}", resp);
+
+    for regions in &[json!(["atlantis"]), json!([])] {
+        let mut request = index_request(environment, "osm");
+        request["index"]["regions"] = regions.clone();
+        let resp = ctx.execute_as_admin(CREATE_INDEX, request).await;
+        assert_eq!(error_message(&resp), "Invalid Argument", "{}", resp);
+        assert_eq!(
+            resp["errors"][0]["extensions"]["field"]
+                .as_str()
+                .map(|field| field.starts_with("region")),
+            Some(true)
+        );
+    }
+
+    // Nothing reached the twerg.
+    assert!(ctx.twerg.requests().is_empty());
+
+    let resp = ctx
+        .execute_as_admin(
+            "query { indexTypes { indexTypes { name dataSources } regions } }",
+            json!({}),
+        )
+        .await;
+    let types = &resp["data"]["indexTypes"];
+    assert_eq!(
+        types["indexTypes"][0],
+        json!({ "name": "admins", "dataSources": ["cosmogony", "osm"] }),
+        "{}",
+        resp
+    );
+    assert!(types["regions"]
+        .as_array()
+        .map(|regions| regions.contains(&json!("fr")))
+        .unwrap_or_default());
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn twerg_graphql_errors_are_reported() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "errors", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.twerg.push(Reply::Errors(vec![String::from(
+        "cosmogony file not found",
+    )]));
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "cosmogony"))
+        .await;
+
+    assert_eq!(error_message(&resp), "Twerg Error");
+    assert!(
+        resp.to_string().contains("cosmogony file not found"),
+        "{}",
+        resp
+    );
+
+    // Nothing was recorded for the failed index.
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envs"][0]["indexes"],
+        json!([])
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn index_creations_are_not_retried_on_server_errors() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "retries", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    // The twerg may have created the index before failing.
+    ctx.twerg.push(Reply::Status(503)).push(Reply::Index(7));
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    assert_eq!(error_message(&resp), "Reqwest Error");
+    assert_eq!(ctx.twerg.requests().len(), 1);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn idempotent_twerg_queries_are_retried_on_server_errors() {
+    let ctx = TestContext::new().await;
+    let logger = Logger::root(slog::Discard, o!());
+    let endpoint = ctx.state.twerg.endpoint("twerg", ctx.twerg.port());
+
+    ctx.twerg.progress(1, &["available"]).fail(503);
+    let status = ctx
+        .state
+        .twerg
+        .index_status(&endpoint, 1, &logger)
+        .await
+        .expect("index status");
+
+    assert_eq!(status, IndexStatus::Available);
+    assert_eq!(ctx.twerg.requests().len(), 2);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn slow_twergs_time_out() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "timeouts", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    // The testing configuration gives up after 2 seconds.
+    let slow = Reply::Slow(Duration::from_secs(3), Box::new(Reply::Index(1)));
+    ctx.twerg.push(slow);
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    assert_eq!(error_message(&resp), "Reqwest Error");
+    assert_eq!(ctx.twerg.requests().len(), 1);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn twergs_can_be_reached_at_their_own_endpoint() {
+    // Only the twerg of the environment named twerg is reachable.
+    let ctx = TestContext::with_settings(|settings, twerg| {
+        let client = &mut settings.twerg.client;
+        client.url = String::from("http://127.0.0.1:1/mimir/graphql");
+        client.urls.insert(
+            String::from("twerg"),
+            format!("http://{}/mimir/graphql", twerg.addr),
+        );
+    })
+    .await;
+    let project = create_project(&ctx, "endpoints", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+    let elsewhere = ctx.insert_environment("elsewhere", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    assert!(resp["errors"].is_null(), "{}", resp);
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(elsewhere, "osm"))
+        .await;
+    assert_eq!(error_message(&resp), "Reqwest Error");
+    assert_eq!(ctx.twerg.requests().len(), 1);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn index_quota_is_enforced() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "quota", Some(1)).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    assert!(resp["errors"].is_null(), "{}", resp);
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "bano"))
+        .await;
+    assert_eq!(error_message(&resp), "Quota Exceeded");
+    assert_eq!(ctx.twerg.requests().len(), 1);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn failed_indexes_are_retried_in_place() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "retry", Some(1)).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    let index = resp["data"]["createIndex"]["index"]["id"].clone();
+    let ids = json!({ "index": { "environment": environment.to_string(), "index": index } });
+
+    // Only failed indexes are retried.
+    ctx.twerg.progress(1, &["indexing_in_progress"]);
+    let resp = ctx.execute_as_admin(RETRY_INDEX, ids.clone()).await;
+    assert_eq!(error_message(&resp), "Miscellaneous Error");
+    assert!(resp.to_string().contains("has not failed"), "{}", resp);
+
+    // The retried index does not count twice against the quota.
+    ctx.twerg.progress(1, &["downloading_error"]);
+    let resp = ctx.execute_as_admin(RETRY_INDEX, ids).await;
+    let retried = &resp["data"]["retryIndex"]["index"];
+    assert_eq!(retried["id"], index, "{}", resp);
+    assert_eq!(retried["twergId"], json!(2));
+    assert_eq!(retried["status"], json!("NOT_AVAILABLE"));
+    assert_eq!(ctx.twerg.deleted(), vec![1]);
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    let indexes = &resp["data"]["environments"]["envs"][0]["indexes"];
+    assert_eq!(indexes.as_array().map(Vec::len), Some(1), "{}", resp);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn mutations_require_a_role() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "roles", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute(CREATE_INDEX, index_request(environment, "osm"), None)
+        .await;
+    assert_eq!(error_message(&resp), "Unauthenticated");
+
+    let resp = ctx
+        .execute(
+            CREATE_INDEX,
+            index_request(environment, "osm"),
+            Some(viewer()),
+        )
+        .await;
+    assert_eq!(error_message(&resp), "Unauthorized");
+    assert!(ctx.twerg.requests().is_empty());
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn operators_create_indexes_in_their_environments_only() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "owners", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute(
+            CREATE_INDEX,
+            index_request(environment, "osm"),
+            Some(operator("intruder")),
+        )
+        .await;
+    assert_eq!(error_message(&resp), "Unauthorized", "{}", resp);
+    assert!(ctx.twerg.requests().is_empty());
+
+    ctx.twerg.push(Reply::Index(1));
+    let resp = ctx
+        .execute(
+            CREATE_INDEX,
+            index_request(environment, "osm"),
+            Some(operator("tester")),
+        )
+        .await;
+    assert!(resp["errors"].is_null(), "{}", resp);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn mutations_are_audited() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "audit", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.execute(
+        CREATE_INDEX,
+        index_request(environment, "osm"),
+        Some(viewer()),
+    )
+    .await;
+    ctx.twerg.push(Reply::Index(3));
+    ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    let query = r#"
+        query auditLog($filter: AuditFilterBody) {
+            auditLog(filter: $filter) {
+                entries { actor kind operation environment index outcome error }
+                entriesCount
+            }
+        }"#;
+    let resp = ctx
+        .execute_as_admin(
+            query,
+            json!({ "filter": { "operation": "createIndex", "environment": environment.to_string() } }),
+        )
+        .await;
+
+    let entries = &resp["data"]["auditLog"]["entries"];
+    assert_eq!(
+        resp["data"]["auditLog"]["entriesCount"],
+        json!(2),
+        "{}",
+        resp
+    );
+    // Most recent first.
+    assert_eq!(entries[0]["actor"], json!("tester"));
+    assert_eq!(entries[0]["kind"], json!("MUTATION"));
+    assert_eq!(entries[0]["outcome"], json!("SUCCESS"));
+    assert!(entries[0]["index"].is_string(), "{}", resp);
+    assert_eq!(entries[1]["actor"], json!("viewer"));
+    assert_eq!(entries[1]["outcome"], json!("FAILURE"));
+    assert!(entries[1]["error"].is_string(), "{}", resp);
+
+    let resp = ctx.execute(query, json!({}), Some(viewer())).await;
+    assert_eq!(error_message(&resp), "Unauthorized");
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn snapshots_are_restored_by_their_environment_managers_only() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "snapshots", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let snapshot = Uuid::new_v4();
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    tx.create_snapshot(&InputSnapshotEntity {
+        id: snapshot,
+        name: String::from("before upgrade"),
+        environment,
+        environment_name: String::from("twerg"),
+        path: String::from("/tmp/snapshots/twerg"),
+        volumes: Vec::new(),
+    })
+    .await
+    .expect("snapshot");
+    tx.commit().await.expect("commit");
+
+    let resp = ctx
+        .execute(
+            r#"mutation restoreEnvironment($snapshot: Uuid!, $env: EnvironmentRequestBody!) {
+                restoreEnvironment(snapshot: $snapshot, env: $env) { env { id } }
+            }"#,
+            json!({ "snapshot": snapshot, "env": { "name": "restored", "project": project } }),
+            Some(operator("operator")),
+        )
+        .await;
+    assert_eq!(error_message(&resp), "Unauthorized", "{}", resp);
+
+    // The template of a restored environment is not ignored.
+    let resp = ctx
+        .execute_as_admin(
+            r#"mutation restoreEnvironment($snapshot: Uuid!, $env: EnvironmentRequestBody!) {
+                restoreEnvironment(snapshot: $snapshot, env: $env) { env { id } }
+            }"#,
+            json!({
+                "snapshot": snapshot,
+                "env": { "name": "restored", "project": project, "template": "unknown" }
+            }),
+        )
+        .await;
+    assert!(
+        resp.to_string().contains("Unknown template unknown"),
+        "{}",
+        resp
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn clones_cannot_be_given_a_template() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "clones", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(
+            r#"mutation cloneEnvironment($source: Uuid!, $env: EnvironmentRequestBody!) {
+                cloneEnvironment(source: $source, env: $env) { env { id } }
+            }"#,
+            json!({
+                "source": environment,
+                "env": { "name": "clone", "project": project, "template": "empty" }
+            }),
+        )
+        .await;
+    assert!(
+        resp.to_string().contains("not of template empty"),
+        "{}",
+        resp
+    );
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envsCount"],
+        json!(1),
+        "{}",
+        resp
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn only_running_environments_of_the_same_owner_project_and_signature_are_reused() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "reuse", None).await;
+    let other_project = create_project(&ctx, "elsewhere", None).await;
+
+    // The signature of the empty template, which resolves no image.
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    let environment = tx
+        .create_environment(&InputEnvironmentEntity {
+            name: String::from("twerg"),
+            port: ctx.twerg.port(),
+            owner: String::from("tester"),
+            project,
+            memory: 0,
+            expires_at: None,
+            services: Vec::new(),
+            config: json!([]),
+            signature: signature::environment_signature(&[], &HashMap::new()),
+        })
+        .await
+        .expect("environment creation")
+        .id;
+    tx.commit().await.expect("commit");
+    ctx.insert_environment("stale", project).await;
+
+    let reuse = |name: &str, project: Uuid, subject: &str| {
+        let vars = json!({
+            "env": { "name": name, "project": project, "template": "empty", "reuse": true }
+        });
+        ctx.execute(
+            r#"mutation createEnvironment($env: EnvironmentRequestBody!) {
+                createEnvironment(env: $env) { env { id } }
+            }"#,
+            vars,
+            Some(operator(subject)),
+        )
+    };
+
+    let resp = reuse("twerg", project, "tester").await;
+    assert_eq!(
+        resp["data"]["createEnvironment"]["env"]["id"],
+        json!(environment),
+        "{}",
+        resp
+    );
+
+    let resp = reuse("twerg", project, "other").await;
+    assert!(
+        resp.to_string()
+            .contains("not a running environment of other"),
+        "{}",
+        resp
+    );
+
+    let resp = reuse("twerg", other_project, "tester").await;
+    assert!(
+        resp.to_string()
+            .contains("not a running environment of tester"),
+        "{}",
+        resp
+    );
+
+    let resp = reuse("stale", project, "tester").await;
+    assert!(resp.to_string().contains("cannot be reused"), "{}", resp);
+
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    tx.update_environment_state(&environment, &EnvironmentState::Stopped)
+        .await
+        .expect("environment stop");
+    tx.commit().await.expect("commit");
+    let resp = reuse("twerg", project, "tester").await;
+    assert!(
+        resp.to_string()
+            .contains("not a running environment of tester"),
+        "{}",
+        resp
+    );
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envsCount"],
+        json!(2),
+        "{}",
+        resp
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn clones_copy_or_replay_the_indexes_of_their_source() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "clones", None).await;
+    let source = ctx.insert_environment("source", project).await;
+    let copy = ctx.insert_environment("copy", project).await;
+    let replay = ctx.insert_environment("replay", project).await;
+
+    ctx.twerg.push(Reply::Index(1)).push(Reply::Index(2));
+    for data_source in &["osm", "bano"] {
+        let resp = ctx
+            .execute_as_admin(CREATE_INDEX, index_request(source, data_source))
+            .await;
+        assert!(resp["errors"].is_null(), "{}", resp);
+    }
+    let context = Context::new(
+        ctx.state.clone(),
+        Some(operator("tester")),
+        String::from("clone"),
+    );
+
+    // Restored volumes hold the indexes already, they are only recorded.
+    let mut copied = model::clone_indexes(&source, &copy, true, &context)
+        .await
+        .expect("copied indexes");
+    copied.sort_by_key(|index| index.twerg_id);
+    assert_eq!(
+        copied
+            .iter()
+            .map(|index| (index.twerg_id, index.data_source.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(Some(1), "osm"), (Some(2), "bano")]
+    );
+    assert_eq!(ctx.twerg.requests().len(), 2);
+
+    // Otherwise the indexes are requested again, and those which fail are skipped.
+    ctx.twerg.push(Reply::Index(3)).push(Reply::Status(500));
+    let replayed = model::clone_indexes(&source, &replay, false, &context)
+        .await
+        .expect("replayed indexes");
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(replayed[0].twerg_id, Some(3));
+    assert_eq!(ctx.twerg.requests().len(), 4);
+
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    let recorded = tx.get_environment_indexes(&replay).await.expect("indexes");
+    tx.commit().await.expect("commit");
+    assert_eq!(recorded.len(), 1);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn metrics_count_environments_and_indexes() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "metrics", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.twerg.push(Reply::Index(5));
+    ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+
+    let metrics = ctx
+        .state
+        .metrics
+        .gather(ctx.state.store.as_ref())
+        .await
+        .expect("metrics");
+
+    assert!(
+        metrics.contains(r#"nidavellir_environments{state="running"} 1"#),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains(r#"nidavellir_indexes{status="not_available"} 1"#),
+        "{}",
+        metrics
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn readiness_checks_the_store() {
+    let ctx = TestContext::new().await;
+
+    let readiness = health::readiness(&ctx.state).await;
+    assert_eq!(readiness.checks.database.status, health::Status::Ok);
+    assert_eq!(readiness.checks.migrations.status, health::Status::Ok);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn draining_refuses_mutations() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "draining", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    assert!(ctx.state.shutdown.drain(Duration::from_secs(1)).await);
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    assert_eq!(error_message(&resp), "Shutting Down");
+    assert!(ctx.twerg.requests().is_empty());
+
+    let readiness = health::readiness(&ctx.state).await;
+    assert_eq!(readiness.checks.shutdown.status, health::Status::Failed);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn interrupted_index_requests_are_resumed() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "recovery", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    tx.create_operation(&InputOperationEntity {
+        kind: OperationKind::CreateIndex,
+        actor: String::from("tester"),
+        name: String::from("twerg"),
+        arguments: json!({
+            "environment": environment,
+            "index_type": "admins",
+            "data_source": "osm",
+            "regions": ["fr"],
+            "reuse": null,
+        }),
+    })
+    .await
+    .expect("operation creation");
+    tx.commit().await.expect("commit");
+
+    ctx.twerg.push(Reply::Index(7));
+    shutdown::recover(&ctx.state).await.expect("recovery");
+
+    assert_eq!(ctx.twerg.requests().len(), 1);
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envs"][0]["indexes"]
+            .as_array()
+            .map(Vec::len),
+        Some(1),
+        "{}",
+        resp
+    );
+
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    let operations = tx.get_all_operations().await.expect("operations");
+    tx.commit().await.expect("commit");
+    assert!(operations.is_empty());
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn index_requests_accepted_by_the_twerg_are_not_resubmitted() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "recovery", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+    let operation = InputOperationEntity {
+        kind: OperationKind::CreateIndex,
+        actor: String::from("tester"),
+        name: String::from("twerg"),
+        arguments: json!({
+            "environment": environment,
+            "index_type": "admins",
+            "data_source": "osm",
+            "regions": ["fr"],
+            "reuse": null,
+            "twerg_id": 7,
+        }),
+    };
+
+    // Interrupted once before the index was recorded, and once after.
+    for _ in 0..2 {
+        let mut tx = ctx.state.store.begin().await.expect("transaction");
+        tx.create_operation(&operation)
+            .await
+            .expect("operation creation");
+        tx.commit().await.expect("commit");
+
+        shutdown::recover(&ctx.state).await.expect("recovery");
+    }
+
+    assert!(ctx.twerg.requests().is_empty());
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    let indexes = tx
+        .get_environment_indexes(&environment)
+        .await
+        .expect("indexes");
+    tx.commit().await.expect("commit");
+    assert_eq!(indexes.len(), 1);
+    assert_eq!(indexes[0].twerg_id, Some(7));
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn draining_waits_for_background_work() {
+    let ctx = TestContext::new().await;
+
+    let in_flight = ctx.state.shutdown.hold();
+    assert!(!ctx.state.shutdown.drain(Duration::from_millis(50)).await);
+    drop(in_flight);
+    assert!(ctx.state.shutdown.drain(Duration::from_millis(50)).await);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn index_status_progression() {
+    let ctx = TestContext::new().await;
+    let logger = Logger::root(slog::Discard, o!());
+    let endpoint = ctx.state.twerg.endpoint("twerg", ctx.twerg.port());
+
+    ctx.twerg.progress(
+        3,
+        &[
+            "downloading_in_progress",
+            "indexing_in_progress",
+            "available",
+        ],
+    );
+
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        let data: Value = ctx
+            .state
+            .twerg
+            .query(
+                &endpoint,
+                "query index($id: Int!) { index(id: $id) { indexId status } }",
+                &json!({ "id": 3 }),
+                &logger,
+            )
+            .await
+            .expect("index status");
+        statuses.push(
+            data["index"]["status"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        );
+    }
+
+    assert_eq!(
+        statuses,
+        vec![
+            "downloading_in_progress",
+            "indexing_in_progress",
+            "available",
+            "available"
+        ]
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn manifests_converge_indexes() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "manifests", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.twerg.push(Reply::Index(1));
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "bano"))
+        .await;
+    assert!(resp["errors"].is_null(), "{}", resp);
+
+    let manifest = format!(
+        r#"
+project: {}
+environments:
+  - name: twerg
+    template: empty
+    indexes:
+      - indexType: admins
+        dataSource: osm
+        regions: [fr]
+"#,
+        project
+    );
+    let osm = json!({ "indexType": "admins", "dataSource": "osm", "regions": ["fr"] });
+    let bano = json!({ "indexType": "admins", "dataSource": "bano", "regions": ["fr"] });
+
+    let resp = ctx
+        .execute_as_admin(
+            DIFF_MANIFEST,
+            json!({ "manifest": &manifest, "prune": true }),
+        )
+        .await;
+    assert_eq!(
+        resp["data"]["diffManifest"]["changes"],
+        json!([
+            { "action": "DELETE", "environment": "twerg", "index": bano },
+            { "action": "CREATE", "environment": "twerg", "index": osm },
+        ]),
+        "{}",
+        resp
+    );
+
+    ctx.twerg.push(Reply::Index(2));
+    let resp = ctx
+        .execute_as_admin(APPLY_MANIFEST, json!({ "manifest": &manifest }))
+        .await;
+    assert_eq!(
+        resp["data"]["applyManifest"]["changes"],
+        json!([{ "action": "CREATE", "environment": "twerg", "index": osm }]),
+        "{}",
+        resp
+    );
+
+    // Applying the manifest again changes nothing.
+    let resp = ctx
+        .execute_as_admin(APPLY_MANIFEST, json!({ "manifest": &manifest }))
+        .await;
+    assert_eq!(resp["data"]["applyManifest"]["changesCount"], json!(0));
+    assert_eq!(ctx.twerg.requests().len(), 2);
+
+    let resp = ctx
+        .execute_as_admin(PRUNE_MANIFEST, json!({ "manifest": &manifest }))
+        .await;
+    assert_eq!(
+        resp["data"]["pruneManifest"]["changes"],
+        json!([{ "action": "DELETE", "environment": "twerg", "index": bano }]),
+        "{}",
+        resp
+    );
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["environments"]["envs"][0]["indexes"]
+            .as_array()
+            .map(Vec::len),
+        Some(1)
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn manifest_diff_plans_environments() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "plans", None).await;
+    // Inserted environments have no services, unlike the default template.
+    ctx.insert_environment("stale", project).await;
+    ctx.insert_environment("extra", project).await;
+
+    let manifest = format!(
+        r#"
+project: {}
+environments:
+  - name: stale
+  - name: fresh
+    ttl: 3600
+    indexes:
+      - indexType: admins
+        dataSource: osm
+        regions: [fr]
+"#,
+        project
+    );
+
+    let resp = ctx
+        .execute_as_admin(
+            DIFF_MANIFEST,
+            json!({ "manifest": &manifest, "prune": true }),
+        )
+        .await;
+    let changes = resp["data"]["diffManifest"]["changes"]
+        .as_array()
+        .unwrap_or_else(|| panic!("changes: {}", resp))
+        .iter()
+        .map(|change| {
+            format!(
+                "{} {} {}",
+                change["action"].as_str().unwrap_or_default(),
+                change["environment"].as_str().unwrap_or_default(),
+                change["index"]["dataSource"].as_str().unwrap_or("-"),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            "RECREATE stale -",
+            "CREATE fresh -",
+            "CREATE fresh osm",
+            "DELETE extra -"
+        ]
+    );
+
+    let resp = ctx
+        .execute_as_admin(
+            DIFF_MANIFEST,
+            json!({ "manifest": "project: [not, an, id]" }),
+        )
+        .await;
+    assert!(error_message(&resp).contains("YAML Error"), "{}", resp);
+
+    ctx.teardown().await;
+}
+
+/// The entries of the audit log for an operation on an environment.
+async fn audited(ctx: &TestContext, operation: &str, environment: Uuid) -> Vec<Value> {
+    let resp = ctx
+        .execute_as_admin(
+            r#"query auditLog($filter: AuditFilterBody) {
+                auditLog(filter: $filter) { entries { kind outcome } }
+            }"#,
+            json!({ "filter": { "operation": operation, "environment": environment } }),
+        )
+        .await;
+    resp["data"]["auditLog"]["entries"]
+        .as_array()
+        .cloned()
+        .unwrap_or_else(|| panic!("audit log: {}", resp))
+}
+
+/// The docker deletions of the twerg of an environment recorded in the audit log, and
+/// whether the environment is still in the catalog.
+async fn twerg_deletions(ctx: &TestContext, environment: Uuid) -> (Vec<Value>, bool) {
+    let deletions = audited(ctx, "delete_twerg", environment).await;
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    let listed = resp["data"]["environments"]["envs"]
+        .as_array()
+        .unwrap_or_else(|| panic!("environments: {}", resp))
+        .iter()
+        .any(|env| env["id"] == json!(environment));
+    (deletions, listed)
+}
+
+#[tokio::test]
+async fn manifests_remove_the_twergs_of_the_environments_they_replace() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "teardown", None).await;
+    // Inserted environments have no services, so the default template recreates them.
+    ctx.insert_environment("stale", project).await;
+    let extra = ctx.insert_environment("extra", project).await;
+
+    let manifest = format!(
+        r#"
+project: {}
+environments:
+  - name: stale
+"#,
+        project
+    );
+
+    ctx.execute_as_admin(APPLY_MANIFEST, json!({ "manifest": &manifest }))
+        .await;
+    ctx.execute_as_admin(PRUNE_MANIFEST, json!({ "manifest": &manifest }))
+        .await;
+
+    // The twergs are removed before the environments are forgotten, and environments
+    // whose twerg could not be removed, eg without docker, are kept.
+    for environment in &[stale, extra] {
+        let (deletions, listed) = twerg_deletions(&ctx, *environment).await;
+        assert_eq!(deletions.len(), 1, "{:?}", deletions);
+        assert_eq!(deletions[0]["kind"], json!("DOCKER"));
+        assert_eq!(listed, deletions[0]["outcome"] == json!("FAILURE"));
+    }
+
+    ctx.teardown().await;
+}
+
+/// Record an environment served by the mock twerg, which expires at the given date.
+async fn insert_expiring_environment(
+    ctx: &TestContext,
+    name: &str,
+    project: Uuid,
+    expires_at: Option<DateTime<Utc>>,
+) -> Uuid {
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    let environment = tx
+        .create_environment(&InputEnvironmentEntity {
+            name: String::from(name),
+            port: ctx.twerg.port(),
+            owner: String::from("tester"),
+            project,
+            memory: 0,
+            expires_at,
+            services: Vec::new(),
+            config: json!([]),
+            signature: format!("signature of {}", name),
+        })
+        .await
+        .expect("environment creation");
+    tx.commit().await.expect("commit");
+    environment.id
+}
+
+#[tokio::test]
+async fn sweeps_warn_of_expiries_and_tear_down_expired_environments() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "expiry", None).await;
+    let now = Utc::now();
+    let expired = insert_expiring_environment(&ctx, "expired", project, Some(now)).await;
+    let soon = ChronoDuration::minutes(10);
+    let expiring = insert_expiring_environment(&ctx, "expiring", project, Some(now + soon)).await;
+    let later = ChronoDuration::days(2);
+    let lasting = insert_expiring_environment(&ctx, "lasting", project, Some(now + later)).await;
+    let forever = insert_expiring_environment(&ctx, "forever", project, None).await;
+
+    let mut events = ctx.state.events.subscribe();
+    expiry::sweep(&ctx.state).await.expect("sweep");
+    let mut published = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        published.push((event.name, event.kind));
+    }
+    assert!(published.contains(&(String::from("expiring"), EventKind::ExpiryWarning)));
+    assert!(published.contains(&(String::from("expired"), EventKind::Expired)));
+    assert!(!published
+        .iter()
+        .any(|(name, _)| name == "lasting" || name == "forever"));
+    assert!(!published
+        .iter()
+        .any(|(name, kind)| name == "expiring" && *kind == EventKind::Expired));
+
+    // Environments are warned once.
+    expiry::sweep(&ctx.state).await.expect("sweep");
+    while let Ok(event) = events.try_recv() {
+        assert_ne!(
+            (event.name.as_str(), event.kind),
+            ("expiring", EventKind::ExpiryWarning)
+        );
+    }
+
+    // The twerg of the expired environment is removed with its stored configuration,
+    // before the environment is forgotten. Without docker, it is kept.
+    let (deletions, listed) = twerg_deletions(&ctx, expired).await;
+    assert!(!deletions.is_empty(), "{:?}", deletions);
+    assert_eq!(deletions[0]["kind"], json!("DOCKER"));
+    assert_eq!(listed, deletions[0]["outcome"] == json!("FAILURE"));
+
+    for environment in &[expiring, lasting, forever] {
+        let (deletions, listed) = twerg_deletions(&ctx, *environment).await;
+        assert!(deletions.is_empty(), "{:?}", deletions);
+        assert!(listed);
+    }
+
+    ctx.teardown().await;
+}
+
+#[test]
+fn environment_states_follow_the_services_running() {
+    assert_eq!(
+        ApiEnvironmentState::from_running_count(0, 3),
+        ApiEnvironmentState::Stopped
+    );
+    assert_eq!(
+        ApiEnvironmentState::from_running_count(1, 3),
+        ApiEnvironmentState::PartiallyRunning
+    );
+    assert_eq!(
+        ApiEnvironmentState::from_running_count(3, 3),
+        ApiEnvironmentState::Running
+    );
+}
+
+/// Stop or start an environment on behalf of an operator.
+async fn transition(ctx: &TestContext, environment: Uuid, mutation: &str, subject: &str) -> Value {
+    let query = format!(
+        "mutation {0}($id: EnvironmentIdBody!) {{ {0}(id: $id) {{ env {{ state }} }} }}",
+        mutation
+    );
+    ctx.execute(
+        &query,
+        json!({ "id": { "id": environment } }),
+        Some(operator(subject)),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn environments_are_stopped_and_started_by_their_managers() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "states", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = transition(&ctx, environment, "stopEnvironment", "other").await;
+    assert_eq!(error_message(&resp), "Unauthorized", "{}", resp);
+    assert!(audited(&ctx, "stop_twerg", environment).await.is_empty());
+
+    // The twerg has no services, so none runs once it is stopped.
+    let resp = transition(&ctx, environment, "stopEnvironment", "tester").await;
+    assert_eq!(
+        resp["data"]["stopEnvironment"]["env"]["state"],
+        json!("STOPPED"),
+        "{}",
+        resp
+    );
+    let stops = audited(&ctx, "stop_twerg", environment).await;
+    assert_eq!(
+        stops,
+        vec![json!({ "kind": "DOCKER", "outcome": "SUCCESS" })]
+    );
+
+    let resp = transition(&ctx, environment, "startEnvironment", "other").await;
+    assert_eq!(error_message(&resp), "Unauthorized", "{}", resp);
+    let resp = transition(&ctx, environment, "startEnvironment", "tester").await;
+    assert!(resp["errors"].is_null(), "{}", resp);
+    let starts = audited(&ctx, "start_twerg", environment).await;
+    assert_eq!(
+        starts,
+        vec![json!({ "kind": "DOCKER", "outcome": "SUCCESS" })]
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn index_sources_are_downloaded_once_into_the_cache() {
+    let ctx = TestContext::with_settings(|settings, twerg| {
+        settings.cache.sources.insert(
+            String::from("osm"),
+            format!("http://{}/sources/{{region}}.osm.pbf", twerg.addr),
+        );
+        // Nothing fits, so that everything goes at the next eviction.
+        settings.cache.max_size = 0;
+    })
+    .await;
+    let project = create_project(&ctx, "cache", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    ctx.twerg.source("fr.osm.pbf", "\"v1\"", b"france");
+    for _ in 0..2 {
+        ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+            .await;
+    }
+    assert_eq!(ctx.twerg.downloads(), vec![String::from("fr.osm.pbf")]);
+
+    let resp = ctx.execute_as_admin(CACHED_ARTIFACTS, json!({})).await;
+    let artifacts = &resp["data"]["cachedArtifacts"];
+    assert_eq!(artifacts["artifactsCount"], json!(1), "{}", resp);
+    assert_eq!(artifacts["artifacts"][0]["version"], json!("\"v1\""));
+    assert_eq!(artifacts["artifacts"][0]["size"], json!(6.0));
+    let digest = artifacts["artifacts"][0]["digest"]
+        .as_str()
+        .expect("digest")
+        .to_string();
+
+    // Twergs are given the file in the cache mounted in their ingestion services.
+    let file = format!("{}/{}", ctx.state.settings.cache.target, digest);
+    for request in ctx.twerg.requests() {
+        assert_eq!(request["variables"]["index"]["file"], json!(file));
+    }
+
+    // A new upstream version is downloaded again.
+    ctx.twerg.source("fr.osm.pbf", "\"v2\"", b"france, updated");
+    ctx.execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    assert_eq!(ctx.twerg.downloads().len(), 2);
+
+    let resp = ctx.execute_as_admin(CACHED_ARTIFACTS, json!({})).await;
+    let artifacts = &resp["data"]["cachedArtifacts"];
+    assert_eq!(artifacts["artifactsCount"], json!(2), "{}", resp);
+    assert_eq!(artifacts["artifacts"][0]["version"], json!("\"v2\""));
+
+    let evicted = cache::evict(&ctx.state).await.expect("eviction");
+    assert_eq!(evicted.len(), 2);
+    let host_file = std::path::Path::new(&ctx.state.settings.cache.directory).join(&digest);
+    assert!(!host_file.exists());
+
+    let resp = ctx.execute_as_admin(CACHED_ARTIFACTS, json!({})).await;
+    assert_eq!(
+        resp["data"]["cachedArtifacts"]["artifactsCount"],
+        json!(0),
+        "{}",
+        resp
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn scheduled_refreshes_swap_in_the_new_index() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "refresh", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    let index = resp["data"]["createIndex"]["index"]["id"]
+        .as_str()
+        .unwrap_or_else(|| panic!("index creation: {}", resp))
+        .to_string();
+
+    let schedule = |cron: &str| {
+        json!({ "schedule": {
+            "environment": environment.to_string(),
+            "index": index,
+            "schedule": cron,
+        }})
+    };
+    let resp = ctx
+        .execute_as_admin(SCHEDULE_INDEX_REFRESH, schedule("61 * * * *"))
+        .await;
+    assert_eq!(error_message(&resp), "Invalid Argument");
+    let resp = ctx
+        .execute_as_admin(SCHEDULE_INDEX_REFRESH, schedule("0  3 * * 0"))
+        .await;
+    let scheduled = &resp["data"]["scheduleIndexRefresh"]["indexes"][0];
+    assert_eq!(scheduled["schedule"], json!("0 3 * * 0"), "{}", resp);
+    assert_eq!(scheduled["twergId"], json!(1));
+    assert!(scheduled["nextRefreshAt"].is_string(), "{}", resp);
+
+    // The new index is swapped in once available.
+    ctx.twerg
+        .progress(2, &["indexing_in_progress", "available"]);
+    let refreshed = refresh_index(&ctx, environment, &index).await;
+    assert!(refreshed["error"].is_null(), "{}", refreshed);
+    assert_eq!(refreshed["twergId"], json!(2));
+
+    // A failed build leaves the previous index in place.
+    ctx.twerg.progress(3, &["indexing_error"]);
+    let failed = refresh_index(&ctx, environment, &index).await;
+    assert!(failed["error"].is_string(), "{}", failed);
+
+    let resp = ctx
+        .execute_as_admin(
+            INDEX_REFRESHES,
+            json!({ "environment": environment.to_string(), "index": index }),
+        )
+        .await;
+    let refreshes = &resp["data"]["indexRefreshes"];
+    assert_eq!(refreshes["refreshesCount"], json!(2), "{}", resp);
+    assert_eq!(refreshes["refreshes"][0]["outcome"], json!("FAILURE"));
+    assert_eq!(refreshes["refreshes"][0]["status"], json!("INDEXING_ERROR"));
+    assert_eq!(refreshes["refreshes"][1]["outcome"], json!("SUCCESS"));
+    assert!(
+        refreshes["refreshes"][1]["duration"].is_number(),
+        "{}",
+        resp
+    );
+
+    let resp = ctx.execute_as_admin(ENVIRONMENTS, json!({})).await;
+    let indexes = &resp["data"]["environments"]["envs"][0]["indexes"];
+    assert_eq!(indexes[0]["status"], json!("AVAILABLE"), "{}", resp);
+
+    // Without a schedule, the refreshes stop.
+    let resp = ctx
+        .execute_as_admin(
+            SCHEDULE_INDEX_REFRESH,
+            json!({ "schedule": { "environment": environment.to_string() } }),
+        )
+        .await;
+    let scheduled = &resp["data"]["scheduleIndexRefresh"];
+    assert_eq!(scheduled["indexesCount"], json!(1), "{}", resp);
+    assert_eq!(scheduled["indexes"][0]["twergId"], json!(2));
+    assert!(scheduled["indexes"][0]["schedule"].is_null());
+    assert!(refresh::start_due(&ctx.state)
+        .await
+        .expect("due")
+        .is_empty());
+
+    // An index is only refreshed once at a time.
+    let index_id = Uuid::parse_str(&index).expect("index id");
+    let mut tx = ctx.state.store.begin().await.expect("transaction");
+    tx.create_index_refresh(&index_id).await.expect("refresh");
+    tx.commit().await.expect("commit");
+    let resp = ctx
+        .execute_as_admin(
+            REFRESH_INDEX,
+            json!({ "index": { "environment": environment.to_string(), "index": index } }),
+        )
+        .await;
+    assert_eq!(error_message(&resp), "Miscellaneous Error");
+    assert!(
+        resp.to_string().contains("still being refreshed"),
+        "{}",
+        resp
+    );
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn refreshed_indexes_are_published_and_can_be_rolled_back() {
+    let ctx = TestContext::new().await;
+    let project = create_project(&ctx, "versions", None).await;
+    let environment = ctx.insert_environment("twerg", project).await;
+
+    let resp = ctx
+        .execute_as_admin(CREATE_INDEX, index_request(environment, "osm"))
+        .await;
+    let index = resp["data"]["createIndex"]["index"]["id"]
+        .as_str()
+        .unwrap_or_else(|| panic!("index creation: {}", resp))
+        .to_string();
+    let ids = json!({ "environment": environment.to_string(), "index": index });
+
+    // Each version is published once validated, and the two previous ones are kept.
+    for version in 2..=4 {
+        ctx.twerg
+            .progress(version, &["validation_in_progress", "available"]);
+        let refreshed = refresh_index(&ctx, environment, &index).await;
+        assert!(refreshed["error"].is_null(), "{}", refreshed);
+    }
+    assert_eq!(ctx.twerg.published(), vec![2, 3, 4]);
+    assert_eq!(ctx.twerg.deleted(), vec![1]);
+
+    let resp = ctx.execute_as_admin(INDEX_VERSIONS, ids.clone()).await;
+    let versions = &resp["data"]["indexVersions"];
+    assert_eq!(versions["versionsCount"], json!(2), "{}", resp);
+    assert_eq!(versions["versions"][0]["twergId"], json!(3));
+    assert_eq!(versions["versions"][1]["twergId"], json!(2));
+
+    // Rolling back serves the most recent previous version, and discards the current one.
+    let rollback = |version: Option<i32>| {
+        json!({ "rollback": {
+            "environment": environment.to_string(),
+            "index": index,
+            "version": version,
+        }})
+    };
+    let resp = ctx.execute_as_admin(ROLLBACK_INDEX, rollback(None)).await;
+    let rolled_back = &resp["data"]["rollbackIndex"]["index"];
+    assert_eq!(rolled_back["twergId"], json!(3), "{}", resp);
+    assert_eq!(rolled_back["status"], json!("AVAILABLE"));
+    assert_eq!(ctx.twerg.published(), vec![2, 3, 4, 3]);
+    assert_eq!(ctx.twerg.deleted(), vec![1, 4]);
+
+    let resp = ctx
+        .execute_as_admin(ROLLBACK_INDEX, rollback(Some(4)))
+        .await;
+    assert_eq!(error_message(&resp), "Miscellaneous Error");
+    assert!(resp.to_string().contains("no version 4"), "{}", resp);
+
+    let resp = ctx
+        .execute_as_admin(ROLLBACK_INDEX, rollback(Some(2)))
+        .await;
+    assert_eq!(
+        resp["data"]["rollbackIndex"]["index"]["twergId"],
+        json!(2),
+        "{}",
+        resp
+    );
+
+    let resp = ctx.execute_as_admin(INDEX_VERSIONS, ids).await;
+    assert_eq!(
+        resp["data"]["indexVersions"]["versionsCount"],
+        json!(0),
+        "{}",
+        resp
+    );
+    let resp = ctx.execute_as_admin(ROLLBACK_INDEX, rollback(None)).await;
+    assert_eq!(error_message(&resp), "Miscellaneous Error");
+    assert!(resp.to_string().contains("no previous version"), "{}", resp);
+
+    ctx.teardown().await;
+}
@@ -0,0 +1,233 @@
+//! Tests of the resolution of the images of services, against a fake docker engine.
+
+mod common;
+
+use serde_json::json;
+use slog::{o, Logger};
+use std::cell::Cell;
+
+use common::docker::MockDocker;
+use nidavellir::docker::images::{
+    create_image, image_repository, resolve_image, PullPolicy, ResolvedImage,
+};
+use nidavellir::docker::DockerConfig;
+use nidavellir::settings::DockerRegistry;
+
+const BRAGI: &str = "localhost:5000/bragi:latest";
+
+fn logger() -> Logger {
+    Logger::root(slog::Discard, o!())
+}
+
+fn registry() -> DockerRegistry {
+    DockerRegistry {
+        url: String::from("localhost:5000"),
+        username: None,
+        password: None,
+    }
+}
+
+async fn resolve(docker: &MockDocker, policy: PullPolicy) -> Result<ResolvedImage, String> {
+    resolve_image(
+        &docker.client(),
+        BRAGI,
+        policy,
+        &registry(),
+        |_| {},
+        &logger(),
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+/// The error of pulling bragi, and how many progress lines were reported.
+async fn pull(docker: &MockDocker) -> (Result<(), String>, usize) {
+    let reported = Cell::new(0);
+    let result = create_image(
+        &docker.client(),
+        BRAGI,
+        None,
+        |_| reported.set(reported.get() + 1),
+        &logger(),
+    )
+    .await
+    .map_err(|err| err.to_string());
+    (result, reported.get())
+}
+
+#[test]
+fn pull_policies_default_to_always() {
+    let policy = |config| {
+        serde_json::from_value::<DockerConfig>(config)
+            .map(|config| config.pull)
+            .ok()
+    };
+
+    assert_eq!(
+        policy(json!({ "image": "bragi", "tag": "latest" })),
+        Some(PullPolicy::Always)
+    );
+    assert_eq!(
+        policy(json!({ "image": "bragi", "tag": "latest", "pull": "if-not-present" })),
+        Some(PullPolicy::IfNotPresent)
+    );
+    assert_eq!(
+        policy(json!({ "image": "bragi", "tag": "latest", "pull": "never" })),
+        Some(PullPolicy::Never)
+    );
+    assert_eq!(
+        policy(json!({ "image": "bragi", "tag": "latest", "pull": "sometimes" })),
+        None
+    );
+}
+
+#[test]
+fn image_repositories_drop_the_tag_only() {
+    assert_eq!(image_repository(BRAGI), "localhost:5000/bragi");
+    assert_eq!(
+        image_repository("localhost:5000/bragi"),
+        "localhost:5000/bragi"
+    );
+    assert_eq!(
+        image_repository("registry.example.com/team/bragi:1.2"),
+        "registry.example.com/team/bragi"
+    );
+    assert_eq!(image_repository("bragi:latest"), "bragi");
+    assert_eq!(image_repository("bragi"), "bragi");
+}
+
+#[tokio::test]
+async fn images_are_always_pulled_by_default() {
+    let docker = MockDocker::start();
+    docker.image(BRAGI, &[]);
+
+    assert!(resolve(&docker, PullPolicy::Always).await.is_ok());
+    assert_eq!(docker.pulls(), vec![BRAGI]);
+}
+
+#[tokio::test]
+async fn images_present_are_not_pulled_if_not_present() {
+    let docker = MockDocker::start();
+
+    assert!(resolve(&docker, PullPolicy::IfNotPresent).await.is_ok());
+    assert!(resolve(&docker, PullPolicy::IfNotPresent).await.is_ok());
+    assert_eq!(docker.pulls(), vec![BRAGI]);
+}
+
+#[tokio::test]
+async fn images_are_never_pulled_with_the_never_policy() {
+    let docker = MockDocker::start();
+
+    let err = resolve(&docker, PullPolicy::Never)
+        .await
+        .expect_err("a missing image");
+    assert!(err.contains("its pull policy is never"), "{}", err);
+
+    docker.image(BRAGI, &[]);
+    assert!(resolve(&docker, PullPolicy::Never).await.is_ok());
+    assert!(docker.pulls().is_empty());
+}
+
+#[tokio::test]
+async fn images_are_pinned_by_the_digest_of_their_repository() {
+    let docker = MockDocker::start();
+    docker.image(
+        BRAGI,
+        &[
+            "mirror.example.com/bragi@sha256:aaaa",
+            "localhost:5000/bragi@sha256:bbbb",
+        ],
+    );
+
+    assert_eq!(
+        resolve(&docker, PullPolicy::Never).await,
+        Ok(ResolvedImage {
+            reference: String::from("localhost:5000/bragi@sha256:bbbb"),
+            digest: Some(String::from("sha256:bbbb")),
+        })
+    );
+
+    // Images built locally are used by name.
+    docker.image(BRAGI, &["mirror.example.com/bragi@sha256:aaaa"]);
+    assert_eq!(
+        resolve(&docker, PullPolicy::Never).await,
+        Ok(ResolvedImage {
+            reference: String::from(BRAGI),
+            digest: None,
+        })
+    );
+}
+
+#[tokio::test]
+async fn pulled_images_are_pinned_by_their_new_digest() {
+    let docker = MockDocker::start();
+    docker.image(BRAGI, &["localhost:5000/bragi@sha256:aaaa"]);
+    docker.pull(
+        vec![json!({ "status": "Downloaded newer image" })],
+        &["localhost:5000/bragi@sha256:bbbb"],
+    );
+
+    let image = resolve(&docker, PullPolicy::Always)
+        .await
+        .expect("a resolved image");
+    assert_eq!(image.digest.as_deref(), Some("sha256:bbbb"));
+}
+
+#[tokio::test]
+async fn pull_progress_is_reported() {
+    let docker = MockDocker::start();
+    docker.pull(
+        vec![
+            json!({ "status": "Pulling from bragi", "id": "latest" }),
+            json!({ "status": "Downloading", "progress": "[==>   ]", "id": "0123" }),
+            json!({ "status": "Digest: sha256:bbbb" }),
+        ],
+        &[],
+    );
+
+    assert_eq!(pull(&docker).await, (Ok(()), 3));
+}
+
+#[tokio::test]
+async fn pull_errors_in_the_stream_are_errors() {
+    let docker = MockDocker::start();
+    docker.pull(
+        vec![
+            json!({ "status": "Pulling from bragi", "id": "latest" }),
+            json!({
+                "error": "manifest unknown",
+                "errorDetail": { "message": "manifest unknown" },
+            }),
+        ],
+        &[],
+    );
+
+    let (result, reported) = pull(&docker).await;
+    let err = result.expect_err("a failed pull");
+    assert!(err.contains("manifest unknown"), "{}", err);
+    assert_eq!(reported, 1);
+}
+
+#[tokio::test]
+async fn pull_error_details_alone_are_errors() {
+    let docker = MockDocker::start();
+    docker.pull(
+        vec![json!({ "errorDetail": { "message": "unauthorized" } })],
+        &[],
+    );
+
+    let (result, _) = pull(&docker).await;
+    let err = result.expect_err("a failed pull");
+    assert!(err.contains("unauthorized"), "{}", err);
+}
+
+#[tokio::test]
+async fn pulls_without_progress_are_errors() {
+    let docker = MockDocker::start();
+    docker.pull(Vec::new(), &[]);
+
+    let (result, reported) = pull(&docker).await;
+    let err = result.expect_err("a failed pull");
+    assert!(err.contains("returned no information"), "{}", err);
+    assert_eq!(reported, 0);
+}
@@ -0,0 +1,98 @@
+//! Tests of the validation of the resources given to the services of twergs.
+
+use slog::{o, Logger};
+use std::env;
+
+use nidavellir::docker;
+use nidavellir::docker::resources::{ResourcesConfig, RestartConfig, RestartPolicyConfig};
+use nidavellir::settings::Settings;
+
+/// The error reported for the resources of a service.
+fn rejection(resources: ResourcesConfig) -> String {
+    match resources.validate("bragi") {
+        Ok(()) => panic!("expected invalid resources: {:?}", resources),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[test]
+fn valid_resources_are_accepted() {
+    let resources = ResourcesConfig {
+        memory: Some(2048),
+        memory_reservation: Some(1024),
+        cpu_shares: Some(512),
+        cpu_quota: Some(50_000),
+        cpu_period: Some(100_000),
+        pids_limit: Some(-1),
+        ulimits: None,
+        restart: Some(RestartConfig {
+            policy: RestartPolicyConfig::OnFailure,
+            max_retries: Some(3),
+        }),
+    };
+    assert!(resources.validate("bragi").is_ok());
+    assert!(ResourcesConfig::default().validate("bragi").is_ok());
+}
+
+#[test]
+fn bad_memory_values_are_rejected() {
+    let err = rejection(ResourcesConfig {
+        memory: Some(4),
+        ..Default::default()
+    });
+    assert!(err.contains("bragi"), "{}", err);
+    assert!(err.contains("below the 6MiB minimum"), "{}", err);
+
+    let err = rejection(ResourcesConfig {
+        memory: Some(512),
+        memory_reservation: Some(1024),
+        ..Default::default()
+    });
+    assert!(err.contains("exceeds memory limit 512MiB"), "{}", err);
+
+    let err = rejection(ResourcesConfig {
+        memory_reservation: Some(0),
+        ..Default::default()
+    });
+    assert!(err.contains("memory reservation 0MiB"), "{}", err);
+}
+
+#[test]
+fn bad_cpu_values_are_rejected() {
+    let err = rejection(ResourcesConfig {
+        cpu_shares: Some(1),
+        ..Default::default()
+    });
+    assert!(err.contains("cpu shares 1 is below 2"), "{}", err);
+
+    let err = rejection(ResourcesConfig {
+        cpu_quota: Some(500),
+        ..Default::default()
+    });
+    assert!(err.contains("cpu quota 500us"), "{}", err);
+
+    for period in &[999, 1_000_001] {
+        let err = rejection(ResourcesConfig {
+            cpu_period: Some(*period),
+            ..Default::default()
+        });
+        assert!(err.contains("not between 1ms and 1s"), "{}", err);
+    }
+}
+
+#[tokio::test]
+async fn configurations_with_bad_resources_are_rejected() {
+    env::set_var("RUN_MODE", "testing");
+    let settings = Settings::new(None).expect("testing settings");
+    let logger = Logger::root(slog::Discard, o!());
+
+    let err = docker::get_template_config(&settings, Some("invalid"), &logger)
+        .await
+        .expect_err("invalid template")
+        .to_string();
+    assert!(
+        err.contains("Invalid resources for service bragi"),
+        "{}",
+        err
+    );
+}
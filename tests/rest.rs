@@ -0,0 +1,106 @@
+//! End to end tests of the REST API. See `common` for the requirements.
+
+mod common;
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+use common::twerg::Reply;
+use common::{admin, viewer, TestContext};
+
+fn json_body(body: &[u8]) -> Value {
+    serde_json::from_slice(body).expect("json body")
+}
+
+#[tokio::test]
+async fn create_project_and_index() {
+    let ctx = TestContext::new().await;
+    let routes = ctx.rest(Some(admin()));
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/v1/projects")
+        .json(&json!({ "name": "rest", "quota": {} }))
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.headers()["x-request-id"], "test");
+    let project = json_body(resp.body())["project"]["id"]
+        .as_str()
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .expect("project id");
+
+    let environment = ctx.insert_environment("twerg", project).await;
+    ctx.twerg.push(Reply::Index(42));
+    let resp = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/v1/environments/{}/indexes", environment))
+        .json(&json!({ "index_type": "admins", "data_source": "osm", "regions": ["fr"] }))
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED, "{:?}", resp.body());
+    assert_eq!(json_body(resp.body())["index"]["dataSource"], json!("osm"));
+
+    let resp = warp::test::request()
+        .path(&format!("/api/v1/environments/{}/indexes", environment))
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(json_body(resp.body())["indexesCount"], json!(1));
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn errors_are_reported_with_their_status() {
+    let ctx = TestContext::new().await;
+
+    let resp = warp::test::request()
+        .path(&format!("/api/v1/environments/{}", Uuid::new_v4()))
+        .reply(&ctx.rest(Some(admin())))
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(json_body(resp.body())["error"].is_string());
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/v1/projects")
+        .json(&json!({ "name": "forbidden", "quota": {} }))
+        .reply(&ctx.rest(Some(viewer())))
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = warp::test::request()
+        .path("/api/v1/projects")
+        .reply(&ctx.rest(None))
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    ctx.teardown().await;
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_endpoints() {
+    let ctx = TestContext::new().await;
+
+    let resp = warp::test::request()
+        .path("/api/v1/openapi.json")
+        .reply(&ctx.rest(None))
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let document = json_body(resp.body());
+    let create_index = &document["paths"]["/environments/{id}/indexes"]["post"];
+    assert_eq!(create_index["operationId"], json!("createIndex"));
+    assert_eq!(create_index["parameters"][0]["name"], json!("id"));
+    let schemas = &document["components"]["schemas"];
+    assert!(schemas["IndexBody"].is_object(), "{}", schemas);
+    assert!(
+        schemas["SingleIndexResponseBody"].is_object(),
+        "{}",
+        schemas
+    );
+
+    ctx.teardown().await;
+}
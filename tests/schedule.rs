@@ -0,0 +1,161 @@
+//! Tests of the cron schedules of index refreshes.
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use nidavellir::schedule::Schedule;
+
+fn schedule(expression: &str) -> Schedule {
+    expression
+        .parse()
+        .unwrap_or_else(|err| panic!("expected a valid schedule '{}': {}", expression, err))
+}
+
+/// The first time on the schedule strictly after the given one.
+fn next(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+    schedule(expression)
+        .next_after(&after)
+        .unwrap_or_else(|| panic!("expected a next time for '{}'", expression))
+}
+
+#[test]
+fn schedules_have_five_fields() {
+    assert!("0 3 * *".parse::<Schedule>().is_err());
+    assert!("0 3 * * 0 2021".parse::<Schedule>().is_err());
+    assert!("".parse::<Schedule>().is_err());
+    assert_eq!(schedule("0   3 * *  0").to_string(), "0 3 * * 0");
+}
+
+#[test]
+fn values_out_of_range_are_rejected() {
+    for expression in &[
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "* * 32 * *",
+        "* * * 0 *",
+        "* * * 13 *",
+        "* * * * 8",
+        "0-60 * * * *",
+        "5-1 * * * *",
+        "*/0 * * * *",
+        "a * * * *",
+        "1,,2 * * * *",
+        "-1 * * * *",
+    ] {
+        assert!(
+            expression.parse::<Schedule>().is_err(),
+            "'{}' should be rejected",
+            expression
+        );
+    }
+}
+
+#[test]
+fn schedules_which_never_come_are_rejected() {
+    assert!("0 0 30 2 *".parse::<Schedule>().is_err());
+    assert!("0 0 31 4,6,9,11 *".parse::<Schedule>().is_err());
+    schedule("0 0 29 2 *");
+}
+
+#[test]
+fn ranges_steps_and_lists_are_expanded() {
+    let at = |hour, minute| Utc.ymd(2021, 1, 1).and_hms(hour, minute, 0);
+
+    assert_eq!(next("*/15 * * * *", at(10, 7)), at(10, 15));
+    assert_eq!(next("*/15 * * * *", at(10, 45)), at(11, 0));
+    assert_eq!(next("5,35 * * * *", at(10, 5)), at(10, 35));
+    assert_eq!(next("0 9-17/4 * * *", at(10, 0)), at(13, 0));
+    assert_eq!(next("0 1-3,20 * * *", at(3, 0)), at(20, 0));
+    // A single value with a step runs to the end of the field.
+    assert_eq!(next("10/20 * * * *", at(10, 30)), at(10, 50));
+    assert_eq!(next("10/20 * * * *", at(10, 50)), at(11, 10));
+}
+
+#[test]
+fn the_next_time_is_strictly_after_the_given_minute() {
+    let after = Utc.ymd(2021, 1, 1).and_hms(2, 29, 59);
+    assert_eq!(
+        next("30 2 * * *", after),
+        Utc.ymd(2021, 1, 1).and_hms(2, 30, 0)
+    );
+
+    let after = Utc.ymd(2021, 1, 1).and_hms(2, 30, 0);
+    assert_eq!(
+        next("30 2 * * *", after),
+        Utc.ymd(2021, 1, 2).and_hms(2, 30, 0)
+    );
+}
+
+#[test]
+fn schedules_roll_over_months_and_years() {
+    let after = Utc.ymd(2021, 1, 31).and_hms(12, 0, 0);
+    assert_eq!(
+        next("0 0 1 * *", after),
+        Utc.ymd(2021, 2, 1).and_hms(0, 0, 0)
+    );
+
+    let after = Utc.ymd(2021, 12, 31).and_hms(23, 59, 0);
+    assert_eq!(
+        next("* * * * *", after),
+        Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)
+    );
+
+    let after = Utc.ymd(2021, 4, 30).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 31 * *", after),
+        Utc.ymd(2021, 5, 31).and_hms(0, 0, 0)
+    );
+
+    let after = Utc.ymd(2021, 3, 1).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 29 2 *", after),
+        Utc.ymd(2024, 2, 29).and_hms(0, 0, 0)
+    );
+}
+
+#[test]
+fn days_match_either_the_day_of_the_month_or_of_the_week() {
+    // January 1st 2021 is a Friday.
+    let after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 13 * 5", after),
+        Utc.ymd(2021, 1, 8).and_hms(0, 0, 0)
+    );
+
+    let after = Utc.ymd(2021, 1, 8).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 13 * 5", after),
+        Utc.ymd(2021, 1, 13).and_hms(0, 0, 0)
+    );
+
+    // Only the days of the week are restricted.
+    let after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 * * 1", after),
+        Utc.ymd(2021, 1, 4).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        next("0 0 * * 7", after),
+        Utc.ymd(2021, 1, 3).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        next("0 0 * * 0", after),
+        Utc.ymd(2021, 1, 3).and_hms(0, 0, 0)
+    );
+}
+
+#[test]
+fn fields_starting_with_a_star_do_not_restrict_days() {
+    // Odd days which are Mondays, rather than odd days or Mondays.
+    let after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+    assert_eq!(
+        next("0 0 */2 * 1", after),
+        Utc.ymd(2021, 1, 11).and_hms(0, 0, 0)
+    );
+
+    // The 13th when it is an even day of the week, rather than the 13th or any of those.
+    assert_eq!(
+        next("0 0 13 * */2", after),
+        Utc.ymd(2021, 2, 13).and_hms(0, 0, 0)
+    );
+}
@@ -0,0 +1,22 @@
+//! Tests of the settings of the production mode, which take their secrets from the
+//! environment.
+
+use std::env;
+
+use nidavellir::auth::Authenticator;
+use nidavellir::settings::Settings;
+
+#[test]
+fn production_secrets_are_read_from_the_environment() {
+    env::set_var("RUN_MODE", "production");
+    env::set_var("DATABASE_URL", "sqlite://nidavellir.db");
+
+    let settings = Settings::new(None).expect("production settings");
+    assert!(settings.auth.secret.is_none());
+    assert!(Authenticator::new(&settings).is_err());
+
+    env::set_var("APP_AUTH__SECRET", "production");
+    let settings = Settings::new(None).expect("production settings");
+    assert_eq!(settings.auth.secret.as_deref(), Some("production"));
+    assert!(Authenticator::new(&settings).is_ok());
+}
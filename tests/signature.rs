@@ -0,0 +1,94 @@
+//! Tests of the signatures identifying environments and indexes by their content.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use nidavellir::docker::images::ResolvedImage;
+use nidavellir::docker::ServiceConfig;
+use nidavellir::signature::{environment_signature, index_signature};
+
+/// A bragi service, with the given additions to its configuration.
+fn service(additions: Value) -> ServiceConfig {
+    let mut config = json!({
+        "service": "bragi",
+        "docker": { "image": "bragi", "tag": "latest" },
+        "network": { "addr_base": "172.19", "addr_suffix": 20 },
+        "envs": ["BRAGI_PORT=4000", "RUST_LOG=info"],
+        "ports": { "4000": "4000" },
+    });
+    if let (Some(config), Some(additions)) = (config.as_object_mut(), additions.as_object()) {
+        config.extend(additions.clone());
+    }
+    serde_json::from_value(config).expect("service configuration")
+}
+
+fn signature(config: &[ServiceConfig]) -> String {
+    environment_signature(config, &HashMap::new())
+}
+
+fn regions(regions: &[&str]) -> Vec<String> {
+    regions.iter().map(|region| String::from(*region)).collect()
+}
+
+#[test]
+fn environment_signatures_ignore_the_order_of_variables() {
+    let reordered = service(json!({ "envs": ["RUST_LOG=info", "BRAGI_PORT=4000"] }));
+    assert_eq!(signature(&[service(json!({}))]), signature(&[reordered]));
+
+    let changed = service(json!({ "envs": ["RUST_LOG=debug", "BRAGI_PORT=4000"] }));
+    assert_ne!(signature(&[service(json!({}))]), signature(&[changed]));
+}
+
+#[test]
+fn environment_signatures_ignore_the_order_of_services() {
+    let other = service(json!({ "service": "mimir", "ports": {} }));
+    assert_eq!(
+        signature(&[service(json!({})), other.clone()]),
+        signature(&[other, service(json!({}))])
+    );
+}
+
+#[test]
+fn environment_signatures_exclude_resources_and_volumes() {
+    let constrained = service(json!({
+        "resources": { "memory": 512, "cpu_shares": 256 },
+        "volumes": [{ "type": "volume", "source": "data", "target": "/data" }],
+    }));
+    assert_eq!(signature(&[service(json!({}))]), signature(&[constrained]));
+}
+
+#[test]
+fn environment_signatures_include_the_image_digest() {
+    let config = [service(json!({}))];
+    let pinned = |digest: &str| {
+        let mut images = HashMap::new();
+        images.insert(
+            String::from("bragi"),
+            ResolvedImage {
+                reference: format!("bragi@{}", digest),
+                digest: Some(String::from(digest)),
+            },
+        );
+        environment_signature(&config, &images)
+    };
+
+    assert_eq!(pinned("sha256:aaaa"), pinned("sha256:aaaa"));
+    assert_ne!(pinned("sha256:aaaa"), pinned("sha256:bbbb"));
+    assert_ne!(pinned("sha256:aaaa"), signature(&config));
+}
+
+#[test]
+fn index_signatures_ignore_the_order_and_repetition_of_regions() {
+    assert_eq!(
+        index_signature("admins", "osm", &regions(&["fr", "be"])),
+        index_signature("admins", "osm", &regions(&["be", "fr", "be"]))
+    );
+    assert_ne!(
+        index_signature("admins", "osm", &regions(&["fr"])),
+        index_signature("admins", "osm", &regions(&["fr", "be"]))
+    );
+    assert_ne!(
+        index_signature("admins", "osm", &regions(&["fr"])),
+        index_signature("admins", "bano", &regions(&["fr"]))
+    );
+}
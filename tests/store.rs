@@ -0,0 +1,155 @@
+//! Tests of the stores which run without a database server, on SQLite files.
+
+use serde_json::json;
+use slog::{o, Logger};
+use sqlx::{Connect, Connection, Executor, SqliteConnection};
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use nidavellir::db::model::{
+    InputEnvironmentEntity, InputIndexEntity, InputProjectEntity, ProvideData, QuotaEntity,
+};
+use nidavellir::db::{self, Store};
+
+/// A new SQLite file, removed when the test is done.
+struct SqliteFile {
+    path: PathBuf,
+}
+
+impl SqliteFile {
+    fn new() -> Self {
+        let path = env::temp_dir().join(format!("nidavellir-{}.db", Uuid::new_v4()));
+        SqliteFile { path }
+    }
+
+    fn url(&self) -> String {
+        format!("sqlite://{}", self.path.display())
+    }
+
+    async fn open(&self) -> Arc<dyn Store> {
+        let logger = Logger::root(slog::Discard, o!());
+        db::connect(&self.url(), &logger).await.expect("store")
+    }
+}
+
+impl Drop for SqliteFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Record a project, an environment and an index, and return their ids.
+async fn insert_index(store: &Arc<dyn Store>) -> (Uuid, Uuid) {
+    let mut tx = store.begin().await.expect("transaction");
+    let project = tx
+        .create_project(&InputProjectEntity {
+            name: String::from("store"),
+            quota: QuotaEntity {
+                max_environments: None,
+                max_indexes: None,
+                max_memory: None,
+            },
+        })
+        .await
+        .expect("project creation");
+    let environment = tx
+        .create_environment(&InputEnvironmentEntity {
+            name: String::from("twerg"),
+            port: 8080,
+            owner: String::from("tester"),
+            project: project.id,
+            memory: 0,
+            expires_at: None,
+            services: Vec::new(),
+            config: json!([]),
+            signature: String::from("signature of twerg"),
+        })
+        .await
+        .expect("environment creation");
+    let index = tx
+        .create_index(&InputIndexEntity {
+            environment: environment.id,
+            index_type: String::from("admins"),
+            data_source: String::from("osm"),
+            regions: vec![String::from("fr")],
+            signature: String::from("signature of index"),
+            twerg_id: Some(1),
+        })
+        .await
+        .expect("index creation");
+    tx.commit().await.expect("commit");
+    (environment.id, index.id)
+}
+
+#[tokio::test]
+async fn sqlite_deletions_cascade() {
+    let file = SqliteFile::new();
+    let store = file.open().await;
+    let (environment, index) = insert_index(&store).await;
+
+    let mut tx = store.begin().await.expect("transaction");
+    tx.create_index_refresh(&index).await.expect("refresh");
+    tx.create_index_version(&index, 1).await.expect("version");
+    tx.delete_index(&environment, &index)
+        .await
+        .expect("deletion");
+    let refreshes = tx.get_index_refreshes(&index).await.expect("refreshes");
+    let versions = tx.get_index_versions(&index).await.expect("versions");
+    tx.commit().await.expect("commit");
+
+    assert!(refreshes.is_empty());
+    assert!(versions.is_empty());
+    store.close().await;
+}
+
+#[tokio::test]
+async fn sqlite_stores_are_upgraded() {
+    let file = SqliteFile::new();
+
+    // The indexes of a store created before their refreshes.
+    let mut conn = SqliteConnection::connect(&file.url())
+        .await
+        .expect("connection");
+    conn.execute(
+        "CREATE TABLE indexes (
+          id TEXT PRIMARY KEY,
+          environment TEXT NOT NULL REFERENCES environments(id) ON DELETE CASCADE,
+          index_type TEXT NOT NULL,
+          data_source TEXT NOT NULL,
+          regions TEXT NOT NULL,
+          signature TEXT NOT NULL,
+          status TEXT NOT NULL DEFAULT 'not_available',
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL
+        )",
+    )
+    .await
+    .expect("old schema");
+    conn.close().await.expect("close");
+
+    let store = file.open().await;
+    let (environment, index) = insert_index(&store).await;
+    let mut tx = store.begin().await.expect("transaction");
+    let scheduled = tx
+        .schedule_index(&index, Some("0 3 * * 0"), None)
+        .await
+        .expect("schedule");
+    let indexes = tx
+        .get_environment_indexes(&environment)
+        .await
+        .expect("indexes");
+    tx.commit().await.expect("commit");
+    store.close().await;
+
+    assert_eq!(scheduled.twerg_id, Some(1));
+    assert_eq!(scheduled.schedule.as_deref(), Some("0 3 * * 0"));
+    assert_eq!(indexes.len(), 1);
+
+    // Opening it again applies nothing more.
+    let store = file.open().await;
+    let (_, again) = insert_index(&store).await;
+    store.close().await;
+    assert_ne!(again, index);
+}